@@ -1,7 +1,4 @@
-#define INSCATTER_R_SIZE 32
-#define INSCATTER_MU_SIZE 128
-#define INSCATTER_MU_S_SIZE 32
-#define INSCATTER_NU_SIZE 8
+#include "atmosphere_common.hlsli"
 
 StructuredBuffer<float3> Rayleigh : register(t0);
 StructuredBuffer<float3> Mie : register(t1);
@@ -1,9 +1,10 @@
+#include "atmosphere_common.hlsli"
 
 StructuredBuffer<float3> Buf : register(t0);
 RWTexture2D<float4> Tex: register(u0);
 
 [numthreads(32, 1, 1)]
 void main (uint3 DTid: SV_DispatchThreadId) {
-    uint index = (DTid.y * 256 ) + DTid.x;
+    uint index = (DTid.y * TRANSMITTANCE_WIDTH) + DTid.x;
     Tex[DTid.xy] = float4(Buf[index], 1.0);
 }
\ No newline at end of file
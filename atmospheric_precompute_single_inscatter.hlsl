@@ -1,45 +1,9 @@
-#define TRANSMITTANCE_INTEGRAL_SAMPLES 1000
-
-#define TRANSMITTANCE_WIDTH 256
-#define TRANSMITTANCE_HEIGHT 64
-
-#define IRRADIANCE_WIDTH 64
-#define IRRADIANCE_HEIGHT 16
-
-#define INSCATTER_MU_S_SIZE 32
-#define INSCATTER_NU_SIZE 8
-#define INSCATTER_MU_SIZE 128
-#define INSCATTER_R_SIZE 32
-
-#define INSCATTER_INTEGRAL_SAMPLES 1000
-
-cbuffer AtmosphericConstants : register(b0) {
-    float3 beta_rayleigh;
-    uint num_scattering;
-    float3 wave_lengths;
-    float mu_s_min;
-    float3 solar_irradiance;
-    float atmos_bottom;
-    float atmos_top;
-    float Hm;
-    float Hr;
-};
-
-StructuredBuffer<float3> Transmittance : register(t0);
+#define ATMOSPHERE_TRANSMITTANCE_LUT
+#include "atmosphere_common.hlsli"
+
 RWStructuredBuffer<float3> DeltaInScatterRayleigh : register(u0);
 RWStructuredBuffer<float3> DeltaInScatterMie : register(u1);
 
-float DistanceToAtmosTop(float r, float mu) {
-
-    float discriminant = (r*r * ((mu*mu) - 1.0)) + (atmos_top * atmos_top);
-
-    return max(0.0, (-r * mu) + sqrt(max(0.0, discriminant)));
-}
-
-float DistanceToAtmosBottom(float r, float mu) {
-    return max(0.0, (-r * mu) - sqrt(max(r * r * (mu * mu - 1.0) + atmos_bottom * atmos_bottom, 0.0)));
-}
-
 float DistanceToAtmos(float r, float mu, bool intersects_ground) {
     if (intersects_ground) {
         return DistanceToAtmosBottom(r, mu);
@@ -48,41 +12,6 @@ float DistanceToAtmos(float r, float mu, bool intersects_ground) {
     return DistanceToAtmosTop(r, mu);
 }
 
-bool ViewIntersectsGround(float r, float mu) {
-    return mu < 0.0 && r*r * (mu*mu - 1.0) + atmos_bottom * atmos_bottom >= 0.0;
-}
-
-float3 BetaMieScattering(float r, float mu) {
-    return float3(4e-3, 4e-3, 4e-3);
-}
-
-float3 BetaMieExtinction(float r, float mu) {
-    return BetaMieScattering(r,mu) / 0.9;
-}
-
-float2 GetTransmittanceUVFromRMu(float r, float mu) {
-    float H = sqrt(atmos_top * atmos_top - atmos_bottom * atmos_bottom);
-    float rho = sqrt(max(0.0, r * r - atmos_bottom * atmos_bottom));
-
-    float d = DistanceToAtmosTop(r, mu);
-    float d_min = atmos_top - r;
-    float d_max = rho + H;
-
-    float x_mu = (d - d_min) / (d_max - d_min);
-    float x_r = rho / H;
-
-    return float2(x_mu, x_r);
-}
-
-float3 GetTransmittance(float r, float mu) {
-
-    float2 uv = GetTransmittanceUVFromRMu(r, mu);
-    uint2 xy = uint2(TRANSMITTANCE_WIDTH * uv.x, TRANSMITTANCE_HEIGHT * uv.y);
-    uint index = TRANSMITTANCE_WIDTH * xy.y + xy.x;
-
-    return Transmittance[index];
-}
-
 float3 GetTransmittance(float r, float mu, float dist, bool intersects_ground) {
    
     float r_y = sqrt(r*r + dist*dist + 2.0*r*mu*dist);
@@ -102,17 +31,6 @@ float3 GetTransmittance(float r, float mu, float dist, bool intersects_ground) {
     }
 }
 
-float2 RMusFromUV(float2 uv) {
-    float mu_s = uv.x;
-    float r = uv.y;
-
-    r = atmos_bottom + (r * (atmos_top - atmos_bottom));
-    mu_s = clamp(2.0 * mu_s - 1.0, -1.0, 1.0);
-
-
-    return float2(r, mu_s);
-}
-
 #define mod(x, y) (x - y * floor(x / y))
 
 float4 GetRMuMuSNuFromUVWZ(float4 uvwz, out bool intersects_ground) {
@@ -170,8 +88,8 @@ void InScatterAtPoint(float r, float mu, float mu_s, float nu, float dist, bool
 
     if (mu_s_y >= -sqrt(1.0 - ((atmos_bottom*atmos_bottom) / (r_y * r_y)))) {
         float3 transmittance = GetTransmittance(r, mu, dist, intersects_ground) * GetTransmittance(r_y, mu_s_y);
-        rayleigh = exp(-(r_y - atmos_bottom) / Hr) * transmittance;
-        mie = exp(-(r_y - atmos_bottom) / Hm) * transmittance;
+        rayleigh = exp(-(r_y - atmos_bottom) / h_r) * transmittance;
+        mie = exp(-(r_y - atmos_bottom) / h_m) * transmittance;
     }
 
 }
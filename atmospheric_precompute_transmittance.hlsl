@@ -1,39 +1,7 @@
-#define TRANSMITTANCE_INTEGRAL_SAMPLES 1000
-
-cbuffer AtmosphericConstants : register(b0) {
-    float3 beta_rayleigh;
-    uint num_scattering;
-    float3 wave_lengths;
-    float mu_s_min;
-    float3 solar_irradiance;
-    float atmos_bottom;
-    float atmos_top;
-    float Hm;
-    float Hr;
-};
+#include "atmosphere_common.hlsli"
 
 RWStructuredBuffer<float3> Transmittance : register(u0);
 
-float DistanceToAtmosTop(float r, float mu) {
-    return (-r * mu) + sqrt(max(r * r * (mu * mu - 1.0) + atmos_top * atmos_top, 0.0));
-}
-
-float DistanceToAtmosBottom(float r, float mu) {
-    return (-r * mu) - sqrt(max(r * r * (mu * mu - 1.0) + atmos_bottom * atmos_bottom, 0.0));
-}
-
-bool ViewIntersectsGround(float r, float mu) {
-    return mu < 0.0 && r*r * (mu*mu - 1.0) + atmos_bottom * atmos_bottom >= 0.0;
-}
-
-float3 BetaMieScattering(float r, float mu) {
-    return float3(4e-3, 4e-3, 4e-3);
-}
-
-float3 BetaMieExtinction(float r, float mu) {
-    return BetaMieScattering(r,mu) / 0.9;
-}
-
 float DensityAlongView(float scale_height, float r, float mu) {
 
     bool ray_below_horizon = mu < -sqrt(1.0 - ((atmos_bottom * atmos_bottom) / (r * r)));
@@ -64,7 +32,7 @@ float3 ComputeTransmittanceToAtmosTop(float r, float mu) {
 
     float3 beta_mie_e = BetaMieExtinction(r, mu);
 
-    float3 integral_result = (beta_rayleigh * DensityAlongView(Hr, r, mu)) + (beta_mie_e * DensityAlongView(Hm, r, mu));
+    float3 integral_result = (beta_rayleigh * DensityAlongView(h_r, r, mu)) + (beta_mie_e * DensityAlongView(h_m, r, mu));
 
 
     return exp(-integral_result);
@@ -1,11 +1,18 @@
+// Which G-buffer channel to show instead of the lit result.
+#pragma keyword DEBUG_VIEW VIEW_LIT VIEW_ALBEDO VIEW_NORMAL VIEW_POSITION
+#define VIEW_LIT 0
+#define VIEW_ALBEDO 1
+#define VIEW_NORMAL 2
+#define VIEW_POSITION 3
 
-Texture2D pos : register(t0);
-Texture2D albedo : register(t1);
-Texture2D normal : register(t2);
 
-Texture2D transmittance : register(t3);
-Texture2D irradiance : register(t4);
-Texture3D inscatter : register(t5);
+Texture2D pos;
+Texture2D albedo;
+Texture2D normal;
+
+Texture2D transmittance;
+Texture2D irradiance;
+Texture3D inscatter;
 
 SamplerState Sampler;
 
@@ -32,6 +39,14 @@ float4 main(float4 position : SV_POSITION, float2 uv: TEXCOORD) : SV_TARGET {
     float3 p = pos.Sample(Sampler, uv).xyz;
     float4 c = albedo.Sample(Sampler, uv);
 
+#if DEBUG_VIEW == VIEW_ALBEDO
+    return float4(c.xyz, 1.0);
+#elif DEBUG_VIEW == VIEW_NORMAL
+    return float4(n.xyz * 0.5 + 0.5, 1.0);
+#elif DEBUG_VIEW == VIEW_POSITION
+    return float4(frac(p), 1.0);
+#endif
+
     float3 dir_light = normalize(float3(50.0, 10.0, 0.0));
 
     float sun_zenith = dot(float3(0.0, 1.0, 0.0), dir_light);
@@ -6,12 +6,14 @@ struct Vout
     float3 ws_position: POSITIONT;
     float3 normal: NORMAL;
     float2 uv: TEXCOORD;
+    float4 tangent: TANGENT;
 };
 
 struct Vin {
     float3 position: POSITION;
     float3 normal: NORMAL;
     float2 uv : TEXCOORD;
+    float4 tangent: TANGENT;
     uint vertexId : SV_VertexID;
 };
 
@@ -33,6 +35,11 @@ Vout vertex(Vin input) {
     output.ws_position = pos;
     output.normal = normalize(mul(float4(input.normal, 0.0), WorldView).xyz);
     output.uv = input.uv;
+    // Bitangent handedness stays in w for normal mapping. Meshes without
+    // tangents have zeros, which normalize to NaN.
+    float3 tangent = mul(float4(input.tangent.xyz, 0.0), WorldView).xyz;
+    tangent = dot(tangent, tangent) > 0.0 ? normalize(tangent) : float3(0.0, 0.0, 0.0);
+    output.tangent = float4(tangent, input.tangent.w);
 
     return output;
 }
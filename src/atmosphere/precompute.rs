@@ -5,21 +5,46 @@ use windows::Win32::Graphics::{
     Direct3D::D3D11_SRV_DIMENSION_BUFFEREX, Direct3D11::*, Dxgi::Common::*,
 };
 
-use crate::render_backend::{backend::Backend, gpu_buffer::GPUBuffer, shader::*, texture::*};
+use crate::constant_buffer;
+use crate::error::{Context, EngineResult};
+use crate::render_backend::{
+    backend::Backend, compute_pass::ComputePass, gpu_buffer::GPUBuffer, shader::*,
+    shader_cache::ShaderSource, texture::*, typed_buffer::TypedBuffer,
+};
 
 use std::ffi::{CStr, CString};
+use std::path::Path;
+
+/// Every shader `precompute_textures` runs, for `precompile_shaders`.
+pub const SHADERS: &[ShaderSource] = &[
+    compute_source("atmospheric_precompute_transmittance.hlsl"),
+    compute_source("atmospheric_precompute_single_irradiance.hlsl"),
+    compute_source("atmospheric_precompute_single_inscatter.hlsl"),
+    compute_source("atmospheric_precompute_copy_transmittance.hlsl"),
+    compute_source("atmospheric_precompute_copy_irradiance.hlsl"),
+    compute_source("atmospheric_precompute_copy_single_inscatter.hlsl"),
+];
+
+const fn compute_source(path: &'static str) -> ShaderSource {
+    ShaderSource {
+        stage: ShaderStage::Compute,
+        path,
+        entry_point: "main",
+    }
+}
 
-#[repr(C)]
-pub struct AtmosphericConstants {
-    beta_rayleigh: Vec3,
-    num_scattering: u32,
-    wave_lengths: Vec3,
-    mu_s_min: f32,
-    solar_irradiance: Vec3,
-    atmos_bottom: f32,
-    atmos_top: f32,
-    h_m: f32,
-    h_r: f32,
+constant_buffer! {
+    pub struct AtmosphericConstants {
+        beta_rayleigh: Vec3,
+        num_scattering: u32,
+        wave_lengths: Vec3,
+        mu_s_min: f32,
+        solar_irradiance: Vec3,
+        atmos_bottom: f32,
+        atmos_top: f32,
+        h_m: f32,
+        h_r: f32,
+    }
 }
 
 impl Default for AtmosphericConstants {
@@ -38,210 +63,133 @@ impl Default for AtmosphericConstants {
     }
 }
 
+/// The structured buffers the precompute passes write before the results
+/// are copied into textures, kept for inspecting them with `TypedBuffer::read`.
+pub struct PrecomputeBuffers {
+    pub transmittance: TypedBuffer<Vec3>,
+    pub delta_irradiance: TypedBuffer<Vec3>,
+    pub delta_inscatter_rayleigh: TypedBuffer<Vec3>,
+    pub delta_inscatter_mie: TypedBuffer<Vec3>,
+}
+
+pub struct PrecomputedAtmosphere {
+    pub transmittance: Tex2D,
+    pub irradiance: Tex2D,
+    pub inscatter: Tex3D,
+    pub buffers: PrecomputeBuffers,
+}
+
+impl PrecomputedAtmosphere {
+    /// Writes the lookup tables as they appear in `img/`, the inscatter
+    /// table as its first depth slice, plus lossless EXR copies.
+    pub fn save_luts(&self, backend: &Backend, dir: &Path) -> EngineResult<()> {
+        let luts = [
+            (
+                "transmittance",
+                self.transmittance.read_back(backend, 0, 0)?,
+            ),
+            (
+                "direct_irradiance",
+                self.irradiance.read_back(backend, 0, 0)?,
+            ),
+            (
+                "single_inscattering",
+                self.inscatter.read_slice(backend, 0, 0)?,
+            ),
+        ];
+
+        for (name, image) in &luts {
+            image.save(dir.join(name).with_extension("png"))?;
+            image.save(dir.join(name).with_extension("exr"))?;
+        }
+
+        Ok(())
+    }
+}
+
 pub fn precompute_textures(
     backend: &Backend,
     constants: AtmosphericConstants,
-) -> (Tex2D, Tex2D, Tex3D) {
-    let cbuffer =
-        GPUBuffer::constant_buffer(backend, std::mem::size_of::<AtmosphericConstants>() as u32)
-            .expect("Create cbuffer");
+) -> EngineResult<(Tex2D, Tex2D, Tex3D)> {
+    let atmosphere = precompute_atmosphere(backend, constants)?;
+
+    Ok((
+        atmosphere.transmittance,
+        atmosphere.irradiance,
+        atmosphere.inscatter,
+    ))
+}
+
+pub fn precompute_atmosphere(
+    backend: &Backend,
+    constants: AtmosphericConstants,
+) -> EngineResult<PrecomputedAtmosphere> {
+    let cbuffer = GPUBuffer::constant_buffer_for::<AtmosphericConstants>(backend)
+        .context("Create atmospheric constants buffer")?;
 
     //let buffer = GPUBuffer::structured_buffer::<AtmosphericConstants>(&backend, 1, false)
     //    .expect("Create constants buffer");
 
     {
-        let mapped = cbuffer.map(backend).expect("Mapping buffer");
+        let mapped = cbuffer.map(backend).context("Map atmospheric constants")?;
         mapped.copy_from(&[constants]);
     }
 
-    let transmittance_buffer = GPUBuffer::structured_buffer::<Vec3>(backend, 256 * 64, true)
-        .expect("Create transmittance buffer");
+    let transmittance_buffer = TypedBuffer::<Vec3>::structured_buffer(backend, 256 * 64)
+        .context("Create transmittance buffer")?;
 
     let transmittance_uav = backend
         .unordered_access_view_buffer(&transmittance_buffer, None)
-        .expect("Create UAV");
+        .context("Create transmittance uav")?;
 
     let transmittance_srv = backend
         .shader_resource_view_buffer(&transmittance_buffer, None)
-        .expect("Create srv");
+        .context("Create transmittance srv")?;
 
-    let irradiance_buffer = GPUBuffer::structured_buffer::<Vec3>(backend, 64 * 16, true)
-        .expect("Create irradiance buffer");
+    let irradiance_buffer = TypedBuffer::<Vec3>::structured_buffer(backend, 64 * 16)
+        .context("Create irradiance buffer")?;
 
     let irradiance_uav = backend
         .unordered_access_view_buffer(&irradiance_buffer, None)
-        .expect("irradiance uav");
+        .context("Create irradiance uav")?;
 
     let irradiance_srv = backend
         .shader_resource_view_buffer(&irradiance_buffer, None)
-        .expect("irradiance srv");
+        .context("Create irradiance srv")?;
 
-    let delta_irradiance_buffer = GPUBuffer::structured_buffer::<Vec3>(backend, 64 * 16, true)
-        .expect("Create delta_irradiance buffer");
+    let delta_irradiance_buffer = TypedBuffer::<Vec3>::structured_buffer(backend, 64 * 16)
+        .context("Create delta irradiance buffer")?;
 
     let delta_irradiance_uav = backend
         .unordered_access_view_buffer(&delta_irradiance_buffer, None)
-        .expect("delta_irradiance uav");
+        .context("Create delta irradiance uav")?;
 
     let delta_irradiance_srv = backend
         .shader_resource_view_buffer(&delta_irradiance_buffer, None)
-        .expect("dIr srv");
+        .context("Create delta irradiance srv")?;
 
     let delta_inscatter_rayleigh_buffer =
-        GPUBuffer::structured_buffer::<Vec3>(backend, 32 * 128 * 32 * 8, true)
-            .expect("Create delta in scatter buffer");
+        TypedBuffer::<Vec3>::structured_buffer(backend, 32 * 128 * 32 * 8)
+            .context("Create delta rayleigh inscatter buffer")?;
 
     let delta_inscatter_rayleigh_uav = backend
         .unordered_access_view_buffer(&delta_inscatter_rayleigh_buffer, None)
-        .expect("uav");
+        .context("Create delta rayleigh inscatter uav")?;
     let delta_inscatter_rayleigh_srv = backend
         .shader_resource_view_buffer(&delta_inscatter_rayleigh_buffer, None)
-        .expect("srv");
+        .context("Create delta rayleigh inscatter srv")?;
 
     let delta_inscatter_mie_buffer =
-        GPUBuffer::structured_buffer::<Vec3>(backend, 32 * 128 * 32 * 8, true)
-            .expect("Create delta in scatter buffer");
+        TypedBuffer::<Vec3>::structured_buffer(backend, 32 * 128 * 32 * 8)
+            .context("Create delta mie inscatter buffer")?;
 
     let delta_inscatter_mie_uav = backend
         .unordered_access_view_buffer(&delta_inscatter_mie_buffer, None)
-        .expect("uav");
+        .context("Create delta mie inscatter uav")?;
 
     let delta_inscatter_mie_srv = backend
         .shader_resource_view_buffer(&delta_inscatter_mie_buffer, None)
-        .expect("srv");
-
-    let transmittance_shader =
-        Shader::compute_shader(backend, "atmospheric_precompute_transmittance.hlsl", "main")
-            .expect("Create shader");
-
-    // Compute Transmittance
-    if let Shader::Compute(shader, _) = transmittance_shader {
-        unsafe {
-            backend
-                .device_context
-                .CSSetShader(shader.clone(), std::ptr::null(), 0);
-
-            //backend
-            //    .device_context
-            //    .CSSetShaderResources(0, 1, &Some(constants_srv.clone()));
-            backend
-                .device_context
-                .CSSetConstantBuffers(0, 1, &Some(cbuffer.buffer.clone()));
-
-            backend.device_context.CSSetUnorderedAccessViews(
-                0,
-                1,
-                &Some(transmittance_uav.clone()),
-                std::ptr::null(),
-            );
-
-            backend.device_context.Dispatch(8, 64, 1);
-
-            backend
-                .device_context
-                .CSSetShader(None, std::ptr::null(), 0);
-
-            backend.unbind_shader_resources();
-
-            backend
-                .device_context
-                .CSSetUnorderedAccessViews(0, 1, &None, std::ptr::null());
-        }
-    }
-
-    let single_irradiance_shader = Shader::compute_shader(
-        backend,
-        "atmospheric_precompute_single_irradiance.hlsl",
-        "main",
-    )
-    .expect("Create shader");
-
-    // Compute Single Irradiance
-    if let Shader::Compute(shader, _) = single_irradiance_shader {
-        unsafe {
-            backend
-                .device_context
-                .CSSetShader(shader.clone(), std::ptr::null(), 0);
-
-            backend
-                .device_context
-                .CSSetConstantBuffers(0, 1, &Some(cbuffer.buffer.clone()));
-
-            backend.device_context.CSSetShaderResources(
-                0,
-                1,
-                [Some(transmittance_srv.clone())].as_ptr(),
-            );
-
-            backend.device_context.CSSetUnorderedAccessViews(
-                0,
-                1,
-                [Some(delta_irradiance_uav.clone())].as_ptr(),
-                std::ptr::null(),
-            );
-
-            backend.device_context.Dispatch(2, 16, 1);
-
-            backend
-                .device_context
-                .CSSetShader(None, std::ptr::null(), 0);
-
-            backend.unbind_shader_resources();
-
-            backend
-                .device_context
-                .CSSetUnorderedAccessViews(0, 1, &None, std::ptr::null());
-        }
-    }
-
-    let single_inscatter_shader = Shader::compute_shader(
-        backend,
-        "atmospheric_precompute_single_inscatter.hlsl",
-        "main",
-    )
-    .expect("Create shader");
-
-    // Compute Single Inscatter
-    if let Shader::Compute(shader, _) = single_inscatter_shader {
-        unsafe {
-            backend
-                .device_context
-                .CSSetShader(shader.clone(), std::ptr::null(), 0);
-
-            backend
-                .device_context
-                .CSSetConstantBuffers(0, 1, &Some(cbuffer.buffer.clone()));
-
-            backend.device_context.CSSetShaderResources(
-                0,
-                1,
-                [Some(transmittance_srv.clone())].as_ptr(),
-            );
-
-            backend.device_context.CSSetUnorderedAccessViews(
-                0,
-                2,
-                [
-                    Some(delta_inscatter_rayleigh_uav.clone()),
-                    Some(delta_inscatter_mie_uav.clone()),
-                ]
-                .as_ptr(),
-                std::ptr::null(),
-            );
-
-            backend.device_context.Dispatch(8, 128, 32);
-
-            backend
-                .device_context
-                .CSSetShader(None, std::ptr::null(), 0);
-
-            backend.unbind_shader_resources();
-
-            backend
-                .device_context
-                .CSSetUnorderedAccessViews(0, 1, &None, std::ptr::null());
-        }
-    }
+        .context("Create delta mie inscatter srv")?;
 
     let transmittance_texture = Tex2D::new(
         backend,
@@ -252,51 +200,11 @@ pub fn precompute_textures(
             .size([256, 64, 0])
             .build_texture2d(),
     )
-    .expect("Create irradiance texture");
+    .context("Create transmittance texture")?;
 
     let transmittance_texture_uav = backend
         .unordered_access_view(&transmittance_texture, None)
-        .expect("Create texture uav");
-
-    let copy_transmittance_shader = Shader::compute_shader(
-        backend,
-        "atmospheric_precompute_copy_transmittance.hlsl",
-        "main",
-    )
-    .expect("Create shader");
-    // Copy transmittance buffer to texture
-    if let Shader::Compute(shader, _) = copy_transmittance_shader {
-        unsafe {
-            backend
-                .device_context
-                .CSSetShader(shader.clone(), std::ptr::null(), 0);
-
-            backend.device_context.CSSetShaderResources(
-                0,
-                1,
-                [Some(transmittance_srv.clone())].as_ptr(),
-            );
-
-            backend.device_context.CSSetUnorderedAccessViews(
-                0,
-                1,
-                [Some(transmittance_texture_uav.clone())].as_ptr(),
-                std::ptr::null(),
-            );
-
-            backend.device_context.Dispatch(8, 64, 1);
-
-            backend
-                .device_context
-                .CSSetShader(None, std::ptr::null(), 0);
-
-            backend.unbind_shader_resources();
-
-            backend
-                .device_context
-                .CSSetUnorderedAccessViews(0, 1, &None, std::ptr::null());
-        }
-    }
+        .context("Create transmittance texture uav")?;
 
     let irradiance_texture = Tex2D::new(
         backend,
@@ -307,51 +215,11 @@ pub fn precompute_textures(
             .size([64, 16, 0])
             .build_texture2d(),
     )
-    .expect("Create irradiance texture");
+    .context("Create irradiance texture")?;
 
     let irradiance_texture_uav = backend
         .unordered_access_view(&irradiance_texture, None)
-        .expect("Create texture uav");
-
-    let copy_irradiance_shader = Shader::compute_shader(
-        backend,
-        "atmospheric_precompute_copy_irradiance.hlsl",
-        "main",
-    )
-    .expect("Create shader");
-    // Copy irradiance buffer to texture
-    if let Shader::Compute(shader, _) = copy_irradiance_shader {
-        unsafe {
-            backend
-                .device_context
-                .CSSetShader(shader.clone(), std::ptr::null(), 0);
-
-            backend.device_context.CSSetShaderResources(
-                0,
-                1,
-                [Some(delta_irradiance_srv.clone())].as_ptr(),
-            );
-
-            backend.device_context.CSSetUnorderedAccessViews(
-                0,
-                1,
-                [Some(irradiance_texture_uav.clone())].as_ptr(),
-                std::ptr::null(),
-            );
-
-            backend.device_context.Dispatch(2, 16, 1);
-
-            backend
-                .device_context
-                .CSSetShader(None, std::ptr::null(), 0);
-
-            backend.unbind_shader_resources();
-
-            backend
-                .device_context
-                .CSSetUnorderedAccessViews(0, 1, &None, std::ptr::null());
-        }
-    }
+        .context("Create irradiance texture uav")?;
 
     let inscatter_texture = Tex3D::new(
         backend,
@@ -362,55 +230,66 @@ pub fn precompute_textures(
             .size([256, 128, 32])
             .build_texture3d(),
     )
-    .expect("Create inscatter tex");
+    .context("Create inscatter texture")?;
 
     let inscatter_texture_uav = backend
         .unordered_access_view(&inscatter_texture, None)
-        .expect("uav");
-    let copy_inscatter_single_shader = Shader::compute_shader(
-        backend,
-        "atmospheric_precompute_copy_single_inscatter.hlsl",
-        "main",
-    )
-    .expect("Create shader");
-
-    // Copy inscatter buffer to texture
-    if let Shader::Compute(shader, _) = copy_inscatter_single_shader {
-        unsafe {
-            backend
-                .device_context
-                .CSSetShader(shader.clone(), std::ptr::null(), 0);
-
-            backend.device_context.CSSetShaderResources(
-                0,
-                2,
-                [
-                    Some(delta_inscatter_rayleigh_srv.clone()),
-                    Some(delta_inscatter_mie_srv.clone()),
-                ]
-                .as_ptr(),
-            );
-
-            backend.device_context.CSSetUnorderedAccessViews(
-                0,
-                1,
-                [Some(inscatter_texture_uav.clone())].as_ptr(),
-                std::ptr::null(),
-            );
-
-            backend.device_context.Dispatch(8, 128, 32);
-
-            backend
-                .device_context
-                .CSSetShader(None, std::ptr::null(), 0);
-
-            backend.unbind_shader_resources();
-
-            backend
-                .device_context
-                .CSSetUnorderedAccessViews(0, 1, &None, std::ptr::null());
-        }
+        .context("Create inscatter texture uav")?;
+
+    let compute_pass = |path: &str| -> EngineResult<ComputePass> {
+        ComputePass::new(Shader::compute_shader(backend, path, "main")?)
+    };
+
+    let passes = [
+        // Compute Transmittance
+        compute_pass("atmospheric_precompute_transmittance.hlsl")?
+            .constant_buffer("AtmosphericConstants", &cbuffer)
+            .unordered_access("Transmittance", transmittance_uav)
+            .thread_count([256, 64, 1]),
+        // Compute Single Irradiance
+        compute_pass("atmospheric_precompute_single_irradiance.hlsl")?
+            .constant_buffer("AtmosphericConstants", &cbuffer)
+            .shader_resource("Transmittance", transmittance_srv.clone())
+            .unordered_access("DeltaIrradiance", delta_irradiance_uav)
+            .thread_count([64, 16, 1]),
+        // Compute Single Inscatter
+        compute_pass("atmospheric_precompute_single_inscatter.hlsl")?
+            .constant_buffer("AtmosphericConstants", &cbuffer)
+            .shader_resource("Transmittance", transmittance_srv.clone())
+            .unordered_access("DeltaInScatterRayleigh", delta_inscatter_rayleigh_uav)
+            .unordered_access("DeltaInScatterMie", delta_inscatter_mie_uav)
+            .thread_count([256, 128, 32]),
+        // Copy transmittance buffer to texture
+        compute_pass("atmospheric_precompute_copy_transmittance.hlsl")?
+            .shader_resource("Buf", transmittance_srv)
+            .unordered_access("Tex", transmittance_texture_uav)
+            .thread_count([256, 64, 1]),
+        // Copy irradiance buffer to texture
+        compute_pass("atmospheric_precompute_copy_irradiance.hlsl")?
+            .shader_resource("Buf", delta_irradiance_srv)
+            .unordered_access("Tex", irradiance_texture_uav)
+            .thread_count([64, 16, 1]),
+        // Copy inscatter buffer to texture
+        compute_pass("atmospheric_precompute_copy_single_inscatter.hlsl")?
+            .shader_resource("Rayleigh", delta_inscatter_rayleigh_srv)
+            .shader_resource("Mie", delta_inscatter_mie_srv)
+            .unordered_access("Tex", inscatter_texture_uav)
+            .thread_count([256, 128, 32]),
+    ];
+
+    for pass in &passes {
+        pass.execute(backend)?;
     }
 
-    (transmittance_texture, irradiance_texture, inscatter_texture)
+    Ok(PrecomputedAtmosphere {
+        transmittance: transmittance_texture,
+        irradiance: irradiance_texture,
+        inscatter: inscatter_texture,
+        buffers: PrecomputeBuffers {
+            transmittance: transmittance_buffer,
+            delta_irradiance: delta_irradiance_buffer,
+            delta_inscatter_rayleigh: delta_inscatter_rayleigh_buffer,
+            delta_inscatter_mie: delta_inscatter_mie_buffer,
+        },
+    })
 }
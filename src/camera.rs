@@ -1,3 +1,4 @@
+use crate::error::{Context, EngineResult};
 use crate::object::GameObject;
 use crate::render_backend::{backend::*, gpu_buffer::GPUBuffer};
 use glam::{Mat4, Vec3};
@@ -9,7 +10,7 @@ pub struct Camera {
 }
 
 impl Camera {
-    pub fn new(backend: &Backend) -> Camera {
+    pub fn new(backend: &Backend) -> EngineResult<Camera> {
         let camera_pos = Vec3::new(0.0, 0.5, -1.0);
         let focal_point = Vec3::new(0.0, 0.0, 0.2);
 
@@ -21,23 +22,28 @@ impl Camera {
             Mat4::perspective_lh(std::f32::consts::PI / 4.0, 800.0 / 600.0, 0.001, 1000.0)
                 * Mat4::look_at_lh(camera_pos, focal_point, up);
 
+        Camera::from_view_projection(backend, transform)
+    }
+
+    /// A camera for a view-projection matrix, e.g. of an imported camera.
+    pub fn from_view_projection(backend: &Backend, transform: Mat4) -> EngineResult<Camera> {
         let cbuffer = GPUBuffer::constant_buffer(backend, std::mem::size_of::<Mat4>() as u32)
-            .expect("Creating camera cbuffer");
+            .context("Create camera cbuffer")?;
 
-        Camera {
+        Ok(Camera {
             transform,
             transform_dirty: true,
             cbuffer,
-        }
+        })
     }
 }
 
 impl GameObject for Camera {
     fn update(&mut self) {}
-    fn bind(&mut self, backend: &Backend) {
+    fn bind(&mut self, backend: &Backend) -> EngineResult<()> {
         if self.transform_dirty {
             {
-                let mapped_cbuffer = self.cbuffer.map(backend).expect("Mapping camera cbuffer");
+                let mapped_cbuffer = self.cbuffer.map(backend).context("Map camera cbuffer")?;
                 mapped_cbuffer.copy_from(&[self.transform])
             }
 
@@ -56,5 +62,7 @@ impl GameObject for Camera {
                 &Some(self.cbuffer.buffer.clone()),
             );
         }
+
+        Ok(())
     }
 }
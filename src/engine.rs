@@ -1,3 +1,4 @@
+use crate::error::{Context, EngineError, EngineResult};
 use crate::render_backend::renderer::BasicRenderer;
 use crate::render_backend::{backend::Backend, renderer::Renderer};
 use crate::scene::Scene;
@@ -13,7 +14,7 @@ pub struct Engine {
     pub scene: Option<Scene>,
 }
 
-fn create_backend(hwnd: HWND) -> Backend {
+fn create_backend(hwnd: HWND) -> EngineResult<Backend> {
     let swap_chain_desc = DXGI_SWAP_CHAIN_DESC {
         BufferDesc: DXGI_MODE_DESC {
             Format: DXGI_FORMAT_R8G8B8A8_UNORM,
@@ -50,25 +51,31 @@ fn create_backend(hwnd: HWND) -> Backend {
             std::ptr::null_mut(),
             &mut context,
         )
-        .expect("Error creating device and swapchain");
+        .context("Create device and swapchain")?;
     };
 
-    let device = device.expect("Device should be created");
-    let swapchain = swapchain.expect("Swapchain should be created");
-    let context = context.expect("DeviceContext should be created");
+    let missing = |what: &str| {
+        EngineError::Validation(format!(
+            "D3D11CreateDeviceAndSwapChain returned no {}",
+            what
+        ))
+    };
+    let device = device.ok_or_else(|| missing("device"))?;
+    let swapchain = swapchain.ok_or_else(|| missing("swapchain"))?;
+    let context = context.ok_or_else(|| missing("device context"))?;
 
-    Backend::new(device, context, swapchain)
+    Ok(Backend::new(device, context, swapchain))
 }
 
 impl Engine {
-    pub fn new(hwnd: HWND) -> Engine {
-        let backend = create_backend(hwnd);
+    pub fn new(hwnd: HWND) -> EngineResult<Engine> {
+        let backend = create_backend(hwnd)?;
 
-        Engine {
+        Ok(Engine {
             backend,
             renderer: None,
             scene: None,
-        }
+        })
     }
 
     pub fn update(&mut self) {
@@ -77,23 +84,28 @@ impl Engine {
         }
     }
 
-    pub fn render(&mut self, time: usize, delta_time: usize) {
+    pub fn render(&mut self, time: usize, delta_time: usize) -> EngineResult<()> {
         if let Some(scene) = &mut self.scene {
             if let Some(renderer) = &self.renderer {
-                renderer.render(&self.backend, scene, time, delta_time)
+                renderer.render(&self.backend, scene, time, delta_time)?;
             }
         }
+
+        Ok(())
     }
 
-    pub fn add_basic_renderer(mut self, width: u32, height: u32) -> Self {
-        self.renderer = Some(Box::new(BasicRenderer::new(&self.backend, width, height)));
+    pub fn add_basic_renderer(mut self, width: u32, height: u32) -> EngineResult<Self> {
+        self.renderer = Some(Box::new(BasicRenderer::new(&self.backend, width, height)?));
 
-        self
+        Ok(self)
     }
 
-    pub fn add_scene(mut self, scene_fn: &dyn (Fn(&Backend) -> Scene)) -> Self {
-        self.scene = Some(scene_fn(&self.backend));
+    pub fn add_scene(
+        mut self,
+        scene_fn: &dyn (Fn(&Backend) -> EngineResult<Scene>),
+    ) -> EngineResult<Self> {
+        self.scene = Some(scene_fn(&self.backend)?);
 
-        self
+        Ok(self)
     }
 }
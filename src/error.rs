@@ -0,0 +1,136 @@
+use std::fmt;
+use std::io;
+
+use crate::render_backend::shader::ShaderError;
+
+/// Everything that can go wrong in the engine, with enough context to tell
+/// which asset or call failed.
+#[derive(Debug)]
+pub enum EngineError {
+    Io {
+        path: String,
+        source: io::Error,
+    },
+    ImageDecode {
+        path: String,
+        source: image::ImageError,
+    },
+    ImageEncode {
+        path: String,
+        message: String,
+    },
+    ObjParse {
+        path: String,
+        message: String,
+    },
+    /// A glTF file is malformed or needs something the importer doesn't do.
+    GltfParse {
+        path: String,
+        message: String,
+    },
+    /// A texture file is malformed or uses a format the loader doesn't handle.
+    TextureLoad {
+        path: String,
+        message: String,
+    },
+    Shader(ShaderError),
+    /// A D3D call failed. `context` says what the engine was doing.
+    Device {
+        context: String,
+        source: windows::core::Error,
+    },
+    /// The engine was asked to do something that can't work, e.g. a pass
+    /// without an execution function or a binding the shader doesn't declare.
+    Validation(String),
+}
+
+pub type EngineResult<T> = std::result::Result<T, EngineError>;
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Io { path, source } => write!(f, "{}: {}", path, source),
+            EngineError::ImageDecode { path, source } => {
+                write!(f, "{}: failed to decode image: {}", path, source)
+            }
+            EngineError::ImageEncode { path, message } => {
+                write!(f, "{}: failed to encode image: {}", path, message)
+            }
+            EngineError::ObjParse { path, message } => write!(f, "{}: {}", path, message),
+            EngineError::GltfParse { path, message } => write!(f, "{}: {}", path, message),
+            EngineError::TextureLoad { path, message } => {
+                write!(f, "{}: failed to load texture: {}", path, message)
+            }
+            EngineError::Shader(err) => write!(f, "{}", err),
+            EngineError::Device { context, source } if context.is_empty() => {
+                write!(f, "device error: {}", source)
+            }
+            EngineError::Device { context, source } => write!(f, "{}: {}", context, source),
+            EngineError::Validation(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EngineError::Io { source, .. } => Some(source),
+            EngineError::ImageDecode { source, .. } => Some(source),
+            EngineError::Shader(err) => Some(err),
+            // `windows::core::Error` doesn't implement `std::error::Error`.
+            EngineError::Device { .. }
+            | EngineError::ImageEncode { .. }
+            | EngineError::ObjParse { .. }
+            | EngineError::GltfParse { .. }
+            | EngineError::TextureLoad { .. }
+            | EngineError::Validation(_) => None,
+        }
+    }
+}
+
+impl From<ShaderError> for EngineError {
+    fn from(err: ShaderError) -> Self {
+        match err {
+            ShaderError::Device(source) => EngineError::Device {
+                context: String::new(),
+                source,
+            },
+            err => EngineError::Shader(err),
+        }
+    }
+}
+
+impl From<windows::core::Error> for EngineError {
+    fn from(source: windows::core::Error) -> Self {
+        EngineError::Device {
+            context: String::new(),
+            source,
+        }
+    }
+}
+
+/// Attaches what the engine was doing to a failed D3D call.
+pub trait Context<T> {
+    fn context(self, context: &str) -> EngineResult<T>;
+}
+
+impl<T> Context<T> for windows::core::Result<T> {
+    fn context(self, context: &str) -> EngineResult<T> {
+        self.map_err(|source| EngineError::Device {
+            context: context.to_owned(),
+            source,
+        })
+    }
+}
+
+impl<T> Context<T> for EngineResult<T> {
+    fn context(self, context: &str) -> EngineResult<T> {
+        self.map_err(|err| match err {
+            EngineError::Device { source, .. } => EngineError::Device {
+                context: context.to_owned(),
+                source,
+            },
+            err => err,
+        })
+    }
+}
@@ -1,7 +1,7 @@
 use std::time::SystemTime;
 
 use engine::Engine;
-use scene::create_minecraft_scene;
+use scene::{create_gltf_scene, create_minecraft_scene};
 use windows::Win32::Foundation::*;
 use winit::dpi::LogicalSize;
 use winit::event::VirtualKeyCode;
@@ -12,7 +12,9 @@ use winit_input_helper::WinitInputHelper;
 use winit::platform::windows::WindowExtWindows;
 
 pub mod atmosphere;
+pub mod error;
 pub mod render_backend;
+pub mod vfs;
 
 mod simple_triangle;
 use simple_triangle::SimpleTriangleScene;
@@ -24,7 +26,214 @@ mod scene;
 mod simple_gbuffer_pass;
 mod vertex_colour_stage;
 
+/// Compiles every shader variant for both profiles into the shader cache,
+/// so builds with the `shipping` feature never compile at runtime.
+fn precompile_shaders() {
+    use render_backend::shader_cache::{self, CompileProfile, ShaderCache};
+
+    let shaders = [
+        render_backend::renderer::BasicRenderer::SHADERS,
+        atmosphere::precompute::SHADERS,
+    ]
+    .concat();
+
+    for profile in [CompileProfile::Debug, CompileProfile::Release] {
+        let cache = ShaderCache::new(ShaderCache::default_dir(), profile);
+        match shader_cache::precompile_shaders(&cache, &shaders) {
+            Ok(count) => println!("Precompiled {} {:?} shader variants", count, profile),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Regenerates the atmosphere lookup tables in `img/`.
+fn dump_luts(backend: &render_backend::backend::Backend) {
+    use atmosphere::precompute::{precompute_atmosphere, AtmosphericConstants};
+
+    let result = precompute_atmosphere(backend, AtmosphericConstants::default())
+        .and_then(|atmosphere| atmosphere.save_luts(backend, std::path::Path::new("img")));
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+/// `--compress-texture <input> <output.dds> [albedo|normal|mask] [fast|normal|high]`
+fn compress_texture(args: &[String]) {
+    use render_backend::block_compression::{compress_file, CompressionQuality, TextureUsage};
+
+    let usage = match args.get(2).map(String::as_str) {
+        None | Some("albedo") => Some(TextureUsage::Albedo),
+        Some("normal") => Some(TextureUsage::NormalMap),
+        Some("mask") => Some(TextureUsage::Mask),
+        Some(_) => None,
+    };
+    let quality = match args.get(3).map(String::as_str) {
+        Some("fast") => Some(CompressionQuality::Fast),
+        None | Some("normal") => Some(CompressionQuality::Normal),
+        Some("high") => Some(CompressionQuality::High),
+        Some(_) => None,
+    };
+    let (input, output, usage, quality) = match (args.first(), args.get(1), usage, quality) {
+        (Some(input), Some(output), Some(usage), Some(quality)) => (input, output, usage, quality),
+        _ => {
+            eprintln!("Usage: --compress-texture <input> <output.dds> [albedo|normal|mask] [fast|normal|high]");
+            std::process::exit(2);
+        }
+    };
+
+    match compress_file(input, std::path::Path::new(output), usage, quality) {
+        Ok(report) => println!("{}: {}", output, report),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `--pack-textures <dir> <output.dds> [atlas|array] [maxrects|skyline]`
+fn pack_textures(args: &[String]) {
+    use error::EngineError;
+    use render_backend::atlas::{load_tiles, AtlasPacker, PackingAlgorithm};
+
+    let array = match args.get(2).map(String::as_str) {
+        None | Some("atlas") => Some(false),
+        Some("array") => Some(true),
+        Some(_) => None,
+    };
+    let algorithm = match args.get(3).map(String::as_str) {
+        None | Some("maxrects") => Some(PackingAlgorithm::MaxRects),
+        Some("skyline") => Some(PackingAlgorithm::Skyline),
+        Some(_) => None,
+    };
+    let (dir, output, array, algorithm) = match (args.first(), args.get(1), array, algorithm) {
+        (Some(dir), Some(output), Some(array), Some(algorithm)) => (dir, output, array, algorithm),
+        _ => {
+            eprintln!("Usage: --pack-textures <dir> <output.dds> [atlas|array] [maxrects|skyline]");
+            std::process::exit(2);
+        }
+    };
+
+    let output = std::path::Path::new(output);
+    let packed = load_tiles(std::path::Path::new(dir)).and_then(|tiles| {
+        let packer = AtlasPacker::new().algorithm(algorithm);
+        let packed = if array {
+            packer.pack_array(&tiles)
+        } else {
+            packer.pack(&tiles)
+        };
+        let packed = packed.map_err(|message| EngineError::TextureLoad {
+            path: dir.clone(),
+            message,
+        })?;
+        packed.save(output)?;
+        Ok(packed)
+    });
+
+    match packed {
+        Ok(packed) => println!(
+            "{}: {} tiles in {}x{}x{}, {:.0}% occupied",
+            output.display(),
+            packed.layout.regions.len(),
+            packed.layout.width,
+            packed.layout.height,
+            packed.layout.layers,
+            packed.layout.occupancy() * 100.0
+        ),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `--mesh-report <file.obj|file.gltf|file.glb> [layout.atlas]`
+///
+/// With an atlas layout, OBJ submeshes whose diffuse map was packed into it
+/// get their UVs remapped first.
+fn mesh_report(args: &[String]) {
+    use render_backend::atlas::AtlasLayout;
+    use render_backend::gltf::GltfScene;
+    use render_backend::mesh::ObjModel;
+
+    let file = match args.first() {
+        Some(file) => file,
+        None => {
+            eprintln!("Usage: --mesh-report <file.obj|file.gltf|file.glb> [layout.atlas]");
+            std::process::exit(2);
+        }
+    };
+    let layout = match args.get(1).map(|layout| AtlasLayout::load(layout)) {
+        Some(Ok(layout)) => Some(layout),
+        Some(Err(err)) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    let meshes = if file.to_ascii_lowercase().ends_with(".obj") {
+        ObjModel::load(file).map(|mut model| {
+            if let Some(layout) = &layout {
+                let remapped = model.remap_to_atlas(layout);
+                println!("{} submeshes remapped into the atlas", remapped);
+            }
+            model
+                .submeshes
+                .into_iter()
+                .map(|submesh| (submesh.object, submesh.mesh))
+                .collect::<Vec<_>>()
+        })
+    } else {
+        GltfScene::load(file).map(|scene| {
+            scene
+                .meshes
+                .into_iter()
+                .flat_map(|mesh| {
+                    let name = mesh.name;
+                    mesh.primitives
+                        .into_iter()
+                        .map(move |primitive| (name.clone(), primitive.mesh))
+                })
+                .collect()
+        })
+    };
+
+    match meshes {
+        Ok(meshes) => {
+            for (name, mut mesh) in meshes {
+                println!("{}: {}", name, mesh.optimize());
+            }
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn main() {
+    if std::env::args().any(|arg| arg == "--precompile-shaders") {
+        precompile_shaders();
+        return;
+    }
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(index) = args.iter().position(|arg| arg == "--compress-texture") {
+        compress_texture(&args[index + 1..]);
+        return;
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "--pack-textures") {
+        pack_textures(&args[index + 1..]);
+        return;
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "--mesh-report") {
+        mesh_report(&args[index + 1..]);
+        return;
+    }
+
     let mut input = WinitInputHelper::new();
 
     let event_loop = EventLoop::new();
@@ -45,9 +254,30 @@ fn main() {
 
     let mut last_time = SystemTime::now();
 
-    let mut engine = Engine::new(hwnd as HWND)
-        .add_basic_renderer(1920, 1080)
-        .add_scene(&create_minecraft_scene);
+    // `--gltf <file>` shows a glTF scene instead of the Minecraft world.
+    let gltf_file = args
+        .iter()
+        .position(|arg| arg == "--gltf")
+        .and_then(|index| args.get(index + 1));
+
+    let engine = Engine::new(hwnd as HWND)
+        .and_then(|engine| engine.add_basic_renderer(1920, 1080))
+        .and_then(|engine| match gltf_file {
+            Some(file) => engine.add_scene(&|backend| create_gltf_scene(backend, file)),
+            None => engine.add_scene(&create_minecraft_scene),
+        });
+    let mut engine = match engine {
+        Ok(engine) => engine,
+        Err(err) => {
+            eprintln!("Failed to start: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if std::env::args().any(|arg| arg == "--dump-luts") {
+        dump_luts(&engine.backend);
+        return;
+    }
 
     event_loop.run(move |event, _, control_flow| {
         // Pass every event to the WindowInputHelper.
@@ -66,7 +296,11 @@ fn main() {
             }
 
             engine.update();
-            engine.render(time, delta_time as usize);
+            if let Err(err) = engine.render(time, delta_time as usize) {
+                eprintln!("Failed to render: {}", err);
+                *control_flow = ControlFlow::Exit;
+                return;
+            }
 
             //triangle_scene.render(time, delta_time as usize);
 
@@ -1,6 +1,7 @@
 use glam::{Mat4, Vec3};
-use windows::Win32::Graphics::{Direct3D11::*, Dxgi::Common::DXGI_FORMAT_R32_UINT};
+use windows::Win32::Graphics::Direct3D11::*;
 
+use crate::error::{Context, EngineResult};
 use crate::render_backend::{
     backend::{Backend, OBJECT_CONSTANTS},
     gpu_buffer::GPUBuffer,
@@ -35,7 +36,7 @@ pub enum Flag {
 
 pub trait GameObject {
     fn update(&mut self);
-    fn bind(&mut self, backend: &Backend);
+    fn bind(&mut self, backend: &Backend) -> EngineResult<()>;
 
     fn material_id(&self) -> Option<u32> {
         None
@@ -64,16 +65,16 @@ pub struct SimpleMesh {
 }
 
 impl SimpleMesh {
-    pub fn new(backend: &Backend, mesh: GpuMesh) -> SimpleMesh {
-        SimpleMesh {
+    pub fn new(backend: &Backend, mesh: GpuMesh) -> EngineResult<SimpleMesh> {
+        Ok(SimpleMesh {
             mesh,
             transform: Mat4::from_translation(Vec3::new(0.0, 0.0, 0.0)),
             transform_dirty: true,
             model_cbuffer: GPUBuffer::constant_buffer(backend, std::mem::size_of::<Mat4>() as u32)
-                .expect("Creating constant buffer"),
+                .context("Create model cbuffer")?,
             textures: vec![],
             flags: vec![Flag::Opaque],
-        }
+        })
     }
 }
 
@@ -93,10 +94,13 @@ impl GameObject for SimpleMesh {
         Some(self.mesh.clone())
     }
 
-    fn bind(&mut self, backend: &Backend) {
+    fn bind(&mut self, backend: &Backend) -> EngineResult<()> {
         if self.transform_dirty {
             {
-                let mapped_buffer = self.model_cbuffer.map(backend).expect("Mapping cbuffer");
+                let mapped_buffer = self
+                    .model_cbuffer
+                    .map(backend)
+                    .context("Map model cbuffer")?;
                 mapped_buffer.copy_from(&[self.transform]);
             }
 
@@ -117,8 +121,8 @@ impl GameObject for SimpleMesh {
         }
         unsafe {
             backend.device_context.IASetIndexBuffer(
-                self.mesh.index_buffer.buffer.clone(),
-                DXGI_FORMAT_R32_UINT,
+                self.mesh.index_buffer.buffer().buffer.clone(),
+                self.mesh.index_buffer.to_dxgi(),
                 0,
             );
         }
@@ -140,6 +144,8 @@ impl GameObject for SimpleMesh {
                 [0].as_ptr(),
             )
         }
+
+        Ok(())
     }
 }
 
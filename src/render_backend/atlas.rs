@@ -0,0 +1,978 @@
+use std::fmt;
+use std::path::Path;
+
+use glam::Vec2;
+
+use crate::error::{EngineError, EngineResult};
+use crate::vfs;
+
+use super::cpu_image::{CpuImage, PixelType};
+use super::dds;
+use super::mipmaps::MipGenerator;
+use super::texture_data::{TextureData, TextureDimension};
+
+/// How `AtlasPacker` places tiles. Both are deterministic: the same tiles
+/// always give the same atlas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PackingAlgorithm {
+    /// Tracks every maximal free rectangle and picks the best short side
+    /// fit. Packs tightest, slower with many tiles.
+    MaxRects,
+    /// Keeps the top edge of the packed tiles and drops each tile as low as
+    /// it goes. Fast, wastes a little more space below ledges.
+    Skyline,
+}
+
+/// An image to pack, named after its file.
+#[derive(Clone, Debug)]
+pub struct Tile {
+    pub name: String,
+    pub image: CpuImage,
+}
+
+const TILE_EXTENSIONS: [&str; 10] = [
+    "png", "jpg", "jpeg", "tga", "bmp", "gif", "tif", "tiff", "hdr", "exr",
+];
+
+/// Every image in `dir`, sorted by file name. Tiles are named after the
+/// file without its extension. `dir` is read from disk, not the VFS.
+pub fn load_tiles(dir: &Path) -> EngineResult<Vec<Tile>> {
+    let io_error = |path: &Path| {
+        let path = path.display().to_string();
+        move |source| EngineError::Io { path, source }
+    };
+
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(io_error(dir))? {
+        let path = entry.map_err(io_error(dir))?.path();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        if path.is_file() && TILE_EXTENSIONS.contains(&extension.as_deref().unwrap_or("")) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    paths
+        .iter()
+        .map(|path| {
+            let bytes = std::fs::read(path).map_err(io_error(path))?;
+            let name = path.file_stem().unwrap_or_default().to_string_lossy();
+            Ok(Tile {
+                name: name.into_owned(),
+                image: CpuImage::decode(&path.display().to_string(), bytes)?,
+            })
+        })
+        .collect()
+}
+
+/// Where a tile ended up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AtlasRegion {
+    pub name: String,
+    /// Array slice, always 0 in an atlas.
+    pub layer: u32,
+    /// The tile's texels in the top mip, borders excluded.
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// `remap` moves a UV of the tile to `uv_offset + uv * uv_scale`.
+    pub uv_offset: Vec2,
+    pub uv_scale: Vec2,
+}
+
+impl AtlasRegion {
+    fn new(name: &str, layer: u32, [x, y, width, height]: [u32; 4], atlas: [u32; 2]) -> Self {
+        let atlas = Vec2::new(atlas[0] as f32, atlas[1] as f32);
+        AtlasRegion {
+            name: name.to_owned(),
+            layer,
+            x,
+            y,
+            width,
+            height,
+            uv_offset: Vec2::new(x as f32, y as f32) / atlas,
+            uv_scale: Vec2::new(width as f32, height as f32) / atlas,
+        }
+    }
+
+    /// Maps a UV of the original tile into the atlas. UVs outside [0, 1]
+    /// land in neighboring tiles, tiles can't repeat in an atlas.
+    pub fn remap(&self, uv: Vec2) -> Vec2 {
+        self.uv_offset + uv * self.uv_scale
+    }
+}
+
+/// The metadata of a packed atlas or array, saved next to the texture so
+/// meshes can remap their UVs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AtlasLayout {
+    pub width: u32,
+    pub height: u32,
+    pub layers: u32,
+    pub regions: Vec<AtlasRegion>,
+}
+
+impl AtlasLayout {
+    pub fn region(&self, name: &str) -> Option<&AtlasRegion> {
+        self.regions.iter().find(|region| region.name == name)
+    }
+
+    /// Fraction of the texels that belong to tiles rather than borders or
+    /// free space.
+    pub fn occupancy(&self) -> f32 {
+        let used: u64 = self
+            .regions
+            .iter()
+            .map(|region| region.width as u64 * region.height as u64)
+            .sum();
+        used as f32 / (self.width as u64 * self.height as u64 * self.layers as u64) as f32
+    }
+
+    /// Reads what `fmt::Display` writes: an `atlas <width> <height>
+    /// <layers>` line, then `tile <layer> <x> <y> <width> <height> <name>`
+    /// per tile.
+    pub fn parse(text: &str) -> Result<AtlasLayout, String> {
+        let number = |field: Option<&str>, line: usize| {
+            field
+                .and_then(|field| field.parse::<u32>().ok())
+                .ok_or_else(|| format!("line {}: expected a number", line))
+        };
+
+        let mut layout: Option<AtlasLayout> = None;
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let mut fields = line.splitn(7, ' ');
+            match fields.next() {
+                Some("atlas") => {
+                    layout = Some(AtlasLayout {
+                        width: number(fields.next(), line_number)?,
+                        height: number(fields.next(), line_number)?,
+                        layers: number(fields.next(), line_number)?,
+                        regions: Vec::new(),
+                    })
+                }
+                Some("tile") => {
+                    let layout = layout.as_mut().ok_or_else(|| {
+                        format!("line {}: tile before the atlas line", line_number)
+                    })?;
+                    let layer = number(fields.next(), line_number)?;
+                    let mut rect = [0; 4];
+                    for value in &mut rect {
+                        *value = number(fields.next(), line_number)?;
+                    }
+                    let name = fields
+                        .next()
+                        .ok_or_else(|| format!("line {}: tile without a name", line_number))?;
+                    layout.regions.push(AtlasRegion::new(
+                        name,
+                        layer,
+                        rect,
+                        [layout.width, layout.height],
+                    ));
+                }
+                Some("") | None => {}
+                Some(other) => {
+                    return Err(format!("line {}: unknown entry {}", line_number, other))
+                }
+            }
+        }
+
+        layout.ok_or_else(|| "no atlas line".to_owned())
+    }
+
+    /// Reads a layout saved by `PackedAtlas::save` through the VFS.
+    pub fn load(file: &str) -> EngineResult<AtlasLayout> {
+        let text = vfs::read_to_string(file).map_err(|source| EngineError::Io {
+            path: file.to_owned(),
+            source,
+        })?;
+
+        AtlasLayout::parse(&text).map_err(|message| EngineError::TextureLoad {
+            path: file.to_owned(),
+            message,
+        })
+    }
+}
+
+impl fmt::Display for AtlasLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "atlas {} {} {}", self.width, self.height, self.layers)?;
+        for region in &self.regions {
+            writeln!(
+                f,
+                "tile {} {} {} {} {} {}",
+                region.layer, region.x, region.y, region.width, region.height, region.name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A packed texture and where its tiles are.
+#[derive(Clone, Debug)]
+pub struct PackedAtlas {
+    pub layout: AtlasLayout,
+    pub texture: TextureData,
+}
+
+impl PackedAtlas {
+    /// Writes the texture as DDS to `path` and the layout next to it, with
+    /// the extension `.atlas`.
+    pub fn save(&self, path: &Path) -> EngineResult<()> {
+        dds::save(&self.texture, path)?;
+
+        let layout_path = path.with_extension("atlas");
+        std::fs::write(&layout_path, self.layout.to_string()).map_err(|source| EngineError::Io {
+            path: layout_path.display().to_string(),
+            source,
+        })
+    }
+}
+
+/// Packs tiles into one atlas texture, or stacks them into a texture array.
+///
+/// Atlas tiles get their mips made one by one and are surrounded by a
+/// border of repeated edge texels that is `border` texels wide in every
+/// mip. Tiles are aligned so every mip keeps them apart, which means
+/// filtering and mipmapping never bleed one tile into another.
+#[derive(Clone, Copy, Debug)]
+pub struct AtlasPacker {
+    algorithm: PackingAlgorithm,
+    max_size: u32,
+    border: u32,
+    mip_levels: u32,
+    mips: MipGenerator,
+}
+
+impl AtlasPacker {
+    pub fn new() -> AtlasPacker {
+        AtlasPacker {
+            algorithm: PackingAlgorithm::MaxRects,
+            max_size: 4096,
+            border: 1,
+            mip_levels: 5,
+            mips: MipGenerator::new().srgb(true),
+        }
+    }
+
+    pub fn algorithm(mut self, algorithm: PackingAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Largest width and height the atlas may grow to.
+    pub fn max_size(mut self, max_size: u32) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Border width around every tile, in texels of the smallest mip.
+    pub fn border(mut self, border: u32) -> Self {
+        self.border = border;
+        self
+    }
+
+    /// Mips of the atlas. Every extra mip doubles the border in the top
+    /// mip. Arrays always get full mip chains.
+    pub fn mip_levels(mut self, mip_levels: u32) -> Self {
+        self.mip_levels = mip_levels.max(1);
+        self
+    }
+
+    /// How tile mips are made, sRGB by default.
+    pub fn mips(mut self, mips: MipGenerator) -> Self {
+        self.mips = mips;
+        self
+    }
+
+    /// Packs `tiles` into the smallest power of two atlas they fit into,
+    /// preferring square ones.
+    pub fn pack(&self, tiles: &[Tile]) -> Result<PackedAtlas, String> {
+        if tiles.is_empty() {
+            return Err("no tiles to pack".to_owned());
+        }
+
+        // More mips than `max_size` has room for would only widen borders.
+        let max_mips = (32 - self.max_size.leading_zeros()).max(1);
+        let alignment = 1 << (self.mip_levels.min(max_mips) - 1);
+        let padding = self
+            .border
+            .checked_mul(alignment)
+            .filter(|&padding| padding <= self.max_size / 2)
+            .ok_or_else(|| {
+                format!(
+                    "borders of {} texels at {} mips don't fit into {}x{} texels",
+                    self.border, self.mip_levels, self.max_size, self.max_size
+                )
+            })?;
+        let cells: Vec<[u32; 2]> = tiles
+            .iter()
+            .map(|tile| {
+                [
+                    (tile.image.width + 2 * padding).next_multiple_of(alignment),
+                    (tile.image.height + 2 * padding).next_multiple_of(alignment),
+                ]
+            })
+            .collect();
+
+        let (size, positions) = self.place(tiles, &cells)?;
+        let regions = tiles
+            .iter()
+            .zip(&positions)
+            .map(|(tile, [x, y])| {
+                let rect = [
+                    x + padding,
+                    y + padding,
+                    tile.image.width,
+                    tile.image.height,
+                ];
+                AtlasRegion::new(&tile.name, 0, rect, size)
+            })
+            .collect();
+
+        let mip_levels = self
+            .mip_levels
+            .min(32 - size[0].max(size[1]).leading_zeros());
+        let pixel_type = common_pixel_type(tiles);
+        let chains: Vec<Vec<CpuImage>> = tiles
+            .iter()
+            .map(|tile| {
+                self.mips
+                    .generate(&self.mips.convert(&tile.image, pixel_type))
+            })
+            .collect();
+
+        let mut data = Vec::new();
+        for mip in 0..mip_levels {
+            let [width, height] = size.map(|size| (size >> mip).max(1));
+            let mut pixels = vec![[0.0; 4]; (width * height) as usize];
+            for ((chain, [x, y]), [cell_width, cell_height]) in
+                chains.iter().zip(&positions).zip(&cells)
+            {
+                let tile = &chain[(mip as usize).min(chain.len() - 1)];
+                let tile_pixels = tile.to_rgba32f();
+                let cell = [x >> mip, y >> mip, cell_width >> mip, cell_height >> mip];
+                let inner = [(x + padding) >> mip, (y + padding) >> mip];
+                extrude(&mut pixels, width, cell, inner, tile, &tile_pixels);
+            }
+            data.extend(CpuImage::from_rgba32f(width, height, pixel_type, pixels).to_bytes());
+        }
+
+        Ok(PackedAtlas {
+            layout: AtlasLayout {
+                width: size[0],
+                height: size[1],
+                layers: 1,
+                regions,
+            },
+            texture: TextureData {
+                format: self.mips.format(pixel_type),
+                dimension: TextureDimension::Texture2D,
+                width: size[0],
+                height: size[1],
+                depth: 1,
+                mip_levels,
+                array_size: 1,
+                cubemap: false,
+                data,
+            },
+        })
+    }
+
+    /// Stacks `tiles` into a texture array, layer `i` holding `tiles[i]`.
+    /// Every tile has to be the same size.
+    pub fn pack_array(&self, tiles: &[Tile]) -> Result<PackedAtlas, String> {
+        let first = tiles.first().ok_or("no tiles to pack")?;
+        let (width, height) = (first.image.width, first.image.height);
+        if let Some(tile) = tiles
+            .iter()
+            .find(|tile| (tile.image.width, tile.image.height) != (width, height))
+        {
+            return Err(format!(
+                "{} is {}x{}, but array layers have to be {}x{} like {}",
+                tile.name, tile.image.width, tile.image.height, width, height, first.name
+            ));
+        }
+
+        let pixel_type = common_pixel_type(tiles);
+        let mut data = Vec::new();
+        let mut mip_levels = 0;
+        for tile in tiles {
+            let chain = self
+                .mips
+                .generate(&self.mips.convert(&tile.image, pixel_type));
+            mip_levels = chain.len() as u32;
+            data.extend(chain.iter().flat_map(CpuImage::to_bytes));
+        }
+
+        let regions = tiles
+            .iter()
+            .zip(0..)
+            .map(|(tile, layer)| {
+                AtlasRegion::new(&tile.name, layer, [0, 0, width, height], [width, height])
+            })
+            .collect();
+
+        Ok(PackedAtlas {
+            layout: AtlasLayout {
+                width,
+                height,
+                layers: tiles.len() as u32,
+                regions,
+            },
+            texture: TextureData {
+                format: self.mips.format(pixel_type),
+                dimension: TextureDimension::Texture2D,
+                width,
+                height,
+                depth: 1,
+                mip_levels,
+                array_size: tiles.len() as u32,
+                cubemap: false,
+                data,
+            },
+        })
+    }
+
+    /// Tries atlas sizes from the smallest area up and returns the first
+    /// one every cell fits into.
+    fn place(
+        &self,
+        tiles: &[Tile],
+        cells: &[[u32; 2]],
+    ) -> Result<([u32; 2], Vec<[u32; 2]>), String> {
+        // Large tiles first; names break ties so the order is stable.
+        let mut order: Vec<usize> = (0..cells.len()).collect();
+        order.sort_by(|&a, &b| {
+            let key = |i: usize| {
+                let [width, height] = cells[i];
+                (width.max(height), width.min(height))
+            };
+            key(b)
+                .cmp(&key(a))
+                .then_with(|| tiles[a].name.cmp(&tiles[b].name))
+        });
+        let sizes: Vec<[u32; 2]> = order.iter().map(|&i| cells[i]).collect();
+
+        let area: u64 = cells
+            .iter()
+            .map(|[width, height]| *width as u64 * *height as u64)
+            .sum();
+        let widest = cells.iter().map(|cell| cell[0]).max().unwrap_or(1);
+        let tallest = cells.iter().map(|cell| cell[1]).max().unwrap_or(1);
+
+        let powers: Vec<u32> = (0..32)
+            .map(|shift| 1u32 << shift)
+            .take_while(|&size| size <= self.max_size)
+            .collect();
+        let mut candidates: Vec<[u32; 2]> = powers
+            .iter()
+            .flat_map(|&width| powers.iter().map(move |&height| [width, height]))
+            .filter(|&[width, height]| {
+                width >= widest && height >= tallest && width as u64 * height as u64 >= area
+            })
+            .collect();
+        candidates.sort_by_key(|&[width, height]| {
+            (width as u64 * height as u64, width.max(height), height)
+        });
+
+        for size in candidates {
+            let placed = match self.algorithm {
+                PackingAlgorithm::MaxRects => pack_max_rects(&sizes, size),
+                PackingAlgorithm::Skyline => pack_skyline(&sizes, size),
+            };
+            if let Some(placed) = placed {
+                let mut positions = vec![[0, 0]; cells.len()];
+                for (&index, position) in order.iter().zip(placed) {
+                    positions[index] = position;
+                }
+                return Ok((size, positions));
+            }
+        }
+
+        Err(format!(
+            "{} tiles don't fit into {}x{} texels",
+            tiles.len(),
+            self.max_size,
+            self.max_size
+        ))
+    }
+}
+
+impl Default for AtlasPacker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The most precise pixel type among the tiles. 8-bit tiles are converted
+/// with `MipGenerator::convert` when mixed with float ones.
+fn common_pixel_type(tiles: &[Tile]) -> PixelType {
+    let types: Vec<PixelType> = tiles.iter().map(|tile| tile.image.pixel_type()).collect();
+    if types.contains(&PixelType::Rgba32F) {
+        PixelType::Rgba32F
+    } else if types.contains(&PixelType::Rgba16F) {
+        PixelType::Rgba16F
+    } else {
+        PixelType::Rgba8
+    }
+}
+
+/// Copies `tile` to `inner` and repeats its edge texels out to the edges of
+/// `cell`, given as x, y, width and height.
+fn extrude(
+    pixels: &mut [[f32; 4]],
+    width: u32,
+    [cell_x, cell_y, cell_width, cell_height]: [u32; 4],
+    [inner_x, inner_y]: [u32; 2],
+    tile: &CpuImage,
+    tile_pixels: &[[f32; 4]],
+) {
+    for y in cell_y..cell_y + cell_height {
+        let tile_y = (y as i64 - inner_y as i64).clamp(0, tile.height as i64 - 1) as u32;
+        for x in cell_x..cell_x + cell_width {
+            let tile_x = (x as i64 - inner_x as i64).clamp(0, tile.width as i64 - 1) as u32;
+            pixels[(y * width + x) as usize] = tile_pixels[(tile_y * tile.width + tile_x) as usize];
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Rect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Rect {
+    fn right(&self) -> u32 {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> u32 {
+        self.y + self.height
+    }
+
+    fn intersects(&self, other: &Rect) -> bool {
+        self.x < other.right()
+            && other.x < self.right()
+            && self.y < other.bottom()
+            && other.y < self.bottom()
+    }
+
+    fn contains(&self, other: &Rect) -> bool {
+        other.x >= self.x
+            && other.y >= self.y
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+}
+
+/// MaxRects with the best short side fit heuristic. `None` if a rectangle
+/// doesn't fit.
+fn pack_max_rects(sizes: &[[u32; 2]], [width, height]: [u32; 2]) -> Option<Vec<[u32; 2]>> {
+    let mut free = vec![Rect {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    }];
+    let mut positions = Vec::with_capacity(sizes.len());
+
+    for &[width, height] in sizes {
+        let best = free
+            .iter()
+            .filter(|rect| rect.width >= width && rect.height >= height)
+            .min_by_key(|rect| {
+                let (leftover_x, leftover_y) = (rect.width - width, rect.height - height);
+                (
+                    leftover_x.min(leftover_y),
+                    leftover_x.max(leftover_y),
+                    rect.y,
+                    rect.x,
+                )
+            })?;
+        let placed = Rect {
+            x: best.x,
+            y: best.y,
+            width,
+            height,
+        };
+        positions.push([placed.x, placed.y]);
+
+        // Split every free rectangle the new one overlaps into the up to
+        // four maximal rectangles around it.
+        let mut next = Vec::with_capacity(free.len() + 4);
+        for rect in free {
+            if !rect.intersects(&placed) {
+                next.push(rect);
+                continue;
+            }
+            if placed.x > rect.x {
+                next.push(Rect {
+                    width: placed.x - rect.x,
+                    ..rect
+                });
+            }
+            if placed.right() < rect.right() {
+                next.push(Rect {
+                    x: placed.right(),
+                    width: rect.right() - placed.right(),
+                    ..rect
+                });
+            }
+            if placed.y > rect.y {
+                next.push(Rect {
+                    height: placed.y - rect.y,
+                    ..rect
+                });
+            }
+            if placed.bottom() < rect.bottom() {
+                next.push(Rect {
+                    y: placed.bottom(),
+                    height: rect.bottom() - placed.bottom(),
+                    ..rect
+                });
+            }
+        }
+
+        // Drop rectangles inside others, keeping the first of duplicates.
+        free =
+            next.iter()
+                .enumerate()
+                .filter(|(i, rect)| {
+                    !next.iter().enumerate().any(|(j, other)| {
+                        j != *i && other.contains(rect) && (other != *rect || j < *i)
+                    })
+                })
+                .map(|(_, rect)| *rect)
+                .collect();
+    }
+
+    Some(positions)
+}
+
+/// Bottom-left skyline packing. `None` if a rectangle doesn't fit.
+fn pack_skyline(sizes: &[[u32; 2]], [width, height]: [u32; 2]) -> Option<Vec<[u32; 2]>> {
+    // Segments of the skyline as x, y and width, left to right.
+    let mut skyline = vec![[0, 0, width]];
+    let mut positions = Vec::with_capacity(sizes.len());
+
+    for &[rect_width, rect_height] in sizes {
+        let mut best: Option<[u32; 2]> = None;
+        for &[x, _, _] in &skyline {
+            if x + rect_width > width {
+                break;
+            }
+            // The rectangle rests on the highest segment below it.
+            let y = skyline
+                .iter()
+                .filter(|[segment_x, _, segment_width]| {
+                    *segment_x < x + rect_width && segment_x + segment_width > x
+                })
+                .map(|[_, y, _]| *y)
+                .max()
+                .unwrap_or(0);
+            if y + rect_height <= height && best.is_none_or(|[_, best_y]| y < best_y) {
+                best = Some([x, y]);
+            }
+        }
+
+        let [x, y] = best?;
+        positions.push([x, y]);
+
+        // Raise the skyline under the rectangle, trimming the segments it
+        // covers.
+        let (left, right) = (x, x + rect_width);
+        let mut next = Vec::with_capacity(skyline.len() + 2);
+        for [segment_x, segment_y, segment_width] in skyline {
+            let segment_right = segment_x + segment_width;
+            if segment_right <= left || segment_x >= right {
+                next.push([segment_x, segment_y, segment_width]);
+                continue;
+            }
+            if segment_x < left {
+                next.push([segment_x, segment_y, left - segment_x]);
+            }
+            if segment_x <= left {
+                next.push([left, y + rect_height, rect_width]);
+            }
+            if segment_right > right {
+                next.push([right, segment_y, segment_right - right]);
+            }
+        }
+
+        // Merge neighbors at the same height.
+        skyline = Vec::with_capacity(next.len());
+        for segment in next {
+            match skyline.last_mut() {
+                Some(last) if last[1] == segment[1] => last[2] += segment[2],
+                _ => skyline.push(segment),
+            }
+        }
+    }
+
+    Some(positions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_backend::cpu_image::CpuPixels;
+    use crate::render_backend::mipmaps::srgb_to_linear;
+    use windows::Win32::Graphics::Dxgi::Common::*;
+
+    fn tile(name: &str, width: u32, height: u32, color: [u8; 4]) -> Tile {
+        Tile {
+            name: name.to_owned(),
+            image: CpuImage {
+                width,
+                height,
+                pixels: CpuPixels::Rgba8(vec![color; (width * height) as usize]),
+            },
+        }
+    }
+
+    /// 40 tiles between 3 and 66 texels wide, the same every run.
+    fn random_tiles() -> Vec<Tile> {
+        let mut state = 0x2545_f491_u32;
+        let mut next = move |range: u32| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            3 + state % range
+        };
+        (0..40)
+            .map(|i| {
+                let (width, height) = (next(64), next(64));
+                tile(
+                    &format!("tile{:02}", i),
+                    width,
+                    height,
+                    [i as u8 * 6, 0, 0, 255],
+                )
+            })
+            .collect()
+    }
+
+    const ALGORITHMS: [PackingAlgorithm; 2] =
+        [PackingAlgorithm::MaxRects, PackingAlgorithm::Skyline];
+
+    /// The tile's rectangle grown by `padding`, as x, y, right and bottom.
+    fn cell(region: &AtlasRegion, padding: u32) -> [u32; 4] {
+        [
+            region.x - padding,
+            region.y - padding,
+            region.x + region.width + padding,
+            region.y + region.height + padding,
+        ]
+    }
+
+    #[test]
+    fn packs_tightly_without_overlaps() {
+        let tiles = random_tiles();
+        for algorithm in ALGORITHMS {
+            // Without borders, then with 1 texel borders over 3 mips, which
+            // pad every tile by 4 texels aligned to 4.
+            for (border, mips, padding, occupancy) in [(0, 1, 0, 0.75), (1, 3, 4, 0.35)] {
+                let packed = AtlasPacker::new()
+                    .algorithm(algorithm)
+                    .border(border)
+                    .mip_levels(mips)
+                    .pack(&tiles)
+                    .unwrap();
+                let layout = &packed.layout;
+                assert!(
+                    layout.occupancy() >= occupancy,
+                    "{:?} {}",
+                    algorithm,
+                    layout.occupancy()
+                );
+
+                let cells: Vec<[u32; 4]> = layout
+                    .regions
+                    .iter()
+                    .map(|region| cell(region, padding))
+                    .collect();
+                for (i, a) in cells.iter().enumerate() {
+                    assert!(
+                        a[2] <= layout.width && a[3] <= layout.height,
+                        "{:?}",
+                        algorithm
+                    );
+                    assert!(a[0] % (1 << (mips - 1)) == 0, "{:?} misaligned", algorithm);
+                    for b in &cells[i + 1..] {
+                        let overlap = a[0] < b[2] && b[0] < a[2] && a[1] < b[3] && b[1] < a[3];
+                        assert!(!overlap, "{:?}: {:?} overlaps {:?}", algorithm, a, b);
+                    }
+                }
+
+                // Half the area wouldn't hold the cells, the atlas is the
+                // smallest power of two size possible.
+                let area: u32 = cells
+                    .iter()
+                    .map(|[x, y, right, bottom]| (right - x) * (bottom - y))
+                    .sum();
+                assert!(layout.width * layout.height < 2 * area, "{:?}", algorithm);
+                assert_eq!(packed.texture.mip_levels, mips);
+            }
+        }
+    }
+
+    #[test]
+    fn packs_deterministically() {
+        let tiles = random_tiles();
+        let mut reversed = tiles.clone();
+        reversed.reverse();
+
+        for algorithm in ALGORITHMS {
+            let packer = AtlasPacker::new().algorithm(algorithm);
+            let first = packer.pack(&tiles).unwrap();
+            let second = packer.pack(&tiles).unwrap();
+            assert_eq!(first.layout, second.layout);
+            assert_eq!(first.texture, second.texture);
+
+            // The input order only changes the order of the regions.
+            let mut from_reversed = packer.pack(&reversed).unwrap().layout;
+            from_reversed.regions.reverse();
+            assert_eq!(from_reversed, first.layout, "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn extrudes_tile_edges_into_borders() {
+        let tiles = [
+            tile("red", 6, 5, [255, 0, 0, 255]),
+            tile("blue", 3, 7, [0, 0, 255, 255]),
+        ];
+        let packed = AtlasPacker::new()
+            .border(1)
+            .mip_levels(2)
+            .pack(&tiles)
+            .unwrap();
+        let texture = &packed.texture;
+        assert_eq!(texture.format, DXGI_FORMAT_R8G8B8A8_UNORM_SRGB);
+
+        let subresources = texture.subresources().unwrap();
+        for (mip, subresource) in subresources.iter().enumerate() {
+            let width = packed.layout.width >> mip;
+            let texel = |x: u32, y: u32| {
+                let offset = subresource.offset + ((y * width + x) * 4) as usize;
+                &texture.data[offset..offset + 4]
+            };
+
+            for (region, tile) in packed.layout.regions.iter().zip(&tiles) {
+                // Every mip keeps a 1 texel border of the tile's color.
+                let [x, y, right, bottom] = cell(region, 2).map(|value| value >> mip);
+                let color = tile.image.to_rgba8()[0];
+                for y in y..bottom {
+                    for x in x..right {
+                        assert_eq!(texel(x, y), color, "{} mip {}", region.name, mip);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn converts_8_bit_tiles_mixed_with_float_ones() {
+        let float = Tile {
+            name: "float".to_owned(),
+            image: CpuImage {
+                width: 4,
+                height: 4,
+                pixels: CpuPixels::Rgba32F(vec![[2.0, 1.0, 0.5, 1.0]; 16]),
+            },
+        };
+        let tiles = [tile("srgb", 4, 4, [188, 188, 188, 255]), float];
+        let linear = srgb_to_linear(188.0 / 255.0);
+
+        for array in [false, true] {
+            let packer = AtlasPacker::new().mip_levels(1);
+            let packed = if array {
+                packer.pack_array(&tiles)
+            } else {
+                packer.pack(&tiles)
+            }
+            .unwrap();
+            assert_eq!(packed.texture.format, DXGI_FORMAT_R32G32B32A32_FLOAT);
+
+            let texels: Vec<f32> = packed
+                .texture
+                .data
+                .chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+                .collect();
+            let region = packed.layout.region("srgb").unwrap();
+            let offset = ((region.y * packed.layout.width + region.x) * 4) as usize;
+            assert!((texels[offset] - linear).abs() < 1e-6, "{}", texels[offset]);
+            assert_eq!(texels[offset + 3], 1.0);
+        }
+    }
+
+    #[test]
+    fn clamps_mip_levels_and_borders() {
+        let tiles = [tile("a", 10, 10, [0; 4])];
+
+        // Alignment stops at the largest allowed atlas instead of shifting
+        // past 32 bits.
+        let packed = AtlasPacker::new()
+            .max_size(64)
+            .border(0)
+            .mip_levels(40)
+            .pack(&tiles)
+            .unwrap();
+        assert_eq!([packed.layout.width, packed.layout.height], [64, 64]);
+        assert_eq!(packed.texture.mip_levels, 7);
+
+        let packer = AtlasPacker::new().max_size(64);
+        assert!(packer.border(u32::MAX).pack(&tiles).is_err());
+        assert!(packer.border(33).mip_levels(1).pack(&tiles).is_err());
+        assert!(packer.max_size(0).pack(&tiles).is_err());
+        assert!(packer.max_size(8).pack(&tiles).is_err());
+    }
+
+    #[test]
+    fn stacks_arrays() {
+        let tiles = [tile("a", 8, 4, [1, 2, 3, 4]), tile("b", 8, 4, [5, 6, 7, 8])];
+        let packed = AtlasPacker::new().pack_array(&tiles).unwrap();
+        assert_eq!(packed.texture.array_size, 2);
+        assert_eq!(packed.texture.mip_levels, 4);
+        assert_eq!(packed.layout.region("b").unwrap().layer, 1);
+        assert_eq!(
+            packed.layout.region("b").unwrap().remap(Vec2::ONE),
+            Vec2::ONE
+        );
+        assert_eq!(packed.layout.occupancy(), 1.0);
+
+        assert!(AtlasPacker::new().pack_array(&[]).is_err());
+        let mismatched = [tiles[0].clone(), tile("c", 4, 4, [0; 4])];
+        assert!(AtlasPacker::new().pack_array(&mismatched).is_err());
+        assert!(AtlasPacker::new().pack(&[]).is_err());
+    }
+
+    #[test]
+    fn layouts_round_trip_through_text() {
+        let packed = AtlasPacker::new().pack(&random_tiles()).unwrap();
+        let text = packed.layout.to_string();
+        assert_eq!(AtlasLayout::parse(&text).unwrap(), packed.layout);
+
+        let region = AtlasLayout::parse("atlas 64 32 1\ntile 0 16 8 32 16 name with spaces\n")
+            .unwrap()
+            .regions
+            .remove(0);
+        assert_eq!(region.name, "name with spaces");
+        assert_eq!(region.remap(Vec2::ZERO), Vec2::new(0.25, 0.25));
+        assert_eq!(region.remap(Vec2::ONE), Vec2::new(0.75, 0.75));
+
+        assert!(AtlasLayout::parse("tile 0 0 0 1 1 a").is_err());
+        assert!(AtlasLayout::parse("atlas 64 x 1").is_err());
+        assert!(AtlasLayout::parse("atlas 4 4 1\ntile 0 0 0 1 1").is_err());
+        assert!(AtlasLayout::parse("").is_err());
+    }
+}
@@ -1,13 +1,22 @@
-use windows::core::Result;
 use windows::Win32::Graphics::{Direct3D11::*, Dxgi::IDXGISwapChain};
 
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::error::{Context, EngineResult};
+
 use super::gpu_buffer::GPUBuffer;
+use super::pipeline_state::*;
+use super::shader::Shader;
+use super::shader_cache::ShaderCache;
 use super::texture::{Tex, Tex2D};
 
 pub struct Backend {
     pub device: ID3D11Device,
     pub device_context: ID3D11DeviceContext,
     pub swap_chain: IDXGISwapChain,
+    pub state_cache: StateCache,
+    pub shader_cache: ShaderCache,
 }
 
 pub const FRAME_CONSTANTS: u32 = 0;
@@ -24,11 +33,77 @@ impl Backend {
             device,
             device_context,
             swap_chain,
+            state_cache: Default::default(),
+            shader_cache: Default::default(),
         }
     }
 
-    pub fn backbuffer(&self, buffer: u32) -> Result<Tex2D> {
-        let raw_backbuffer: ID3D11Texture2D = unsafe { self.swap_chain.GetBuffer(buffer)? };
+    pub fn depth_stencil_state(
+        &self,
+        desc: &DepthStencilDesc,
+    ) -> EngineResult<ID3D11DepthStencilState> {
+        get_or_create(&self.state_cache.depth_stencil, *desc, || unsafe {
+            self.device.CreateDepthStencilState(&desc.to_d3d11())
+        })
+        .context("Create depth stencil state")
+    }
+
+    pub fn blend_state(&self, desc: &BlendDesc) -> EngineResult<ID3D11BlendState> {
+        get_or_create(&self.state_cache.blend, *desc, || unsafe {
+            self.device.CreateBlendState(&desc.to_d3d11())
+        })
+        .context("Create blend state")
+    }
+
+    pub fn rasterizer_state(&self, desc: &RasterizerDesc) -> EngineResult<ID3D11RasterizerState> {
+        get_or_create(&self.state_cache.rasterizer, *desc, || unsafe {
+            self.device.CreateRasterizerState(&desc.to_d3d11())
+        })
+        .context("Create rasterizer state")
+    }
+
+    pub fn sampler_state(&self, desc: &SamplerDesc) -> EngineResult<ID3D11SamplerState> {
+        get_or_create(&self.state_cache.sampler, *desc, || unsafe {
+            self.device.CreateSamplerState(&desc.to_d3d11())
+        })
+        .context("Create sampler state")
+    }
+
+    /// Input layouts are validated against a vertex shader's input signature,
+    /// so they are cached per layout and shader bytecode.
+    pub fn input_layout(
+        &self,
+        layout: &[InputElement],
+        vertex_shader: &Shader,
+    ) -> EngineResult<ID3D11InputLayout> {
+        let blob = vertex_shader.blob();
+        let bytecode = unsafe {
+            std::slice::from_raw_parts(blob.GetBufferPointer() as *const u8, blob.GetBufferSize())
+        };
+
+        let mut hasher = DefaultHasher::new();
+        bytecode.hash(&mut hasher);
+
+        get_or_create(
+            &self.state_cache.input_layout,
+            (layout.to_vec(), hasher.finish()),
+            || {
+                with_d3d11_input_layout(layout, |descs| unsafe {
+                    self.device.CreateInputLayout(
+                        descs.as_ptr(),
+                        descs.len() as u32,
+                        bytecode.as_ptr() as _,
+                        bytecode.len(),
+                    )
+                })
+            },
+        )
+        .context("Create input layout")
+    }
+
+    pub fn backbuffer(&self, buffer: u32) -> EngineResult<Tex2D> {
+        let raw_backbuffer: ID3D11Texture2D =
+            unsafe { self.swap_chain.GetBuffer(buffer) }.context("Get backbuffer")?;
         let mut backbuffer_desc = Default::default();
         unsafe { raw_backbuffer.GetDesc(&mut backbuffer_desc) }
 
@@ -42,26 +117,25 @@ impl Backend {
         &self,
         texture: &Tex2D,
         desc: Option<D3D11_DEPTH_STENCIL_VIEW_DESC>,
-    ) -> Result<ID3D11DepthStencilView> {
+    ) -> EngineResult<ID3D11DepthStencilView> {
         let desc = if let Some(desc) = desc {
             &desc
         } else {
             std::ptr::null()
         };
 
-        let dsv = unsafe {
+        unsafe {
             self.device
-                .CreateDepthStencilView(texture.device_texture(), desc)?
-        };
-
-        Ok(dsv)
+                .CreateDepthStencilView(texture.device_texture(), desc)
+        }
+        .context("Create depth stencil view")
     }
 
     pub fn render_target_view<'a>(
         &self,
         texture: &impl Tex<'a>,
         desc: Option<D3D11_RENDER_TARGET_VIEW_DESC>,
-    ) -> Result<ID3D11RenderTargetView> {
+    ) -> EngineResult<ID3D11RenderTargetView> {
         let desc = if let Some(desc) = desc {
             &desc
         } else {
@@ -72,13 +146,14 @@ impl Backend {
             self.device
                 .CreateRenderTargetView(texture.device_texture(), desc)
         }
+        .context("Create render target view")
     }
 
     pub fn shader_resource_view<'a>(
         &self,
         texture: &impl Tex<'a>,
         desc: Option<D3D11_SHADER_RESOURCE_VIEW_DESC>,
-    ) -> Result<ID3D11ShaderResourceView> {
+    ) -> EngineResult<ID3D11ShaderResourceView> {
         let desc = if let Some(desc) = desc {
             &desc
         } else {
@@ -89,13 +164,14 @@ impl Backend {
             self.device
                 .CreateShaderResourceView(texture.device_texture(), desc)
         }
+        .context("Create shader resource view")
     }
 
     pub fn shader_resource_view_buffer(
         &self,
         buffer: &GPUBuffer,
         desc: Option<D3D11_SHADER_RESOURCE_VIEW_DESC>,
-    ) -> Result<ID3D11ShaderResourceView> {
+    ) -> EngineResult<ID3D11ShaderResourceView> {
         let desc = if let Some(desc) = desc {
             &desc
         } else {
@@ -106,13 +182,14 @@ impl Backend {
             self.device
                 .CreateShaderResourceView(buffer.buffer.clone(), desc)
         }
+        .context("Create buffer shader resource view")
     }
 
     pub fn unordered_access_view_buffer(
         &self,
         buffer: &GPUBuffer,
         desc: Option<D3D11_UNORDERED_ACCESS_VIEW_DESC>,
-    ) -> Result<ID3D11UnorderedAccessView> {
+    ) -> EngineResult<ID3D11UnorderedAccessView> {
         let desc = if let Some(desc) = desc {
             &desc
         } else {
@@ -123,13 +200,14 @@ impl Backend {
             self.device
                 .CreateUnorderedAccessView(buffer.buffer.clone(), desc)
         }
+        .context("Create buffer unordered access view")
     }
 
     pub fn unordered_access_view<'a>(
         &self,
         texture: &impl Tex<'a>,
         desc: Option<D3D11_UNORDERED_ACCESS_VIEW_DESC>,
-    ) -> Result<ID3D11UnorderedAccessView> {
+    ) -> EngineResult<ID3D11UnorderedAccessView> {
         let desc = if let Some(desc) = desc {
             &desc
         } else {
@@ -140,6 +218,7 @@ impl Backend {
             self.device
                 .CreateUnorderedAccessView(texture.device_texture(), desc)
         }
+        .context("Create unordered access view")
     }
 
     pub fn set_render_targets(
@@ -0,0 +1,1160 @@
+use std::fmt;
+use std::path::Path;
+
+use windows::Win32::Graphics::Dxgi::Common::*;
+
+use crate::error::{EngineError, EngineResult};
+
+use super::cpu_image::{CpuImage, CpuPixels};
+use super::dds;
+use super::format_info::format_name;
+use super::mipmaps::MipGenerator;
+use super::texture_data::TextureData;
+
+/// The block compressed formats the encoder writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BcFormat {
+    /// RGB at 4 bits per texel, no alpha.
+    Bc1,
+    /// BC1 color plus a BC4 alpha channel, 8 bits per texel.
+    Bc3,
+    /// One channel, red, at 4 bits per texel.
+    Bc4,
+    /// Two channels, red and green, at 8 bits per texel.
+    Bc5,
+    /// RGBA at 8 bits per texel, the best quality. Only mode 5 and 6
+    /// blocks are written.
+    Bc7,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CompressionQuality {
+    /// Endpoints straight from the principal axis of each block.
+    Fast,
+    /// Endpoints refined by least squares.
+    Normal,
+    /// More refinement and a search around the endpoints. Slow.
+    High,
+}
+
+/// What a texture holds, which decides its format and how its mips are made.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureUsage {
+    /// sRGB color, maybe with alpha.
+    Albedo,
+    /// Tangent space normals in RG; B is reconstructed in the shader.
+    NormalMap,
+    /// A single linear channel in R, e.g. roughness or a cutout mask.
+    Mask,
+}
+
+impl TextureUsage {
+    /// Albedo is sRGB and keeps BC1 for fast builds. Normal maps and masks
+    /// get the formats made for them.
+    pub fn format(&self, quality: CompressionQuality, has_alpha: bool) -> BcFormat {
+        match self {
+            TextureUsage::Albedo if quality > CompressionQuality::Fast => BcFormat::Bc7,
+            TextureUsage::Albedo if has_alpha => BcFormat::Bc3,
+            TextureUsage::Albedo => BcFormat::Bc1,
+            TextureUsage::NormalMap => BcFormat::Bc5,
+            TextureUsage::Mask => BcFormat::Bc4,
+        }
+    }
+
+    pub fn mip_generator(&self) -> MipGenerator {
+        match self {
+            TextureUsage::Albedo => MipGenerator::new().srgb(true),
+            TextureUsage::NormalMap => MipGenerator::new().normal_map(true),
+            TextureUsage::Mask => MipGenerator::new(),
+        }
+    }
+}
+
+impl BcFormat {
+    pub fn dxgi_format(&self, srgb: bool) -> DXGI_FORMAT {
+        match (self, srgb) {
+            (BcFormat::Bc1, false) => DXGI_FORMAT_BC1_UNORM,
+            (BcFormat::Bc1, true) => DXGI_FORMAT_BC1_UNORM_SRGB,
+            (BcFormat::Bc3, false) => DXGI_FORMAT_BC3_UNORM,
+            (BcFormat::Bc3, true) => DXGI_FORMAT_BC3_UNORM_SRGB,
+            (BcFormat::Bc4, _) => DXGI_FORMAT_BC4_UNORM,
+            (BcFormat::Bc5, _) => DXGI_FORMAT_BC5_UNORM,
+            (BcFormat::Bc7, false) => DXGI_FORMAT_BC7_UNORM,
+            (BcFormat::Bc7, true) => DXGI_FORMAT_BC7_UNORM_SRGB,
+        }
+    }
+
+    fn block_bytes(&self) -> usize {
+        match self {
+            BcFormat::Bc1 | BcFormat::Bc4 => 8,
+            BcFormat::Bc3 | BcFormat::Bc5 | BcFormat::Bc7 => 16,
+        }
+    }
+
+    /// How many channels, starting at red, the format stores.
+    fn channels(&self) -> usize {
+        match self {
+            BcFormat::Bc1 => 3,
+            BcFormat::Bc4 => 1,
+            BcFormat::Bc5 => 2,
+            BcFormat::Bc3 | BcFormat::Bc7 => 4,
+        }
+    }
+
+    fn encode_block(&self, block: &Block, quality: CompressionQuality, out: &mut Vec<u8>) {
+        let channel = |index: usize| block.map(|pixel| pixel[index]);
+
+        match self {
+            BcFormat::Bc1 => out.extend(encode_bc1(block, quality)),
+            BcFormat::Bc3 => {
+                out.extend(encode_bc4(&channel(3), quality));
+                out.extend(encode_bc1(block, quality));
+            }
+            BcFormat::Bc4 => out.extend(encode_bc4(&channel(0), quality)),
+            BcFormat::Bc5 => {
+                out.extend(encode_bc4(&channel(0), quality));
+                out.extend(encode_bc4(&channel(1), quality));
+            }
+            BcFormat::Bc7 => out.extend(encode_bc7(block, quality)),
+        }
+    }
+
+    fn decode_block(&self, bytes: &[u8]) -> Block {
+        fn set_channel(block: &mut Block, index: usize, values: [u8; 16]) {
+            for (pixel, value) in block.iter_mut().zip(values) {
+                pixel[index] = value;
+            }
+        }
+
+        let mut block = [[0, 0, 0, 255]; 16];
+        match self {
+            BcFormat::Bc1 => block = decode_bc1(bytes),
+            BcFormat::Bc3 => {
+                block = decode_bc1(&bytes[8..]);
+                set_channel(&mut block, 3, decode_bc4(&bytes[..8]));
+            }
+            BcFormat::Bc4 => set_channel(&mut block, 0, decode_bc4(bytes)),
+            BcFormat::Bc5 => {
+                set_channel(&mut block, 0, decode_bc4(&bytes[..8]));
+                set_channel(&mut block, 1, decode_bc4(&bytes[8..]));
+            }
+            BcFormat::Bc7 => block = decode_bc7(bytes),
+        }
+
+        block
+    }
+}
+
+impl fmt::Display for BcFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BcFormat::Bc1 => "BC1",
+            BcFormat::Bc3 => "BC3",
+            BcFormat::Bc4 => "BC4",
+            BcFormat::Bc5 => "BC5",
+            BcFormat::Bc7 => "BC7",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// 4x4 texels, row by row.
+type Block = [[u8; 4]; 16];
+
+/// Compresses an 8-bit image. Partial blocks at the right and bottom edges
+/// repeat the last column or row.
+pub fn compress_image(image: &CpuImage, format: BcFormat, quality: CompressionQuality) -> Vec<u8> {
+    let pixels = image.to_rgba8();
+    let (width, height) = (image.width as usize, image.height as usize);
+    let (blocks_wide, blocks_high) = (width.div_ceil(4), height.div_ceil(4));
+
+    let mut out = Vec::with_capacity(blocks_wide * blocks_high * format.block_bytes());
+    for block_y in 0..blocks_high {
+        for block_x in 0..blocks_wide {
+            let mut block = [[0; 4]; 16];
+            for (i, texel) in block.iter_mut().enumerate() {
+                let x = (block_x * 4 + i % 4).min(width - 1);
+                let y = (block_y * 4 + i / 4).min(height - 1);
+                *texel = pixels[y * width + x];
+            }
+            format.encode_block(&block, quality, &mut out);
+        }
+    }
+
+    out
+}
+
+/// Decodes what `compress_image` wrote, e.g. to measure its quality. BC7
+/// blocks other than mode 5 and 6 decode as black.
+pub fn decompress_image(format: BcFormat, width: u32, height: u32, data: &[u8]) -> CpuImage {
+    let (width, height) = (width as usize, height as usize);
+    let blocks_wide = width.div_ceil(4);
+
+    let mut pixels = vec![[0u8; 4]; width * height];
+    for (index, bytes) in data.chunks_exact(format.block_bytes()).enumerate() {
+        let block = format.decode_block(bytes);
+        let (block_x, block_y) = (index % blocks_wide * 4, index / blocks_wide * 4);
+        for (i, texel) in block.iter().enumerate() {
+            let (x, y) = (block_x + i % 4, block_y + i / 4);
+            if x < width && y < height {
+                pixels[y * width + x] = *texel;
+            }
+        }
+    }
+
+    CpuImage {
+        width: width as u32,
+        height: height as u32,
+        pixels: CpuPixels::Rgba8(pixels),
+    }
+}
+
+/// Peak signal to noise ratio in dB over the channels `format` stores.
+pub fn psnr(format: BcFormat, original: &CpuImage, decoded: &CpuImage) -> f32 {
+    let channels = format.channels();
+    let squared_error: f64 = original
+        .to_rgba8()
+        .iter()
+        .zip(decoded.to_rgba8())
+        .flat_map(|(a, b)| (0..channels).map(move |c| (a[c] as f64 - b[c] as f64).powi(2)))
+        .sum();
+    let mse = squared_error / (original.width * original.height) as f64 / channels as f64;
+    if mse == 0.0 {
+        return f32::INFINITY;
+    }
+
+    (10.0 * (255.0 * 255.0 / mse).log10()) as f32
+}
+
+/// Compresses every subresource of an `R8G8B8A8` texture. sRGB textures get
+/// the sRGB variant of `format` where there is one.
+pub fn compress_texture(
+    texture: &TextureData,
+    format: BcFormat,
+    quality: CompressionQuality,
+) -> Result<TextureData, String> {
+    let srgb = match texture.format {
+        DXGI_FORMAT_R8G8B8A8_UNORM => false,
+        DXGI_FORMAT_R8G8B8A8_UNORM_SRGB => true,
+        other => return Err(format!("can't compress {}", format_name(other))),
+    };
+
+    let mut data = Vec::new();
+    for subresource in texture.subresources()? {
+        let [width, height, depth] = subresource.size;
+        for z in 0..depth as usize {
+            let start = subresource.offset + z * subresource.slice_pitch;
+            let pixels = texture.data[start..start + subresource.slice_pitch]
+                .chunks_exact(4)
+                .map(|texel| [texel[0], texel[1], texel[2], texel[3]])
+                .collect();
+            let image = CpuImage {
+                width,
+                height,
+                pixels: CpuPixels::Rgba8(pixels),
+            };
+            data.extend(compress_image(&image, format, quality));
+        }
+    }
+
+    Ok(TextureData {
+        format: format.dxgi_format(srgb),
+        data,
+        ..texture.clone()
+    })
+}
+
+/// What `compress_file` did.
+#[derive(Clone, Copy, Debug)]
+pub struct CompressionReport {
+    pub format: BcFormat,
+    pub mip_levels: u32,
+    pub uncompressed_bytes: usize,
+    pub compressed_bytes: usize,
+    /// Of the top mip.
+    pub psnr: f32,
+}
+
+impl fmt::Display for CompressionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, {} mips, {} -> {} bytes, {:.2} dB PSNR",
+            self.format, self.mip_levels, self.uncompressed_bytes, self.compressed_bytes, self.psnr
+        )
+    }
+}
+
+/// Compresses an image read through the VFS, with a mip chain, into a DDS
+/// file. The format follows from `usage` and whether the image has alpha.
+pub fn compress_file(
+    input: &str,
+    output: &Path,
+    usage: TextureUsage,
+    quality: CompressionQuality,
+) -> EngineResult<CompressionReport> {
+    let image = CpuImage::load(input)?;
+    let has_alpha = image.to_rgba8().iter().any(|pixel| pixel[3] < 255);
+    let format = usage.format(quality, has_alpha);
+
+    let uncompressed = usage.mip_generator().texture_data(&image);
+    let compressed = compress_texture(&uncompressed, format, quality).map_err(|message| {
+        EngineError::ImageEncode {
+            path: output.display().to_string(),
+            message,
+        }
+    })?;
+
+    let top_mip_bytes =
+        image.width.div_ceil(4) as usize * image.height.div_ceil(4) as usize * format.block_bytes();
+    let decoded = decompress_image(
+        format,
+        image.width,
+        image.height,
+        &compressed.data[..top_mip_bytes],
+    );
+
+    dds::save(&compressed, output)?;
+
+    Ok(CompressionReport {
+        format,
+        mip_levels: compressed.mip_levels,
+        uncompressed_bytes: uncompressed.data.len(),
+        compressed_bytes: compressed.data.len(),
+        psnr: psnr(format, &image, &decoded),
+    })
+}
+
+fn squared_distance<const N: usize>(a: &[f32; N], b: &[f32; N]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+/// Mean and principal axis of `points`, found by power iteration on their
+/// covariance. The axis is a unit vector, or zero if all points are equal.
+fn principal_axis<const N: usize>(points: &[[f32; N]]) -> ([f32; N], [f32; N]) {
+    let mut mean = [0.0; N];
+    for point in points {
+        for c in 0..N {
+            mean[c] += point[c] / points.len() as f32;
+        }
+    }
+
+    let mut covariance = [[0.0; N]; N];
+    for point in points {
+        for i in 0..N {
+            for j in 0..N {
+                covariance[i][j] += (point[i] - mean[i]) * (point[j] - mean[j]);
+            }
+        }
+    }
+
+    let mut axis = [1.0; N];
+    for _ in 0..8 {
+        let mut next = [0.0; N];
+        for i in 0..N {
+            next[i] = (0..N).map(|j| covariance[i][j] * axis[j]).sum();
+        }
+        let length = next.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if length < 1e-6 {
+            return (mean, [0.0; N]);
+        }
+        axis = next.map(|v| v / length);
+    }
+
+    (mean, axis)
+}
+
+/// The ends of the segment along `axis` through `mean` that covers every
+/// point, brightest end first.
+fn axis_endpoints<const N: usize>(
+    points: &[[f32; N]],
+    mean: [f32; N],
+    axis: [f32; N],
+) -> ([f32; N], [f32; N]) {
+    let project =
+        |point: &[f32; N]| -> f32 { (0..N).map(|c| (point[c] - mean[c]) * axis[c]).sum() };
+    let (mut low, mut high) = (f32::MAX, f32::MIN);
+    for point in points {
+        let t = project(point);
+        low = low.min(t);
+        high = high.max(t);
+    }
+
+    let along = |t: f32| {
+        let mut point = mean;
+        for c in 0..N {
+            point[c] += axis[c] * t;
+        }
+        point
+    };
+    (along(high), along(low))
+}
+
+/// The endpoints that best reproduce `points` as `(1 - t) * a + t * b`, with
+/// one interpolation weight `t` per point. `None` if the weights don't
+/// constrain both ends.
+fn least_squares<const N: usize>(
+    points: &[[f32; N]],
+    weights: impl Iterator<Item = f32>,
+) -> Option<([f32; N], [f32; N])> {
+    let (mut aa, mut ab, mut bb) = (0.0, 0.0, 0.0);
+    let (mut ax, mut bx) = ([0.0; N], [0.0; N]);
+    for (point, t) in points.iter().zip(weights) {
+        let s = 1.0 - t;
+        aa += s * s;
+        ab += s * t;
+        bb += t * t;
+        for c in 0..N {
+            ax[c] += s * point[c];
+            bx[c] += t * point[c];
+        }
+    }
+
+    let determinant = aa * bb - ab * ab;
+    if determinant.abs() < 1e-6 {
+        return None;
+    }
+
+    let mut a = [0.0; N];
+    let mut b = [0.0; N];
+    for c in 0..N {
+        a[c] = (bb * ax[c] - ab * bx[c]) / determinant;
+        b[c] = (aa * bx[c] - ab * ax[c]) / determinant;
+    }
+    Some((a, b))
+}
+
+/// Index of the palette entry closest to `point` and the squared error.
+fn closest<const N: usize>(palette: &[[f32; N]], point: &[f32; N]) -> (usize, f32) {
+    palette
+        .iter()
+        .map(|entry| squared_distance(entry, point))
+        .enumerate()
+        .fold((0, f32::MAX), |best, (index, error)| {
+            if error < best.1 {
+                (index, error)
+            } else {
+                best
+            }
+        })
+}
+
+fn to_565(color: [f32; 3]) -> u16 {
+    let quantize = |value: f32, max: f32| (value.clamp(0.0, 255.0) / 255.0 * max).round() as u16;
+    quantize(color[0], 31.0) << 11 | quantize(color[1], 63.0) << 5 | quantize(color[2], 31.0)
+}
+
+fn from_565(color: u16) -> [u32; 3] {
+    let (r, g, b) = (
+        (color >> 11) as u32,
+        (color >> 5 & 0x3f) as u32,
+        (color & 0x1f) as u32,
+    );
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+}
+
+/// The four colors of a BC1 block in four color mode, as the GPU decodes
+/// them. Index 0 is `c0`, 1 is `c1`, 2 and 3 lie between.
+fn bc1_palette(c0: u16, c1: u16) -> [[f32; 3]; 4] {
+    let (a, b) = (from_565(c0), from_565(c1));
+    let mix = |wa: u32, wb: u32| [0, 1, 2].map(|c| ((wa * a[c] + wb * b[c]) / 3) as f32);
+    [mix(3, 0), mix(0, 3), mix(2, 1), mix(1, 2)]
+}
+
+/// Interpolation weight towards `c1` of each BC1 index.
+const BC1_WEIGHTS: [f32; 4] = [0.0, 1.0, 1.0 / 3.0, 2.0 / 3.0];
+
+fn bc1_indices(points: &[[f32; 3]], endpoints: [u16; 2]) -> ([usize; 16], f32) {
+    let palette = bc1_palette(endpoints[0], endpoints[1]);
+    let mut indices = [0; 16];
+    let mut error = 0.0;
+    for (index, point) in indices.iter_mut().zip(points) {
+        let (closest, distance) = closest(&palette, point);
+        *index = closest;
+        error += distance;
+    }
+    (indices, error)
+}
+
+/// Always four color mode, BC3 can't use the other one anyway.
+fn encode_bc1(block: &Block, quality: CompressionQuality) -> [u8; 8] {
+    let points = block.map(|pixel| [pixel[0], pixel[1], pixel[2]].map(f32::from));
+    let (mean, axis) = principal_axis(&points);
+    let (start, end) = axis_endpoints(&points, mean, axis);
+
+    let mut endpoints = [to_565(start), to_565(end)];
+    let (mut indices, mut error) = bc1_indices(&points, endpoints);
+
+    let refinements = match quality {
+        CompressionQuality::Fast => 0,
+        CompressionQuality::Normal => 2,
+        CompressionQuality::High => 4,
+    };
+    for _ in 0..refinements {
+        let weights = indices.iter().map(|&index| BC1_WEIGHTS[index]);
+        let Some((a, b)) = least_squares(&points, weights) else {
+            break;
+        };
+        let candidate = [to_565(a), to_565(b)];
+        let (candidate_indices, candidate_error) = bc1_indices(&points, candidate);
+        if candidate_error >= error {
+            break;
+        }
+        (endpoints, indices, error) = (candidate, candidate_indices, candidate_error);
+    }
+
+    if quality == CompressionQuality::High {
+        // Nudge each 565 channel of each endpoint while that helps.
+        let fields = [(11, 31), (5, 63), (0, 31)];
+        let mut improved = true;
+        while improved {
+            improved = false;
+            for endpoint in 0..2 {
+                for (shift, max) in fields {
+                    for delta in [-1i32, 1] {
+                        let value = (endpoints[endpoint] >> shift & max) as i32 + delta;
+                        if !(0..=max as i32).contains(&value) {
+                            continue;
+                        }
+                        let mut candidate = endpoints;
+                        candidate[endpoint] =
+                            candidate[endpoint] & !(max << shift) | (value as u16) << shift;
+                        let (candidate_indices, candidate_error) = bc1_indices(&points, candidate);
+                        if candidate_error < error {
+                            (endpoints, indices, error) =
+                                (candidate, candidate_indices, candidate_error);
+                            improved = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Four color mode needs c0 > c1. Equal endpoints only ever use index 0.
+    let [mut c0, mut c1] = endpoints;
+    if c0 < c1 {
+        (c0, c1) = (c1, c0);
+        indices = indices.map(|index| [1, 0, 3, 2][index]);
+    } else if c0 == c1 {
+        indices = [0; 16];
+    }
+
+    let bits = indices
+        .iter()
+        .enumerate()
+        .fold(0u32, |bits, (i, &index)| bits | (index as u32) << (2 * i));
+
+    let mut out = [0; 8];
+    out[..2].copy_from_slice(&c0.to_le_bytes());
+    out[2..4].copy_from_slice(&c1.to_le_bytes());
+    out[4..].copy_from_slice(&bits.to_le_bytes());
+    out
+}
+
+fn decode_bc1(bytes: &[u8]) -> Block {
+    let c0 = u16::from_le_bytes([bytes[0], bytes[1]]);
+    let c1 = u16::from_le_bytes([bytes[2], bytes[3]]);
+    let bits = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    let palette = bc1_palette(c0, c1);
+
+    let mut block = [[0; 4]; 16];
+    for (i, texel) in block.iter_mut().enumerate() {
+        let [r, g, b] = palette[(bits >> (2 * i) & 3) as usize];
+        *texel = [r as u8, g as u8, b as u8, 255];
+    }
+    block
+}
+
+/// The eight values of a BC4 block. `a0 > a1` interpolates six values
+/// between them, otherwise four, plus 0 and 255.
+fn bc4_palette(a0: u8, a1: u8) -> [u8; 8] {
+    let (a, b) = (a0 as u32, a1 as u32);
+    let mut palette = [a0, a1, 0, 0, 0, 0, 0, 255];
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i as u32) * a + i as u32 * b + 3) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i as u32) * a + i as u32 * b + 2) / 5) as u8;
+        }
+    }
+    palette
+}
+
+fn bc4_indices(values: &[u8; 16], a0: u8, a1: u8) -> ([u8; 16], u32) {
+    let palette = bc4_palette(a0, a1);
+    let mut indices = [0; 16];
+    let mut error = 0;
+    for (index, &value) in indices.iter_mut().zip(values) {
+        let (closest, distance) = palette
+            .iter()
+            .map(|&entry| (entry as i32 - value as i32).pow(2) as u32)
+            .enumerate()
+            .min_by_key(|&(_, distance)| distance)
+            .unwrap();
+        *index = closest as u8;
+        error += distance;
+    }
+    (indices, error)
+}
+
+fn encode_bc4(values: &[u8; 16], quality: CompressionQuality) -> [u8; 8] {
+    let min = *values.iter().min().unwrap();
+    let max = *values.iter().max().unwrap();
+
+    let mut candidates = vec![(max, min)];
+    if quality > CompressionQuality::Fast {
+        // Six value mode spends its interpolated values on the texels
+        // between the extremes, which it represents exactly.
+        let inner = values.iter().filter(|&&value| value != 0 && value != 255);
+        if let (Some(&low), Some(&high)) = (inner.clone().min(), inner.max()) {
+            candidates.push((low, high));
+        }
+    }
+    if quality == CompressionQuality::High {
+        for d0 in -2i32..=2 {
+            for d1 in -2i32..=2 {
+                let a0 = (max as i32 + d0).clamp(0, 255) as u8;
+                let a1 = (min as i32 + d1).clamp(0, 255) as u8;
+                if a0 > a1 {
+                    candidates.push((a0, a1));
+                }
+            }
+        }
+    }
+
+    let (a0, a1, indices) = candidates
+        .into_iter()
+        .map(|(a0, a1)| {
+            let (indices, error) = bc4_indices(values, a0, a1);
+            (error, a0, a1, indices)
+        })
+        .min_by_key(|&(error, ..)| error)
+        .map(|(_, a0, a1, indices)| (a0, a1, indices))
+        .unwrap();
+
+    let bits = indices
+        .iter()
+        .enumerate()
+        .fold(0u64, |bits, (i, &index)| bits | (index as u64) << (3 * i));
+
+    let mut out = [0; 8];
+    out[0] = a0;
+    out[1] = a1;
+    out[2..].copy_from_slice(&bits.to_le_bytes()[..6]);
+    out
+}
+
+fn decode_bc4(bytes: &[u8]) -> [u8; 16] {
+    let palette = bc4_palette(bytes[0], bytes[1]);
+    let mut bits = [0; 8];
+    bits[..6].copy_from_slice(&bytes[2..8]);
+    let bits = u64::from_le_bytes(bits);
+
+    let mut values = [0; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[(bits >> (3 * i) & 7) as usize];
+    }
+    values
+}
+
+/// Interpolation weights of 4 bit BC7 indices, out of 64.
+const BC7_WEIGHTS: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// A BC7 mode 6 block: one subset, RGBA endpoints of 7 bits plus a shared
+/// p-bit per endpoint, and 4 bit indices.
+#[derive(Clone, Copy)]
+struct Mode6 {
+    /// Full 8 bit endpoints, the p-bit in the lowest bit.
+    endpoints: [[u8; 4]; 2],
+    indices: [u8; 16],
+    error: f32,
+}
+
+fn bc7_palette(endpoints: &[[u8; 4]; 2]) -> [[f32; 4]; 16] {
+    BC7_WEIGHTS.map(|weight| {
+        [0, 1, 2, 3].map(|c| {
+            let (a, b) = (endpoints[0][c] as u32, endpoints[1][c] as u32);
+            (((64 - weight) * a + weight * b + 32) >> 6) as f32
+        })
+    })
+}
+
+/// Quantizes endpoints to 7 bits plus p-bit and picks the best indices.
+fn mode6(points: &[[f32; 4]; 16], a: [f32; 4], b: [f32; 4], p_bits: &[[u8; 2]]) -> Mode6 {
+    let quantize = |endpoint: [f32; 4], p_bit: u8| {
+        endpoint.map(|value| {
+            let high = ((value - p_bit as f32) / 2.0).round().clamp(0.0, 127.0) as u8;
+            high << 1 | p_bit
+        })
+    };
+
+    p_bits
+        .iter()
+        .map(|&[p0, p1]| {
+            let endpoints = [quantize(a, p0), quantize(b, p1)];
+            let palette = bc7_palette(&endpoints);
+            let mut indices = [0; 16];
+            let mut error = 0.0;
+            for (index, point) in indices.iter_mut().zip(points) {
+                let (closest, distance) = closest(&palette, point);
+                *index = closest as u8;
+                error += distance;
+            }
+            Mode6 {
+                endpoints,
+                indices,
+                error,
+            }
+        })
+        .min_by(|x, y| x.error.total_cmp(&y.error))
+        .unwrap()
+}
+
+fn encode_bc7(block: &Block, quality: CompressionQuality) -> [u8; 16] {
+    let points = block.map(|pixel| pixel.map(f32::from));
+    let alpha_varies = block.iter().any(|pixel| pixel[3] != block[0][3]);
+
+    // Mode 5 gives alpha, or with a rotation another channel, indices of
+    // its own, which suits blocks where it doesn't follow the color.
+    let rotations = match quality {
+        CompressionQuality::Fast if !alpha_varies => 0..0,
+        CompressionQuality::Fast | CompressionQuality::Normal => 0..1,
+        CompressionQuality::High => 0..4,
+    };
+
+    rotations
+        .map(|rotation| encode_mode5(&points, rotation, quality))
+        .chain([encode_mode6(&points, quality)])
+        .min_by(|x, y| x.1.total_cmp(&y.1))
+        .unwrap()
+        .0
+}
+
+fn encode_mode6(points: &[[f32; 4]; 16], quality: CompressionQuality) -> ([u8; 16], f32) {
+    let (mean, axis) = principal_axis(points);
+    let (start, end) = axis_endpoints(points, mean, axis);
+
+    let (p_bits, refinements): (&[[u8; 2]], _) = match quality {
+        CompressionQuality::Fast => (&[[0, 0], [1, 1]], 0),
+        CompressionQuality::Normal => (&[[0, 0], [0, 1], [1, 0], [1, 1]], 2),
+        CompressionQuality::High => (&[[0, 0], [0, 1], [1, 0], [1, 1]], 6),
+    };
+
+    let mut best = mode6(points, start, end, p_bits);
+    for _ in 0..refinements {
+        let weights = best
+            .indices
+            .iter()
+            .map(|&index| BC7_WEIGHTS[index as usize] as f32 / 64.0);
+        let Some((a, b)) = least_squares(points, weights) else {
+            break;
+        };
+        let candidate = mode6(points, a, b, p_bits);
+        if candidate.error >= best.error {
+            break;
+        }
+        best = candidate;
+    }
+
+    // The anchor index, of texel 0, is stored without its top bit, so it
+    // has to be below 8. Swapping the endpoints mirrors the indices.
+    let Mode6 {
+        mut endpoints,
+        mut indices,
+        error,
+    } = best;
+    if indices[0] >= 8 {
+        endpoints.swap(0, 1);
+        indices = indices.map(|index| 15 - index);
+    }
+
+    let mut bits = BitWriter::default();
+    bits.write(1 << 6, 7);
+    for (a, b) in endpoints[0].iter().zip(&endpoints[1]) {
+        bits.write(*a as u128 >> 1, 7);
+        bits.write(*b as u128 >> 1, 7);
+    }
+    bits.write(endpoints[0][0] as u128 & 1, 1);
+    bits.write(endpoints[1][0] as u128 & 1, 1);
+    bits.write(indices[0] as u128, 3);
+    for &index in &indices[1..] {
+        bits.write(index as u128, 4);
+    }
+
+    (bits.bits.to_le_bytes(), error)
+}
+
+/// Interpolation weights of 2 bit BC7 indices, out of 64.
+const BC7_WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+
+/// One half of a mode 5 block: endpoints quantized to `precision` bits and
+/// 2 bit indices, with the anchor index already below 2.
+struct Mode5Part<const N: usize> {
+    endpoints: [[u8; N]; 2],
+    indices: [u8; 16],
+    error: f32,
+}
+
+fn mode5_part<const N: usize>(
+    points: &[[f32; N]; 16],
+    precision: u32,
+    refinements: usize,
+) -> Mode5Part<N> {
+    let max = ((1 << precision) - 1) as f32;
+    let quantize = |endpoint: [f32; N]| {
+        endpoint.map(|value| (value / 255.0 * max).round().clamp(0.0, max) as u8)
+    };
+    let fit = |a: [f32; N], b: [f32; N]| {
+        let endpoints = [quantize(a), quantize(b)];
+        let palette = bc7_palette_2(&endpoints, precision);
+        let mut indices = [0; 16];
+        let mut error = 0.0;
+        for (index, point) in indices.iter_mut().zip(points) {
+            let (closest, distance) = closest(&palette, point);
+            *index = closest as u8;
+            error += distance;
+        }
+        Mode5Part {
+            endpoints,
+            indices,
+            error,
+        }
+    };
+
+    let (mean, axis) = principal_axis(points);
+    let (start, end) = axis_endpoints(points, mean, axis);
+    let mut best = fit(start, end);
+    for _ in 0..refinements {
+        let weights = best
+            .indices
+            .iter()
+            .map(|&index| BC7_WEIGHTS_2[index as usize] as f32 / 64.0);
+        let Some((a, b)) = least_squares(points, weights) else {
+            break;
+        };
+        let candidate = fit(a, b);
+        if candidate.error >= best.error {
+            break;
+        }
+        best = candidate;
+    }
+
+    if best.indices[0] >= 2 {
+        best.endpoints.swap(0, 1);
+        best.indices = best.indices.map(|index| 3 - index);
+    }
+    best
+}
+
+/// Mode 5: RGB endpoints of 7 bits and alpha endpoints of 8 bits, each
+/// with their own 2 bit indices. `rotation` swaps alpha with red, green or
+/// blue first.
+fn encode_mode5(
+    points: &[[f32; 4]; 16],
+    rotation: usize,
+    quality: CompressionQuality,
+) -> ([u8; 16], f32) {
+    let refinements = match quality {
+        CompressionQuality::Fast => 0,
+        CompressionQuality::Normal => 2,
+        CompressionQuality::High => 6,
+    };
+    let rotated = points.map(|mut point| {
+        if rotation > 0 {
+            point.swap(3, rotation - 1);
+        }
+        point
+    });
+
+    let color = mode5_part(&rotated.map(|[r, g, b, _]| [r, g, b]), 7, refinements);
+    let alpha = mode5_part(&rotated.map(|[.., a]| [a]), 8, refinements);
+
+    let mut bits = BitWriter::default();
+    bits.write(1 << 5, 6);
+    bits.write(rotation as u128, 2);
+    for (a, b) in color.endpoints[0].iter().zip(&color.endpoints[1]) {
+        bits.write(*a as u128, 7);
+        bits.write(*b as u128, 7);
+    }
+    bits.write(alpha.endpoints[0][0] as u128, 8);
+    bits.write(alpha.endpoints[1][0] as u128, 8);
+    for indices in [color.indices, alpha.indices] {
+        bits.write(indices[0] as u128, 1);
+        for &index in &indices[1..] {
+            bits.write(index as u128, 2);
+        }
+    }
+
+    (bits.bits.to_le_bytes(), color.error + alpha.error)
+}
+
+fn bc7_palette_2<const N: usize>(endpoints: &[[u8; N]; 2], precision: u32) -> [[f32; N]; 4] {
+    let expand = |value: u8| {
+        let value = value as u32;
+        if precision == 7 {
+            value << 1 | value >> 6
+        } else {
+            value
+        }
+    };
+
+    BC7_WEIGHTS_2.map(|weight| {
+        let mut entry = [0.0; N];
+        for (c, value) in entry.iter_mut().enumerate() {
+            let (a, b) = (expand(endpoints[0][c]), expand(endpoints[1][c]));
+            *value = (((64 - weight) * a + weight * b + 32) >> 6) as f32;
+        }
+        entry
+    })
+}
+
+/// Decodes mode 5 and 6 blocks, the ones `encode_bc7` writes.
+fn decode_bc7(bytes: &[u8]) -> Block {
+    let bits = u128::from_le_bytes(bytes.try_into().unwrap());
+    let field = |offset: u32, count: u32| (bits >> offset & ((1 << count) - 1)) as u8;
+
+    let mut block = [[0; 4]; 16];
+    if bits & 0x7f == 1 << 6 {
+        let (p0, p1) = (field(63, 1), field(64, 1));
+        let endpoints = [
+            [0, 1, 2, 3].map(|c| field(7 + 14 * c, 7) << 1 | p0),
+            [0, 1, 2, 3].map(|c| field(14 + 14 * c, 7) << 1 | p1),
+        ];
+        let palette = bc7_palette(&endpoints);
+        for (i, texel) in block.iter_mut().enumerate() {
+            let index = if i == 0 {
+                field(65, 3)
+            } else {
+                field(68 + 4 * (i as u32 - 1), 4)
+            };
+            *texel = palette[index as usize].map(|value| value as u8);
+        }
+    } else if bits & 0x3f == 1 << 5 {
+        let rotation = field(6, 2) as usize;
+        let color = [
+            [0, 1, 2].map(|c| field(8 + 14 * c, 7)),
+            [0, 1, 2].map(|c| field(15 + 14 * c, 7)),
+        ];
+        let alpha = [[field(50, 8)], [field(58, 8)]];
+        let (color, alpha) = (bc7_palette_2(&color, 7), bc7_palette_2(&alpha, 8));
+        let index = |start: u32, i: u32| {
+            if i == 0 {
+                field(start, 1)
+            } else {
+                field(start + 1 + 2 * (i - 1), 2)
+            }
+        };
+        for (i, texel) in block.iter_mut().enumerate() {
+            let [r, g, b] = color[index(66, i as u32) as usize];
+            let [a] = alpha[index(97, i as u32) as usize];
+            *texel = [r, g, b, a].map(|value| value as u8);
+            if rotation > 0 {
+                texel.swap(3, rotation - 1);
+            }
+        }
+    }
+
+    block
+}
+
+/// Packs fields from the least significant bit up, like BC7 blocks.
+#[derive(Default)]
+struct BitWriter {
+    bits: u128,
+    position: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u128, count: u32) {
+        self.bits |= value << self.position;
+        self.position += count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: [BcFormat; 5] = [
+        BcFormat::Bc1,
+        BcFormat::Bc3,
+        BcFormat::Bc4,
+        BcFormat::Bc5,
+        BcFormat::Bc7,
+    ];
+    const QUALITIES: [CompressionQuality; 3] = [
+        CompressionQuality::Fast,
+        CompressionQuality::Normal,
+        CompressionQuality::High,
+    ];
+
+    /// Smooth gradients with a few hard edges and some noise, like a
+    /// typical albedo texture. Not a multiple of 4, so edge blocks repeat.
+    fn test_image() -> CpuImage {
+        let (width, height) = (38u32, 30u32);
+        let mut seed = 0x1234_5678u32;
+        let pixels = (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as f32, (i / width) as f32);
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                let noise = (seed % 9) as f32 - 4.0;
+                let edge = if (x - 20.0).powi(2) + (y - 14.0).powi(2) < 60.0 {
+                    90.0
+                } else {
+                    0.0
+                };
+                let channel = |value: f32| value.clamp(0.0, 255.0) as u8;
+                [
+                    channel(x * 6.0 + noise + edge),
+                    channel(y * 8.0 - noise),
+                    channel(200.0 - x * 3.0 - y * 2.0 + edge),
+                    channel(255.0 - (x + y) * 3.0 + noise),
+                ]
+            })
+            .collect();
+
+        CpuImage {
+            width,
+            height,
+            pixels: CpuPixels::Rgba8(pixels),
+        }
+    }
+
+    fn round_trip(image: &CpuImage, format: BcFormat, quality: CompressionQuality) -> f32 {
+        let data = compress_image(image, format, quality);
+        let blocks = image.width.div_ceil(4) * image.height.div_ceil(4);
+        assert_eq!(data.len(), blocks as usize * format.block_bytes());
+
+        let decoded = decompress_image(format, image.width, image.height, &data);
+        psnr(format, image, &decoded)
+    }
+
+    #[test]
+    fn round_trips_above_psnr_thresholds() {
+        // About a dB under what the encoder reaches, in dB for Fast, Normal
+        // and High.
+        let thresholds = [
+            (BcFormat::Bc1, [33.0, 33.5, 33.5]),
+            (BcFormat::Bc3, [34.0, 34.5, 34.5]),
+            (BcFormat::Bc4, [42.5, 42.5, 44.0]),
+            (BcFormat::Bc5, [44.0, 44.0, 45.5]),
+            (BcFormat::Bc7, [35.0, 35.0, 38.0]),
+        ];
+
+        let image = test_image();
+        for (format, thresholds) in thresholds {
+            let mut previous = 0.0;
+            for (quality, threshold) in QUALITIES.into_iter().zip(thresholds) {
+                let psnr = round_trip(&image, format, quality);
+                assert!(
+                    psnr >= threshold,
+                    "{} {:?}: {:.2} dB, expected at least {} dB",
+                    format,
+                    quality,
+                    psnr,
+                    threshold
+                );
+                // Higher qualities search more and never do worse.
+                assert!(psnr >= previous - 0.01, "{} {:?}", format, quality);
+                previous = psnr;
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_solid_and_two_color_blocks_exact() {
+        // Colors BC1 endpoints hold exactly.
+        let colors = [[255, 0, 255, 255], [0, 255, 0, 255]];
+        let image = |pixel: &dyn Fn(u32, u32) -> [u8; 4]| CpuImage {
+            width: 8,
+            height: 4,
+            pixels: CpuPixels::Rgba8((0..32).map(|i| pixel(i % 8, i / 8)).collect()),
+        };
+        let solid = image(&|_, _| colors[0]);
+        let checkerboard = image(&|x, y| colors[((x + y) % 2) as usize]);
+
+        for format in FORMATS {
+            for quality in QUALITIES {
+                for image in [&solid, &checkerboard] {
+                    let psnr = round_trip(image, format, quality);
+                    if (format, quality) == (BcFormat::Bc7, CompressionQuality::Fast) {
+                        // Fast skips the endpoint refinement the mode 5
+                        // fit needs to land exactly.
+                        assert!(psnr > 50.0, "{}", psnr);
+                    } else {
+                        assert_eq!(psnr, f32::INFINITY, "{} {:?}", format, quality);
+                    }
+                }
+            }
+        }
+
+        // Single channel formats are exact for any two values.
+        let two_values = image(&|x, _| [[17, 201][x as usize % 2], 99, 0, 255]);
+        for format in [BcFormat::Bc4, BcFormat::Bc5] {
+            let psnr = round_trip(&two_values, format, CompressionQuality::Fast);
+            assert_eq!(psnr, f32::INFINITY, "{}", format);
+        }
+    }
+
+    #[test]
+    fn decodes_reference_blocks() {
+        // BC1: red and blue endpoints, indices 0, 1, 2 and 3 in every row.
+        let mut bc1 = vec![0x00, 0xf8, 0x1f, 0x00];
+        bc1.extend([0b1110_0100; 4]);
+        let block = BcFormat::Bc1.decode_block(&bc1);
+        assert_eq!(block[0], [255, 0, 0, 255]);
+        assert_eq!(block[1], [0, 0, 255, 255]);
+        assert_eq!(block[2], [170, 0, 85, 255]);
+        assert_eq!(block[3], [85, 0, 170, 255]);
+
+        // BC4: 8 interpolated values between 255 and 0.
+        let bc4 = [255, 0, 0b1000_1000, 0b1100_0110, 0b1111_1010, 0, 0, 0];
+        let values = BcFormat::Bc4.decode_block(&bc4).map(|pixel| pixel[0]);
+        assert_eq!(&values[..8], [255, 0, 219, 182, 146, 109, 73, 36]);
+    }
+
+    #[test]
+    fn compresses_every_subresource() {
+        let texture = MipGenerator::new().srgb(true).texture_data(&test_image());
+        let compressed =
+            compress_texture(&texture, BcFormat::Bc1, CompressionQuality::Fast).unwrap();
+
+        assert_eq!(compressed.format, DXGI_FORMAT_BC1_UNORM_SRGB);
+        assert_eq!(compressed.mip_levels, texture.mip_levels);
+        assert_eq!(compressed.subresources().unwrap().len(), 6);
+        assert_eq!(
+            compressed.data.len(),
+            compressed
+                .subresources()
+                .unwrap()
+                .iter()
+                .map(|subresource| subresource.slice_pitch)
+                .sum::<usize>()
+        );
+
+        let float = TextureData {
+            format: DXGI_FORMAT_R32G32B32A32_FLOAT,
+            ..texture
+        };
+        assert!(compress_texture(&float, BcFormat::Bc7, CompressionQuality::Fast).is_err());
+    }
+
+    #[test]
+    fn picks_formats_by_usage() {
+        use CompressionQuality::*;
+
+        assert_eq!(TextureUsage::Albedo.format(Fast, false), BcFormat::Bc1);
+        assert_eq!(TextureUsage::Albedo.format(Fast, true), BcFormat::Bc3);
+        assert_eq!(TextureUsage::Albedo.format(Normal, false), BcFormat::Bc7);
+        assert_eq!(TextureUsage::NormalMap.format(High, true), BcFormat::Bc5);
+        assert_eq!(TextureUsage::Mask.format(Fast, true), BcFormat::Bc4);
+
+        assert_eq!(BcFormat::Bc7.dxgi_format(true), DXGI_FORMAT_BC7_UNORM_SRGB);
+        assert_eq!(BcFormat::Bc4.dxgi_format(true), DXGI_FORMAT_BC4_UNORM);
+    }
+}
@@ -0,0 +1,411 @@
+use std::fmt;
+
+use glam::{IVec2, IVec3, IVec4, Mat4, UVec2, UVec3, UVec4, Vec2, Vec3, Vec4};
+
+/// Size of an HLSL constant register. Members of a cbuffer may not straddle
+/// a register boundary, and matrices always start on a new register.
+pub const REGISTER_SIZE: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ScalarType {
+    Float,
+    Int,
+    UInt,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HlslType {
+    Vector(ScalarType, usize),
+    /// Column-major `float4x4`, the default matrix packing in HLSL.
+    Float4x4,
+}
+
+impl HlslType {
+    pub fn size(&self) -> usize {
+        match self {
+            HlslType::Vector(_, components) => 4 * components,
+            HlslType::Float4x4 => 64,
+        }
+    }
+
+    fn starts_new_register(&self) -> bool {
+        matches!(self, HlslType::Float4x4)
+    }
+
+    pub fn hlsl_name(&self) -> String {
+        match self {
+            HlslType::Vector(scalar, components) => {
+                let scalar = match scalar {
+                    ScalarType::Float => "float",
+                    ScalarType::Int => "int",
+                    ScalarType::UInt => "uint",
+                };
+
+                if *components == 1 {
+                    scalar.to_string()
+                } else {
+                    format!("{}{}", scalar, components)
+                }
+            }
+            HlslType::Float4x4 => "float4x4".to_string(),
+        }
+    }
+}
+
+/// A Rust type that can be placed in a constant buffer.
+pub trait HlslField {
+    const HLSL_TYPE: HlslType;
+}
+
+macro_rules! hlsl_field {
+    ($($rust:ty => $hlsl:expr),* $(,)?) => {
+        $(impl HlslField for $rust {
+            const HLSL_TYPE: HlslType = $hlsl;
+        })*
+    };
+}
+
+hlsl_field! {
+    f32 => HlslType::Vector(ScalarType::Float, 1),
+    Vec2 => HlslType::Vector(ScalarType::Float, 2),
+    Vec3 => HlslType::Vector(ScalarType::Float, 3),
+    Vec4 => HlslType::Vector(ScalarType::Float, 4),
+    [f32; 4] => HlslType::Vector(ScalarType::Float, 4),
+    i32 => HlslType::Vector(ScalarType::Int, 1),
+    IVec2 => HlslType::Vector(ScalarType::Int, 2),
+    IVec3 => HlslType::Vector(ScalarType::Int, 3),
+    IVec4 => HlslType::Vector(ScalarType::Int, 4),
+    u32 => HlslType::Vector(ScalarType::UInt, 1),
+    UVec2 => HlslType::Vector(ScalarType::UInt, 2),
+    UVec3 => HlslType::Vector(ScalarType::UInt, 3),
+    UVec4 => HlslType::Vector(ScalarType::UInt, 4),
+    Mat4 => HlslType::Float4x4,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CBufferField {
+    pub name: String,
+    pub ty: HlslType,
+    pub offset: usize,
+}
+
+/// The HLSL packing of a constant buffer, checked against the Rust struct
+/// that is copied into it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CBufferLayout {
+    pub name: String,
+    pub fields: Vec<CBufferField>,
+    /// Size rounded up to a whole number of registers, as required for `ByteWidth`.
+    pub size: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CBufferLayoutError {
+    /// The field sits at a different offset in Rust than HLSL would put it,
+    /// usually because HLSL moved it to the next register.
+    OffsetMismatch {
+        field: String,
+        rust_offset: usize,
+        hlsl_offset: usize,
+    },
+    /// The Rust struct is larger than the HLSL cbuffer, e.g. trailing padding.
+    SizeMismatch { rust_size: usize, hlsl_size: usize },
+}
+
+impl fmt::Display for CBufferLayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CBufferLayoutError::OffsetMismatch {
+                field,
+                rust_offset,
+                hlsl_offset,
+            } => write!(
+                f,
+                "field `{}` is at offset {} in Rust but HLSL packs it at {}",
+                field, rust_offset, hlsl_offset
+            ),
+            CBufferLayoutError::SizeMismatch {
+                rust_size,
+                hlsl_size,
+            } => write!(
+                f,
+                "Rust struct is {} bytes but the HLSL cbuffer is {}",
+                rust_size, hlsl_size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CBufferLayoutError {}
+
+pub struct CBufferLayoutBuilder {
+    name: String,
+    fields: Vec<(String, HlslType, Option<usize>)>,
+    rust_size: Option<usize>,
+}
+
+impl CBufferLayoutBuilder {
+    pub fn new(name: &str) -> CBufferLayoutBuilder {
+        CBufferLayoutBuilder {
+            name: name.to_string(),
+            fields: Vec::new(),
+            rust_size: None,
+        }
+    }
+
+    /// Adds a field without a Rust counterpart to check against.
+    pub fn field(mut self, name: &str, ty: HlslType) -> Self {
+        self.fields.push((name.to_string(), ty, None));
+        self
+    }
+
+    /// Adds a field that must be at `rust_offset` in HLSL as well.
+    pub fn checked_field(mut self, name: &str, ty: HlslType, rust_offset: usize) -> Self {
+        self.fields.push((name.to_string(), ty, Some(rust_offset)));
+        self
+    }
+
+    pub fn rust_size(mut self, size: usize) -> Self {
+        self.rust_size = Some(size);
+        self
+    }
+
+    pub fn build(self) -> Result<CBufferLayout, CBufferLayoutError> {
+        let mut offset = 0;
+        let mut fields = Vec::with_capacity(self.fields.len());
+
+        for (name, ty, rust_offset) in self.fields {
+            let register_offset = offset % REGISTER_SIZE;
+            if ty.starts_new_register()
+                || (register_offset != 0 && register_offset + ty.size() > REGISTER_SIZE)
+            {
+                offset = round_up_to_register(offset);
+            }
+
+            if let Some(rust_offset) = rust_offset {
+                if rust_offset != offset {
+                    return Err(CBufferLayoutError::OffsetMismatch {
+                        field: name,
+                        rust_offset,
+                        hlsl_offset: offset,
+                    });
+                }
+            }
+
+            fields.push(CBufferField { name, ty, offset });
+            offset += ty.size();
+        }
+
+        if let Some(rust_size) = self.rust_size {
+            // Trailing padding in Rust is harmless as long as it fits in the
+            // last register; anything beyond that would be cut off on upload.
+            if rust_size > round_up_to_register(offset) {
+                return Err(CBufferLayoutError::SizeMismatch {
+                    rust_size,
+                    hlsl_size: round_up_to_register(offset),
+                });
+            }
+        }
+
+        Ok(CBufferLayout {
+            name: self.name,
+            fields,
+            size: round_up_to_register(offset).max(REGISTER_SIZE),
+        })
+    }
+}
+
+fn round_up_to_register(offset: usize) -> usize {
+    offset.div_ceil(REGISTER_SIZE) * REGISTER_SIZE
+}
+
+impl CBufferLayout {
+    /// Emits the matching HLSL declaration, bound to `register(b<slot>)`.
+    pub fn to_hlsl(&self, slot: u32) -> String {
+        let mut hlsl = format!("cbuffer {} : register(b{}) {{\n", self.name, slot);
+        for field in &self.fields {
+            hlsl.push_str(&format!("    {} {};\n", field.ty.hlsl_name(), field.name));
+        }
+        hlsl.push_str("};\n");
+        hlsl
+    }
+}
+
+/// A `#[repr(C)]` struct whose layout matches an HLSL cbuffer.
+/// Implemented through `constant_buffer!`.
+pub trait ConstantBuffer: Sized {
+    fn layout() -> Result<CBufferLayout, CBufferLayoutError>;
+}
+
+/// Declares a `#[repr(C)]` struct and implements `ConstantBuffer` for it,
+/// checking every field's Rust offset against HLSL packing.
+///
+/// ```ignore
+/// constant_buffer! {
+///     pub struct FrameConstants {
+///         pub world_view: Mat4,
+///         pub sun_direction: Vec3,
+///         pub time: f32,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! constant_buffer {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($field_vis:vis $field:ident : $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[repr(C)]
+        $vis struct $name {
+            $($field_vis $field: $ty),*
+        }
+
+        impl $crate::render_backend::cbuffer_layout::ConstantBuffer for $name {
+            fn layout() -> ::std::result::Result<
+                $crate::render_backend::cbuffer_layout::CBufferLayout,
+                $crate::render_backend::cbuffer_layout::CBufferLayoutError,
+            > {
+                use $crate::render_backend::cbuffer_layout::HlslField;
+
+                $crate::render_backend::cbuffer_layout::CBufferLayoutBuilder::new(stringify!($name))
+                    $(.checked_field(
+                        stringify!($field),
+                        <$ty as HlslField>::HLSL_TYPE,
+                        ::std::mem::offset_of!($name, $field),
+                    ))*
+                    .rust_size(::std::mem::size_of::<$name>())
+                    .build()
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLOAT: HlslType = HlslType::Vector(ScalarType::Float, 1);
+    const FLOAT2: HlslType = HlslType::Vector(ScalarType::Float, 2);
+    const FLOAT3: HlslType = HlslType::Vector(ScalarType::Float, 3);
+
+    crate::constant_buffer! {
+        struct Packed {
+            direction: Vec3,
+            time: f32,
+            transform: Mat4,
+            scale: Vec2,
+        }
+    }
+
+    crate::constant_buffer! {
+        struct Straddling {
+            scale: Vec2,
+            direction: Vec3,
+        }
+    }
+
+    fn offsets(layout: &CBufferLayout) -> Vec<usize> {
+        layout.fields.iter().map(|field| field.offset).collect()
+    }
+
+    #[test]
+    fn packs_into_registers() {
+        let layout = CBufferLayoutBuilder::new("Test")
+            .field("a", FLOAT3)
+            .field("b", FLOAT)
+            .field("c", FLOAT2)
+            .field("d", FLOAT3)
+            .field("e", FLOAT)
+            .build()
+            .unwrap();
+
+        // `d` would straddle the second register, so it moves to the third.
+        assert_eq!(offsets(&layout), [0, 12, 16, 32, 44]);
+        assert_eq!(layout.size, 48);
+    }
+
+    #[test]
+    fn matrices_start_a_register() {
+        let layout = CBufferLayoutBuilder::new("Test")
+            .field("a", FLOAT)
+            .field("m", HlslType::Float4x4)
+            .field("b", FLOAT)
+            .build()
+            .unwrap();
+
+        assert_eq!(offsets(&layout), [0, 16, 80]);
+        assert_eq!(layout.size, 96);
+    }
+
+    #[test]
+    fn empty_layout_is_one_register() {
+        assert_eq!(CBufferLayoutBuilder::new("Empty").build().unwrap().size, 16);
+    }
+
+    #[test]
+    fn checks_rust_offsets() {
+        let err = CBufferLayoutBuilder::new("Test")
+            .checked_field("a", FLOAT2, 0)
+            .checked_field("b", FLOAT3, 8)
+            .build()
+            .unwrap_err();
+
+        assert_eq!(
+            err,
+            CBufferLayoutError::OffsetMismatch {
+                field: "b".to_owned(),
+                rust_offset: 8,
+                hlsl_offset: 16,
+            }
+        );
+    }
+
+    #[test]
+    fn checks_rust_size() {
+        let err = CBufferLayoutBuilder::new("Test")
+            .field("a", FLOAT)
+            .rust_size(20)
+            .build()
+            .unwrap_err();
+
+        assert_eq!(
+            err,
+            CBufferLayoutError::SizeMismatch {
+                rust_size: 20,
+                hlsl_size: 16,
+            }
+        );
+        assert!(CBufferLayoutBuilder::new("Test")
+            .field("a", FLOAT)
+            .rust_size(16)
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn macro_checks_struct_layout() {
+        let layout = Packed::layout().unwrap();
+        assert_eq!(offsets(&layout), [0, 12, 16, 80]);
+        assert_eq!(layout.size, 96);
+
+        assert!(matches!(
+            Straddling::layout(),
+            Err(CBufferLayoutError::OffsetMismatch {
+                hlsl_offset: 16,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn emits_hlsl() {
+        assert_eq!(
+            Packed::layout().unwrap().to_hlsl(1),
+            "cbuffer Packed : register(b1) {\n    float3 direction;\n    float time;\n    \
+             float4x4 transform;\n    float2 scale;\n};\n"
+        );
+    }
+}
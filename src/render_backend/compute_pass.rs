@@ -0,0 +1,199 @@
+use windows::Win32::Graphics::Direct3D11::*;
+
+use crate::error::{EngineError, EngineResult};
+
+use super::backend::Backend;
+use super::gpu_buffer::GPUBuffer;
+use super::shader::{Shader, ShaderStage};
+use super::shader_library::ShaderHandle;
+use super::shader_reflection::ShaderReflection;
+
+/// Where a resource is bound: either an explicit register or the name it
+/// is declared with in the shader, which is resolved through reflection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Binding {
+    Slot(u32),
+    Name(String),
+}
+
+impl From<u32> for Binding {
+    fn from(slot: u32) -> Self {
+        Binding::Slot(slot)
+    }
+}
+
+impl From<&str> for Binding {
+    fn from(name: &str) -> Self {
+        Binding::Name(name.to_owned())
+    }
+}
+
+impl Binding {
+    fn resolve(&self, reflection: &ShaderReflection) -> EngineResult<u32> {
+        match self {
+            Binding::Slot(slot) => Ok(*slot),
+            Binding::Name(name) => reflection.register_of(name).ok_or_else(|| {
+                EngineError::Validation(format!("compute shader has no resource named {}", name))
+            }),
+        }
+    }
+}
+
+/// Number of thread groups needed to cover `thread_count` threads with
+/// groups of `group_size`, rounding up in every dimension.
+pub fn dispatch_group_count(thread_count: [u32; 3], group_size: [u32; 3]) -> [u32; 3] {
+    [0, 1, 2].map(|i| thread_count[i].div_ceil(group_size[i].max(1)))
+}
+
+pub struct ComputePass {
+    shader: ShaderHandle,
+    constant_buffers: Vec<(Binding, ID3D11Buffer)>,
+    shader_resources: Vec<(Binding, ID3D11ShaderResourceView)>,
+    unordered_access_views: Vec<(Binding, ID3D11UnorderedAccessView)>,
+    thread_count: [u32; 3],
+}
+
+impl ComputePass {
+    pub fn new(shader: Shader) -> EngineResult<ComputePass> {
+        Self::from_handle(ShaderHandle::new(shader)?)
+    }
+
+    /// Like `new`, but follows the handle when it is reloaded.
+    pub fn from_handle(shader: ShaderHandle) -> EngineResult<ComputePass> {
+        if shader.stage() != ShaderStage::Compute {
+            return Err(EngineError::Validation(format!(
+                "creating a compute pass from a {:?} shader",
+                shader.stage()
+            )));
+        }
+
+        Ok(ComputePass {
+            shader,
+            constant_buffers: Vec::new(),
+            shader_resources: Vec::new(),
+            unordered_access_views: Vec::new(),
+            thread_count: [1, 1, 1],
+        })
+    }
+
+    pub fn constant_buffer(mut self, binding: impl Into<Binding>, buffer: &GPUBuffer) -> Self {
+        self.constant_buffers
+            .push((binding.into(), buffer.buffer.clone()));
+
+        self
+    }
+
+    pub fn shader_resource(
+        mut self,
+        binding: impl Into<Binding>,
+        srv: ID3D11ShaderResourceView,
+    ) -> Self {
+        self.shader_resources.push((binding.into(), srv));
+
+        self
+    }
+
+    pub fn unordered_access(
+        mut self,
+        binding: impl Into<Binding>,
+        uav: ID3D11UnorderedAccessView,
+    ) -> Self {
+        self.unordered_access_views.push((binding.into(), uav));
+
+        self
+    }
+
+    /// Total number of threads to run. The group count passed to `Dispatch`
+    /// is derived from the shader's `numthreads`.
+    pub fn thread_count(mut self, thread_count: [u32; 3]) -> Self {
+        self.thread_count = thread_count;
+
+        self
+    }
+
+    pub fn dispatch_size(&self) -> EngineResult<[u32; 3]> {
+        let group_size = self
+            .shader
+            .get()
+            .reflection
+            .thread_group_size
+            .ok_or_else(|| {
+                EngineError::Validation("compute shader declares no numthreads".to_owned())
+            })?;
+
+        Ok(dispatch_group_count(self.thread_count, group_size))
+    }
+
+    pub fn execute(&self, backend: &Backend) -> EngineResult<()> {
+        let [x, y, z] = self.dispatch_size()?;
+
+        let loaded = self.shader.get();
+        let constant_buffers = resolve(&loaded.reflection, &self.constant_buffers)?;
+        let shader_resources = resolve(&loaded.reflection, &self.shader_resources)?;
+        let unordered_access_views = resolve(&loaded.reflection, &self.unordered_access_views)?;
+
+        if let Shader::Compute(shader, _) = &loaded.shader {
+            unsafe {
+                backend
+                    .device_context
+                    .CSSetShader(shader, std::ptr::null(), 0);
+
+                for (slot, buffer) in &constant_buffers {
+                    backend
+                        .device_context
+                        .CSSetConstantBuffers(*slot, 1, &Some(buffer.clone()));
+                }
+
+                for (slot, srv) in &shader_resources {
+                    backend
+                        .device_context
+                        .CSSetShaderResources(*slot, 1, &Some(srv.clone()));
+                }
+
+                for (slot, uav) in &unordered_access_views {
+                    backend.device_context.CSSetUnorderedAccessViews(
+                        *slot,
+                        1,
+                        &Some(uav.clone()),
+                        std::ptr::null(),
+                    );
+                }
+
+                backend.device_context.Dispatch(x, y, z);
+
+                backend
+                    .device_context
+                    .CSSetShader(None, std::ptr::null(), 0);
+
+                for (slot, _) in &constant_buffers {
+                    backend.device_context.CSSetConstantBuffers(*slot, 1, &None);
+                }
+
+                for (slot, _) in &shader_resources {
+                    backend.device_context.CSSetShaderResources(*slot, 1, &None);
+                }
+
+                for (slot, _) in &unordered_access_views {
+                    backend.device_context.CSSetUnorderedAccessViews(
+                        *slot,
+                        1,
+                        &None,
+                        std::ptr::null(),
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn resolve<T: Clone>(
+    reflection: &ShaderReflection,
+    bindings: &[(Binding, T)],
+) -> EngineResult<Vec<(u32, T)>> {
+    bindings
+        .iter()
+        .map(|(binding, resource)| Ok((binding.resolve(reflection)?, resource.clone())))
+        .collect()
+}
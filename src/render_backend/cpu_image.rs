@@ -0,0 +1,688 @@
+use std::io::Cursor;
+use std::path::Path;
+
+use half::f16;
+use image::codecs::hdr::{HdrDecoder, HdrEncoder};
+use image::io::Reader as ImageReader;
+use image::{Rgb, RgbaImage};
+use windows::Win32::Graphics::Dxgi::Common::*;
+
+use crate::error::{EngineError, EngineResult};
+use crate::vfs;
+
+use super::format_info::{format_name, FormatInfo};
+
+/// Pixels of a `CpuImage`, always expanded to RGBA. Missing channels read
+/// as 0, except alpha, which reads as 1, like sampling does on the GPU.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CpuPixels {
+    Rgba8(Vec<[u8; 4]>),
+    Rgba16F(Vec<[f16; 4]>),
+    Rgba32F(Vec<[f32; 4]>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelType {
+    Rgba8,
+    Rgba16F,
+    Rgba32F,
+}
+
+/// A 2D image in CPU memory, e.g. a mip or slice read back from a texture.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CpuImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: CpuPixels,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Layout {
+    Unorm8 { channels: usize, bgra: bool },
+    Unorm16 { channels: usize },
+    Float16 { channels: usize },
+    Float32 { channels: usize },
+    R10G10B10A2,
+    R11G11B10,
+}
+
+/// How texels of a `DXGI_FORMAT` are expanded into a `CpuImage`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReadbackFormat {
+    pub pixel_type: PixelType,
+    pub bytes_per_pixel: usize,
+    layout: Layout,
+}
+
+impl ReadbackFormat {
+    /// `None` for formats that can't be read back texel by texel, e.g.
+    /// block compressed or depth formats.
+    pub fn of(format: DXGI_FORMAT) -> Option<ReadbackFormat> {
+        let (pixel_type, layout) = match format {
+            DXGI_FORMAT_R8_UNORM => (
+                PixelType::Rgba8,
+                Layout::Unorm8 {
+                    channels: 1,
+                    bgra: false,
+                },
+            ),
+            DXGI_FORMAT_R8G8_UNORM => (
+                PixelType::Rgba8,
+                Layout::Unorm8 {
+                    channels: 2,
+                    bgra: false,
+                },
+            ),
+            DXGI_FORMAT_R8G8B8A8_UNORM | DXGI_FORMAT_R8G8B8A8_UNORM_SRGB => (
+                PixelType::Rgba8,
+                Layout::Unorm8 {
+                    channels: 4,
+                    bgra: false,
+                },
+            ),
+            DXGI_FORMAT_B8G8R8A8_UNORM | DXGI_FORMAT_B8G8R8A8_UNORM_SRGB => (
+                PixelType::Rgba8,
+                Layout::Unorm8 {
+                    channels: 4,
+                    bgra: true,
+                },
+            ),
+            DXGI_FORMAT_R16_UNORM => (PixelType::Rgba32F, Layout::Unorm16 { channels: 1 }),
+            DXGI_FORMAT_R16G16_UNORM => (PixelType::Rgba32F, Layout::Unorm16 { channels: 2 }),
+            DXGI_FORMAT_R16G16B16A16_UNORM => (PixelType::Rgba32F, Layout::Unorm16 { channels: 4 }),
+            DXGI_FORMAT_R16_FLOAT => (PixelType::Rgba16F, Layout::Float16 { channels: 1 }),
+            DXGI_FORMAT_R16G16_FLOAT => (PixelType::Rgba16F, Layout::Float16 { channels: 2 }),
+            DXGI_FORMAT_R16G16B16A16_FLOAT => (PixelType::Rgba16F, Layout::Float16 { channels: 4 }),
+            DXGI_FORMAT_R32_FLOAT => (PixelType::Rgba32F, Layout::Float32 { channels: 1 }),
+            DXGI_FORMAT_R32G32_FLOAT => (PixelType::Rgba32F, Layout::Float32 { channels: 2 }),
+            DXGI_FORMAT_R32G32B32_FLOAT => (PixelType::Rgba32F, Layout::Float32 { channels: 3 }),
+            DXGI_FORMAT_R32G32B32A32_FLOAT => (PixelType::Rgba32F, Layout::Float32 { channels: 4 }),
+            DXGI_FORMAT_R10G10B10A2_UNORM => (PixelType::Rgba32F, Layout::R10G10B10A2),
+            // 11 and 10 bit floats fit into half floats exactly.
+            DXGI_FORMAT_R11G11B10_FLOAT => (PixelType::Rgba16F, Layout::R11G11B10),
+            _ => return None,
+        };
+
+        let bytes_per_pixel = FormatInfo::of(format)?.bytes;
+
+        Some(ReadbackFormat {
+            pixel_type,
+            bytes_per_pixel,
+            layout,
+        })
+    }
+}
+
+/// The first RGBA layer of an EXR file, alpha 1 if it has none.
+fn load_exr(bytes: Vec<u8>) -> exr::error::Result<CpuImage> {
+    use exr::prelude::traits::*;
+
+    let image = read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .rgba_channels(
+            |size, _| CpuImage {
+                width: size.width() as u32,
+                height: size.height() as u32,
+                pixels: CpuPixels::Rgba32F(vec![[0.0; 4]; size.area()]),
+            },
+            |image: &mut CpuImage, position, (r, g, b, a): (f32, f32, f32, f32)| {
+                let index = position.y() * image.width as usize + position.x();
+                if let CpuPixels::Rgba32F(pixels) = &mut image.pixels {
+                    pixels[index] = [r, g, b, a];
+                }
+            },
+        )
+        .first_valid_layer()
+        .all_attributes()
+        .from_buffered(Cursor::new(bytes))?;
+
+    Ok(image.layer_data.channel_data.pixels)
+}
+
+fn u16_at(bytes: &[u8], index: usize) -> u16 {
+    u16::from_le_bytes([bytes[2 * index], bytes[2 * index + 1]])
+}
+
+fn f32_at(bytes: &[u8], index: usize) -> f32 {
+    f32::from_le_bytes(bytes[4 * index..4 * index + 4].try_into().unwrap())
+}
+
+/// Decodes an unsigned float with a 5 bit exponent, like the channels of
+/// `R11G11B10_FLOAT`.
+fn small_float(bits: u32, mantissa_bits: u32) -> f32 {
+    let mantissa = bits & ((1 << mantissa_bits) - 1);
+    let exponent = bits >> mantissa_bits;
+    let fraction = mantissa as f32 / (1 << mantissa_bits) as f32;
+
+    match exponent {
+        0 => fraction * 2f32.powi(-14),
+        31 if mantissa == 0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1.0 + fraction) * 2f32.powi(exponent as i32 - 15),
+    }
+}
+
+/// Expands tightly packed or padded rows of `format` into a `CpuImage`.
+/// `row_pitch` is the distance between rows in bytes.
+pub fn decode_image(
+    format: DXGI_FORMAT,
+    width: u32,
+    height: u32,
+    row_pitch: usize,
+    data: &[u8],
+) -> EngineResult<CpuImage> {
+    let readback = ReadbackFormat::of(format).ok_or_else(|| {
+        EngineError::Validation(format!("{} can't be read back", format_name(format)))
+    })?;
+
+    let row_bytes = width as usize * readback.bytes_per_pixel;
+    if height > 0 && data.len() < row_pitch * (height as usize - 1) + row_bytes {
+        return Err(EngineError::Validation(format!(
+            "{} bytes are too few for a {}x{} image with a row pitch of {}",
+            data.len(),
+            width,
+            height,
+            row_pitch
+        )));
+    }
+
+    let texels = (0..height as usize).flat_map(|y| {
+        let row = &data[y * row_pitch..y * row_pitch + row_bytes];
+        row.chunks_exact(readback.bytes_per_pixel)
+    });
+
+    let pixels = match readback.layout {
+        Layout::Unorm8 { channels, bgra } => CpuPixels::Rgba8(
+            texels
+                .map(|texel| {
+                    let mut pixel = [0, 0, 0, 255];
+                    pixel[..channels].copy_from_slice(texel);
+                    if bgra {
+                        pixel.swap(0, 2);
+                    }
+                    pixel
+                })
+                .collect(),
+        ),
+        Layout::Unorm16 { channels } => CpuPixels::Rgba32F(
+            texels
+                .map(|texel| {
+                    let mut pixel = [0.0, 0.0, 0.0, 1.0];
+                    for (c, value) in pixel.iter_mut().take(channels).enumerate() {
+                        *value = u16_at(texel, c) as f32 / u16::MAX as f32;
+                    }
+                    pixel
+                })
+                .collect(),
+        ),
+        Layout::Float16 { channels } => CpuPixels::Rgba16F(
+            texels
+                .map(|texel| {
+                    let mut pixel = [f16::ZERO, f16::ZERO, f16::ZERO, f16::ONE];
+                    for (c, value) in pixel.iter_mut().take(channels).enumerate() {
+                        *value = f16::from_bits(u16_at(texel, c));
+                    }
+                    pixel
+                })
+                .collect(),
+        ),
+        Layout::Float32 { channels } => CpuPixels::Rgba32F(
+            texels
+                .map(|texel| {
+                    let mut pixel = [0.0, 0.0, 0.0, 1.0];
+                    for (c, value) in pixel.iter_mut().take(channels).enumerate() {
+                        *value = f32_at(texel, c);
+                    }
+                    pixel
+                })
+                .collect(),
+        ),
+        Layout::R10G10B10A2 => CpuPixels::Rgba32F(
+            texels
+                .map(|texel| {
+                    let bits = u32::from_le_bytes(texel.try_into().unwrap());
+                    [
+                        (bits & 0x3FF) as f32 / 1023.0,
+                        ((bits >> 10) & 0x3FF) as f32 / 1023.0,
+                        ((bits >> 20) & 0x3FF) as f32 / 1023.0,
+                        (bits >> 30) as f32 / 3.0,
+                    ]
+                })
+                .collect(),
+        ),
+        Layout::R11G11B10 => CpuPixels::Rgba16F(
+            texels
+                .map(|texel| {
+                    let bits = u32::from_le_bytes(texel.try_into().unwrap());
+                    [
+                        f16::from_f32(small_float(bits & 0x7FF, 6)),
+                        f16::from_f32(small_float((bits >> 11) & 0x7FF, 6)),
+                        f16::from_f32(small_float(bits >> 22, 5)),
+                        f16::ONE,
+                    ]
+                })
+                .collect(),
+        ),
+    };
+
+    Ok(CpuImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+impl CpuImage {
+    /// Reads an image through the VFS, see `decode`.
+    pub fn load(file: &str) -> EngineResult<CpuImage> {
+        let bytes = vfs::read(file).map_err(|source| EngineError::Io {
+            path: file.to_owned(),
+            source,
+        })?;
+
+        CpuImage::decode(file, bytes)
+    }
+
+    /// Decodes the contents of `file`. `.hdr` and `.exr` files keep their
+    /// range as `Rgba32F`, anything else the `image` crate can decode becomes
+    /// RGBA8.
+    pub fn decode(file: &str, bytes: Vec<u8>) -> EngineResult<CpuImage> {
+        let io_error = |source| EngineError::Io {
+            path: file.to_owned(),
+            source,
+        };
+        let decode_error = |source| EngineError::ImageDecode {
+            path: file.to_owned(),
+            source,
+        };
+
+        let extension = file.rsplit('.').next().map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("hdr") => {
+                let decoder = HdrDecoder::new(Cursor::new(bytes)).map_err(decode_error)?;
+                let metadata = decoder.metadata();
+                let pixels = decoder.read_image_hdr().map_err(decode_error)?;
+
+                Ok(CpuImage {
+                    width: metadata.width,
+                    height: metadata.height,
+                    pixels: CpuPixels::Rgba32F(
+                        pixels
+                            .iter()
+                            .map(|Rgb([r, g, b])| [*r, *g, *b, 1.0])
+                            .collect(),
+                    ),
+                })
+            }
+            Some("exr") => load_exr(bytes).map_err(|err| EngineError::TextureLoad {
+                path: file.to_owned(),
+                message: err.to_string(),
+            }),
+            _ => {
+                let img = ImageReader::new(Cursor::new(bytes))
+                    .with_guessed_format()
+                    .map_err(io_error)?
+                    .decode()
+                    .map_err(decode_error)?
+                    .to_rgba8();
+
+                Ok(CpuImage {
+                    width: img.width(),
+                    height: img.height(),
+                    pixels: CpuPixels::Rgba8(img.pixels().map(|pixel| pixel.0).collect()),
+                })
+            }
+        }
+    }
+
+    pub fn pixel_type(&self) -> PixelType {
+        match self.pixels {
+            CpuPixels::Rgba8(_) => PixelType::Rgba8,
+            CpuPixels::Rgba16F(_) => PixelType::Rgba16F,
+            CpuPixels::Rgba32F(_) => PixelType::Rgba32F,
+        }
+    }
+
+    /// Float pixels are clamped to [0, 1]. No tone mapping or gamma is
+    /// applied, so sRGB textures stay sRGB and linear ones linear.
+    pub fn to_rgba8(&self) -> Vec<[u8; 4]> {
+        let quantize = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+
+        match &self.pixels {
+            CpuPixels::Rgba8(pixels) => pixels.clone(),
+            CpuPixels::Rgba16F(pixels) => pixels
+                .iter()
+                .map(|pixel| pixel.map(|value| quantize(value.to_f32())))
+                .collect(),
+            CpuPixels::Rgba32F(pixels) => pixels.iter().map(|pixel| pixel.map(quantize)).collect(),
+        }
+    }
+
+    /// The inverse of `to_rgba32f`, quantizing to `pixel_type`.
+    pub fn from_rgba32f(
+        width: u32,
+        height: u32,
+        pixel_type: PixelType,
+        pixels: Vec<[f32; 4]>,
+    ) -> CpuImage {
+        let pixels = match pixel_type {
+            PixelType::Rgba8 => CpuPixels::Rgba8(
+                pixels
+                    .iter()
+                    .map(|pixel| pixel.map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8))
+                    .collect(),
+            ),
+            PixelType::Rgba16F => CpuPixels::Rgba16F(
+                pixels
+                    .iter()
+                    .map(|pixel| pixel.map(f16::from_f32))
+                    .collect(),
+            ),
+            PixelType::Rgba32F => CpuPixels::Rgba32F(pixels),
+        };
+
+        CpuImage {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// The pixels as tightly packed `R8G8B8A8`, `R16G16B16A16_FLOAT` or
+    /// `R32G32B32A32_FLOAT` texels, ready to upload.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match &self.pixels {
+            CpuPixels::Rgba8(pixels) => pixels.iter().flatten().copied().collect(),
+            CpuPixels::Rgba16F(pixels) => pixels
+                .iter()
+                .flatten()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+            CpuPixels::Rgba32F(pixels) => pixels
+                .iter()
+                .flatten()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+        }
+    }
+
+    /// 8-bit pixels map to [0, 1].
+    pub fn to_rgba32f(&self) -> Vec<[f32; 4]> {
+        match &self.pixels {
+            CpuPixels::Rgba8(pixels) => pixels
+                .iter()
+                .map(|pixel| pixel.map(|value| value as f32 / 255.0))
+                .collect(),
+            CpuPixels::Rgba16F(pixels) => {
+                pixels.iter().map(|pixel| pixel.map(f16::to_f32)).collect()
+            }
+            CpuPixels::Rgba32F(pixels) => pixels.clone(),
+        }
+    }
+
+    /// Writes a `.png`, `.hdr` or `.exr` file, picked by the extension.
+    /// PNG clamps float images; HDR drops alpha.
+    pub fn save(&self, path: impl AsRef<Path>) -> EngineResult<()> {
+        let path = path.as_ref();
+        let path_name = path.display().to_string();
+        let encode_error = |message: String| EngineError::ImageEncode {
+            path: path_name.clone(),
+            message,
+        };
+        let io_error = |source| EngineError::Io {
+            path: path_name.clone(),
+            source,
+        };
+
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+
+        match extension.as_deref() {
+            Some("png") => {
+                let bytes = self.to_rgba8().concat();
+                RgbaImage::from_raw(self.width, self.height, bytes)
+                    .ok_or_else(|| encode_error("pixel count doesn't match the size".to_owned()))?
+                    .save(path)
+                    .map_err(|err| encode_error(err.to_string()))
+            }
+            Some("hdr") => {
+                let pixels: Vec<Rgb<f32>> = self
+                    .to_rgba32f()
+                    .into_iter()
+                    .map(|[r, g, b, _]| Rgb([r, g, b]))
+                    .collect();
+                let file = std::fs::File::create(path).map_err(io_error)?;
+                HdrEncoder::new(std::io::BufWriter::new(file))
+                    .encode(&pixels, self.width as usize, self.height as usize)
+                    .map_err(|err| encode_error(err.to_string()))
+            }
+            Some("exr") => {
+                let width = self.width as usize;
+                let result = match &self.pixels {
+                    CpuPixels::Rgba16F(pixels) => {
+                        exr::prelude::write_rgba_file(path, width, self.height as usize, |x, y| {
+                            let [r, g, b, a] = pixels[y * width + x];
+                            (r, g, b, a)
+                        })
+                    }
+                    _ => {
+                        let pixels = self.to_rgba32f();
+                        exr::prelude::write_rgba_file(path, width, self.height as usize, |x, y| {
+                            let [r, g, b, a] = pixels[y * width + x];
+                            (r, g, b, a)
+                        })
+                    }
+                };
+                result.map_err(|err| encode_error(err.to_string()))
+            }
+            _ => Err(encode_error(
+                "unknown extension, expected png, hdr or exr".to_owned(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One texel of `format` decoded as floats.
+    fn decode_texel(format: DXGI_FORMAT, texel: &[u8]) -> [f32; 4] {
+        let image = decode_image(format, 1, 1, texel.len(), texel).unwrap();
+        assert_eq!(
+            image.pixel_type(),
+            ReadbackFormat::of(format).unwrap().pixel_type
+        );
+        image.to_rgba32f()[0]
+    }
+
+    fn assert_near(actual: [f32; 4], expected: [f32; 4]) {
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(
+                (actual - expected).abs() < 1e-3,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn describes_readback_formats() {
+        let formats = [
+            (DXGI_FORMAT_R8_UNORM, PixelType::Rgba8, 1),
+            (DXGI_FORMAT_R8G8_UNORM, PixelType::Rgba8, 2),
+            (DXGI_FORMAT_R8G8B8A8_UNORM, PixelType::Rgba8, 4),
+            (DXGI_FORMAT_R8G8B8A8_UNORM_SRGB, PixelType::Rgba8, 4),
+            (DXGI_FORMAT_B8G8R8A8_UNORM, PixelType::Rgba8, 4),
+            (DXGI_FORMAT_B8G8R8A8_UNORM_SRGB, PixelType::Rgba8, 4),
+            (DXGI_FORMAT_R16_UNORM, PixelType::Rgba32F, 2),
+            (DXGI_FORMAT_R16G16_UNORM, PixelType::Rgba32F, 4),
+            (DXGI_FORMAT_R16G16B16A16_UNORM, PixelType::Rgba32F, 8),
+            (DXGI_FORMAT_R16_FLOAT, PixelType::Rgba16F, 2),
+            (DXGI_FORMAT_R16G16_FLOAT, PixelType::Rgba16F, 4),
+            (DXGI_FORMAT_R16G16B16A16_FLOAT, PixelType::Rgba16F, 8),
+            (DXGI_FORMAT_R32_FLOAT, PixelType::Rgba32F, 4),
+            (DXGI_FORMAT_R32G32_FLOAT, PixelType::Rgba32F, 8),
+            (DXGI_FORMAT_R32G32B32_FLOAT, PixelType::Rgba32F, 12),
+            (DXGI_FORMAT_R32G32B32A32_FLOAT, PixelType::Rgba32F, 16),
+            (DXGI_FORMAT_R10G10B10A2_UNORM, PixelType::Rgba32F, 4),
+            (DXGI_FORMAT_R11G11B10_FLOAT, PixelType::Rgba16F, 4),
+        ];
+
+        for (format, pixel_type, bytes_per_pixel) in formats {
+            let readback = ReadbackFormat::of(format).unwrap();
+            assert_eq!(readback.pixel_type, pixel_type, "{}", format_name(format));
+            assert_eq!(
+                readback.bytes_per_pixel,
+                bytes_per_pixel,
+                "{}",
+                format_name(format)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_formats_without_texels() {
+        for format in [
+            DXGI_FORMAT_BC1_UNORM,
+            DXGI_FORMAT_BC7_UNORM_SRGB,
+            DXGI_FORMAT_D32_FLOAT,
+            DXGI_FORMAT_D24_UNORM_S8_UINT,
+            DXGI_FORMAT_R8G8B8A8_UINT,
+            DXGI_FORMAT_UNKNOWN,
+        ] {
+            assert_eq!(ReadbackFormat::of(format), None);
+            assert!(decode_image(format, 1, 1, 16, &[0; 16]).is_err());
+        }
+    }
+
+    #[test]
+    fn decodes_8_bit_formats() {
+        let image = decode_image(DXGI_FORMAT_R8_UNORM, 1, 1, 1, &[51]).unwrap();
+        assert_eq!(image.pixels, CpuPixels::Rgba8(vec![[51, 0, 0, 255]]));
+
+        let image = decode_image(DXGI_FORMAT_R8G8_UNORM, 1, 1, 2, &[51, 102]).unwrap();
+        assert_eq!(image.pixels, CpuPixels::Rgba8(vec![[51, 102, 0, 255]]));
+
+        for format in [DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_FORMAT_R8G8B8A8_UNORM_SRGB] {
+            let image = decode_image(format, 1, 1, 4, &[1, 2, 3, 4]).unwrap();
+            assert_eq!(image.pixels, CpuPixels::Rgba8(vec![[1, 2, 3, 4]]));
+        }
+        for format in [DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_B8G8R8A8_UNORM_SRGB] {
+            let image = decode_image(format, 1, 1, 4, &[1, 2, 3, 4]).unwrap();
+            assert_eq!(image.pixels, CpuPixels::Rgba8(vec![[3, 2, 1, 4]]));
+        }
+    }
+
+    #[test]
+    fn decodes_16_bit_formats() {
+        let unorm: Vec<u8> = [0u16, 0x8000, 0xFFFF, 0x4000]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        let half = 0x8000 as f32 / 65535.0;
+        assert_near(
+            decode_texel(DXGI_FORMAT_R16_UNORM, &unorm[..2]),
+            [0.0, 0.0, 0.0, 1.0],
+        );
+        assert_near(
+            decode_texel(DXGI_FORMAT_R16G16_UNORM, &unorm[..4]),
+            [0.0, half, 0.0, 1.0],
+        );
+        assert_near(
+            decode_texel(DXGI_FORMAT_R16G16B16A16_UNORM, &unorm),
+            [0.0, half, 1.0, 0x4000 as f32 / 65535.0],
+        );
+
+        let float: Vec<u8> = [-2.0f32, 0.5, 1024.0, 0.25]
+            .iter()
+            .flat_map(|&value| f16::from_f32(value).to_le_bytes())
+            .collect();
+        assert_near(
+            decode_texel(DXGI_FORMAT_R16_FLOAT, &float[..2]),
+            [-2.0, 0.0, 0.0, 1.0],
+        );
+        assert_near(
+            decode_texel(DXGI_FORMAT_R16G16_FLOAT, &float[..4]),
+            [-2.0, 0.5, 0.0, 1.0],
+        );
+        assert_near(
+            decode_texel(DXGI_FORMAT_R16G16B16A16_FLOAT, &float),
+            [-2.0, 0.5, 1024.0, 0.25],
+        );
+    }
+
+    #[test]
+    fn decodes_32_bit_float_formats() {
+        let float: Vec<u8> = [-2.0f32, 0.5, 1e6, 0.25]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        assert_eq!(
+            decode_texel(DXGI_FORMAT_R32_FLOAT, &float[..4]),
+            [-2.0, 0.0, 0.0, 1.0]
+        );
+        assert_eq!(
+            decode_texel(DXGI_FORMAT_R32G32_FLOAT, &float[..8]),
+            [-2.0, 0.5, 0.0, 1.0]
+        );
+        assert_eq!(
+            decode_texel(DXGI_FORMAT_R32G32B32_FLOAT, &float[..12]),
+            [-2.0, 0.5, 1e6, 1.0]
+        );
+        assert_eq!(
+            decode_texel(DXGI_FORMAT_R32G32B32A32_FLOAT, &float),
+            [-2.0, 0.5, 1e6, 0.25]
+        );
+    }
+
+    #[test]
+    fn decodes_packed_formats() {
+        let bits: u32 = 1023 | (511 << 10) | (2 << 30);
+        assert_near(
+            decode_texel(DXGI_FORMAT_R10G10B10A2_UNORM, &bits.to_le_bytes()),
+            [1.0, 511.0 / 1023.0, 0.0, 2.0 / 3.0],
+        );
+
+        // 1.0 is exponent 15 with an empty mantissa, 0.5 exponent 14, and
+        // 1.5 exponent 15 with the top mantissa bit of the 5-bit blue channel.
+        let bits: u32 = (15 << 6) | ((14 << 6) << 11) | (((15 << 5) | 16) << 22);
+        assert_eq!(
+            decode_texel(DXGI_FORMAT_R11G11B10_FLOAT, &bits.to_le_bytes()),
+            [1.0, 0.5, 1.5, 1.0]
+        );
+
+        let special: u32 = (31 << 6) | (1 << 11) | ((31 << 5) | 1) << 22;
+        let [r, g, b, _] = decode_texel(DXGI_FORMAT_R11G11B10_FLOAT, &special.to_le_bytes());
+        assert_eq!(r, f32::INFINITY);
+        assert!(g > 0.0 && g < 1e-4, "denormal {}", g);
+        assert!(b.is_nan());
+    }
+
+    #[test]
+    fn skips_row_padding() {
+        // Two rows of two texels, each row padded to 12 bytes.
+        let mut data = vec![0xEE; 12 + 8];
+        data[..8].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        data[12..20].copy_from_slice(&[9, 10, 11, 12, 13, 14, 15, 16]);
+
+        let image = decode_image(DXGI_FORMAT_R8G8B8A8_UNORM, 2, 2, 12, &data).unwrap();
+        assert_eq!(
+            image.pixels,
+            CpuPixels::Rgba8(vec![
+                [1, 2, 3, 4],
+                [5, 6, 7, 8],
+                [9, 10, 11, 12],
+                [13, 14, 15, 16]
+            ])
+        );
+
+        // The last row needn't be padded, but must be complete.
+        assert!(decode_image(DXGI_FORMAT_R8G8B8A8_UNORM, 2, 2, 12, &data[..19]).is_err());
+        assert!(decode_image(DXGI_FORMAT_R8G8B8A8_UNORM, 0, 0, 0, &[]).is_ok());
+    }
+}
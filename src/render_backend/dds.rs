@@ -0,0 +1,772 @@
+use std::path::Path;
+
+use windows::Win32::Graphics::Dxgi::Common::*;
+
+use crate::error::{EngineError, EngineResult};
+use crate::vfs;
+
+use super::backend::Backend;
+use super::format_info::FormatInfo;
+use super::texture_data::{LoadedTexture, TextureData, TextureDimension};
+
+const MAGIC: &[u8; 4] = b"DDS ";
+const HEADER_SIZE: usize = 124;
+const DX10_HEADER_SIZE: usize = 20;
+
+const DDSD_CAPS: u32 = 0x1;
+const DDSD_HEIGHT: u32 = 0x2;
+const DDSD_WIDTH: u32 = 0x4;
+const DDSD_PITCH: u32 = 0x8;
+const DDSD_PIXELFORMAT: u32 = 0x1000;
+const DDSD_MIPMAPCOUNT: u32 = 0x2_0000;
+const DDSD_LINEARSIZE: u32 = 0x8_0000;
+const DDSD_DEPTH: u32 = 0x80_0000;
+
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_ALPHA: u32 = 0x2;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDPF_LUMINANCE: u32 = 0x2_0000;
+const DDPF_BUMPDUDV: u32 = 0x8_0000;
+
+const DDSCAPS_COMPLEX: u32 = 0x8;
+const DDSCAPS_TEXTURE: u32 = 0x1000;
+const DDSCAPS_MIPMAP: u32 = 0x40_0000;
+
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_CUBEMAP_ALLFACES: u32 = 0xfc00;
+const DDSCAPS2_VOLUME: u32 = 0x20_0000;
+
+const DIMENSION_TEXTURE1D: u32 = 2;
+const DIMENSION_TEXTURE2D: u32 = 3;
+const DIMENSION_TEXTURE3D: u32 = 4;
+const DX10_MISC_TEXTURECUBE: u32 = 0x4;
+
+/// 10:10:10:2 with red in the high bits, which DXGI has no format for.
+const A2R10G10B10_MASKS: [u32; 4] = [0x3ff0_0000, 0xf_fc00, 0x3ff, 0xc000_0000];
+
+/// The pixel format block of the legacy header.
+struct PixelFormat {
+    flags: u32,
+    four_cc: [u8; 4],
+    bit_count: u32,
+    masks: [u32; 4],
+}
+
+/// Reads a DDS file through the VFS and creates a texture of the type it
+/// describes.
+pub fn load(backend: &Backend, path: &str) -> EngineResult<LoadedTexture> {
+    let bytes = vfs::read(path).map_err(|source| EngineError::Io {
+        path: path.to_owned(),
+        source,
+    })?;
+    let data = parse(&bytes).map_err(|message| EngineError::TextureLoad {
+        path: path.to_owned(),
+        message,
+    })?;
+
+    data.create(backend)
+}
+
+/// Parses a whole DDS file, with either the legacy header or the DX10
+/// extension. The texture data is copied out unchanged, so block compressed
+/// and float textures stay that way.
+pub fn parse(bytes: &[u8]) -> Result<TextureData, String> {
+    if bytes.len() < 4 + HEADER_SIZE || &bytes[..4] != MAGIC {
+        return Err("not a DDS file".to_owned());
+    }
+
+    let header = &bytes[4..4 + HEADER_SIZE];
+    if read_u32(header, 0) as usize != HEADER_SIZE {
+        return Err(format!(
+            "header size is {}, expected {}",
+            read_u32(header, 0),
+            HEADER_SIZE
+        ));
+    }
+
+    let flags = read_u32(header, 4);
+    let height = read_u32(header, 8);
+    let width = read_u32(header, 12);
+    let depth = read_u32(header, 20);
+    let mip_count = read_u32(header, 24);
+    let pixel_format = PixelFormat {
+        flags: read_u32(header, 76),
+        four_cc: header[80..84].try_into().unwrap(),
+        bit_count: read_u32(header, 84),
+        masks: [
+            read_u32(header, 88),
+            read_u32(header, 92),
+            read_u32(header, 96),
+            read_u32(header, 100),
+        ],
+    };
+    let caps2 = read_u32(header, 108);
+
+    let mip_levels = if flags & DDSD_MIPMAPCOUNT != 0 {
+        mip_count.max(1)
+    } else {
+        1
+    };
+
+    let mut data_offset = 4 + HEADER_SIZE;
+    let mut texture = TextureData {
+        format: DXGI_FORMAT_UNKNOWN,
+        dimension: TextureDimension::Texture2D,
+        width,
+        height: height.max(1),
+        depth: 1,
+        mip_levels,
+        array_size: 1,
+        cubemap: false,
+        data: Vec::new(),
+    };
+    let mut swap_red_blue = false;
+
+    if pixel_format.flags & DDPF_FOURCC != 0 && &pixel_format.four_cc == b"DX10" {
+        let dx10 = bytes
+            .get(data_offset..data_offset + DX10_HEADER_SIZE)
+            .ok_or("DX10 header is truncated")?;
+        data_offset += DX10_HEADER_SIZE;
+
+        texture.format = read_u32(dx10, 0);
+        let dimension = read_u32(dx10, 4);
+        let misc_flag = read_u32(dx10, 8);
+        let array_size = read_u32(dx10, 12);
+        if array_size == 0 {
+            return Err("array size is 0".to_owned());
+        }
+
+        match dimension {
+            // Loaded as 2D textures one texel high.
+            DIMENSION_TEXTURE1D => {
+                texture.height = 1;
+                texture.array_size = array_size;
+            }
+            DIMENSION_TEXTURE2D if misc_flag & DX10_MISC_TEXTURECUBE != 0 => {
+                texture.array_size = array_size
+                    .checked_mul(6)
+                    .ok_or_else(|| format!("{} cubes is too many", array_size))?;
+                texture.cubemap = true;
+            }
+            DIMENSION_TEXTURE2D => texture.array_size = array_size,
+            DIMENSION_TEXTURE3D => {
+                if array_size != 1 {
+                    return Err("3D textures can't be arrays".to_owned());
+                }
+                texture.dimension = TextureDimension::Texture3D;
+                texture.depth = depth.max(1);
+            }
+            dimension => return Err(format!("unknown resource dimension {}", dimension)),
+        }
+    } else {
+        texture.format = legacy_format(&pixel_format)?;
+        swap_red_blue = pixel_format.flags & DDPF_FOURCC == 0
+            && pixel_format.bit_count == 32
+            && pixel_format.masks == A2R10G10B10_MASKS;
+
+        if caps2 & DDSCAPS2_CUBEMAP != 0 {
+            if caps2 & DDSCAPS2_CUBEMAP_ALLFACES != DDSCAPS2_CUBEMAP_ALLFACES {
+                return Err("cubemaps without all six faces are not supported".to_owned());
+            }
+            texture.array_size = 6;
+            texture.cubemap = true;
+        } else if caps2 & DDSCAPS2_VOLUME != 0 && flags & DDSD_DEPTH != 0 {
+            texture.dimension = TextureDimension::Texture3D;
+            texture.depth = depth.max(1);
+        }
+    }
+
+    if width == 0 {
+        return Err("width is 0".to_owned());
+    }
+    if texture.cubemap && width != height {
+        return Err(format!(
+            "cubemap faces are {}x{}, not square",
+            width, height
+        ));
+    }
+    texture.check_limits()?;
+    texture.data = bytes[data_offset..].to_vec();
+    let subresources = texture.subresources()?;
+    // Writers may pad the end of the file, the texture only needs its own bytes.
+    if let Some(last) = subresources.last() {
+        let end = last.offset + last.slice_pitch * last.size[2] as usize;
+        texture.data.truncate(end);
+    }
+    if swap_red_blue {
+        for texel in texture.data.chunks_exact_mut(4) {
+            let bits = u32::from_le_bytes((&*texel).try_into().unwrap());
+            let swapped = (bits & 0xc00f_fc00) | ((bits & 0x3ff) << 20) | ((bits >> 20) & 0x3ff);
+            texel.copy_from_slice(&swapped.to_le_bytes());
+        }
+    }
+
+    Ok(texture)
+}
+
+/// Writes `texture` with a DX10 header, which can describe every DXGI
+/// format, sRGB included.
+pub fn write(texture: &TextureData) -> Result<Vec<u8>, String> {
+    let subresources = texture.subresources()?;
+    if subresources.is_empty() {
+        return Err("texture has no subresources to write".to_owned());
+    }
+    let top = subresources[0];
+    let compressed = FormatInfo::of(texture.format).is_some_and(FormatInfo::is_block_compressed);
+    let volume = texture.dimension == TextureDimension::Texture3D;
+
+    let mut flags = DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT | DDSD_MIPMAPCOUNT;
+    flags |= if compressed {
+        DDSD_LINEARSIZE
+    } else {
+        DDSD_PITCH
+    };
+    if volume {
+        flags |= DDSD_DEPTH;
+    }
+
+    let mut caps = DDSCAPS_TEXTURE;
+    if texture.mip_levels > 1 {
+        caps |= DDSCAPS_COMPLEX | DDSCAPS_MIPMAP;
+    }
+    if texture.cubemap || volume || texture.array_size > 1 {
+        caps |= DDSCAPS_COMPLEX;
+    }
+    let caps2 = if texture.cubemap {
+        DDSCAPS2_CUBEMAP | DDSCAPS2_CUBEMAP_ALLFACES
+    } else if volume {
+        DDSCAPS2_VOLUME
+    } else {
+        0
+    };
+
+    let mut header = [0u32; HEADER_SIZE / 4];
+    header[0] = HEADER_SIZE as u32;
+    header[1] = flags;
+    header[2] = texture.height;
+    header[3] = texture.width;
+    header[4] = if compressed {
+        top.slice_pitch as u32
+    } else {
+        top.row_pitch as u32
+    };
+    header[5] = if volume { texture.depth } else { 0 };
+    header[6] = texture.mip_levels;
+    // Pixel format: size, flags and FourCC.
+    header[18] = 32;
+    header[19] = DDPF_FOURCC;
+    header[20] = u32::from_le_bytes(*b"DX10");
+    header[26] = caps;
+    header[27] = caps2;
+
+    let (dimension, misc_flag, array_size) = match texture.dimension {
+        TextureDimension::Texture3D => (DIMENSION_TEXTURE3D, 0, 1),
+        TextureDimension::Texture2D if texture.cubemap => (
+            DIMENSION_TEXTURE2D,
+            DX10_MISC_TEXTURECUBE,
+            texture.array_size / 6,
+        ),
+        TextureDimension::Texture2D => (DIMENSION_TEXTURE2D, 0, texture.array_size),
+    };
+    let dx10 = [texture.format, dimension, misc_flag, array_size, 0];
+
+    let last = subresources[subresources.len() - 1];
+    let data = &texture.data[..last.offset + last.slice_pitch * last.size[2] as usize];
+
+    let mut bytes = Vec::with_capacity(4 + HEADER_SIZE + DX10_HEADER_SIZE + data.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend(
+        header
+            .iter()
+            .chain(&dx10)
+            .flat_map(|value| value.to_le_bytes()),
+    );
+    bytes.extend_from_slice(data);
+
+    Ok(bytes)
+}
+
+pub fn save(texture: &TextureData, path: &Path) -> EngineResult<()> {
+    let path_name = path.display().to_string();
+    let bytes = write(texture).map_err(|message| EngineError::ImageEncode {
+        path: path_name.clone(),
+        message,
+    })?;
+
+    std::fs::write(path, bytes).map_err(|source| EngineError::Io {
+        path: path_name,
+        source,
+    })
+}
+
+/// The DXGI format a legacy pixel format block describes, as D3DX and
+/// DirectXTex write them. 24 bit RGB has no DXGI equivalent.
+fn legacy_format(pixel_format: &PixelFormat) -> Result<DXGI_FORMAT, String> {
+    let flags = pixel_format.flags;
+
+    if flags & DDPF_FOURCC != 0 {
+        let format = match &pixel_format.four_cc {
+            b"DXT1" => DXGI_FORMAT_BC1_UNORM,
+            b"DXT2" | b"DXT3" => DXGI_FORMAT_BC2_UNORM,
+            b"DXT4" | b"DXT5" => DXGI_FORMAT_BC3_UNORM,
+            b"ATI1" | b"BC4U" => DXGI_FORMAT_BC4_UNORM,
+            b"BC4S" => DXGI_FORMAT_BC4_SNORM,
+            b"ATI2" | b"BC5U" => DXGI_FORMAT_BC5_UNORM,
+            b"BC5S" => DXGI_FORMAT_BC5_SNORM,
+            // D3DFORMAT values stored in place of a FourCC.
+            four_cc => match u32::from_le_bytes(*four_cc) {
+                36 => DXGI_FORMAT_R16G16B16A16_UNORM,
+                110 => DXGI_FORMAT_R16G16B16A16_SNORM,
+                111 => DXGI_FORMAT_R16_FLOAT,
+                112 => DXGI_FORMAT_R16G16_FLOAT,
+                113 => DXGI_FORMAT_R16G16B16A16_FLOAT,
+                114 => DXGI_FORMAT_R32_FLOAT,
+                115 => DXGI_FORMAT_R32G32_FLOAT,
+                116 => DXGI_FORMAT_R32G32B32A32_FLOAT,
+                _ => {
+                    return Err(format!(
+                        "FourCC {:?} is not supported",
+                        String::from_utf8_lossy(four_cc)
+                    ))
+                }
+            },
+        };
+
+        return Ok(format);
+    }
+
+    let masks = pixel_format.masks;
+    let format = match (pixel_format.bit_count, masks) {
+        (32, [0xff, 0xff00, 0xff_0000, 0xff00_0000]) if flags & DDPF_BUMPDUDV != 0 => {
+            DXGI_FORMAT_R8G8B8A8_SNORM
+        }
+        (32, [0xffff, 0xffff_0000, 0, 0]) if flags & DDPF_BUMPDUDV != 0 => DXGI_FORMAT_R16G16_SNORM,
+        (16, [0xff, 0xff00, 0, 0]) if flags & DDPF_BUMPDUDV != 0 => DXGI_FORMAT_R8G8_SNORM,
+        _ if flags & DDPF_BUMPDUDV != 0 => return Err("unsupported signed pixel format".to_owned()),
+
+        (32, [0xff, 0xff00, 0xff_0000, 0xff00_0000]) => DXGI_FORMAT_R8G8B8A8_UNORM,
+        (32, [0xff_0000, 0xff00, 0xff, 0xff00_0000]) => DXGI_FORMAT_B8G8R8A8_UNORM,
+        (32, [0xff_0000, 0xff00, 0xff, 0]) => DXGI_FORMAT_B8G8R8X8_UNORM,
+        (32, [0x3ff, 0xf_fc00, 0x3ff0_0000, 0xc000_0000]) => DXGI_FORMAT_R10G10B10A2_UNORM,
+        // `parse` swaps red and blue to match.
+        (32, A2R10G10B10_MASKS) => DXGI_FORMAT_R10G10B10A2_UNORM,
+        (32, [0xffff, 0xffff_0000, 0, 0]) => DXGI_FORMAT_R16G16_UNORM,
+        (32, [0xffff_ffff, 0, 0, 0]) => DXGI_FORMAT_R32_FLOAT,
+        (16, [0xf800, 0x7e0, 0x1f, 0]) => DXGI_FORMAT_B5G6R5_UNORM,
+        (16, [0x7c00, 0x3e0, 0x1f, 0x8000]) => DXGI_FORMAT_B5G5R5A1_UNORM,
+        (16, [0xf00, 0xf0, 0xf, 0xf000]) => DXGI_FORMAT_B4G4R4A4_UNORM,
+        (16, [0xffff, 0, 0, 0]) if flags & DDPF_LUMINANCE != 0 => DXGI_FORMAT_R16_UNORM,
+        (16, [0xff, 0, 0, 0xff00]) if flags & DDPF_LUMINANCE != 0 => DXGI_FORMAT_R8G8_UNORM,
+        (8, [0xff, 0, 0, 0]) if flags & DDPF_LUMINANCE != 0 => DXGI_FORMAT_R8_UNORM,
+        (8, [0, 0, 0, 0xff]) if flags & DDPF_ALPHA != 0 => DXGI_FORMAT_A8_UNORM,
+        (bit_count, masks) => {
+            let kind = if flags & DDPF_RGB != 0 {
+                "RGB"
+            } else if flags & DDPF_LUMINANCE != 0 {
+                "luminance"
+            } else if flags & (DDPF_ALPHA | DDPF_ALPHAPIXELS) != 0 {
+                "alpha"
+            } else {
+                "unknown"
+            };
+            return Err(format!(
+                "{} bit {} format with masks {:08x?} is not supported",
+                bit_count, kind, masks
+            ));
+        }
+    };
+
+    Ok(format)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A legacy header with the given pixel format block, as u32s so tests
+    /// can tweak fields by index.
+    fn legacy_header(
+        width: u32,
+        height: u32,
+        pixel_flags: u32,
+        four_cc: &[u8; 4],
+        bit_count: u32,
+        masks: [u32; 4],
+    ) -> [u32; HEADER_SIZE / 4] {
+        let mut header = [0u32; HEADER_SIZE / 4];
+        header[0] = HEADER_SIZE as u32;
+        header[1] = DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT;
+        header[2] = height;
+        header[3] = width;
+        header[18] = 32;
+        header[19] = pixel_flags;
+        header[20] = u32::from_le_bytes(*four_cc);
+        header[21] = bit_count;
+        header[22..26].copy_from_slice(&masks);
+        header[26] = DDSCAPS_TEXTURE;
+        header
+    }
+
+    fn rgba8_header(width: u32, height: u32) -> [u32; HEADER_SIZE / 4] {
+        legacy_header(
+            width,
+            height,
+            DDPF_RGB | DDPF_ALPHAPIXELS,
+            &[0; 4],
+            32,
+            [0xff, 0xff00, 0xff_0000, 0xff00_0000],
+        )
+    }
+
+    fn luminance_header(width: u32, height: u32) -> [u32; HEADER_SIZE / 4] {
+        legacy_header(width, height, DDPF_LUMINANCE, &[0; 4], 8, [0xff, 0, 0, 0])
+    }
+
+    fn dx10_header(width: u32, height: u32) -> [u32; HEADER_SIZE / 4] {
+        legacy_header(width, height, DDPF_FOURCC, b"DX10", 0, [0; 4])
+    }
+
+    fn file(header: &[u32], dx10: Option<[u32; 5]>, data: &[u8]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        for value in header.iter().chain(dx10.iter().flatten()) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    fn ramp(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn parses_legacy_mip_chain() {
+        let mut header = rgba8_header(2, 2);
+        header[1] |= DDSD_MIPMAPCOUNT;
+        header[6] = 2;
+        // Two mips of 16 and 4 bytes, then padding.
+        let data = ramp(20 + 12);
+
+        let texture = parse(&file(&header, None, &data)).unwrap();
+        assert_eq!(texture.format, DXGI_FORMAT_R8G8B8A8_UNORM);
+        assert_eq!(texture.dimension, TextureDimension::Texture2D);
+        assert_eq!((texture.width, texture.height, texture.depth), (2, 2, 1));
+        assert_eq!((texture.mip_levels, texture.array_size), (2, 1));
+        assert!(!texture.cubemap);
+        assert_eq!(texture.data, data[..20]);
+
+        // The mip count is ignored without its flag.
+        header[1] &= !DDSD_MIPMAPCOUNT;
+        assert_eq!(parse(&file(&header, None, &data)).unwrap().mip_levels, 1);
+    }
+
+    #[test]
+    fn maps_legacy_pixel_formats() {
+        let rgb = DDPF_RGB | DDPF_ALPHAPIXELS;
+        let formats = [
+            (
+                legacy_header(
+                    4,
+                    4,
+                    rgb,
+                    &[0; 4],
+                    32,
+                    [0xff_0000, 0xff00, 0xff, 0xff00_0000],
+                ),
+                DXGI_FORMAT_B8G8R8A8_UNORM,
+            ),
+            (
+                legacy_header(4, 4, DDPF_RGB, &[0; 4], 32, [0xff_0000, 0xff00, 0xff, 0]),
+                DXGI_FORMAT_B8G8R8X8_UNORM,
+            ),
+            (
+                legacy_header(4, 4, DDPF_RGB, &[0; 4], 16, [0xf800, 0x7e0, 0x1f, 0]),
+                DXGI_FORMAT_B5G6R5_UNORM,
+            ),
+            (
+                legacy_header(4, 4, rgb, &[0; 4], 16, [0x7c00, 0x3e0, 0x1f, 0x8000]),
+                DXGI_FORMAT_B5G5R5A1_UNORM,
+            ),
+            (luminance_header(4, 4), DXGI_FORMAT_R8_UNORM),
+            (
+                legacy_header(4, 4, DDPF_LUMINANCE, &[0; 4], 16, [0xff, 0, 0, 0xff00]),
+                DXGI_FORMAT_R8G8_UNORM,
+            ),
+            (
+                legacy_header(4, 4, DDPF_ALPHA, &[0; 4], 8, [0, 0, 0, 0xff]),
+                DXGI_FORMAT_A8_UNORM,
+            ),
+            (
+                legacy_header(4, 4, DDPF_BUMPDUDV, &[0; 4], 16, [0xff, 0xff00, 0, 0]),
+                DXGI_FORMAT_R8G8_SNORM,
+            ),
+            (
+                legacy_header(4, 4, DDPF_FOURCC, b"DXT1", 0, [0; 4]),
+                DXGI_FORMAT_BC1_UNORM,
+            ),
+            (
+                legacy_header(4, 4, DDPF_FOURCC, b"DXT5", 0, [0; 4]),
+                DXGI_FORMAT_BC3_UNORM,
+            ),
+            (
+                legacy_header(4, 4, DDPF_FOURCC, b"ATI2", 0, [0; 4]),
+                DXGI_FORMAT_BC5_UNORM,
+            ),
+            (
+                legacy_header(4, 4, DDPF_FOURCC, &113u32.to_le_bytes(), 0, [0; 4]),
+                DXGI_FORMAT_R16G16B16A16_FLOAT,
+            ),
+        ];
+
+        for (header, format) in formats {
+            let texture = parse(&file(&header, None, &[0; 256])).unwrap();
+            assert_eq!(texture.format, format);
+        }
+
+        for header in [
+            legacy_header(4, 4, DDPF_RGB, &[0; 4], 24, [0xff_0000, 0xff00, 0xff, 0]),
+            legacy_header(4, 4, DDPF_FOURCC, b"ETC2", 0, [0; 4]),
+            legacy_header(4, 4, DDPF_BUMPDUDV, &[0; 4], 8, [0xff, 0, 0, 0]),
+        ] {
+            assert!(parse(&file(&header, None, &[0; 256])).is_err());
+        }
+    }
+
+    #[test]
+    fn swaps_red_and_blue_of_a2r10g10b10() {
+        let rgb = DDPF_RGB | DDPF_ALPHAPIXELS;
+        let texel = |r: u32, g: u32, b: u32, a: u32| r | (g << 10) | (b << 20) | (a << 30);
+        let swapped = |r: u32, g: u32, b: u32, a: u32| b | (g << 10) | (r << 20) | (a << 30);
+        let data: Vec<u8> = [swapped(1023, 512, 1, 2), swapped(0, 1, 1023, 3)]
+            .iter()
+            .flat_map(|bits| bits.to_le_bytes())
+            .collect();
+
+        let header = legacy_header(2, 1, rgb, &[0; 4], 32, A2R10G10B10_MASKS);
+        let texture = parse(&file(&header, None, &data)).unwrap();
+        assert_eq!(texture.format, DXGI_FORMAT_R10G10B10A2_UNORM);
+        let expected: Vec<u8> = [texel(1023, 512, 1, 2), texel(0, 1, 1023, 3)]
+            .iter()
+            .flat_map(|bits| bits.to_le_bytes())
+            .collect();
+        assert_eq!(texture.data, expected);
+
+        // The layout DXGI describes is copied unchanged.
+        let header = legacy_header(
+            2,
+            1,
+            rgb,
+            &[0; 4],
+            32,
+            [0x3ff, 0xf_fc00, 0x3ff0_0000, 0xc000_0000],
+        );
+        let texture = parse(&file(&header, None, &data)).unwrap();
+        assert_eq!(texture.format, DXGI_FORMAT_R10G10B10A2_UNORM);
+        assert_eq!(texture.data, data);
+    }
+
+    #[test]
+    fn parses_legacy_cubemaps() {
+        let mut header = luminance_header(2, 2);
+        header[26] |= DDSCAPS_COMPLEX;
+        header[27] = DDSCAPS2_CUBEMAP | DDSCAPS2_CUBEMAP_ALLFACES;
+        let data = ramp(6 * 4);
+
+        let texture = parse(&file(&header, None, &data)).unwrap();
+        assert!(texture.cubemap);
+        assert_eq!(texture.array_size, 6);
+        assert_eq!(texture.data, data);
+
+        // Missing faces, not square or too short.
+        header[27] = DDSCAPS2_CUBEMAP | 0x400;
+        assert!(parse(&file(&header, None, &data)).is_err());
+        let mut header = luminance_header(2, 1);
+        header[27] = DDSCAPS2_CUBEMAP | DDSCAPS2_CUBEMAP_ALLFACES;
+        assert!(parse(&file(&header, None, &data)).is_err());
+        let mut header = luminance_header(2, 2);
+        header[27] = DDSCAPS2_CUBEMAP | DDSCAPS2_CUBEMAP_ALLFACES;
+        assert!(parse(&file(&header, None, &data[..23])).is_err());
+    }
+
+    #[test]
+    fn parses_legacy_volumes() {
+        let mut header = luminance_header(2, 2);
+        header[1] |= DDSD_DEPTH | DDSD_MIPMAPCOUNT;
+        header[5] = 2;
+        header[6] = 2;
+        header[27] = DDSCAPS2_VOLUME;
+        // 2x2x2 and 1x1x1.
+        let data = ramp(8 + 1);
+
+        let texture = parse(&file(&header, None, &data)).unwrap();
+        assert_eq!(texture.dimension, TextureDimension::Texture3D);
+        assert_eq!((texture.width, texture.height, texture.depth), (2, 2, 2));
+        assert_eq!(texture.mip_levels, 2);
+        assert_eq!(texture.data, data);
+
+        // Without the depth flag it's a 2D texture.
+        header[1] &= !DDSD_DEPTH;
+        let texture = parse(&file(&header, None, &data)).unwrap();
+        assert_eq!(texture.dimension, TextureDimension::Texture2D);
+        assert_eq!(texture.depth, 1);
+    }
+
+    #[test]
+    fn parses_dx10_arrays() {
+        let header = dx10_header(4, 4);
+        let dx10 = [DXGI_FORMAT_BC7_UNORM_SRGB, DIMENSION_TEXTURE2D, 0, 3, 0];
+        let data = ramp(3 * 16);
+
+        let texture = parse(&file(&header, Some(dx10), &data)).unwrap();
+        assert_eq!(texture.format, DXGI_FORMAT_BC7_UNORM_SRGB);
+        assert_eq!(texture.array_size, 3);
+        assert!(!texture.cubemap);
+        assert_eq!(texture.data, data);
+
+        // 1D arrays load as 2D textures one texel high.
+        let header = dx10_header(4, 0);
+        let dx10 = [DXGI_FORMAT_R8_UNORM, DIMENSION_TEXTURE1D, 0, 2, 0];
+        let texture = parse(&file(&header, Some(dx10), &ramp(8))).unwrap();
+        assert_eq!((texture.width, texture.height), (4, 1));
+        assert_eq!(texture.array_size, 2);
+
+        let dx10 = [DXGI_FORMAT_R8_UNORM, DIMENSION_TEXTURE2D, 0, 0, 0];
+        assert!(parse(&file(&header, Some(dx10), &ramp(8))).is_err());
+    }
+
+    #[test]
+    fn parses_dx10_cube_arrays() {
+        let header = dx10_header(1, 1);
+        let dx10 = [
+            DXGI_FORMAT_R16G16B16A16_FLOAT,
+            DIMENSION_TEXTURE2D,
+            DX10_MISC_TEXTURECUBE,
+            2,
+            0,
+        ];
+        let data = ramp(12 * 8);
+
+        let texture = parse(&file(&header, Some(dx10), &data)).unwrap();
+        assert!(texture.cubemap);
+        assert_eq!(texture.array_size, 12);
+        assert_eq!(texture.data, data);
+    }
+
+    #[test]
+    fn parses_dx10_volumes() {
+        let mut header = dx10_header(4, 4);
+        header[1] |= DDSD_DEPTH | DDSD_MIPMAPCOUNT;
+        header[5] = 4;
+        header[6] = 3;
+        let dx10 = [DXGI_FORMAT_R8G8B8A8_UNORM, DIMENSION_TEXTURE3D, 0, 1, 0];
+        // 4x4x4, 2x2x2 and 1x1x1 texels.
+        let data = ramp(4 * (64 + 8 + 1));
+
+        let texture = parse(&file(&header, Some(dx10), &data)).unwrap();
+        assert_eq!(texture.dimension, TextureDimension::Texture3D);
+        assert_eq!((texture.depth, texture.mip_levels), (4, 3));
+        assert_eq!(texture.data, data);
+
+        let subresources = texture.subresources().unwrap();
+        assert_eq!(
+            subresources.iter().map(|s| s.offset).collect::<Vec<_>>(),
+            [0, 256, 288]
+        );
+
+        let arrayed = [DXGI_FORMAT_R8G8B8A8_UNORM, DIMENSION_TEXTURE3D, 0, 2, 0];
+        assert!(parse(&file(&header, Some(arrayed), &data)).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let header = rgba8_header(2, 2);
+        let valid = file(&header, None, &ramp(16));
+        assert!(parse(&valid).is_ok());
+
+        assert!(parse(&valid[..4 + HEADER_SIZE - 1]).is_err());
+        assert!(parse(&valid[..valid.len() - 1]).is_err());
+
+        let mut bad_magic = valid.clone();
+        bad_magic[0] = b'X';
+        assert!(parse(&bad_magic).is_err());
+
+        let mut bad_size = header;
+        bad_size[0] = 100;
+        assert!(parse(&file(&bad_size, None, &ramp(16))).is_err());
+
+        let zero_width = rgba8_header(0, 2);
+        assert!(parse(&file(&zero_width, None, &ramp(16))).is_err());
+
+        // The DX10 header itself is cut off.
+        assert!(parse(&file(&dx10_header(2, 2), None, &[0; 8])).is_err());
+        let unknown = [DXGI_FORMAT_R8_UNORM, 7, 0, 1, 0];
+        assert!(parse(&file(&dx10_header(2, 2), Some(unknown), &[0; 8])).is_err());
+
+        // Sizes that would overflow the subresource arithmetic.
+        let cubes = [
+            DXGI_FORMAT_R8_UNORM,
+            DIMENSION_TEXTURE2D,
+            DX10_MISC_TEXTURECUBE,
+            0x3000_0000,
+            0,
+        ];
+        assert!(parse(&file(&dx10_header(2, 2), Some(cubes), &[0; 8])).is_err());
+        let mut huge = dx10_header(u32::MAX, u32::MAX);
+        huge[1] |= DDSD_DEPTH;
+        huge[5] = u32::MAX;
+        let volume = [DXGI_FORMAT_R8_UNORM, DIMENSION_TEXTURE3D, 0, 1, 0];
+        assert!(parse(&file(&huge, Some(volume), &[0; 8])).is_err());
+        let too_wide = rgba8_header(16385, 1);
+        assert!(parse(&file(&too_wide, None, &[])).is_err());
+    }
+
+    #[test]
+    fn round_trips_through_write() {
+        let cube = [
+            DXGI_FORMAT_R16G16B16A16_FLOAT,
+            DIMENSION_TEXTURE2D,
+            DX10_MISC_TEXTURECUBE,
+            2,
+            0,
+        ];
+        let mut volume_header = dx10_header(4, 4);
+        volume_header[1] |= DDSD_DEPTH | DDSD_MIPMAPCOUNT;
+        volume_header[5] = 4;
+        volume_header[6] = 3;
+        let volume = [DXGI_FORMAT_R8G8B8A8_UNORM, DIMENSION_TEXTURE3D, 0, 1, 0];
+        let array = [DXGI_FORMAT_BC1_UNORM_SRGB, DIMENSION_TEXTURE2D, 0, 3, 0];
+
+        for bytes in [
+            file(&dx10_header(1, 1), Some(cube), &ramp(12 * 8)),
+            file(&volume_header, Some(volume), &ramp(4 * 73)),
+            file(&dx10_header(4, 4), Some(array), &ramp(3 * 8)),
+            file(&luminance_header(3, 5), None, &ramp(15)),
+        ] {
+            let texture = parse(&bytes).unwrap();
+            let written = write(&texture).unwrap();
+            assert_eq!(parse(&written).unwrap(), texture);
+        }
+    }
+
+    #[test]
+    fn refuses_to_write_empty_textures() {
+        let texture = parse(&file(&luminance_header(3, 5), None, &ramp(15))).unwrap();
+        for empty in [
+            TextureData {
+                mip_levels: 0,
+                ..texture.clone()
+            },
+            TextureData {
+                array_size: 0,
+                ..texture
+            },
+        ] {
+            assert!(write(&empty).is_err());
+        }
+    }
+}
@@ -0,0 +1,815 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+use half::f16;
+use windows::Win32::Graphics::Dxgi::Common::*;
+
+use crate::error::{EngineError, EngineResult};
+
+use super::backend::Backend;
+use super::cpu_image::{CpuImage, CpuPixels};
+use super::texture::Tex2D;
+use super::texture_data::{TextureData, TextureDimension};
+
+/// A cubemap in CPU memory. Faces are in D3D's order, +X, -X, +Y, -Y, +Z,
+/// -Z, each `size` x `size` texels row by row, linear RGBA.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cubemap {
+    pub size: u32,
+    pub faces: [Vec<[f32; 4]>; 6],
+}
+
+impl Cubemap {
+    /// Fills every texel with `f(direction)`.
+    pub fn from_fn(size: u32, f: impl Fn(Vec3) -> [f32; 4] + Sync) -> Cubemap {
+        Cubemap::from_texels(size, |face, x, y| f(texel_direction(face, size, x, y)))
+    }
+
+    /// Fills every texel with `f(face, x, y)`, one thread per face.
+    fn from_texels(size: u32, f: impl Fn(usize, u32, u32) -> [f32; 4] + Sync) -> Cubemap {
+        let f = &f;
+        let faces = std::thread::scope(|scope| {
+            let threads = [0, 1, 2, 3, 4, 5].map(|face| {
+                scope.spawn(move || {
+                    (0..size * size)
+                        .map(|i| f(face, i % size, i / size))
+                        .collect::<Vec<_>>()
+                })
+            });
+            threads.map(|thread| thread.join().unwrap())
+        });
+
+        Cubemap { size, faces }
+    }
+
+    /// Resamples an equirectangular panorama: its center looks down +Z,
+    /// its top row straight up along +Y. Every texel averages 2x2 bilinear
+    /// samples.
+    pub fn from_equirectangular(panorama: &CpuImage, size: u32) -> Cubemap {
+        let pixels = panorama.to_rgba32f();
+        let (width, height) = (panorama.width as f32, panorama.height as f32);
+
+        Cubemap::from_texels(size, |face, x, y| {
+            let mut sum = [0.0; 4];
+            for offset in [[0.25, 0.25], [0.75, 0.25], [0.25, 0.75], [0.75, 0.75]] {
+                let direction = face_direction(
+                    face,
+                    (x as f32 + offset[0]) / size as f32 * 2.0 - 1.0,
+                    (y as f32 + offset[1]) / size as f32 * 2.0 - 1.0,
+                );
+                let u = 0.5 + direction.x.atan2(direction.z) / (2.0 * PI);
+                let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+                let texel = bilinear(
+                    &pixels,
+                    panorama.width,
+                    panorama.height,
+                    u * width,
+                    v * height,
+                );
+                for c in 0..4 {
+                    sum[c] += texel[c] / 4.0;
+                }
+            }
+            sum
+        })
+    }
+
+    /// Bilinear sample of the face `direction` points at. Texels at face
+    /// edges are clamped rather than blended with the neighboring face.
+    pub fn sample(&self, direction: Vec3) -> [f32; 4] {
+        let (face, u, v) = direction_face(direction);
+        let size = self.size as f32;
+        bilinear_clamped(
+            &self.faces[face],
+            self.size,
+            (u * 0.5 + 0.5) * size,
+            (v * 0.5 + 0.5) * size,
+        )
+    }
+
+    /// The cube and every smaller mip down to 1x1, each averaging 2x2 texels
+    /// of the one before.
+    pub fn mip_chain(&self) -> Vec<Cubemap> {
+        let mut chain = vec![self.clone()];
+        while chain.last().unwrap().size > 1 {
+            let previous = chain.last().unwrap();
+            let (size, source) = (previous.size / 2, previous.size as usize);
+            let faces = previous.faces.clone().map(|face| {
+                let texel =
+                    |x: usize, y: usize| face[y.min(source - 1) * source + x.min(source - 1)];
+                (0..(size * size) as usize)
+                    .map(|i| {
+                        let (x, y) = (i % size as usize * 2, i / size as usize * 2);
+                        let texels = [
+                            texel(x, y),
+                            texel(x + 1, y),
+                            texel(x, y + 1),
+                            texel(x + 1, y + 1),
+                        ];
+                        [0, 1, 2, 3].map(|c| texels.iter().map(|texel| texel[c]).sum::<f32>() / 4.0)
+                    })
+                    .collect()
+            });
+            chain.push(Cubemap { size, faces });
+        }
+
+        chain
+    }
+
+    /// One face as an image, e.g. to save it for inspection.
+    pub fn face_image(&self, face: usize) -> CpuImage {
+        CpuImage {
+            width: self.size,
+            height: self.size,
+            pixels: CpuPixels::Rgba32F(self.faces[face].clone()),
+        }
+    }
+
+    /// A cube texture with `mips` as its mip chain, as
+    /// `R16G16B16A16_FLOAT`.
+    pub fn texture_data(mips: &[Cubemap]) -> TextureData {
+        let mut data = Vec::new();
+        for face in 0..6 {
+            for mip in mips {
+                data.extend(
+                    mip.faces[face]
+                        .iter()
+                        .flatten()
+                        .flat_map(|value| f16::from_f32(*value).to_le_bytes()),
+                );
+            }
+        }
+
+        TextureData {
+            format: DXGI_FORMAT_R16G16B16A16_FLOAT,
+            dimension: TextureDimension::Texture2D,
+            width: mips[0].size,
+            height: mips[0].size,
+            depth: 1,
+            mip_levels: mips.len() as u32,
+            array_size: 6,
+            cubemap: true,
+            data,
+        }
+    }
+}
+
+/// Direction through a point on a face, with `u` and `v` in [-1, 1]
+/// running right and down the face like texture coordinates.
+fn face_direction(face: usize, u: f32, v: f32) -> Vec3 {
+    let direction = match face {
+        0 => Vec3::new(1.0, -v, -u),
+        1 => Vec3::new(-1.0, -v, u),
+        2 => Vec3::new(u, 1.0, v),
+        3 => Vec3::new(u, -1.0, -v),
+        4 => Vec3::new(u, -v, 1.0),
+        _ => Vec3::new(-u, -v, -1.0),
+    };
+    direction.normalize()
+}
+
+fn texel_direction(face: usize, size: u32, x: u32, y: u32) -> Vec3 {
+    face_direction(
+        face,
+        (x as f32 + 0.5) / size as f32 * 2.0 - 1.0,
+        (y as f32 + 0.5) / size as f32 * 2.0 - 1.0,
+    )
+}
+
+/// The inverse of `face_direction`.
+fn direction_face(direction: Vec3) -> (usize, f32, f32) {
+    let (x, y, z) = (direction.x, direction.y, direction.z);
+    let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+    if ax >= ay && ax >= az {
+        if x > 0.0 {
+            (0, -z / ax, -y / ax)
+        } else {
+            (1, z / ax, -y / ax)
+        }
+    } else if ay >= az {
+        if y > 0.0 {
+            (2, x / ay, z / ay)
+        } else {
+            (3, x / ay, -z / ay)
+        }
+    } else if z > 0.0 {
+        (4, x / az, -y / az)
+    } else {
+        (5, -x / az, -y / az)
+    }
+}
+
+/// Solid angle a texel of a `size` x `size` face covers.
+fn texel_solid_angle(size: u32, x: u32, y: u32) -> f32 {
+    let area = |u: f32, v: f32| (u * v).atan2((u * u + v * v + 1.0).sqrt());
+    let texel = 2.0 / size as f32;
+    let (u0, v0) = (x as f32 * texel - 1.0, y as f32 * texel - 1.0);
+    let (u1, v1) = (u0 + texel, v0 + texel);
+
+    area(u0, v0) - area(u0, v1) - area(u1, v0) + area(u1, v1)
+}
+
+/// Bilinear sample at texel coordinates `(x, y)`, wrapping horizontally
+/// and clamping vertically like a panorama.
+fn bilinear(pixels: &[[f32; 4]], width: u32, height: u32, x: f32, y: f32) -> [f32; 4] {
+    let (x, y) = (x - 0.5, y - 0.5);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let texel = |x: i64, y: i64| {
+        let x = x.rem_euclid(width as i64) as usize;
+        let y = y.clamp(0, height as i64 - 1) as usize;
+        pixels[y * width as usize + x]
+    };
+
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let corners = [
+        (texel(x0, y0), (1.0 - fx) * (1.0 - fy)),
+        (texel(x0 + 1, y0), fx * (1.0 - fy)),
+        (texel(x0, y0 + 1), (1.0 - fx) * fy),
+        (texel(x0 + 1, y0 + 1), fx * fy),
+    ];
+    [0, 1, 2, 3].map(|c| {
+        corners
+            .iter()
+            .map(|(texel, weight)| texel[c] * weight)
+            .sum()
+    })
+}
+
+fn bilinear_clamped(pixels: &[[f32; 4]], size: u32, x: f32, y: f32) -> [f32; 4] {
+    let limit = size as f32 - 0.5;
+    bilinear_square(pixels, size, x.clamp(0.5, limit), y.clamp(0.5, limit))
+}
+
+fn bilinear_square(pixels: &[[f32; 4]], size: u32, x: f32, y: f32) -> [f32; 4] {
+    let (x, y) = (x - 0.5, y - 0.5);
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(size - 1), (y0 + 1).min(size - 1));
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let texel = |x: u32, y: u32| pixels[(y * size + x) as usize];
+
+    let corners = [
+        (texel(x0, y0), (1.0 - fx) * (1.0 - fy)),
+        (texel(x1, y0), fx * (1.0 - fy)),
+        (texel(x0, y1), (1.0 - fx) * fy),
+        (texel(x1, y1), fx * fy),
+    ];
+    [0, 1, 2, 3].map(|c| {
+        corners
+            .iter()
+            .map(|(texel, weight)| texel[c] * weight)
+            .sum()
+    })
+}
+
+/// Samples a mip chain between mips, `lod` 0 being the largest.
+fn sample_lod(chain: &[Cubemap], direction: Vec3, lod: f32) -> [f32; 4] {
+    let lod = lod.clamp(0.0, (chain.len() - 1) as f32);
+    let (low, fraction) = (lod.floor() as usize, lod.fract());
+    let a = chain[low].sample(direction);
+    if fraction == 0.0 {
+        return a;
+    }
+
+    let b = chain[low + 1].sample(direction);
+    [0, 1, 2, 3].map(|c| a[c] + (b[c] - a[c]) * fraction)
+}
+
+/// Point `i` of `count` in the Hammersley sequence.
+fn hammersley(i: u32, count: u32) -> (f32, f32) {
+    (
+        i as f32 / count as f32,
+        i.reverse_bits() as f32 / 2f32.powi(32),
+    )
+}
+
+/// A GGX distributed half vector around `normal`.
+fn importance_sample_ggx((u, v): (f32, f32), alpha: f32, normal: Vec3) -> Vec3 {
+    let phi = 2.0 * PI * u;
+    let cos_theta = ((1.0 - v) / (1.0 + (alpha * alpha - 1.0) * v)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+    let up = if normal.z.abs() < 0.999 {
+        Vec3::Z
+    } else {
+        Vec3::X
+    };
+    let tangent = up.cross(normal).normalize();
+    let bitangent = normal.cross(tangent);
+    (tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + normal * cos_theta)
+        .normalize()
+}
+
+fn ggx_distribution(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let denominator = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    alpha2 / (PI * denominator * denominator)
+}
+
+/// Prefilters `source` for the split-sum approximation: mip `m` of the
+/// result is convolved with GGX at roughness `m / (mips - 1)`, assuming
+/// the view direction equals the normal. Samples read lower mips of the
+/// source where they're sparse (filtered importance sampling), so few
+/// samples don't alias. `mips` is clamped to the chain `size` has room for.
+pub fn prefilter_specular(
+    source: &Cubemap,
+    size: u32,
+    mips: u32,
+    samples: u32,
+) -> Result<Vec<Cubemap>, String> {
+    if size == 0 || source.size == 0 {
+        return Err(format!(
+            "can't prefilter a {0}x{0} cube into {1}x{1}",
+            source.size, size
+        ));
+    }
+    if samples == 0 {
+        return Err("prefiltering needs at least one sample".to_owned());
+    }
+
+    let chain = source.mip_chain();
+    let full_chain = 32 - size.leading_zeros();
+    let mips = mips.clamp(1, full_chain);
+    let texel_solid_angle = 4.0 * PI / (6.0 * source.size as f32 * source.size as f32);
+
+    let prefiltered = (0..mips)
+        .map(|mip| {
+            let size = (size >> mip).max(1);
+            let roughness = if mips > 1 {
+                mip as f32 / (mips - 1) as f32
+            } else {
+                0.0
+            };
+            let alpha = roughness * roughness;
+            // The source mip with texels about as large as the output's.
+            let base_lod = (source.size as f32 / size as f32).log2().max(0.0);
+
+            Cubemap::from_fn(size, |normal| {
+                if roughness == 0.0 {
+                    return sample_lod(&chain, normal, base_lod);
+                }
+
+                let (mut sum, mut total_weight) = ([0.0; 4], 0.0);
+                for i in 0..samples {
+                    let half = importance_sample_ggx(hammersley(i, samples), alpha, normal);
+                    let n_dot_h = normal.dot(half).max(0.0);
+                    let light = half * (2.0 * n_dot_h) - normal;
+                    let n_dot_l = normal.dot(light);
+                    if n_dot_l <= 0.0 {
+                        continue;
+                    }
+
+                    // With N = V the pdf is D * NdotH / (4 * VdotH) = D / 4.
+                    let pdf = ggx_distribution(n_dot_h, alpha) / 4.0;
+                    let sample_solid_angle = 1.0 / (samples as f32 * pdf + 1e-4);
+                    let lod =
+                        (0.5 * (sample_solid_angle / texel_solid_angle).log2() + 1.0).max(base_lod);
+
+                    let texel = sample_lod(&chain, light, lod);
+                    for c in 0..4 {
+                        sum[c] += texel[c] * n_dot_l;
+                    }
+                    total_weight += n_dot_l;
+                }
+
+                sum.map(|value| value / total_weight)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(prefiltered)
+}
+
+/// Order 2 spherical harmonics, 9 RGB coefficients, of the radiance of an
+/// environment. Enough to reproduce its diffuse lighting closely.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SphericalHarmonics {
+    pub coefficients: [[f32; 3]; 9],
+}
+
+impl SphericalHarmonics {
+    /// Integrates `cubemap` against the SH basis, weighting every texel by
+    /// its solid angle.
+    pub fn project(cubemap: &Cubemap) -> SphericalHarmonics {
+        let mut coefficients = [[0.0; 3]; 9];
+        for (face, texels) in cubemap.faces.iter().enumerate() {
+            for (i, texel) in texels.iter().enumerate() {
+                let (x, y) = (i as u32 % cubemap.size, i as u32 / cubemap.size);
+                let direction = texel_direction(face, cubemap.size, x, y);
+                let weight = texel_solid_angle(cubemap.size, x, y);
+                for (coefficient, basis) in coefficients.iter_mut().zip(sh_basis(direction)) {
+                    for c in 0..3 {
+                        coefficient[c] += texel[c] * basis * weight;
+                    }
+                }
+            }
+        }
+
+        SphericalHarmonics { coefficients }
+    }
+
+    /// Coefficients convolved with the cosine lobe and divided by pi, so
+    /// summing them times the SH basis of a normal gives `irradiance`. Ready
+    /// to upload for evaluation in a shader.
+    pub fn irradiance_coefficients(&self) -> [[f32; 3]; 9] {
+        // Ramamoorthi and Hanrahan's cosine lobe factors, pi, 2pi/3 and pi/4
+        // per band, over pi.
+        const BANDS: [f32; 9] = [
+            1.0,
+            2.0 / 3.0,
+            2.0 / 3.0,
+            2.0 / 3.0,
+            0.25,
+            0.25,
+            0.25,
+            0.25,
+            0.25,
+        ];
+
+        let mut coefficients = self.coefficients;
+        for (coefficient, band) in coefficients.iter_mut().zip(BANDS) {
+            *coefficient = coefficient.map(|value| value * band);
+        }
+        coefficients
+    }
+
+    /// Irradiance arriving at a surface facing `normal`, divided by pi, so
+    /// a Lambertian surface reflects `albedo * irradiance`.
+    pub fn irradiance(&self, normal: Vec3) -> [f32; 3] {
+        let mut sum = [0.0; 3];
+        for (coefficient, basis) in self.irradiance_coefficients().iter().zip(sh_basis(normal)) {
+            for c in 0..3 {
+                sum[c] += coefficient[c] * basis;
+            }
+        }
+        sum.map(|value| value.max(0.0))
+    }
+
+    /// A cubemap of `irradiance` for every direction.
+    pub fn irradiance_map(&self, size: u32) -> Cubemap {
+        Cubemap::from_fn(size, |normal| {
+            let [r, g, b] = self.irradiance(normal);
+            [r, g, b, 1.0]
+        })
+    }
+}
+
+fn sh_basis(direction: Vec3) -> [f32; 9] {
+    let (x, y, z) = (direction.x, direction.y, direction.z);
+    [
+        0.282_095,
+        0.488_603 * y,
+        0.488_603 * z,
+        0.488_603 * x,
+        1.092_548 * x * y,
+        1.092_548 * y * z,
+        0.315_392 * (3.0 * z * z - 1.0),
+        1.092_548 * x * z,
+        0.546_274 * (x * x - y * y),
+    ]
+}
+
+/// The split-sum BRDF lookup table: `n_dot_v` along x and roughness along
+/// y, both in [0, 1]. R is the scale and G the bias applied to F0, so the
+/// specular term is `prefiltered * (F0 * lut.r + lut.g)`.
+pub fn brdf_lut(size: u32, samples: u32) -> CpuImage {
+    let geometry = |n_dot: f32, k: f32| n_dot / (n_dot * (1.0 - k) + k);
+    let normal = Vec3::Z;
+
+    let pixels = (0..size * size)
+        .map(|i| {
+            let n_dot_v = ((i % size) as f32 + 0.5) / size as f32;
+            let roughness = ((i / size) as f32 + 0.5) / size as f32;
+            let alpha = roughness * roughness;
+            // Schlick-GGX with the k image based lighting uses.
+            let k = alpha / 2.0;
+            let view = Vec3::new((1.0 - n_dot_v * n_dot_v).sqrt(), 0.0, n_dot_v);
+
+            let (mut scale, mut bias) = (0.0, 0.0);
+            for i in 0..samples {
+                let half = importance_sample_ggx(hammersley(i, samples), alpha, normal);
+                let v_dot_h = view.dot(half);
+                let light = half * (2.0 * v_dot_h) - view;
+                let (n_dot_l, n_dot_h) = (light.z, half.z);
+                if n_dot_l <= 0.0 {
+                    continue;
+                }
+
+                let visibility = geometry(n_dot_v, k) * geometry(n_dot_l, k) * v_dot_h.max(0.0)
+                    / (n_dot_h * n_dot_v);
+                let fresnel = (1.0 - v_dot_h.max(0.0)).powi(5);
+                scale += (1.0 - fresnel) * visibility;
+                bias += fresnel * visibility;
+            }
+
+            [scale / samples as f32, bias / samples as f32, 0.0, 1.0]
+        })
+        .collect();
+
+    CpuImage {
+        width: size,
+        height: size,
+        pixels: CpuPixels::Rgba32F(pixels),
+    }
+}
+
+/// `brdf_lut` as an `R16G16_FLOAT` texture.
+pub fn brdf_lut_texture_data(size: u32, samples: u32) -> TextureData {
+    let lut = brdf_lut(size, samples);
+    TextureData {
+        format: DXGI_FORMAT_R16G16_FLOAT,
+        dimension: TextureDimension::Texture2D,
+        width: size,
+        height: size,
+        depth: 1,
+        mip_levels: 1,
+        array_size: 1,
+        cubemap: false,
+        data: lut
+            .to_rgba32f()
+            .iter()
+            .flat_map(|[scale, bias, ..]| [*scale, *bias])
+            .flat_map(|value| f16::from_f32(value).to_le_bytes())
+            .collect(),
+    }
+}
+
+/// Bakes everything image based lighting needs from an HDR panorama.
+#[derive(Clone, Copy, Debug)]
+pub struct EnvironmentMapBuilder {
+    cube_size: u32,
+    specular_size: u32,
+    specular_mips: u32,
+    samples: u32,
+    irradiance_size: u32,
+}
+
+impl EnvironmentMapBuilder {
+    pub fn new() -> EnvironmentMapBuilder {
+        EnvironmentMapBuilder {
+            cube_size: 512,
+            specular_size: 128,
+            specular_mips: 6,
+            samples: 128,
+            irradiance_size: 32,
+        }
+    }
+
+    /// Face size of the skybox cube the panorama is converted to.
+    pub fn cube_size(mut self, cube_size: u32) -> Self {
+        self.cube_size = cube_size;
+        self
+    }
+
+    /// Face size of the top mip of the prefiltered specular cube.
+    pub fn specular_size(mut self, specular_size: u32) -> Self {
+        self.specular_size = specular_size;
+        self
+    }
+
+    /// Number of roughness levels, from 0 at the top mip to 1 at the last.
+    pub fn specular_mips(mut self, specular_mips: u32) -> Self {
+        self.specular_mips = specular_mips;
+        self
+    }
+
+    /// GGX samples per prefiltered texel.
+    pub fn samples(mut self, samples: u32) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn irradiance_size(mut self, irradiance_size: u32) -> Self {
+        self.irradiance_size = irradiance_size;
+        self
+    }
+
+    /// Loads a `.hdr` or `.exr` panorama and bakes it, see `build`.
+    pub fn load(&self, file: &str) -> EngineResult<EnvironmentMap> {
+        self.build(&CpuImage::load(file)?)
+            .map_err(|message| EngineError::TextureLoad {
+                path: file.to_owned(),
+                message,
+            })
+    }
+
+    pub fn build(&self, panorama: &CpuImage) -> Result<EnvironmentMap, String> {
+        if self.cube_size == 0 || self.irradiance_size == 0 {
+            return Err("environment cubes need a size of at least 1".to_owned());
+        }
+
+        let skybox = Cubemap::from_equirectangular(panorama, self.cube_size).mip_chain();
+        let specular = prefilter_specular(
+            &skybox[0],
+            self.specular_size,
+            self.specular_mips,
+            self.samples,
+        )?;
+        let irradiance = SphericalHarmonics::project(&skybox[0]);
+
+        Ok(EnvironmentMap {
+            irradiance_map: irradiance.irradiance_map(self.irradiance_size),
+            skybox,
+            specular,
+            irradiance,
+        })
+    }
+}
+
+impl Default for EnvironmentMapBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An environment baked for image based lighting. All cubes are linear
+/// radiance.
+#[derive(Clone, Debug)]
+pub struct EnvironmentMap {
+    /// The panorama as a cube, with a full mip chain.
+    pub skybox: Vec<Cubemap>,
+    /// GGX prefiltered radiance, roughness increasing per mip.
+    pub specular: Vec<Cubemap>,
+    pub irradiance: SphericalHarmonics,
+    /// `irradiance` evaluated per direction, for shaders that would rather
+    /// sample a cube than evaluate SH.
+    pub irradiance_map: Cubemap,
+}
+
+/// The GPU side of an `EnvironmentMap`, cube textures in
+/// `R16G16B16A16_FLOAT`.
+pub struct EnvironmentTextures {
+    pub skybox: Tex2D,
+    pub specular: Tex2D,
+    pub irradiance: Tex2D,
+}
+
+impl EnvironmentMap {
+    pub fn create(&self, backend: &Backend) -> EngineResult<EnvironmentTextures> {
+        let create = |mips: &[Cubemap], name: &str| {
+            Cubemap::texture_data(mips).create(backend)?.into_2d(name)
+        };
+
+        Ok(EnvironmentTextures {
+            skybox: create(&self.skybox, "skybox")?,
+            specular: create(&self.specular, "specular environment")?,
+            irradiance: create(
+                std::slice::from_ref(&self.irradiance_map),
+                "irradiance environment",
+            )?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32, tolerance: f32) {
+        assert!((a - b).abs() <= tolerance, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn face_directions_round_trip() {
+        let centers = [Vec3::X, -Vec3::X, Vec3::Y, -Vec3::Y, Vec3::Z, -Vec3::Z];
+        for (face, center) in centers.into_iter().enumerate() {
+            assert!(face_direction(face, 0.0, 0.0).abs_diff_eq(center, 1e-6));
+
+            for (u, v) in [(-0.9, -0.5), (0.3, 0.8), (0.99, -0.99), (0.0, 0.5)] {
+                let (back, back_u, back_v) = direction_face(face_direction(face, u, v));
+                assert_eq!(back, face);
+                assert_close(back_u, u, 1e-5);
+                assert_close(back_v, v, 1e-5);
+            }
+        }
+
+        // Faces meet without a seam: u = 1 on +Z is u = -1 on +X.
+        assert!(face_direction(4, 1.0, 0.3).abs_diff_eq(face_direction(0, -1.0, 0.3), 1e-6));
+        // Down on +Z, like texture v, is -Y.
+        assert!(face_direction(4, 0.0, 1.0).y < 0.0);
+    }
+
+    #[test]
+    fn texel_solid_angles_cover_the_sphere() {
+        for size in [1, 3, 16] {
+            let face: f32 = (0..size * size)
+                .map(|i| texel_solid_angle(size, i % size, i / size))
+                .sum();
+            assert_close(face * 6.0, 4.0 * PI, 1e-4);
+        }
+    }
+
+    #[test]
+    fn projects_a_constant_environment() {
+        let cubemap = Cubemap::from_fn(8, |_| [0.5, 1.0, 2.0, 1.0]);
+        let sh = SphericalHarmonics::project(&cubemap);
+
+        // Only the constant band, with the basis' 0.282095 over 4 pi.
+        let dc = 0.282_095 * 4.0 * PI;
+        for (c, value) in [0.5, 1.0, 2.0].into_iter().enumerate() {
+            assert_close(sh.coefficients[0][c], value * dc, 1e-4);
+        }
+        for coefficient in &sh.coefficients[1..] {
+            for value in coefficient {
+                assert_close(*value, 0.0, 1e-4);
+            }
+        }
+
+        // Constant radiance L lights every normal with pi * L.
+        for normal in [Vec3::X, -Vec3::Y, Vec3::new(1.0, 2.0, -3.0).normalize()] {
+            let [r, g, b] = sh.irradiance(normal);
+            assert_close(r, 0.5, 1e-4);
+            assert_close(g, 1.0, 1e-4);
+            assert_close(b, 2.0, 1e-4);
+        }
+    }
+
+    #[test]
+    fn projects_light_from_above() {
+        let cubemap = Cubemap::from_fn(16, |direction| {
+            let up = direction.y.max(0.0);
+            [up, up, up, 1.0]
+        });
+        let sh = SphericalHarmonics::project(&cubemap);
+
+        let [up, ..] = sh.irradiance(Vec3::Y);
+        let [side, ..] = sh.irradiance(Vec3::X);
+        let [down, ..] = sh.irradiance(-Vec3::Y);
+        assert!(up > side && side > down, "{} {} {}", up, side, down);
+        // The exact irradiance over pi facing the light is 2/3.
+        assert_close(up, 2.0 / 3.0, 0.03);
+    }
+
+    #[test]
+    fn mip_chain_halves_down_to_one() {
+        let cubemap = Cubemap::from_fn(5, |_| [1.0, 0.0, 0.0, 1.0]);
+        let chain = cubemap.mip_chain();
+        let sizes: Vec<_> = chain.iter().map(|mip| mip.size).collect();
+        assert_eq!(sizes, [5, 2, 1]);
+        assert!(chain.iter().all(|mip| mip
+            .faces
+            .iter()
+            .flatten()
+            .all(|texel| *texel == [1.0, 0.0, 0.0, 1.0])));
+    }
+
+    #[test]
+    fn prefilters_a_constant_environment_unchanged() {
+        let source = Cubemap::from_fn(16, |_| [0.25, 0.5, 1.0, 1.0]);
+        let mips = prefilter_specular(&source, 8, 10, 16).unwrap();
+
+        // 10 mips don't fit 8x8, the chain stops at 1x1.
+        let sizes: Vec<_> = mips.iter().map(|mip| mip.size).collect();
+        assert_eq!(sizes, [8, 4, 2, 1]);
+        for texel in mips.iter().flat_map(|mip| mip.faces.iter().flatten()) {
+            for (value, expected) in texel.iter().zip([0.25, 0.5, 1.0, 1.0]) {
+                assert_close(*value, expected, 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn refuses_empty_prefilters() {
+        let source = Cubemap::from_fn(4, |_| [1.0; 4]);
+        assert!(prefilter_specular(&source, 0, 4, 16).is_err());
+        assert!(prefilter_specular(&source, 4, 4, 0).is_err());
+        assert!(prefilter_specular(&Cubemap::from_fn(0, |_| [1.0; 4]), 4, 4, 16).is_err());
+        assert_eq!(prefilter_specular(&source, 4, 0, 16).unwrap().len(), 1);
+
+        let panorama = CpuImage {
+            width: 8,
+            height: 4,
+            pixels: CpuPixels::Rgba32F(vec![[1.0; 4]; 32]),
+        };
+        let builder = EnvironmentMapBuilder::new()
+            .cube_size(4)
+            .specular_size(4)
+            .samples(4)
+            .irradiance_size(2);
+        assert!(builder.build(&panorama).is_ok());
+        assert!(builder.cube_size(0).build(&panorama).is_err());
+        assert!(builder.specular_size(0).build(&panorama).is_err());
+        assert!(builder.irradiance_size(0).build(&panorama).is_err());
+    }
+
+    #[test]
+    fn brdf_lut_stays_in_bounds() {
+        let lut = brdf_lut(16, 64).to_rgba32f();
+        for [scale, bias, ..] in &lut {
+            assert!((0.0..=1.0).contains(scale), "{}", scale);
+            assert!((0.0..=1.0).contains(bias), "{}", bias);
+            // Energy conserving: F0 = 1 reflects at most everything.
+            assert!(scale + bias <= 1.0 + 1e-3, "{}", scale + bias);
+        }
+
+        // Smooth and facing the viewer reflects all of F0 and no bias.
+        let [scale, bias, ..] = lut[15];
+        assert!(scale > 0.9 && bias < 0.05, "{} {}", scale, bias);
+        // Grazing angles lean on the Fresnel bias.
+        assert!(lut[0][1] > lut[15][1]);
+
+        let texture = brdf_lut_texture_data(16, 64);
+        assert_eq!(texture.data.len(), 16 * 16 * 4);
+        assert_eq!(texture.format, DXGI_FORMAT_R16G16_FLOAT);
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Watches groups of files by polling their modification times, so it works
+/// the same on every platform and needs no background thread. Each group is
+/// identified by a key, e.g. a shader and all of its includes.
+pub struct FileWatcher<K> {
+    watches: HashMap<K, Vec<(PathBuf, Option<SystemTime>)>>,
+    poll_interval: Duration,
+    last_poll: Option<Instant>,
+}
+
+impl<K: Clone + Eq + Hash> FileWatcher<K> {
+    pub fn new() -> FileWatcher<K> {
+        FileWatcher {
+            watches: HashMap::new(),
+            poll_interval: Duration::from_millis(500),
+            last_poll: None,
+        }
+    }
+
+    /// Minimum time between two checks in `changed`.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+
+        self
+    }
+
+    /// Starts watching `files` under `key`, replacing any files watched under
+    /// it before. Their current state is the baseline for `changed`.
+    pub fn watch(&mut self, key: K, files: impl IntoIterator<Item = PathBuf>) {
+        let files = files
+            .into_iter()
+            .map(|file| {
+                let modified = modified_time(&file);
+                (file, modified)
+            })
+            .collect();
+
+        self.watches.insert(key, files);
+    }
+
+    pub fn unwatch(&mut self, key: &K) {
+        self.watches.remove(key);
+    }
+
+    pub fn is_watching(&self, key: &K) -> bool {
+        self.watches.contains_key(key)
+    }
+
+    /// Keys with at least one file modified, created or deleted since the
+    /// last check. Returns nothing if the poll interval hasn't elapsed yet.
+    pub fn changed(&mut self) -> Vec<K> {
+        let now = Instant::now();
+        if let Some(last_poll) = self.last_poll {
+            if now.duration_since(last_poll) < self.poll_interval {
+                return Vec::new();
+            }
+        }
+        self.last_poll = Some(now);
+
+        self.check_now()
+    }
+
+    /// Like `changed`, but ignores the poll interval.
+    pub fn check_now(&mut self) -> Vec<K> {
+        let mut changed = Vec::new();
+
+        for (key, files) in &mut self.watches {
+            let mut key_changed = false;
+            for (file, last_modified) in files.iter_mut() {
+                let modified = modified_time(file);
+                if modified != *last_modified {
+                    *last_modified = modified;
+                    key_changed = true;
+                }
+            }
+
+            if key_changed {
+                changed.push(key.clone());
+            }
+        }
+
+        changed
+    }
+}
+
+impl<K: Clone + Eq + Hash> Default for FileWatcher<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of its own per test, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir =
+                std::env::temp_dir().join(format!("file_watcher_{}_{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        fn file(&self, name: &str) -> PathBuf {
+            self.0.join(name)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Writes `file` with a modification time of `seconds` after the epoch,
+    /// rather than waiting for the file system clock to tick.
+    fn touch(file: &Path, seconds: u64) {
+        fs::write(file, seconds.to_string()).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(file)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+            .unwrap();
+    }
+
+    #[test]
+    fn reports_modified_created_and_deleted_files() {
+        let dir = TempDir::new("changes");
+        let (shader, include, missing) =
+            (dir.file("a.hlsl"), dir.file("b.hlsl"), dir.file("c.hlsl"));
+        touch(&shader, 1);
+        touch(&include, 1);
+
+        let mut watcher = FileWatcher::new();
+        watcher.watch("a", vec![shader.clone(), include.clone()]);
+        watcher.watch("c", vec![missing.clone()]);
+        assert!(watcher.check_now().is_empty());
+
+        touch(&include, 2);
+        assert_eq!(watcher.check_now(), ["a"]);
+        assert!(watcher.check_now().is_empty());
+
+        touch(&missing, 1);
+        assert_eq!(watcher.check_now(), ["c"]);
+
+        fs::remove_file(&shader).unwrap();
+        assert_eq!(watcher.check_now(), ["a"]);
+        assert!(watcher.check_now().is_empty());
+    }
+
+    #[test]
+    fn debounces_by_the_poll_interval() {
+        let dir = TempDir::new("debounce");
+        let file = dir.file("a.hlsl");
+        touch(&file, 1);
+
+        let mut watcher = FileWatcher::new().poll_interval(Duration::from_secs(3600));
+        watcher.watch(0, vec![file.clone()]);
+
+        // The first call always polls and starts the interval.
+        assert!(watcher.changed().is_empty());
+        touch(&file, 2);
+        assert!(watcher.changed().is_empty());
+        touch(&file, 3);
+        assert!(watcher.changed().is_empty());
+
+        // Both saves are reported together once the watcher looks again.
+        assert_eq!(watcher.check_now(), [0]);
+        assert!(watcher.check_now().is_empty());
+
+        let mut watcher = FileWatcher::new().poll_interval(Duration::ZERO);
+        watcher.watch(0, vec![file.clone()]);
+        touch(&file, 4);
+        assert_eq!(watcher.changed(), [0]);
+        touch(&file, 5);
+        assert_eq!(watcher.changed(), [0]);
+    }
+
+    #[test]
+    fn replaces_and_removes_watches() {
+        let dir = TempDir::new("replace");
+        let (a, b) = (dir.file("a.hlsl"), dir.file("b.hlsl"));
+        touch(&a, 1);
+        touch(&b, 1);
+
+        let mut watcher = FileWatcher::new();
+        watcher.watch(0, vec![a.clone()]);
+        watcher.watch(0, vec![b.clone()]);
+        touch(&a, 2);
+        assert!(watcher.check_now().is_empty());
+        touch(&b, 2);
+        assert_eq!(watcher.check_now(), [0]);
+
+        // Re-watching takes the current state as the new baseline.
+        touch(&b, 3);
+        watcher.watch(0, vec![b.clone()]);
+        assert!(watcher.check_now().is_empty());
+
+        assert!(watcher.is_watching(&0));
+        watcher.unwatch(&0);
+        assert!(!watcher.is_watching(&0));
+        touch(&b, 4);
+        assert!(watcher.check_now().is_empty());
+    }
+}
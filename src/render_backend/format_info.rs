@@ -0,0 +1,562 @@
+use windows::Win32::Graphics::Dxgi::Common::*;
+
+use ChannelType::*;
+
+/// How the bits of a channel are interpreted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelType {
+    /// Reinterpreted by views, see `FormatInfo::typeless`.
+    Typeless,
+    Unorm,
+    Snorm,
+    Uint,
+    Sint,
+    Float,
+    /// Three mantissas sharing one exponent, `R9G9B9E5_SHAREDEXP`.
+    SharedExponent,
+}
+
+/// What the engine knows about a `DXGI_FORMAT`. Look one up with
+/// `FormatInfo::of`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FormatInfo {
+    pub format: DXGI_FORMAT,
+    /// The name without the `DXGI_FORMAT_` prefix.
+    pub name: &'static str,
+    /// Bytes per texel, or per block for block compressed formats.
+    pub bytes: usize,
+    /// Width and height of a block, 4 for block compressed formats and 1
+    /// for everything else.
+    pub block_size: u32,
+    /// The channels in memory order, e.g. `"BGRA"`. `X` marks unused bits,
+    /// `D` and `S` depth and stencil, `E` a shared exponent.
+    pub channels: &'static str,
+    /// Of the color or depth channels. Stencil is always `Uint`.
+    pub channel_type: ChannelType,
+    pub srgb: bool,
+    pub depth: bool,
+    pub stencil: bool,
+    /// Every D3D11 device can render to it. Depth formats are bound as depth
+    /// stencil views instead and aren't counted.
+    pub renderable: bool,
+    /// The family the format can be viewed as, the format itself if it has
+    /// no typeless version.
+    typeless: DXGI_FORMAT,
+}
+
+impl FormatInfo {
+    const fn block_compressed(mut self) -> Self {
+        self.block_size = 4;
+        self
+    }
+
+    const fn srgb(mut self) -> Self {
+        self.srgb = true;
+        self
+    }
+
+    const fn depth(mut self) -> Self {
+        self.depth = true;
+        self
+    }
+
+    const fn stencil(mut self) -> Self {
+        self.stencil = true;
+        self
+    }
+
+    const fn renderable(mut self) -> Self {
+        self.renderable = true;
+        self
+    }
+
+    /// `None` for `DXGI_FORMAT_UNKNOWN` and formats the engine doesn't use,
+    /// like video and palettized formats.
+    pub fn of(format: DXGI_FORMAT) -> Option<&'static FormatInfo> {
+        FORMATS.iter().find(|info| info.format == format)
+    }
+
+    pub fn is_block_compressed(&self) -> bool {
+        self.block_size > 1
+    }
+
+    /// Bytes between rows of a tightly packed surface `width` texels wide.
+    /// Rows of block compressed formats are rows of blocks.
+    pub fn row_pitch(&self, width: u32) -> usize {
+        width.div_ceil(self.block_size).max(1) as usize * self.bytes
+    }
+
+    /// Number of rows, of texels or blocks, in a surface `height` texels
+    /// high.
+    pub fn rows(&self, height: u32) -> usize {
+        height.div_ceil(self.block_size).max(1) as usize
+    }
+
+    /// Bytes of one tightly packed 2D surface, or depth slice of a 3D one.
+    pub fn surface_size(&self, width: u32, height: u32) -> usize {
+        self.row_pitch(width) * self.rows(height)
+    }
+
+    /// Bytes of a tightly packed subresource, all its depth slices included.
+    pub fn subresource_size(&self, [width, height, depth]: [u32; 3]) -> usize {
+        self.surface_size(width, height) * depth.max(1) as usize
+    }
+
+    /// The typeless format of the family, which views can reinterpret as
+    /// any of its members. The format itself if there is none.
+    pub fn typeless(&self) -> DXGI_FORMAT {
+        self.typeless
+    }
+
+    /// The sRGB variant of a UNORM format, the format itself if there is
+    /// none.
+    pub fn srgb_format(&self) -> DXGI_FORMAT {
+        self.sibling(true)
+    }
+
+    /// The UNORM variant of an sRGB format, the format itself if there is
+    /// none.
+    pub fn linear_format(&self) -> DXGI_FORMAT {
+        self.sibling(false)
+    }
+
+    fn sibling(&self, srgb: bool) -> DXGI_FORMAT {
+        if self.channel_type != ChannelType::Unorm || self.srgb == srgb {
+            return self.format;
+        }
+
+        FORMATS
+            .iter()
+            .find(|info| {
+                info.typeless == self.typeless
+                    && info.channels == self.channels
+                    && info.channel_type == ChannelType::Unorm
+                    && info.srgb == srgb
+            })
+            .map_or(self.format, |info| info.format)
+    }
+}
+
+/// The name of a format for messages, its number if it isn't in the table.
+pub fn format_name(format: DXGI_FORMAT) -> String {
+    match FormatInfo::of(format) {
+        Some(info) => info.name.to_owned(),
+        None => format!("DXGI format {}", format),
+    }
+}
+
+const fn entry(
+    format: DXGI_FORMAT,
+    name: &'static str,
+    bytes: usize,
+    channels: &'static str,
+    channel_type: ChannelType,
+    typeless: DXGI_FORMAT,
+) -> FormatInfo {
+    FormatInfo {
+        format,
+        name,
+        bytes,
+        block_size: 1,
+        channels,
+        channel_type,
+        srgb: false,
+        depth: false,
+        stencil: false,
+        renderable: false,
+        typeless,
+    }
+}
+
+#[rustfmt::skip]
+static FORMATS: &[FormatInfo] = &[
+    entry(DXGI_FORMAT_R32G32B32A32_TYPELESS, "R32G32B32A32_TYPELESS", 16, "RGBA", Typeless, DXGI_FORMAT_R32G32B32A32_TYPELESS),
+    entry(DXGI_FORMAT_R32G32B32A32_FLOAT, "R32G32B32A32_FLOAT", 16, "RGBA", Float, DXGI_FORMAT_R32G32B32A32_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R32G32B32A32_UINT, "R32G32B32A32_UINT", 16, "RGBA", Uint, DXGI_FORMAT_R32G32B32A32_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R32G32B32A32_SINT, "R32G32B32A32_SINT", 16, "RGBA", Sint, DXGI_FORMAT_R32G32B32A32_TYPELESS).renderable(),
+
+    // Rendering to 96 bit formats is optional.
+    entry(DXGI_FORMAT_R32G32B32_TYPELESS, "R32G32B32_TYPELESS", 12, "RGB", Typeless, DXGI_FORMAT_R32G32B32_TYPELESS),
+    entry(DXGI_FORMAT_R32G32B32_FLOAT, "R32G32B32_FLOAT", 12, "RGB", Float, DXGI_FORMAT_R32G32B32_TYPELESS),
+    entry(DXGI_FORMAT_R32G32B32_UINT, "R32G32B32_UINT", 12, "RGB", Uint, DXGI_FORMAT_R32G32B32_TYPELESS),
+    entry(DXGI_FORMAT_R32G32B32_SINT, "R32G32B32_SINT", 12, "RGB", Sint, DXGI_FORMAT_R32G32B32_TYPELESS),
+
+    entry(DXGI_FORMAT_R16G16B16A16_TYPELESS, "R16G16B16A16_TYPELESS", 8, "RGBA", Typeless, DXGI_FORMAT_R16G16B16A16_TYPELESS),
+    entry(DXGI_FORMAT_R16G16B16A16_FLOAT, "R16G16B16A16_FLOAT", 8, "RGBA", Float, DXGI_FORMAT_R16G16B16A16_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R16G16B16A16_UNORM, "R16G16B16A16_UNORM", 8, "RGBA", Unorm, DXGI_FORMAT_R16G16B16A16_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R16G16B16A16_UINT, "R16G16B16A16_UINT", 8, "RGBA", Uint, DXGI_FORMAT_R16G16B16A16_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R16G16B16A16_SNORM, "R16G16B16A16_SNORM", 8, "RGBA", Snorm, DXGI_FORMAT_R16G16B16A16_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R16G16B16A16_SINT, "R16G16B16A16_SINT", 8, "RGBA", Sint, DXGI_FORMAT_R16G16B16A16_TYPELESS).renderable(),
+
+    entry(DXGI_FORMAT_R32G32_TYPELESS, "R32G32_TYPELESS", 8, "RG", Typeless, DXGI_FORMAT_R32G32_TYPELESS),
+    entry(DXGI_FORMAT_R32G32_FLOAT, "R32G32_FLOAT", 8, "RG", Float, DXGI_FORMAT_R32G32_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R32G32_UINT, "R32G32_UINT", 8, "RG", Uint, DXGI_FORMAT_R32G32_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R32G32_SINT, "R32G32_SINT", 8, "RG", Sint, DXGI_FORMAT_R32G32_TYPELESS).renderable(),
+
+    entry(DXGI_FORMAT_R32G8X24_TYPELESS, "R32G8X24_TYPELESS", 8, "RGX", Typeless, DXGI_FORMAT_R32G8X24_TYPELESS),
+    entry(DXGI_FORMAT_D32_FLOAT_S8X24_UINT, "D32_FLOAT_S8X24_UINT", 8, "DSX", Float, DXGI_FORMAT_R32G8X24_TYPELESS).depth().stencil(),
+    entry(DXGI_FORMAT_R32_FLOAT_X8X24_TYPELESS, "R32_FLOAT_X8X24_TYPELESS", 8, "RXX", Float, DXGI_FORMAT_R32G8X24_TYPELESS),
+    entry(DXGI_FORMAT_X32_TYPELESS_G8X24_UINT, "X32_TYPELESS_G8X24_UINT", 8, "XGX", Uint, DXGI_FORMAT_R32G8X24_TYPELESS),
+
+    entry(DXGI_FORMAT_R10G10B10A2_TYPELESS, "R10G10B10A2_TYPELESS", 4, "RGBA", Typeless, DXGI_FORMAT_R10G10B10A2_TYPELESS),
+    entry(DXGI_FORMAT_R10G10B10A2_UNORM, "R10G10B10A2_UNORM", 4, "RGBA", Unorm, DXGI_FORMAT_R10G10B10A2_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R10G10B10A2_UINT, "R10G10B10A2_UINT", 4, "RGBA", Uint, DXGI_FORMAT_R10G10B10A2_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R11G11B10_FLOAT, "R11G11B10_FLOAT", 4, "RGB", Float, DXGI_FORMAT_R11G11B10_FLOAT).renderable(),
+
+    entry(DXGI_FORMAT_R8G8B8A8_TYPELESS, "R8G8B8A8_TYPELESS", 4, "RGBA", Typeless, DXGI_FORMAT_R8G8B8A8_TYPELESS),
+    entry(DXGI_FORMAT_R8G8B8A8_UNORM, "R8G8B8A8_UNORM", 4, "RGBA", Unorm, DXGI_FORMAT_R8G8B8A8_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R8G8B8A8_UNORM_SRGB, "R8G8B8A8_UNORM_SRGB", 4, "RGBA", Unorm, DXGI_FORMAT_R8G8B8A8_TYPELESS).srgb().renderable(),
+    entry(DXGI_FORMAT_R8G8B8A8_UINT, "R8G8B8A8_UINT", 4, "RGBA", Uint, DXGI_FORMAT_R8G8B8A8_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R8G8B8A8_SNORM, "R8G8B8A8_SNORM", 4, "RGBA", Snorm, DXGI_FORMAT_R8G8B8A8_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R8G8B8A8_SINT, "R8G8B8A8_SINT", 4, "RGBA", Sint, DXGI_FORMAT_R8G8B8A8_TYPELESS).renderable(),
+
+    entry(DXGI_FORMAT_R16G16_TYPELESS, "R16G16_TYPELESS", 4, "RG", Typeless, DXGI_FORMAT_R16G16_TYPELESS),
+    entry(DXGI_FORMAT_R16G16_FLOAT, "R16G16_FLOAT", 4, "RG", Float, DXGI_FORMAT_R16G16_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R16G16_UNORM, "R16G16_UNORM", 4, "RG", Unorm, DXGI_FORMAT_R16G16_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R16G16_UINT, "R16G16_UINT", 4, "RG", Uint, DXGI_FORMAT_R16G16_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R16G16_SNORM, "R16G16_SNORM", 4, "RG", Snorm, DXGI_FORMAT_R16G16_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R16G16_SINT, "R16G16_SINT", 4, "RG", Sint, DXGI_FORMAT_R16G16_TYPELESS).renderable(),
+
+    entry(DXGI_FORMAT_R32_TYPELESS, "R32_TYPELESS", 4, "R", Typeless, DXGI_FORMAT_R32_TYPELESS),
+    entry(DXGI_FORMAT_D32_FLOAT, "D32_FLOAT", 4, "D", Float, DXGI_FORMAT_R32_TYPELESS).depth(),
+    entry(DXGI_FORMAT_R32_FLOAT, "R32_FLOAT", 4, "R", Float, DXGI_FORMAT_R32_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R32_UINT, "R32_UINT", 4, "R", Uint, DXGI_FORMAT_R32_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R32_SINT, "R32_SINT", 4, "R", Sint, DXGI_FORMAT_R32_TYPELESS).renderable(),
+
+    entry(DXGI_FORMAT_R24G8_TYPELESS, "R24G8_TYPELESS", 4, "RG", Typeless, DXGI_FORMAT_R24G8_TYPELESS),
+    entry(DXGI_FORMAT_D24_UNORM_S8_UINT, "D24_UNORM_S8_UINT", 4, "DS", Unorm, DXGI_FORMAT_R24G8_TYPELESS).depth().stencil(),
+    entry(DXGI_FORMAT_R24_UNORM_X8_TYPELESS, "R24_UNORM_X8_TYPELESS", 4, "RX", Unorm, DXGI_FORMAT_R24G8_TYPELESS),
+    entry(DXGI_FORMAT_X24_TYPELESS_G8_UINT, "X24_TYPELESS_G8_UINT", 4, "XG", Uint, DXGI_FORMAT_R24G8_TYPELESS),
+
+    entry(DXGI_FORMAT_R8G8_TYPELESS, "R8G8_TYPELESS", 2, "RG", Typeless, DXGI_FORMAT_R8G8_TYPELESS),
+    entry(DXGI_FORMAT_R8G8_UNORM, "R8G8_UNORM", 2, "RG", Unorm, DXGI_FORMAT_R8G8_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R8G8_UINT, "R8G8_UINT", 2, "RG", Uint, DXGI_FORMAT_R8G8_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R8G8_SNORM, "R8G8_SNORM", 2, "RG", Snorm, DXGI_FORMAT_R8G8_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R8G8_SINT, "R8G8_SINT", 2, "RG", Sint, DXGI_FORMAT_R8G8_TYPELESS).renderable(),
+
+    entry(DXGI_FORMAT_R16_TYPELESS, "R16_TYPELESS", 2, "R", Typeless, DXGI_FORMAT_R16_TYPELESS),
+    entry(DXGI_FORMAT_R16_FLOAT, "R16_FLOAT", 2, "R", Float, DXGI_FORMAT_R16_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_D16_UNORM, "D16_UNORM", 2, "D", Unorm, DXGI_FORMAT_R16_TYPELESS).depth(),
+    entry(DXGI_FORMAT_R16_UNORM, "R16_UNORM", 2, "R", Unorm, DXGI_FORMAT_R16_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R16_UINT, "R16_UINT", 2, "R", Uint, DXGI_FORMAT_R16_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R16_SNORM, "R16_SNORM", 2, "R", Snorm, DXGI_FORMAT_R16_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R16_SINT, "R16_SINT", 2, "R", Sint, DXGI_FORMAT_R16_TYPELESS).renderable(),
+
+    entry(DXGI_FORMAT_R8_TYPELESS, "R8_TYPELESS", 1, "R", Typeless, DXGI_FORMAT_R8_TYPELESS),
+    entry(DXGI_FORMAT_R8_UNORM, "R8_UNORM", 1, "R", Unorm, DXGI_FORMAT_R8_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R8_UINT, "R8_UINT", 1, "R", Uint, DXGI_FORMAT_R8_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R8_SNORM, "R8_SNORM", 1, "R", Snorm, DXGI_FORMAT_R8_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_R8_SINT, "R8_SINT", 1, "R", Sint, DXGI_FORMAT_R8_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_A8_UNORM, "A8_UNORM", 1, "A", Unorm, DXGI_FORMAT_A8_UNORM).renderable(),
+
+    entry(DXGI_FORMAT_R9G9B9E5_SHAREDEXP, "R9G9B9E5_SHAREDEXP", 4, "RGBE", SharedExponent, DXGI_FORMAT_R9G9B9E5_SHAREDEXP),
+
+    entry(DXGI_FORMAT_BC1_TYPELESS, "BC1_TYPELESS", 8, "RGBA", Typeless, DXGI_FORMAT_BC1_TYPELESS).block_compressed(),
+    entry(DXGI_FORMAT_BC1_UNORM, "BC1_UNORM", 8, "RGBA", Unorm, DXGI_FORMAT_BC1_TYPELESS).block_compressed(),
+    entry(DXGI_FORMAT_BC1_UNORM_SRGB, "BC1_UNORM_SRGB", 8, "RGBA", Unorm, DXGI_FORMAT_BC1_TYPELESS).block_compressed().srgb(),
+    entry(DXGI_FORMAT_BC2_TYPELESS, "BC2_TYPELESS", 16, "RGBA", Typeless, DXGI_FORMAT_BC2_TYPELESS).block_compressed(),
+    entry(DXGI_FORMAT_BC2_UNORM, "BC2_UNORM", 16, "RGBA", Unorm, DXGI_FORMAT_BC2_TYPELESS).block_compressed(),
+    entry(DXGI_FORMAT_BC2_UNORM_SRGB, "BC2_UNORM_SRGB", 16, "RGBA", Unorm, DXGI_FORMAT_BC2_TYPELESS).block_compressed().srgb(),
+    entry(DXGI_FORMAT_BC3_TYPELESS, "BC3_TYPELESS", 16, "RGBA", Typeless, DXGI_FORMAT_BC3_TYPELESS).block_compressed(),
+    entry(DXGI_FORMAT_BC3_UNORM, "BC3_UNORM", 16, "RGBA", Unorm, DXGI_FORMAT_BC3_TYPELESS).block_compressed(),
+    entry(DXGI_FORMAT_BC3_UNORM_SRGB, "BC3_UNORM_SRGB", 16, "RGBA", Unorm, DXGI_FORMAT_BC3_TYPELESS).block_compressed().srgb(),
+    entry(DXGI_FORMAT_BC4_TYPELESS, "BC4_TYPELESS", 8, "R", Typeless, DXGI_FORMAT_BC4_TYPELESS).block_compressed(),
+    entry(DXGI_FORMAT_BC4_UNORM, "BC4_UNORM", 8, "R", Unorm, DXGI_FORMAT_BC4_TYPELESS).block_compressed(),
+    entry(DXGI_FORMAT_BC4_SNORM, "BC4_SNORM", 8, "R", Snorm, DXGI_FORMAT_BC4_TYPELESS).block_compressed(),
+    entry(DXGI_FORMAT_BC5_TYPELESS, "BC5_TYPELESS", 16, "RG", Typeless, DXGI_FORMAT_BC5_TYPELESS).block_compressed(),
+    entry(DXGI_FORMAT_BC5_UNORM, "BC5_UNORM", 16, "RG", Unorm, DXGI_FORMAT_BC5_TYPELESS).block_compressed(),
+    entry(DXGI_FORMAT_BC5_SNORM, "BC5_SNORM", 16, "RG", Snorm, DXGI_FORMAT_BC5_TYPELESS).block_compressed(),
+
+    // Rendering to 16 bit BGR formats is optional before D3D 11.1.
+    entry(DXGI_FORMAT_B5G6R5_UNORM, "B5G6R5_UNORM", 2, "BGR", Unorm, DXGI_FORMAT_B5G6R5_UNORM),
+    entry(DXGI_FORMAT_B5G5R5A1_UNORM, "B5G5R5A1_UNORM", 2, "BGRA", Unorm, DXGI_FORMAT_B5G5R5A1_UNORM),
+    entry(DXGI_FORMAT_B4G4R4A4_UNORM, "B4G4R4A4_UNORM", 2, "BGRA", Unorm, DXGI_FORMAT_B4G4R4A4_UNORM),
+
+    entry(DXGI_FORMAT_B8G8R8A8_TYPELESS, "B8G8R8A8_TYPELESS", 4, "BGRA", Typeless, DXGI_FORMAT_B8G8R8A8_TYPELESS),
+    entry(DXGI_FORMAT_B8G8R8A8_UNORM, "B8G8R8A8_UNORM", 4, "BGRA", Unorm, DXGI_FORMAT_B8G8R8A8_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_B8G8R8A8_UNORM_SRGB, "B8G8R8A8_UNORM_SRGB", 4, "BGRA", Unorm, DXGI_FORMAT_B8G8R8A8_TYPELESS).srgb().renderable(),
+    entry(DXGI_FORMAT_B8G8R8X8_TYPELESS, "B8G8R8X8_TYPELESS", 4, "BGRX", Typeless, DXGI_FORMAT_B8G8R8X8_TYPELESS),
+    entry(DXGI_FORMAT_B8G8R8X8_UNORM, "B8G8R8X8_UNORM", 4, "BGRX", Unorm, DXGI_FORMAT_B8G8R8X8_TYPELESS).renderable(),
+    entry(DXGI_FORMAT_B8G8R8X8_UNORM_SRGB, "B8G8R8X8_UNORM_SRGB", 4, "BGRX", Unorm, DXGI_FORMAT_B8G8R8X8_TYPELESS).srgb().renderable(),
+
+    entry(DXGI_FORMAT_BC6H_TYPELESS, "BC6H_TYPELESS", 16, "RGB", Typeless, DXGI_FORMAT_BC6H_TYPELESS).block_compressed(),
+    entry(DXGI_FORMAT_BC6H_UF16, "BC6H_UF16", 16, "RGB", Float, DXGI_FORMAT_BC6H_TYPELESS).block_compressed(),
+    entry(DXGI_FORMAT_BC6H_SF16, "BC6H_SF16", 16, "RGB", Float, DXGI_FORMAT_BC6H_TYPELESS).block_compressed(),
+    entry(DXGI_FORMAT_BC7_TYPELESS, "BC7_TYPELESS", 16, "RGBA", Typeless, DXGI_FORMAT_BC7_TYPELESS).block_compressed(),
+    entry(DXGI_FORMAT_BC7_UNORM, "BC7_UNORM", 16, "RGBA", Unorm, DXGI_FORMAT_BC7_TYPELESS).block_compressed(),
+    entry(DXGI_FORMAT_BC7_UNORM_SRGB, "BC7_UNORM_SRGB", 16, "RGBA", Unorm, DXGI_FORMAT_BC7_TYPELESS).block_compressed().srgb(),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bits per texel as DirectXTex's `BitsPerPixel` lists them, kept apart
+    /// from the table so the two check each other.
+    fn bits_per_pixel(format: DXGI_FORMAT) -> usize {
+        match format {
+            DXGI_FORMAT_R32G32B32A32_TYPELESS
+            | DXGI_FORMAT_R32G32B32A32_FLOAT
+            | DXGI_FORMAT_R32G32B32A32_UINT
+            | DXGI_FORMAT_R32G32B32A32_SINT => 128,
+
+            DXGI_FORMAT_R32G32B32_TYPELESS
+            | DXGI_FORMAT_R32G32B32_FLOAT
+            | DXGI_FORMAT_R32G32B32_UINT
+            | DXGI_FORMAT_R32G32B32_SINT => 96,
+
+            DXGI_FORMAT_R16G16B16A16_TYPELESS
+            | DXGI_FORMAT_R16G16B16A16_FLOAT
+            | DXGI_FORMAT_R16G16B16A16_UNORM
+            | DXGI_FORMAT_R16G16B16A16_UINT
+            | DXGI_FORMAT_R16G16B16A16_SNORM
+            | DXGI_FORMAT_R16G16B16A16_SINT
+            | DXGI_FORMAT_R32G32_TYPELESS
+            | DXGI_FORMAT_R32G32_FLOAT
+            | DXGI_FORMAT_R32G32_UINT
+            | DXGI_FORMAT_R32G32_SINT
+            | DXGI_FORMAT_R32G8X24_TYPELESS
+            | DXGI_FORMAT_D32_FLOAT_S8X24_UINT
+            | DXGI_FORMAT_R32_FLOAT_X8X24_TYPELESS
+            | DXGI_FORMAT_X32_TYPELESS_G8X24_UINT => 64,
+
+            DXGI_FORMAT_R10G10B10A2_TYPELESS
+            | DXGI_FORMAT_R10G10B10A2_UNORM
+            | DXGI_FORMAT_R10G10B10A2_UINT
+            | DXGI_FORMAT_R11G11B10_FLOAT
+            | DXGI_FORMAT_R8G8B8A8_TYPELESS
+            | DXGI_FORMAT_R8G8B8A8_UNORM
+            | DXGI_FORMAT_R8G8B8A8_UNORM_SRGB
+            | DXGI_FORMAT_R8G8B8A8_UINT
+            | DXGI_FORMAT_R8G8B8A8_SNORM
+            | DXGI_FORMAT_R8G8B8A8_SINT
+            | DXGI_FORMAT_R16G16_TYPELESS
+            | DXGI_FORMAT_R16G16_FLOAT
+            | DXGI_FORMAT_R16G16_UNORM
+            | DXGI_FORMAT_R16G16_UINT
+            | DXGI_FORMAT_R16G16_SNORM
+            | DXGI_FORMAT_R16G16_SINT
+            | DXGI_FORMAT_R32_TYPELESS
+            | DXGI_FORMAT_D32_FLOAT
+            | DXGI_FORMAT_R32_FLOAT
+            | DXGI_FORMAT_R32_UINT
+            | DXGI_FORMAT_R32_SINT
+            | DXGI_FORMAT_R24G8_TYPELESS
+            | DXGI_FORMAT_D24_UNORM_S8_UINT
+            | DXGI_FORMAT_R24_UNORM_X8_TYPELESS
+            | DXGI_FORMAT_X24_TYPELESS_G8_UINT
+            | DXGI_FORMAT_R9G9B9E5_SHAREDEXP
+            | DXGI_FORMAT_B8G8R8A8_TYPELESS
+            | DXGI_FORMAT_B8G8R8A8_UNORM
+            | DXGI_FORMAT_B8G8R8A8_UNORM_SRGB
+            | DXGI_FORMAT_B8G8R8X8_TYPELESS
+            | DXGI_FORMAT_B8G8R8X8_UNORM
+            | DXGI_FORMAT_B8G8R8X8_UNORM_SRGB => 32,
+
+            DXGI_FORMAT_R8G8_TYPELESS
+            | DXGI_FORMAT_R8G8_UNORM
+            | DXGI_FORMAT_R8G8_UINT
+            | DXGI_FORMAT_R8G8_SNORM
+            | DXGI_FORMAT_R8G8_SINT
+            | DXGI_FORMAT_R16_TYPELESS
+            | DXGI_FORMAT_R16_FLOAT
+            | DXGI_FORMAT_D16_UNORM
+            | DXGI_FORMAT_R16_UNORM
+            | DXGI_FORMAT_R16_UINT
+            | DXGI_FORMAT_R16_SNORM
+            | DXGI_FORMAT_R16_SINT
+            | DXGI_FORMAT_B5G6R5_UNORM
+            | DXGI_FORMAT_B5G5R5A1_UNORM
+            | DXGI_FORMAT_B4G4R4A4_UNORM => 16,
+
+            DXGI_FORMAT_R8_TYPELESS
+            | DXGI_FORMAT_R8_UNORM
+            | DXGI_FORMAT_R8_UINT
+            | DXGI_FORMAT_R8_SNORM
+            | DXGI_FORMAT_R8_SINT
+            | DXGI_FORMAT_A8_UNORM
+            | DXGI_FORMAT_BC2_TYPELESS
+            | DXGI_FORMAT_BC2_UNORM
+            | DXGI_FORMAT_BC2_UNORM_SRGB
+            | DXGI_FORMAT_BC3_TYPELESS
+            | DXGI_FORMAT_BC3_UNORM
+            | DXGI_FORMAT_BC3_UNORM_SRGB
+            | DXGI_FORMAT_BC5_TYPELESS
+            | DXGI_FORMAT_BC5_UNORM
+            | DXGI_FORMAT_BC5_SNORM
+            | DXGI_FORMAT_BC6H_TYPELESS
+            | DXGI_FORMAT_BC6H_UF16
+            | DXGI_FORMAT_BC6H_SF16
+            | DXGI_FORMAT_BC7_TYPELESS
+            | DXGI_FORMAT_BC7_UNORM
+            | DXGI_FORMAT_BC7_UNORM_SRGB => 8,
+
+            DXGI_FORMAT_BC1_TYPELESS
+            | DXGI_FORMAT_BC1_UNORM
+            | DXGI_FORMAT_BC1_UNORM_SRGB
+            | DXGI_FORMAT_BC4_TYPELESS
+            | DXGI_FORMAT_BC4_UNORM
+            | DXGI_FORMAT_BC4_SNORM => 4,
+
+            _ => panic!("{} isn't in the test's table", format),
+        }
+    }
+
+    const SIZES: [u32; 11] = [1, 2, 3, 4, 5, 7, 8, 13, 64, 255, 4097];
+
+    #[test]
+    fn every_entry_matches_its_bits_per_pixel() {
+        for info in FORMATS {
+            let bits = bits_per_pixel(info.format);
+            let texels = (info.block_size * info.block_size) as usize;
+            assert_eq!(info.bytes * 8, bits * texels, "{}", info.name);
+        }
+    }
+
+    #[test]
+    fn every_entry_computes_sizes_like_direct_x_tex() {
+        for info in FORMATS {
+            let bits = bits_per_pixel(info.format);
+            for width in SIZES {
+                for height in SIZES {
+                    // DirectXTex's `ComputePitch` without legacy alignment.
+                    let (row_pitch, rows) = if info.is_block_compressed() {
+                        let blocks = |size: u32| (size as usize).div_ceil(4);
+                        (blocks(width) * bits * 2, blocks(height))
+                    } else {
+                        ((width as usize * bits).div_ceil(8), height as usize)
+                    };
+
+                    let name = info.name;
+                    assert_eq!(info.row_pitch(width), row_pitch, "{} {}", name, width);
+                    assert_eq!(info.rows(height), rows, "{} {}", name, height);
+                    assert_eq!(
+                        info.surface_size(width, height),
+                        row_pitch * rows,
+                        "{}",
+                        name
+                    );
+                    assert_eq!(
+                        info.subresource_size([width, height, 3]),
+                        row_pitch * rows * 3,
+                        "{}",
+                        name
+                    );
+                }
+            }
+
+            // Empty sizes count as one texel, like the last mips do.
+            assert_eq!(info.row_pitch(0), info.row_pitch(1), "{}", info.name);
+            assert_eq!(info.rows(0), 1, "{}", info.name);
+            assert_eq!(
+                info.subresource_size([0, 0, 0]),
+                info.surface_size(1, 1),
+                "{}",
+                info.name
+            );
+        }
+    }
+
+    #[test]
+    fn names_and_flags_agree() {
+        for (i, info) in FORMATS.iter().enumerate() {
+            let name = info.name;
+            assert_eq!(
+                FormatInfo::of(info.format),
+                Some(info),
+                "{} is listed twice",
+                name
+            );
+            assert!(
+                FORMATS[..i].iter().all(|other| other.name != name),
+                "{}",
+                name
+            );
+            assert_eq!(format_name(info.format), name);
+
+            assert_eq!(info.srgb, name.ends_with("_SRGB"), "{}", name);
+            assert_eq!(
+                info.is_block_compressed(),
+                name.starts_with("BC"),
+                "{}",
+                name
+            );
+            assert_eq!(info.depth, name.starts_with('D'), "{}", name);
+            assert_eq!(info.stencil, name.contains("_S8"), "{}", name);
+            assert!(!(info.depth && info.renderable), "{}", name);
+            if info.channel_type == Typeless {
+                assert!(name.ends_with("_TYPELESS"), "{}", name);
+                assert!(!info.renderable, "{}", name);
+            }
+
+            let channels = info.channels.chars().filter(|c| *c != 'X').count();
+            assert!(channels > 0 && info.channels.len() <= 4, "{}", name);
+        }
+
+        assert_eq!(FormatInfo::of(DXGI_FORMAT_UNKNOWN), None);
+        assert_eq!(format_name(999), "DXGI format 999");
+    }
+
+    #[test]
+    fn every_entry_round_trips_through_its_typeless_format() {
+        for info in FORMATS {
+            let typeless = FormatInfo::of(info.typeless())
+                .unwrap_or_else(|| panic!("{} has no typeless entry", info.name));
+
+            assert_eq!(typeless.typeless(), typeless.format, "{}", info.name);
+            assert_eq!(typeless.bytes, info.bytes, "{}", info.name);
+            assert_eq!(typeless.block_size, info.block_size, "{}", info.name);
+            if typeless.format != info.format {
+                assert_eq!(typeless.channel_type, Typeless, "{}", info.name);
+            }
+            if info.channel_type == Typeless {
+                assert_eq!(info.typeless(), info.format, "{}", info.name);
+            }
+        }
+    }
+
+    #[test]
+    fn every_entry_round_trips_through_srgb() {
+        let mut pairs = 0;
+        for info in FORMATS {
+            let srgb = FormatInfo::of(info.srgb_format()).unwrap();
+            let linear = FormatInfo::of(info.linear_format()).unwrap();
+
+            if info.srgb {
+                assert_eq!(srgb, info);
+                assert!(!linear.srgb, "{}", info.name);
+                assert_eq!(linear.srgb_format(), info.format, "{}", info.name);
+                assert_eq!(format!("{}_SRGB", linear.name), info.name);
+                pairs += 1;
+            } else {
+                assert_eq!(linear, info);
+                if srgb != info {
+                    assert!(srgb.srgb, "{}", info.name);
+                    assert_eq!(srgb.linear_format(), info.format, "{}", info.name);
+                }
+            }
+
+            // The variants view the same memory.
+            assert_eq!(srgb.typeless(), info.typeless(), "{}", info.name);
+            assert_eq!(linear.typeless(), info.typeless(), "{}", info.name);
+        }
+
+        // R8G8B8A8, B8G8R8A8, B8G8R8X8, BC1, BC2, BC3 and BC7.
+        assert_eq!(pairs, 7);
+        let srgb = |format| FormatInfo::of(format).unwrap().srgb_format();
+        assert_eq!(
+            srgb(DXGI_FORMAT_R8G8B8A8_UNORM),
+            DXGI_FORMAT_R8G8B8A8_UNORM_SRGB
+        );
+        assert_eq!(srgb(DXGI_FORMAT_BC7_UNORM), DXGI_FORMAT_BC7_UNORM_SRGB);
+        assert_eq!(srgb(DXGI_FORMAT_R8G8B8A8_SNORM), DXGI_FORMAT_R8G8B8A8_SNORM);
+        assert_eq!(
+            srgb(DXGI_FORMAT_R16G16B16A16_UNORM),
+            DXGI_FORMAT_R16G16B16A16_UNORM
+        );
+        assert_eq!(
+            srgb(DXGI_FORMAT_R8G8B8A8_TYPELESS),
+            DXGI_FORMAT_R8G8B8A8_TYPELESS
+        );
+    }
+}
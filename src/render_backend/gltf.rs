@@ -0,0 +1,1645 @@
+use glam::{const_vec3, Mat4, Quat, Vec2, Vec3, Vec4};
+
+use crate::error::{EngineError, EngineResult};
+use crate::vfs;
+
+use super::cpu_image::CpuImage;
+use super::json::Json;
+use super::mesh::{CpuMesh, Vertex};
+
+/// Extensions a file may require that we handle.
+const SUPPORTED_EXTENSIONS: [&str; 2] = ["KHR_lights_punctual", "KHR_mesh_quantization"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlphaMode {
+    Opaque,
+    /// Cut out below `GltfMaterial::alpha_cutoff`.
+    Mask,
+    Blend,
+}
+
+/// A texture slot of a material.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextureRef {
+    /// Index into `GltfScene::images`.
+    pub image: usize,
+    /// UV set 0 is `Vertex::uv`, the others are `CpuMesh::extra_uvs`.
+    pub tex_coord: usize,
+}
+
+/// A metallic-roughness material. Colors are linear, base color textures
+/// are sRGB.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GltfMaterial {
+    pub name: String,
+    pub base_color: Vec4,
+    pub base_color_texture: Option<TextureRef>,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Roughness in green, metalness in blue.
+    pub metallic_roughness_texture: Option<TextureRef>,
+    pub normal_texture: Option<TextureRef>,
+    pub normal_scale: f32,
+    pub occlusion_texture: Option<TextureRef>,
+    pub occlusion_strength: f32,
+    pub emissive: Vec3,
+    pub emissive_texture: Option<TextureRef>,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
+}
+
+impl Default for GltfMaterial {
+    /// What glTF uses for primitives without a material.
+    fn default() -> Self {
+        GltfMaterial {
+            name: String::new(),
+            base_color: Vec4::ONE,
+            base_color_texture: None,
+            metallic: 1.0,
+            roughness: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive: Vec3::ZERO,
+            emissive_texture: None,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ImageSource {
+    /// A VFS path, resolved against the glTF file.
+    File(String),
+    /// Encoded bytes from a buffer view or data URI.
+    Embedded(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GltfImage {
+    pub name: String,
+    pub source: ImageSource,
+}
+
+#[derive(Debug)]
+pub struct Primitive {
+    pub mesh: CpuMesh,
+    /// Index into `GltfScene::materials`, `None` for the default material.
+    pub material: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct GltfMesh {
+    pub name: String,
+    pub primitives: Vec<Primitive>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective {
+        /// Vertical field of view in radians.
+        yfov: f32,
+        aspect_ratio: Option<f32>,
+        znear: f32,
+        /// `None` for an infinite far plane.
+        zfar: Option<f32>,
+    },
+    Orthographic {
+        xmag: f32,
+        ymag: f32,
+        znear: f32,
+        zfar: f32,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GltfCamera {
+    pub name: String,
+    pub projection: Projection,
+}
+
+impl GltfCamera {
+    /// The left-handed projection matrix. `aspect_ratio` is used if the
+    /// camera doesn't have one.
+    pub fn projection_matrix(&self, aspect_ratio: f32) -> Mat4 {
+        match self.projection {
+            Projection::Perspective {
+                yfov,
+                aspect_ratio: own_aspect_ratio,
+                znear,
+                zfar,
+            } => {
+                let aspect_ratio = own_aspect_ratio.unwrap_or(aspect_ratio);
+                match zfar {
+                    Some(zfar) => Mat4::perspective_lh(yfov, aspect_ratio, znear, zfar),
+                    None => Mat4::perspective_infinite_lh(yfov, aspect_ratio, znear),
+                }
+            }
+            Projection::Orthographic {
+                xmag,
+                ymag,
+                znear,
+                zfar,
+            } => Mat4::orthographic_lh(-xmag, xmag, -ymag, ymag, znear, zfar),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    Directional,
+    Point,
+    /// Cone angles in radians from the light's direction.
+    Spot {
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
+}
+
+/// A `KHR_lights_punctual` light. Placed by the nodes that use it, which
+/// point it down their local +Z after the handedness conversion.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GltfLight {
+    pub name: String,
+    pub kind: LightKind,
+    /// Linear RGB.
+    pub color: Vec3,
+    /// Lux for directional lights, candela for the others.
+    pub intensity: f32,
+    /// `None` for an infinite range.
+    pub range: Option<f32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GltfNode {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// Relative to the parent.
+    pub transform: Mat4,
+    /// Relative to the scene root, identity for nodes outside the scene.
+    pub world: Mat4,
+    pub mesh: Option<usize>,
+    pub camera: Option<usize>,
+    pub light: Option<usize>,
+}
+
+/// Everything a glTF 2.0 file describes that the engine can use, converted
+/// to the engine's left-handed coordinates by mirroring Z, with UVs flipped
+/// to start at the bottom left like OBJ's. Loading doesn't need a device,
+/// images are decoded on request.
+#[derive(Debug, Default)]
+pub struct GltfScene {
+    pub meshes: Vec<GltfMesh>,
+    pub materials: Vec<GltfMaterial>,
+    pub images: Vec<GltfImage>,
+    pub cameras: Vec<GltfCamera>,
+    pub lights: Vec<GltfLight>,
+    pub nodes: Vec<GltfNode>,
+    /// Top level nodes of the default scene.
+    pub roots: Vec<usize>,
+}
+
+impl GltfScene {
+    /// Loads a `.gltf` or `.glb` file through the VFS. Buffers and images
+    /// are found relative to it.
+    pub fn load(file: &str) -> EngineResult<GltfScene> {
+        let bytes = vfs::read(file).map_err(|source| EngineError::Io {
+            path: file.to_owned(),
+            source,
+        })?;
+
+        GltfScene::parse(file, &bytes)
+    }
+
+    /// Parses the contents of `file`, telling glTF JSON and GLB apart by
+    /// their first bytes.
+    pub fn parse(file: &str, bytes: &[u8]) -> EngineResult<GltfScene> {
+        let parse_error = |message: String| EngineError::GltfParse {
+            path: file.to_owned(),
+            message,
+        };
+
+        let (json, binary) = if bytes.starts_with(b"glTF") {
+            parse_glb(bytes).map_err(parse_error)?
+        } else {
+            let text = std::str::from_utf8(bytes)
+                .map_err(|_| parse_error("neither GLB nor UTF-8 JSON".to_owned()))?;
+            (text, None)
+        };
+        let json = Json::parse(json.trim_start_matches('\u{feff}')).map_err(parse_error)?;
+
+        let dir = vfs::normalize(file)
+            .map_or_else(|_| String::new(), |file| vfs::parent(&file).to_owned());
+        let buffers = load_buffers(file, &json, &dir, binary)?;
+
+        let document = Document {
+            json: &json,
+            buffers: &buffers,
+            dir: &dir,
+        };
+        document.scene().map_err(parse_error)
+    }
+
+    /// Decodes image `index`.
+    pub fn image(&self, index: usize) -> EngineResult<CpuImage> {
+        let image = self
+            .images
+            .get(index)
+            .ok_or_else(|| EngineError::Validation(format!("there is no image {}", index)))?;
+
+        match &image.source {
+            ImageSource::File(file) => CpuImage::load(file),
+            ImageSource::Embedded(bytes) => CpuImage::decode(&image.name, bytes.clone()),
+        }
+    }
+
+    /// Nodes of the default scene, parents before their children.
+    pub fn scene_nodes(&self) -> Vec<usize> {
+        let mut order = Vec::new();
+        let mut stack: Vec<usize> = self.roots.iter().rev().copied().collect();
+        while let Some(node) = stack.pop() {
+            order.push(node);
+            stack.extend(self.nodes[node].children.iter().rev());
+        }
+        order
+    }
+}
+
+/// Splits a GLB file into its JSON and binary chunks.
+fn parse_glb(bytes: &[u8]) -> Result<(&str, Option<&[u8]>), String> {
+    let u32_at = |offset: usize| {
+        bytes
+            .get(offset..offset + 4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()) as usize)
+            .ok_or_else(|| "GLB is truncated".to_owned())
+    };
+
+    let version = u32_at(4)?;
+    if version != 2 {
+        return Err(format!("GLB version {} isn't supported", version));
+    }
+    let length = u32_at(8)?.min(bytes.len());
+
+    let mut json = None;
+    let mut binary = None;
+    let mut offset = 12;
+    while offset + 8 <= length {
+        let chunk_length = u32_at(offset)?;
+        let chunk_type = u32_at(offset + 4)?;
+        let chunk = bytes
+            .get(offset + 8..offset + 8 + chunk_length)
+            .ok_or_else(|| "GLB chunk is truncated".to_owned())?;
+        match chunk_type {
+            0x4e4f534a if json.is_none() => {
+                json = Some(std::str::from_utf8(chunk).map_err(|_| "GLB JSON isn't UTF-8")?)
+            }
+            0x004e4942 if binary.is_none() => binary = Some(chunk),
+            // Unknown chunks are skipped, as the spec asks.
+            _ => {}
+        }
+        offset += 8 + chunk_length;
+    }
+
+    Ok((json.ok_or("GLB without a JSON chunk")?, binary))
+}
+
+/// Loads every buffer: the GLB binary chunk, data URIs or files next to the
+/// glTF file.
+fn load_buffers(
+    file: &str,
+    json: &Json,
+    dir: &str,
+    binary: Option<&[u8]>,
+) -> EngineResult<Vec<Vec<u8>>> {
+    let parse_error = |message: String| EngineError::GltfParse {
+        path: file.to_owned(),
+        message,
+    };
+
+    let mut buffers = Vec::new();
+
+    for (index, buffer) in json["buffers"].members().iter().enumerate() {
+        let length = buffer["byteLength"].as_usize().unwrap_or(0);
+        let data = match buffer["uri"].as_str() {
+            Some(uri) if uri.starts_with("data:") => decode_data_uri(uri).map_err(parse_error)?,
+            Some(uri) => {
+                let file =
+                    vfs::join(dir, &percent_decode(uri)).map_err(|source| EngineError::Io {
+                        path: uri.to_owned(),
+                        source,
+                    })?;
+                vfs::read(&file).map_err(|source| EngineError::Io { path: file, source })?
+            }
+            None => match binary {
+                Some(binary) if index == 0 => binary.to_vec(),
+                _ => {
+                    return Err(parse_error(format!(
+                        "buffer {} has no URI and isn't the GLB binary chunk",
+                        index
+                    )))
+                }
+            },
+        };
+        if data.len() < length {
+            return Err(parse_error(format!(
+                "buffer {} has {} bytes instead of {}",
+                index,
+                data.len(),
+                length
+            )));
+        }
+        buffers.push(data);
+    }
+
+    Ok(buffers)
+}
+
+/// The bytes of a base64 `data:` URI.
+fn decode_data_uri(uri: &str) -> Result<Vec<u8>, String> {
+    let (header, data) = uri
+        .split_once(',')
+        .ok_or_else(|| "data URI without a comma".to_owned())?;
+    if !header.ends_with(";base64") {
+        return Err("only base64 data URIs are supported".to_owned());
+    }
+
+    let mut bytes = Vec::with_capacity(data.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for byte in data.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' | b' ' | b'\n' | b'\r' | b'\t' => continue,
+            _ => return Err("malformed base64 in data URI".to_owned()),
+        };
+        bits = (bits << 6) | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            bytes.push((bits >> bit_count) as u8);
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Undoes `%20` style escapes of relative URIs.
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Mirrors Z, turning glTF's right-handed coordinates into left-handed ones.
+const MIRROR_Z: Vec3 = const_vec3!([1.0, 1.0, -1.0]);
+
+fn mirror_z(matrix: Mat4) -> Mat4 {
+    let mirror = Mat4::from_scale(MIRROR_Z);
+    mirror * matrix * mirror
+}
+
+struct Document<'a> {
+    json: &'a Json,
+    buffers: &'a [Vec<u8>],
+    dir: &'a str,
+}
+
+/// Raw accessor values, not yet normalized.
+struct AccessorData {
+    components: usize,
+    component_type: usize,
+    normalized: bool,
+    values: Vec<f64>,
+}
+
+impl AccessorData {
+    fn floats(&self) -> Vec<f32> {
+        let scale = match (self.normalized, self.component_type) {
+            (false, _) | (_, 5126) => 1.0,
+            (_, 5120) => 127.0,
+            (_, 5121) => 255.0,
+            (_, 5122) => 32767.0,
+            (_, 5123) => 65535.0,
+            _ => u32::MAX as f64,
+        };
+        self.values
+            .iter()
+            .map(|value| (value / scale).max(-1.0) as f32)
+            .collect()
+    }
+}
+
+fn component_size(component_type: usize) -> Option<usize> {
+    match component_type {
+        5120 | 5121 => Some(1),
+        5122 | 5123 => Some(2),
+        5125 | 5126 => Some(4),
+        _ => None,
+    }
+}
+
+fn read_component(bytes: &[u8], component_type: usize) -> f64 {
+    match component_type {
+        5120 => bytes[0] as i8 as f64,
+        5121 => bytes[0] as f64,
+        5122 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+        5123 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+        5125 => u32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64,
+        _ => f32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64,
+    }
+}
+
+impl Document<'_> {
+    fn scene(&self) -> Result<GltfScene, String> {
+        let json = self.json;
+
+        let version = json["asset"]["version"].as_str().unwrap_or("");
+        if !version.starts_with("2.") {
+            return Err(format!("glTF version {} isn't supported", version));
+        }
+        for extension in json["extensionsRequired"].members() {
+            let extension = extension.as_str().unwrap_or("");
+            if !SUPPORTED_EXTENSIONS.contains(&extension) {
+                return Err(format!("requires the unsupported extension {}", extension));
+            }
+        }
+
+        let images = json["images"]
+            .members()
+            .iter()
+            .enumerate()
+            .map(|(index, image)| self.image(index, image))
+            .collect::<Result<_, _>>()?;
+        let materials = json["materials"]
+            .members()
+            .iter()
+            .map(|material| self.material(material))
+            .collect();
+        let meshes = json["meshes"]
+            .members()
+            .iter()
+            .enumerate()
+            .map(|(index, mesh)| self.mesh(index, mesh))
+            .collect::<Result<_, _>>()?;
+        let cameras = json["cameras"]
+            .members()
+            .iter()
+            .enumerate()
+            .map(|(index, camera)| camera_from_json(index, camera))
+            .collect::<Result<_, _>>()?;
+        let lights = json["extensions"]["KHR_lights_punctual"]["lights"]
+            .members()
+            .iter()
+            .enumerate()
+            .map(|(index, light)| light_from_json(index, light))
+            .collect::<Result<_, _>>()?;
+
+        let mut scene = GltfScene {
+            images,
+            materials,
+            meshes,
+            cameras,
+            lights,
+            ..Default::default()
+        };
+        self.nodes(&mut scene)?;
+
+        Ok(scene)
+    }
+
+    fn image(&self, index: usize, image: &Json) -> Result<GltfImage, String> {
+        let name = image["name"]
+            .as_str()
+            .map_or_else(|| format!("image {}", index), str::to_owned);
+
+        let source = if let Some(uri) = image["uri"].as_str() {
+            if uri.starts_with("data:") {
+                ImageSource::Embedded(decode_data_uri(uri)?)
+            } else {
+                let file = vfs::join(self.dir, &percent_decode(uri))
+                    .map_err(|err| format!("image {}: {}", index, err))?;
+                ImageSource::File(file)
+            }
+        } else if let Some(view) = image["bufferView"].as_usize() {
+            ImageSource::Embedded(self.buffer_view(view)?.to_vec())
+        } else {
+            return Err(format!(
+                "image {} has neither a URI nor a buffer view",
+                index
+            ));
+        };
+
+        Ok(GltfImage { name, source })
+    }
+
+    fn buffer_view(&self, index: usize) -> Result<&[u8], String> {
+        let view = &self.json["bufferViews"][index];
+        let buffer = view["buffer"]
+            .as_usize()
+            .and_then(|buffer| self.buffers.get(buffer))
+            .ok_or_else(|| format!("buffer view {} has no buffer", index))?;
+        let offset = view["byteOffset"].as_usize().unwrap_or(0);
+        let length = view["byteLength"].as_usize().unwrap_or(0);
+
+        offset
+            .checked_add(length)
+            .and_then(|end| buffer.get(offset..end))
+            .ok_or_else(|| format!("buffer view {} is out of bounds", index))
+    }
+
+    /// Maps a `textureInfo` to the image of its texture. Textures whose
+    /// image comes from an extension we don't support are dropped.
+    fn texture(&self, info: &Json) -> Option<TextureRef> {
+        let texture = &self.json["textures"][info["index"].as_usize()?];
+        Some(TextureRef {
+            image: texture["source"].as_usize()?,
+            tex_coord: info["texCoord"].as_usize().unwrap_or(0),
+        })
+    }
+
+    fn material(&self, material: &Json) -> GltfMaterial {
+        let defaults = GltfMaterial::default();
+        let pbr = &material["pbrMetallicRoughness"];
+        let number = |json: &Json, default: f32| json.as_f32().unwrap_or(default);
+
+        GltfMaterial {
+            name: material["name"].as_str().unwrap_or("").to_owned(),
+            base_color: pbr["baseColorFactor"]
+                .as_f32_array::<4>()
+                .map_or(defaults.base_color, Vec4::from),
+            base_color_texture: self.texture(&pbr["baseColorTexture"]),
+            metallic: number(&pbr["metallicFactor"], defaults.metallic),
+            roughness: number(&pbr["roughnessFactor"], defaults.roughness),
+            metallic_roughness_texture: self.texture(&pbr["metallicRoughnessTexture"]),
+            normal_texture: self.texture(&material["normalTexture"]),
+            normal_scale: number(&material["normalTexture"]["scale"], defaults.normal_scale),
+            occlusion_texture: self.texture(&material["occlusionTexture"]),
+            occlusion_strength: number(
+                &material["occlusionTexture"]["strength"],
+                defaults.occlusion_strength,
+            ),
+            emissive: material["emissiveFactor"]
+                .as_f32_array::<3>()
+                .map_or(defaults.emissive, Vec3::from),
+            emissive_texture: self.texture(&material["emissiveTexture"]),
+            alpha_mode: match material["alphaMode"].as_str() {
+                Some("MASK") => AlphaMode::Mask,
+                Some("BLEND") => AlphaMode::Blend,
+                _ => AlphaMode::Opaque,
+            },
+            alpha_cutoff: number(&material["alphaCutoff"], defaults.alpha_cutoff),
+            double_sided: material["doubleSided"].as_bool().unwrap_or(false),
+        }
+    }
+
+    fn accessor(&self, index: usize) -> Result<AccessorData, String> {
+        let accessor = &self.json["accessors"][index];
+        let error = |message: &str| format!("accessor {}: {}", index, message);
+
+        let component_type = accessor["componentType"].as_usize().unwrap_or(0);
+        let size = component_size(component_type).ok_or_else(|| error("unknown component type"))?;
+        let components = match accessor["type"].as_str() {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") | Some("MAT2") => 4,
+            Some("MAT3") => 9,
+            Some("MAT4") => 16,
+            _ => return Err(error("unknown type")),
+        };
+        let count = accessor["count"]
+            .as_usize()
+            .ok_or_else(|| error("no count"))?;
+
+        let value_count = count
+            .checked_mul(components)
+            .ok_or_else(|| error("count too large"))?;
+        let view = match accessor["bufferView"].as_usize() {
+            Some(view_index) => {
+                let view = self.buffer_view(view_index)?;
+                let element_size = size * components;
+                let stride = self.json["bufferViews"][view_index]["byteStride"]
+                    .as_usize()
+                    .unwrap_or(element_size);
+                let offset = accessor["byteOffset"].as_usize().unwrap_or(0);
+                let end = stride
+                    .checked_mul(count.saturating_sub(1))
+                    .and_then(|last| last.checked_add(offset))
+                    .and_then(|last| last.checked_add(element_size));
+                if count > 0 && end.is_none_or(|end| end > view.len()) {
+                    return Err(error("out of bounds of its buffer view"));
+                }
+                Some((view, offset, stride))
+            }
+            None => None,
+        };
+
+        // Accessors without a buffer view are zeros, unless sparse says
+        // otherwise.
+        let mut values = vec![0.0; value_count];
+        if let Some((view, offset, stride)) = view {
+            for (element, chunk) in values.chunks_exact_mut(components).enumerate() {
+                let start = offset + stride * element;
+                for (component, value) in chunk.iter_mut().enumerate() {
+                    let at = start + component * size;
+                    *value = read_component(&view[at..at + size], component_type);
+                }
+            }
+        }
+
+        let sparse = &accessor["sparse"];
+        if let Some(sparse_count) = sparse["count"].as_usize() {
+            let indices = &sparse["indices"];
+            let index_type = indices["componentType"].as_usize().unwrap_or(0);
+            let index_size = component_size(index_type)
+                .filter(|_| index_type != 5126)
+                .ok_or_else(|| error("unknown sparse index type"))?;
+            let index_view = self.buffer_view(
+                indices["bufferView"]
+                    .as_usize()
+                    .ok_or_else(|| error("sparse indices without a buffer view"))?,
+            )?;
+            let index_offset = indices["byteOffset"].as_usize().unwrap_or(0);
+            let value_view = self.buffer_view(
+                sparse["values"]["bufferView"]
+                    .as_usize()
+                    .ok_or_else(|| error("sparse values without a buffer view"))?,
+            )?;
+            let value_offset = sparse["values"]["byteOffset"].as_usize().unwrap_or(0);
+
+            let fits = |offset: usize, element_size: usize, view: &[u8]| {
+                element_size
+                    .checked_mul(sparse_count)
+                    .and_then(|length| length.checked_add(offset))
+                    .is_some_and(|end| end <= view.len())
+            };
+            if !fits(index_offset, index_size, index_view)
+                || !fits(value_offset, size * components, value_view)
+            {
+                return Err(error("sparse data out of bounds"));
+            }
+            for i in 0..sparse_count {
+                let at = index_offset + i * index_size;
+                let target = read_component(&index_view[at..at + index_size], index_type) as usize;
+                if target >= count {
+                    return Err(error("sparse index out of range"));
+                }
+                for component in 0..components {
+                    let at = value_offset + (i * components + component) * size;
+                    values[target * components + component] =
+                        read_component(&value_view[at..at + size], component_type);
+                }
+            }
+        }
+
+        Ok(AccessorData {
+            components,
+            component_type,
+            normalized: accessor["normalized"].as_bool().unwrap_or(false),
+            values,
+        })
+    }
+
+    /// The floats of a vertex attribute with `components` per vertex and
+    /// `count` vertices, `None` if the primitive doesn't have it.
+    fn attribute(
+        &self,
+        attributes: &Json,
+        name: &str,
+        components: usize,
+        count: Option<usize>,
+    ) -> Result<Option<Vec<f32>>, String> {
+        let index = match attributes[name].as_usize() {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let data = self.accessor(index)?;
+        if data.components != components {
+            return Err(format!(
+                "{} has {} components instead of {}",
+                name, data.components, components
+            ));
+        }
+        if count.is_some_and(|count| data.values.len() != count * components) {
+            return Err(format!("{} has another vertex count than POSITION", name));
+        }
+
+        Ok(Some(data.floats()))
+    }
+
+    fn mesh(&self, index: usize, mesh: &Json) -> Result<GltfMesh, String> {
+        let name = mesh["name"].as_str().unwrap_or("").to_owned();
+
+        let mut primitives = Vec::new();
+        for primitive in mesh["primitives"].members() {
+            if let Some(primitive) = self
+                .primitive(primitive)
+                .map_err(|message| format!("mesh {} {}: {}", index, name, message))?
+            {
+                primitives.push(primitive);
+            }
+        }
+
+        Ok(GltfMesh { name, primitives })
+    }
+
+    /// Triangles of a primitive, `None` for points and lines.
+    fn primitive(&self, primitive: &Json) -> Result<Option<Primitive>, String> {
+        let mode = primitive["mode"].as_usize().unwrap_or(4);
+        let attributes = &primitive["attributes"];
+        let positions = match (mode, self.attribute(attributes, "POSITION", 3, None)?) {
+            (4..=6, Some(positions)) => positions,
+            _ => return Ok(None),
+        };
+        let count = positions.len() / 3;
+
+        let normals = self.attribute(attributes, "NORMAL", 3, Some(count))?;
+        let tangents = self.attribute(attributes, "TANGENT", 4, Some(count))?;
+        let mut uv_sets = Vec::new();
+        while let Some(uvs) = self.attribute(
+            attributes,
+            &format!("TEXCOORD_{}", uv_sets.len()),
+            2,
+            Some(count),
+        )? {
+            uv_sets.push(uvs);
+        }
+
+        let indices: Vec<u32> = match primitive["indices"].as_usize() {
+            Some(accessor) => {
+                let data = self.accessor(accessor)?;
+                if data.components != 1 || data.component_type == 5126 {
+                    return Err("indices aren't scalar integers".to_owned());
+                }
+                data.values.iter().map(|&index| index as u32).collect()
+            }
+            None => (0..count as u32).collect(),
+        };
+        if indices.iter().any(|&index| index as usize >= count) {
+            return Err("index out of range".to_owned());
+        }
+
+        let corners = indices.len();
+        let triangles: Vec<[u32; 3]> = match mode {
+            4 => indices
+                .chunks_exact(3)
+                .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+                .collect(),
+            5 => (0..corners.saturating_sub(2))
+                .map(|i| [indices[i], indices[i + 1 + i % 2], indices[i + 2 - i % 2]])
+                .collect(),
+            _ => (1..corners.saturating_sub(1))
+                .map(|i| [indices[i], indices[i + 1], indices[0]])
+                .collect(),
+        };
+
+        let vec3 = |values: &[f32], i: usize| {
+            Vec3::new(values[i * 3], values[i * 3 + 1], values[i * 3 + 2])
+        };
+        // glTF puts the UV origin at the top left, OBJ and the shaders at the
+        // bottom left.
+        let uv = |values: &[f32], i: usize| Vec2::new(values[i * 2], 1.0 - values[i * 2 + 1]);
+
+        let mut mesh = CpuMesh {
+            vertices: (0..count)
+                .map(|i| Vertex {
+                    position: vec3(&positions, i) * MIRROR_Z,
+                    normal: normals
+                        .as_ref()
+                        .map_or(Vec3::ZERO, |normals| vec3(normals, i) * MIRROR_Z),
+                    uv: uv_sets.first().map_or(Vec2::ZERO, |uvs| uv(uvs, i)),
+                    // Mirroring Z flips the handedness of the bitangent and
+                    // flipping V flips it back, so w is kept.
+                    tangent: tangents.as_ref().map_or(Vec4::ZERO, |tangents| {
+                        let t = &tangents[i * 4..i * 4 + 4];
+                        Vec4::new(t[0], t[1], -t[2], t[3])
+                    }),
+                })
+                .collect(),
+            // Mirroring turns counter-clockwise front faces clockwise,
+            // reversing the winding keeps them facing the same way.
+            indices: triangles.iter().flat_map(|&[a, b, c]| [a, c, b]).collect(),
+            extra_uvs: uv_sets
+                .iter()
+                .skip(1)
+                .map(|uvs| (0..count).map(|i| uv(uvs, i)).collect())
+                .collect(),
+        };
+
+        // Without normals glTF asks for flat shading, and without tangents
+        // for MikkTSpace ones where a normal map needs them.
+        if normals.is_none() {
+            mesh.generate_flat_normals();
+        }
+        let material = primitive["material"].as_usize();
+        let normal_mapped = material
+            .is_some_and(|material| !self.json["materials"][material]["normalTexture"].is_null());
+        if tangents.is_none() && normal_mapped {
+            mesh.generate_tangents();
+        }
+
+        Ok(Some(Primitive { mesh, material }))
+    }
+
+    fn nodes(&self, scene: &mut GltfScene) -> Result<(), String> {
+        let json_nodes = self.json["nodes"].members();
+
+        for (index, node) in json_nodes.iter().enumerate() {
+            let transform = match node["matrix"].as_f32_array::<16>() {
+                Some(matrix) => Mat4::from_cols_array(&matrix),
+                None => Mat4::from_scale_rotation_translation(
+                    node["scale"]
+                        .as_f32_array::<3>()
+                        .map_or(Vec3::ONE, Vec3::from),
+                    node["rotation"]
+                        .as_f32_array::<4>()
+                        .map_or(Quat::IDENTITY, |[x, y, z, w]| Quat::from_xyzw(x, y, z, w)),
+                    node["translation"]
+                        .as_f32_array::<3>()
+                        .map_or(Vec3::ZERO, Vec3::from),
+                ),
+            };
+            let reference = |value: &Json, len: usize, what: &str| match value.as_usize() {
+                Some(reference) if reference >= len => Err(format!(
+                    "node {} uses missing {} {}",
+                    index, what, reference
+                )),
+                reference => Ok(reference),
+            };
+
+            scene.nodes.push(GltfNode {
+                name: node["name"].as_str().unwrap_or("").to_owned(),
+                parent: None,
+                children: Vec::new(),
+                transform: mirror_z(transform),
+                world: Mat4::IDENTITY,
+                mesh: reference(&node["mesh"], scene.meshes.len(), "mesh")?,
+                camera: reference(&node["camera"], scene.cameras.len(), "camera")?,
+                light: reference(
+                    &node["extensions"]["KHR_lights_punctual"]["light"],
+                    scene.lights.len(),
+                    "light",
+                )?,
+            });
+        }
+
+        for (index, node) in json_nodes.iter().enumerate() {
+            for child in node["children"].members() {
+                let child = child
+                    .as_usize()
+                    .filter(|&child| child < scene.nodes.len())
+                    .ok_or_else(|| format!("node {} has a missing child", index))?;
+                if scene.nodes[child].parent.is_some() || child == index {
+                    return Err(format!("node {} has more than one parent", child));
+                }
+                scene.nodes[child].parent = Some(index);
+                scene.nodes[index].children.push(child);
+            }
+        }
+
+        let scene_index = self.json["scene"].as_usize().unwrap_or(0);
+        scene.roots = match self.json["scenes"][scene_index]["nodes"].members() {
+            [] if self.json["scenes"].members().is_empty() => (0..scene.nodes.len())
+                .filter(|&node| scene.nodes[node].parent.is_none())
+                .collect(),
+            roots => roots
+                .iter()
+                .map(|root| {
+                    root.as_usize()
+                        .filter(|&root| {
+                            scene
+                                .nodes
+                                .get(root)
+                                .is_some_and(|node| node.parent.is_none())
+                        })
+                        .ok_or_else(|| "scene root is missing or has a parent".to_owned())
+                })
+                .collect::<Result<_, _>>()?,
+        };
+
+        // Every node has one parent at most, so walking down from the roots
+        // can't loop. Cycles have no root and are never reached.
+        let order = scene.scene_nodes();
+        for &node in &order {
+            let parent = scene.nodes[node]
+                .parent
+                .map_or(Mat4::IDENTITY, |parent| scene.nodes[parent].world);
+            scene.nodes[node].world = parent * scene.nodes[node].transform;
+        }
+
+        Ok(())
+    }
+}
+
+fn camera_from_json(index: usize, camera: &Json) -> Result<GltfCamera, String> {
+    let error = || format!("camera {} is incomplete", index);
+    let projection = match camera["type"].as_str() {
+        Some("perspective") => {
+            let perspective = &camera["perspective"];
+            Projection::Perspective {
+                yfov: perspective["yfov"].as_f32().ok_or_else(error)?,
+                aspect_ratio: perspective["aspectRatio"].as_f32(),
+                znear: perspective["znear"].as_f32().ok_or_else(error)?,
+                zfar: perspective["zfar"].as_f32(),
+            }
+        }
+        Some("orthographic") => {
+            let orthographic = &camera["orthographic"];
+            Projection::Orthographic {
+                xmag: orthographic["xmag"].as_f32().ok_or_else(error)?,
+                ymag: orthographic["ymag"].as_f32().ok_or_else(error)?,
+                znear: orthographic["znear"].as_f32().ok_or_else(error)?,
+                zfar: orthographic["zfar"].as_f32().ok_or_else(error)?,
+            }
+        }
+        _ => return Err(format!("camera {} has an unknown type", index)),
+    };
+
+    Ok(GltfCamera {
+        name: camera["name"].as_str().unwrap_or("").to_owned(),
+        projection,
+    })
+}
+
+fn light_from_json(index: usize, light: &Json) -> Result<GltfLight, String> {
+    let kind = match light["type"].as_str() {
+        Some("directional") => LightKind::Directional,
+        Some("point") => LightKind::Point,
+        Some("spot") => LightKind::Spot {
+            inner_cone_angle: light["spot"]["innerConeAngle"].as_f32().unwrap_or(0.0),
+            outer_cone_angle: light["spot"]["outerConeAngle"]
+                .as_f32()
+                .unwrap_or(std::f32::consts::FRAC_PI_4),
+        },
+        _ => return Err(format!("light {} has an unknown type", index)),
+    };
+
+    Ok(GltfLight {
+        name: light["name"].as_str().unwrap_or("").to_owned(),
+        kind,
+        color: light["color"]
+            .as_f32_array::<3>()
+            .map_or(Vec3::ONE, Vec3::from),
+        intensity: light["intensity"].as_f32().unwrap_or(1.0),
+        range: light["range"].as_f32(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base64(bytes: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut text = String::new();
+        for chunk in bytes.chunks(3) {
+            let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+                bits | (byte as u32) << (16 - 8 * i)
+            });
+            for i in 0..=chunk.len() {
+                text.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+            }
+        }
+        text
+    }
+
+    fn floats(values: &[f32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+
+    /// A glTF file with `buffer` as its only buffer and `body` spliced into
+    /// the top level object.
+    fn parse(buffer: &[u8], body: &str) -> EngineResult<GltfScene> {
+        let json = format!(
+            r#"{{"asset": {{"version": "2.0"}},
+                "buffers": [{{"byteLength": {}, "uri": "data:application/octet-stream;base64,{}"}}],
+                {}}}"#,
+            buffer.len(),
+            base64(buffer),
+            body
+        );
+        GltfScene::parse("test.gltf", json.as_bytes())
+    }
+
+    /// A primitive of float `attributes`, given as name, type and byte
+    /// length, read from consecutive buffer views.
+    fn triangle_body(attributes: &[(&str, &str, usize)], mode: usize) -> String {
+        let mut views = Vec::new();
+        let mut accessors = Vec::new();
+        let mut offset = 0;
+        let mut primitive = Vec::new();
+        for (i, (name, kind, length)) in attributes.iter().enumerate() {
+            views.push(format!(
+                r#"{{"buffer": 0, "byteOffset": {}, "byteLength": {}}}"#,
+                offset, length
+            ));
+            let components = match *kind {
+                "VEC2" => 2,
+                "VEC3" => 3,
+                _ => 4,
+            };
+            accessors.push(format!(
+                r#"{{"bufferView": {}, "componentType": 5126, "count": {}, "type": "{}"}}"#,
+                i,
+                length / 4 / components,
+                kind
+            ));
+            primitive.push(format!(r#""{}": {}"#, name, i));
+            offset += length;
+        }
+        format!(
+            r#""bufferViews": [{}], "accessors": [{}],
+               "meshes": [{{"primitives": [{{"attributes": {{{}}}, "mode": {}}}]}}]"#,
+            views.join(","),
+            accessors.join(","),
+            primitive.join(","),
+            mode
+        )
+    }
+
+    #[test]
+    fn converts_to_left_handed_coordinates() {
+        let mut buffer = floats(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.5, 0.0, 1.0, 2.0]);
+        buffer.extend(floats(&[0.0, 0.0, 1.0].repeat(3)));
+        buffer.extend(floats(&[0.0, 0.0, 1.0, 0.0, 0.0, 1.0]));
+        buffer.extend(floats(&[1.0, 0.0, 0.5, 1.0].repeat(3)));
+        let body = triangle_body(
+            &[
+                ("POSITION", "VEC3", 36),
+                ("NORMAL", "VEC3", 36),
+                ("TEXCOORD_0", "VEC2", 24),
+                ("TANGENT", "VEC4", 48),
+            ],
+            4,
+        );
+
+        let scene = parse(&buffer, &body).unwrap();
+        let mesh = &scene.meshes[0].primitives[0].mesh;
+        let vertices = &mesh.vertices;
+        assert_eq!(vertices[1].position, Vec3::new(1.0, 0.0, -0.5));
+        assert_eq!(vertices[2].position, Vec3::new(0.0, 1.0, -2.0));
+        assert_eq!(vertices[0].normal, -Vec3::Z);
+        // Counter-clockwise front faces become clockwise ones.
+        assert_eq!(mesh.indices, [0, 2, 1]);
+        // V runs up from the bottom left.
+        assert_eq!(vertices[0].uv, Vec2::new(0.0, 1.0));
+        assert_eq!(vertices[1].uv, Vec2::new(1.0, 1.0));
+        // Z mirrors, the handedness in w stays.
+        assert_eq!(vertices[0].tangent, Vec4::new(1.0, 0.0, -0.5, 1.0));
+    }
+
+    #[test]
+    fn triangulates_strips_and_fans() {
+        let mut buffer = floats(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0]);
+        buffer.extend(floats(&[0.0, 0.0, 1.0].repeat(4)));
+        let attributes = [("POSITION", "VEC3", 48), ("NORMAL", "VEC3", 48)];
+
+        for (mode, indices) in [(5, [0, 2, 1, 1, 2, 3]), (6, [1, 0, 2, 2, 0, 3])] {
+            let scene = parse(&buffer, &triangle_body(&attributes, mode)).unwrap();
+            assert_eq!(
+                scene.meshes[0].primitives[0].mesh.indices, indices,
+                "mode {}",
+                mode
+            );
+        }
+
+        // Points and lines are skipped.
+        let scene = parse(&buffer, &triangle_body(&attributes, 1)).unwrap();
+        assert!(scene.meshes[0].primitives.is_empty());
+    }
+
+    #[test]
+    fn reads_normalized_strided_and_sparse_accessors() {
+        // Interleaved u8 UVs padded to 4 bytes, then a sparse override of
+        // element 1 with a u16 index.
+        let mut buffer = vec![255, 0, 0, 0, 0, 255, 0, 0, 51, 102, 0, 0];
+        buffer.extend([1, 0, 0, 0]);
+        buffer.extend(floats(&[9.0, 8.0, 7.0]));
+        let json = r#"
+            "bufferViews": [
+                {"buffer": 0, "byteOffset": 0, "byteLength": 12, "byteStride": 4},
+                {"buffer": 0, "byteOffset": 12, "byteLength": 2},
+                {"buffer": 0, "byteOffset": 16, "byteLength": 12}
+            ],
+            "accessors": [
+                {"bufferView": 0, "componentType": 5121, "normalized": true, "count": 3, "type": "VEC2"},
+                {"componentType": 5126, "count": 3, "type": "VEC3", "sparse": {
+                    "count": 1,
+                    "indices": {"bufferView": 1, "componentType": 5123},
+                    "values": {"bufferView": 2}
+                }}
+            ],
+            "meshes": [{"primitives": [{"attributes": {"POSITION": 1, "TEXCOORD_0": 0}}]}]"#;
+
+        let scene = parse(&buffer, json).unwrap();
+        // Flat normals get generated, which reorders the vertices.
+        let vertices = &scene.meshes[0].primitives[0].mesh.vertices;
+        let uv_at = |position: Vec3| {
+            vertices
+                .iter()
+                .find(|vertex| vertex.position == position)
+                .map(|vertex| vertex.uv)
+        };
+        assert_eq!(uv_at(Vec3::new(9.0, 8.0, -7.0)), Some(Vec2::new(0.0, 0.0)));
+        let uvs: Vec<Vec2> = vertices.iter().map(|vertex| vertex.uv).collect();
+        assert!(uvs.contains(&Vec2::new(1.0, 1.0)));
+        assert!(uvs
+            .iter()
+            .any(|uv| uv.abs_diff_eq(Vec2::new(0.2, 0.6), 1e-6)));
+        assert_eq!(
+            vertices.iter().filter(|v| v.position == Vec3::ZERO).count(),
+            2
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_data_without_overflowing() {
+        let buffer = floats(&[0.0; 9]);
+        let max = u32::MAX;
+        let accessor = |view: &str, accessor: &str| {
+            format!(
+                r#""bufferViews": [{{"buffer": 0, {}}}],
+                   "accessors": [{{"bufferView": 0, "componentType": 5126, "type": "VEC3", {}}}],
+                   "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}}}]}}]"#,
+                view, accessor
+            )
+        };
+
+        for (view, accessor_fields) in [
+            (r#""byteLength": 36"#.to_owned(), r#""count": 4"#.to_owned()),
+            (
+                r#""byteLength": 36"#.to_owned(),
+                format!(r#""count": 3, "byteOffset": {}"#, max),
+            ),
+            (
+                r#""byteLength": 36"#.to_owned(),
+                format!(r#""count": {}"#, max),
+            ),
+            (
+                format!(r#""byteLength": 36, "byteStride": {}"#, max),
+                r#""count": 3"#.to_owned(),
+            ),
+            (
+                format!(r#""byteLength": {}, "byteOffset": {}"#, max, max),
+                r#""count": 3"#.to_owned(),
+            ),
+            (r#""byteLength": 40"#.to_owned(), r#""count": 3"#.to_owned()),
+        ] {
+            let result = parse(&buffer, &accessor(&view, &accessor_fields));
+            assert!(result.is_err(), "{} {}", view, accessor_fields);
+        }
+
+        assert!(parse(&buffer, &accessor(r#""byteLength": 36"#, r#""count": 3"#)).is_ok());
+    }
+
+    fn glb(chunks: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (kind, data) in chunks {
+            body.extend((data.len() as u32).to_le_bytes());
+            body.extend(kind.to_le_bytes());
+            body.extend(*data);
+        }
+        let mut bytes = b"glTF".to_vec();
+        bytes.extend(2u32.to_le_bytes());
+        bytes.extend((12 + body.len() as u32).to_le_bytes());
+        bytes.extend(body);
+        bytes
+    }
+
+    const JSON_CHUNK: u32 = 0x4e4f534a;
+    const BIN_CHUNK: u32 = 0x004e4942;
+
+    #[test]
+    fn splits_glb_chunks() {
+        let bytes = glb(&[(JSON_CHUNK, b"{}  "), (BIN_CHUNK, &[1, 2, 3, 4])]);
+        assert_eq!(
+            parse_glb(&bytes).unwrap(),
+            ("{}  ", Some(&[1u8, 2, 3, 4][..]))
+        );
+
+        // Unknown chunks and chunks past the stated length are skipped.
+        let mut bytes = glb(&[(0x1234, b"skip"), (JSON_CHUNK, b"{}  ")]);
+        bytes.extend(4u32.to_le_bytes());
+        bytes.extend(BIN_CHUNK.to_le_bytes());
+        bytes.extend([9; 4]);
+        assert_eq!(parse_glb(&bytes).unwrap(), ("{}  ", None));
+
+        // Only the first chunk of each kind counts.
+        let bytes = glb(&[(JSON_CHUNK, b"[1] "), (JSON_CHUNK, b"[2] ")]);
+        assert_eq!(parse_glb(&bytes).unwrap().0, "[1] ");
+    }
+
+    #[test]
+    fn rejects_malformed_glb() {
+        let valid = glb(&[(JSON_CHUNK, b"{}  ")]);
+        assert!(parse_glb(&valid[..10]).is_err());
+        assert!(parse_glb(&valid[..valid.len() - 1]).is_err());
+
+        let mut version = valid.clone();
+        version[4] = 1;
+        assert!(parse_glb(&version).is_err());
+
+        assert!(parse_glb(&glb(&[(BIN_CHUNK, &[0; 4])])).is_err());
+        assert!(parse_glb(&glb(&[(JSON_CHUNK, &[0xff, 0xfe, 0, 0])])).is_err());
+
+        let mut huge_chunk = valid;
+        huge_chunk[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(parse_glb(&huge_chunk).is_err());
+    }
+
+    #[test]
+    fn parses_glb_scenes() {
+        let buffer = floats(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        let json = r#"{"asset": {"version": "2.0"}, "buffers": [{"byteLength": 36}],
+            "bufferViews": [{"buffer": 0, "byteLength": 36}],
+            "accessors": [{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"}],
+            "meshes": [{"primitives": [{"attributes": {"POSITION": 0}}]}]}  "#;
+        let bytes = glb(&[(JSON_CHUNK, json.as_bytes()), (BIN_CHUNK, &buffer)]);
+
+        let scene = GltfScene::parse("test.glb", &bytes).unwrap();
+        assert_eq!(scene.meshes[0].primitives[0].mesh.vertices.len(), 3);
+
+        // A buffer without a URI needs the binary chunk.
+        let bytes = glb(&[(JSON_CHUNK, json.as_bytes())]);
+        assert!(GltfScene::parse("test.glb", &bytes).is_err());
+    }
+
+    #[test]
+    fn reads_materials_and_alpha_modes() {
+        let json = r#"
+            "images": [{"uri": "textures/albedo.png"}, {"uri": "normal%20map.png"}],
+            "textures": [{"source": 0}, {"source": 1}, {"extensions": {"EXT_texture_webp": {"source": 2}}}],
+            "materials": [
+                {},
+                {"name": "cutout", "alphaMode": "MASK", "alphaCutoff": 0.3, "doubleSided": true,
+                 "pbrMetallicRoughness": {
+                     "baseColorFactor": [0.5, 0.25, 1.0, 0.75],
+                     "baseColorTexture": {"index": 0},
+                     "metallicFactor": 0.2,
+                     "roughnessFactor": 0.6,
+                     "metallicRoughnessTexture": {"index": 0, "texCoord": 1}
+                 },
+                 "normalTexture": {"index": 1, "scale": 0.5},
+                 "occlusionTexture": {"index": 0, "strength": 0.4},
+                 "emissiveTexture": {"index": 2},
+                 "emissiveFactor": [1.0, 0.5, 0.0]},
+                {"alphaMode": "BLEND"},
+                {"alphaMode": "unknown", "pbrMetallicRoughness": {"baseColorFactor": [1.0, 0.0]}}
+            ]"#;
+
+        let scene = parse(&[], json).unwrap();
+        assert_eq!(scene.materials[0], GltfMaterial::default());
+
+        let cutout = &scene.materials[1];
+        assert_eq!(cutout.name, "cutout");
+        assert_eq!(cutout.alpha_mode, AlphaMode::Mask);
+        assert_eq!(cutout.alpha_cutoff, 0.3);
+        assert!(cutout.double_sided);
+        assert_eq!(cutout.base_color, Vec4::new(0.5, 0.25, 1.0, 0.75));
+        assert_eq!(
+            cutout.base_color_texture,
+            Some(TextureRef {
+                image: 0,
+                tex_coord: 0
+            })
+        );
+        assert_eq!((cutout.metallic, cutout.roughness), (0.2, 0.6));
+        assert_eq!(
+            cutout.metallic_roughness_texture,
+            Some(TextureRef {
+                image: 0,
+                tex_coord: 1
+            })
+        );
+        assert_eq!(cutout.normal_texture.map(|texture| texture.image), Some(1));
+        assert_eq!(cutout.normal_scale, 0.5);
+        assert_eq!(
+            cutout.occlusion_texture.map(|texture| texture.image),
+            Some(0)
+        );
+        assert_eq!(cutout.occlusion_strength, 0.4);
+        assert_eq!(cutout.emissive, Vec3::new(1.0, 0.5, 0.0));
+        // The texture's image comes from an extension we don't support.
+        assert_eq!(cutout.emissive_texture, None);
+
+        assert_eq!(scene.materials[2].alpha_mode, AlphaMode::Blend);
+        assert_eq!(scene.materials[3].alpha_mode, AlphaMode::Opaque);
+        // A malformed factor falls back to the default.
+        assert_eq!(scene.materials[3].base_color, Vec4::ONE);
+
+        assert_eq!(
+            scene.images[0].source,
+            ImageSource::File("textures/albedo.png".to_owned())
+        );
+        assert_eq!(
+            scene.images[1].source,
+            ImageSource::File("normal map.png".to_owned())
+        );
+        assert_eq!(scene.images[1].name, "image 1");
+    }
+
+    #[test]
+    fn builds_the_node_hierarchy() {
+        let json = r#"
+            "scene": 1,
+            "scenes": [{"nodes": [3]}, {"nodes": [0, 3]}],
+            "nodes": [
+                {"name": "root", "children": [1, 2], "translation": [1.0, 2.0, 3.0]},
+                {"name": "spun", "rotation": [0.0, 0.7071068, 0.0, 0.7071068], "children": [4]},
+                {"name": "scaled", "scale": [2.0, 2.0, 2.0], "translation": [0.0, 0.0, 1.0]},
+                {"name": "other root", "matrix": [1,0,0,0, 0,1,0,0, 0,0,1,0, 5,0,0,1]},
+                {"name": "leaf", "translation": [0.0, 0.0, 1.0]},
+                {"name": "outside"}
+            ]"#;
+
+        let scene = parse(&[], json).unwrap();
+        let nodes = &scene.nodes;
+        assert_eq!(scene.roots, [0, 3]);
+        assert_eq!(nodes[0].children, [1, 2]);
+        assert_eq!(nodes[4].parent, Some(1));
+        assert_eq!(nodes[0].parent, None);
+        assert_eq!(scene.scene_nodes(), [0, 1, 4, 2, 3]);
+
+        let origin = |node: usize| nodes[node].world.transform_point3(Vec3::ZERO);
+        // Translations have their Z mirrored.
+        assert!(origin(0).abs_diff_eq(Vec3::new(1.0, 2.0, -3.0), 1e-6));
+        assert!(origin(2).abs_diff_eq(Vec3::new(1.0, 2.0, -4.0), 1e-6));
+        assert!(origin(3).abs_diff_eq(Vec3::new(5.0, 0.0, 0.0), 1e-6));
+        // A quarter turn about Y takes glTF's +Z to +X, which mirrored is a
+        // turn taking -Z to +X.
+        assert!(origin(4).abs_diff_eq(Vec3::new(2.0, 2.0, -3.0), 1e-5));
+        assert!(nodes[2]
+            .world
+            .transform_vector3(Vec3::X)
+            .abs_diff_eq(Vec3::new(2.0, 0.0, 0.0), 1e-6));
+        // Nodes outside the scene aren't placed.
+        assert_eq!(nodes[5].world, Mat4::IDENTITY);
+
+        // Without scenes, every node without a parent is a root.
+        let scene = parse(&[], r#""nodes": [{"children": [1]}, {}, {}]"#).unwrap();
+        assert_eq!(scene.roots, [0, 2]);
+    }
+
+    #[test]
+    fn rejects_malformed_hierarchies() {
+        for nodes in [
+            r#""nodes": [{"children": [1]}, {"children": [0]}], "scenes": [{"nodes": [0]}]"#,
+            r#""nodes": [{"children": [2]}, {"children": [2]}, {}]"#,
+            r#""nodes": [{"children": [0]}]"#,
+            r#""nodes": [{"children": [5]}]"#,
+            r#""nodes": [{"mesh": 0}]"#,
+            r#""nodes": [{"camera": 0}]"#,
+            r#""nodes": [{"children": [1]}, {}], "scenes": [{"nodes": [1]}]"#,
+            r#""nodes": [{}], "scenes": [{"nodes": [3]}]"#,
+        ] {
+            assert!(parse(&[], nodes).is_err(), "{}", nodes);
+        }
+    }
+
+    #[test]
+    fn reads_cameras() {
+        let json = r#"
+            "cameras": [
+                {"name": "main", "type": "perspective",
+                 "perspective": {"yfov": 0.8, "aspectRatio": 2.0, "znear": 0.1, "zfar": 100.0}},
+                {"type": "perspective", "perspective": {"yfov": 1.0, "znear": 0.5}},
+                {"type": "orthographic",
+                 "orthographic": {"xmag": 4.0, "ymag": 3.0, "znear": 0.0, "zfar": 10.0}}
+            ],
+            "nodes": [{"camera": 2}]"#;
+
+        let scene = parse(&[], json).unwrap();
+        let cameras = &scene.cameras;
+        assert_eq!(cameras[0].name, "main");
+        assert_eq!(
+            cameras[0].projection,
+            Projection::Perspective {
+                yfov: 0.8,
+                aspect_ratio: Some(2.0),
+                znear: 0.1,
+                zfar: Some(100.0)
+            }
+        );
+        // The camera's own aspect ratio wins.
+        assert_eq!(
+            cameras[0].projection_matrix(1.0),
+            Mat4::perspective_lh(0.8, 2.0, 0.1, 100.0)
+        );
+        assert_eq!(
+            cameras[1].projection_matrix(1.5),
+            Mat4::perspective_infinite_lh(1.0, 1.5, 0.5)
+        );
+        assert_eq!(
+            cameras[2].projection_matrix(1.0),
+            Mat4::orthographic_lh(-4.0, 4.0, -3.0, 3.0, 0.0, 10.0)
+        );
+        assert_eq!(scene.nodes[0].camera, Some(2));
+
+        for camera in [
+            r#"{"type": "perspective", "perspective": {"znear": 0.1}}"#,
+            r#"{"type": "orthographic", "orthographic": {"xmag": 1.0, "ymag": 1.0, "znear": 0.0}}"#,
+            r#"{"type": "fisheye"}"#,
+        ] {
+            assert!(parse(&[], &format!(r#""cameras": [{}]"#, camera)).is_err());
+        }
+    }
+
+    #[test]
+    fn reads_punctual_lights() {
+        let json = r#"
+            "extensionsUsed": ["KHR_lights_punctual"],
+            "extensionsRequired": ["KHR_lights_punctual"],
+            "extensions": {"KHR_lights_punctual": {"lights": [
+                {"name": "sun", "type": "directional", "color": [1.0, 0.9, 0.8], "intensity": 3.0},
+                {"type": "point", "range": 10.0},
+                {"type": "spot", "spot": {"innerConeAngle": 0.2, "outerConeAngle": 0.5}},
+                {"type": "spot"}
+            ]}},
+            "nodes": [{"extensions": {"KHR_lights_punctual": {"light": 2}}}]"#;
+
+        let scene = parse(&[], json).unwrap();
+        let lights = &scene.lights;
+        assert_eq!(lights[0].name, "sun");
+        assert_eq!(lights[0].kind, LightKind::Directional);
+        assert_eq!(lights[0].color, Vec3::new(1.0, 0.9, 0.8));
+        assert_eq!((lights[0].intensity, lights[0].range), (3.0, None));
+        assert_eq!(lights[1].kind, LightKind::Point);
+        assert_eq!((lights[1].color, lights[1].intensity), (Vec3::ONE, 1.0));
+        assert_eq!(lights[1].range, Some(10.0));
+        assert_eq!(
+            lights[2].kind,
+            LightKind::Spot {
+                inner_cone_angle: 0.2,
+                outer_cone_angle: 0.5
+            }
+        );
+        assert_eq!(
+            lights[3].kind,
+            LightKind::Spot {
+                inner_cone_angle: 0.0,
+                outer_cone_angle: std::f32::consts::FRAC_PI_4
+            }
+        );
+        assert_eq!(scene.nodes[0].light, Some(2));
+
+        let unknown = r#""extensions": {"KHR_lights_punctual": {"lights": [{"type": "area"}]}}"#;
+        assert!(parse(&[], unknown).is_err());
+        let missing = r#""nodes": [{"extensions": {"KHR_lights_punctual": {"light": 0}}}]"#;
+        assert!(parse(&[], missing).is_err());
+        let unsupported = r#""extensionsRequired": ["KHR_draco_mesh_compression"]"#;
+        assert!(parse(&[], unsupported).is_err());
+    }
+
+    #[test]
+    fn reads_every_uv_set() {
+        let mut buffer = floats(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        buffer.extend(floats(&[0.0, 0.0, 1.0].repeat(3)));
+        for set in 0..3 {
+            let v = set as f32 * 0.25;
+            buffer.extend(floats(&[0.0, v, 1.0, v, 0.0, 1.0]));
+        }
+        let attributes = [
+            ("POSITION", "VEC3", 36),
+            ("NORMAL", "VEC3", 36),
+            ("TEXCOORD_0", "VEC2", 24),
+            ("TEXCOORD_1", "VEC2", 24),
+            ("TEXCOORD_2", "VEC2", 24),
+        ];
+
+        let scene = parse(&buffer, &triangle_body(&attributes, 4)).unwrap();
+        let mesh = &scene.meshes[0].primitives[0].mesh;
+        assert_eq!(mesh.vertices[1].uv, Vec2::new(1.0, 1.0));
+        assert_eq!(mesh.extra_uvs.len(), 2);
+        // Every set has its V flipped.
+        assert_eq!(mesh.extra_uvs[0][1], Vec2::new(1.0, 0.75));
+        assert_eq!(mesh.extra_uvs[1][1], Vec2::new(1.0, 0.5));
+        assert_eq!(mesh.extra_uvs[1][2], Vec2::new(0.0, 0.0));
+
+        // Sets are read until the first gap.
+        let gap = triangle_body(
+            &[
+                ("POSITION", "VEC3", 36),
+                ("NORMAL", "VEC3", 36),
+                ("TEXCOORD_0", "VEC2", 24),
+                ("TEXCOORD_2", "VEC2", 24),
+            ],
+            4,
+        );
+        let scene = parse(&buffer[..36 + 36 + 48], &gap).unwrap();
+        assert!(scene.meshes[0].primitives[0].mesh.extra_uvs.is_empty());
+    }
+
+    /// The root node of the Box samples turns their Z up into Y up.
+    fn check_box(scene: &GltfScene) {
+        assert_eq!(scene.meshes.len(), 1);
+        assert_eq!(scene.meshes[0].primitives.len(), 1);
+        let primitive = &scene.meshes[0].primitives[0];
+        assert_eq!(primitive.material, Some(0));
+        assert_eq!(primitive.mesh.vertices.len(), 24);
+        assert_eq!(primitive.mesh.indices.len(), 36);
+
+        assert_eq!(scene.roots, [0]);
+        assert_eq!(scene.nodes.len(), 2);
+        assert_eq!(scene.nodes[1].parent, Some(0));
+        assert_eq!(scene.nodes[1].mesh, Some(0));
+        let world = scene.nodes[1].world;
+        assert_eq!(world, scene.nodes[0].transform);
+
+        for vertex in &primitive.mesh.vertices {
+            let position = world.transform_point3(vertex.position);
+            assert!(position.abs().abs_diff_eq(Vec3::splat(0.5), 1e-6));
+            let normal = world.transform_vector3(vertex.normal);
+            // glTF's +Z face ends up on top.
+            let original = vertex.normal * MIRROR_Z;
+            if original == Vec3::Z {
+                assert!(normal.abs_diff_eq(Vec3::Y, 1e-6));
+            }
+            assert!(normal.abs_diff_eq(position * 2.0 * normal.abs(), 1e-6));
+        }
+
+        // Every triangle is clockwise seen from outside.
+        for triangle in primitive.mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| primitive.mesh.vertices[triangle[i] as usize]);
+            let face = (b.position - a.position).cross(c.position - a.position);
+            assert!(face.dot(a.normal) > 0.0);
+        }
+    }
+
+    #[test]
+    fn loads_the_box_samples() {
+        for file in [
+            "tests/fixtures/gltf/Box/Box.gltf",
+            "tests/fixtures/gltf/Box/Box.glb",
+        ] {
+            let scene = GltfScene::load(file).unwrap();
+            check_box(&scene);
+            let material = &scene.materials[0];
+            assert_eq!(material.name, "Red");
+            assert_eq!(material.base_color, Vec4::new(0.8, 0.0, 0.0, 1.0));
+            assert_eq!((material.metallic, material.roughness), (0.0, 1.0));
+            assert_eq!(material.base_color_texture, None);
+            assert!(scene.images.is_empty());
+        }
+    }
+
+    #[test]
+    fn loads_the_textured_box_samples() {
+        let gltf = GltfScene::load("tests/fixtures/gltf/BoxTextured/BoxTextured.gltf").unwrap();
+        let glb = GltfScene::load("tests/fixtures/gltf/BoxTextured/BoxTextured.glb").unwrap();
+        for scene in [&gltf, &glb] {
+            check_box(scene);
+            let material = &scene.materials[0];
+            assert_eq!(material.name, "Texture");
+            assert_eq!(material.base_color, Vec4::ONE);
+            assert_eq!(
+                material.base_color_texture.map(|texture| texture.image),
+                Some(0)
+            );
+            let image = scene.image(0).unwrap();
+            assert_eq!((image.width, image.height), (4, 4));
+        }
+
+        assert_eq!(
+            gltf.images[0].source,
+            ImageSource::File("tests/fixtures/gltf/BoxTextured/Checker.png".to_owned())
+        );
+        assert!(matches!(glb.images[0].source, ImageSource::Embedded(_)));
+        let vertices = |scene: &GltfScene| scene.meshes[0].primitives[0].mesh.vertices.clone();
+        assert_eq!(vertices(&gltf), vertices(&glb));
+        assert!(vertices(&gltf)
+            .iter()
+            .all(|vertex| vertex.uv.cmpge(Vec2::ZERO).all() && vertex.uv.cmple(Vec2::ONE).all()));
+    }
+}
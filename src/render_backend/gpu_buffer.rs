@@ -1,7 +1,9 @@
-use windows::core::*;
 use windows::Win32::Graphics::Direct3D11::*;
 
+use crate::error::{Context, EngineError, EngineResult};
+
 use super::backend::Backend;
+use super::cbuffer_layout::ConstantBuffer;
 
 #[derive(Clone)]
 pub struct GPUBuffer {
@@ -17,12 +19,36 @@ pub struct MappedGPUBuffer<'a, 'b> {
 }
 
 impl<'a, 'b> GPUBuffer {
-    pub fn new(backend: &Backend, desc: D3D11_BUFFER_DESC) -> Result<GPUBuffer> {
-        let buffer = unsafe { backend.device.CreateBuffer(&desc, std::ptr::null())? };
+    pub fn new(backend: &Backend, desc: D3D11_BUFFER_DESC) -> EngineResult<GPUBuffer> {
+        let buffer = unsafe { backend.device.CreateBuffer(&desc, std::ptr::null()) }
+            .context("Create buffer")?;
+        Ok(GPUBuffer { desc, buffer })
+    }
+
+    /// Creates the buffer filled with `data`, which must cover `desc.ByteWidth`.
+    pub fn with_data(
+        backend: &Backend,
+        desc: D3D11_BUFFER_DESC,
+        data: &[u8],
+    ) -> EngineResult<GPUBuffer> {
+        if data.len() < desc.ByteWidth as usize {
+            return Err(EngineError::Validation(format!(
+                "{} bytes of initial data for a buffer of {}",
+                data.len(),
+                desc.ByteWidth
+            )));
+        }
+
+        let initial_data = D3D11_SUBRESOURCE_DATA {
+            pSysMem: data.as_ptr() as _,
+            ..Default::default()
+        };
+        let buffer = unsafe { backend.device.CreateBuffer(&desc, &initial_data) }
+            .context("Create buffer")?;
         Ok(GPUBuffer { desc, buffer })
     }
 
-    pub fn vertex_buffer(backend: &Backend, size_bytes: u32) -> Result<GPUBuffer> {
+    pub fn vertex_buffer(backend: &Backend, size_bytes: u32) -> EngineResult<GPUBuffer> {
         Self::new(
             backend,
             D3D11_BUFFER_DESC {
@@ -35,7 +61,7 @@ impl<'a, 'b> GPUBuffer {
         )
     }
 
-    pub fn index_buffer(backend: &Backend, size_bytes: u32) -> Result<GPUBuffer> {
+    pub fn index_buffer(backend: &Backend, size_bytes: u32) -> EngineResult<GPUBuffer> {
         Self::new(
             backend,
             D3D11_BUFFER_DESC {
@@ -48,7 +74,7 @@ impl<'a, 'b> GPUBuffer {
         )
     }
 
-    pub fn constant_buffer(backend: &Backend, size_bytes: u32) -> Result<GPUBuffer> {
+    pub fn constant_buffer(backend: &Backend, size_bytes: u32) -> EngineResult<GPUBuffer> {
         let size_bytes = if size_bytes % 16 != 0 {
             16 * ((size_bytes / 16) + 1)
         } else {
@@ -67,11 +93,25 @@ impl<'a, 'b> GPUBuffer {
         )
     }
 
+    /// Creates a constant buffer for `T`, failing if `T` does not match HLSL
+    /// cbuffer packing.
+    pub fn constant_buffer_for<T: ConstantBuffer>(backend: &Backend) -> EngineResult<GPUBuffer> {
+        let layout = T::layout().map_err(|err| {
+            EngineError::Validation(format!(
+                "{} does not match HLSL cbuffer packing: {}",
+                std::any::type_name::<T>(),
+                err
+            ))
+        })?;
+
+        Self::constant_buffer(backend, layout.size as u32)
+    }
+
     pub fn structured_buffer<T: Sized>(
         backend: &Backend,
         num_elements: u32,
         gpu_write: bool,
-    ) -> Result<GPUBuffer> {
+    ) -> EngineResult<GPUBuffer> {
         Self::new(
             backend,
             D3D11_BUFFER_DESC {
@@ -81,7 +121,7 @@ impl<'a, 'b> GPUBuffer {
                 } else {
                     D3D11_USAGE_DYNAMIC
                 },
-                CPUAccessFlags: D3D11_CPU_ACCESS_WRITE,
+                CPUAccessFlags: if gpu_write { 0 } else { D3D11_CPU_ACCESS_WRITE },
                 BindFlags: if gpu_write {
                     D3D11_BIND_UNORDERED_ACCESS | D3D11_BIND_SHADER_RESOURCE
                 } else {
@@ -94,12 +134,13 @@ impl<'a, 'b> GPUBuffer {
         )
     }
 
-    pub fn map(&'a self, backend: &'b Backend) -> Result<MappedGPUBuffer<'a, 'b>> {
+    pub fn map(&'a self, backend: &'b Backend) -> EngineResult<MappedGPUBuffer<'a, 'b>> {
         let mapped = unsafe {
             backend
                 .device_context
-                .Map(&self.buffer, 0, D3D11_MAP_WRITE_DISCARD, 0)?
-        };
+                .Map(&self.buffer, 0, D3D11_MAP_WRITE_DISCARD, 0)
+        }
+        .context("Map buffer")?;
 
         Ok(MappedGPUBuffer {
             subresource: mapped,
@@ -0,0 +1,445 @@
+use std::ops::Index;
+
+/// A parsed JSON value. Indexing with a key or position that doesn't exist
+/// gives `Json::Null`, so lookups can be chained and checked once.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// Members in file order. Later duplicates are never found.
+    Object(Vec<(String, Json)>),
+}
+
+static NULL: Json = Json::Null;
+
+/// Nesting deeper than this is rejected instead of overflowing the stack.
+const MAX_DEPTH: usize = 256;
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser {
+            bytes: text.as_bytes(),
+            position: 0,
+        };
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        if parser.position < parser.bytes.len() {
+            return Err(parser.error("trailing characters"));
+        }
+
+        Ok(value)
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Json::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<f32> {
+        self.as_f64().map(|value| value as f32)
+    }
+
+    /// Non-negative whole numbers only.
+    pub fn as_usize(&self) -> Option<usize> {
+        self.as_f64()
+            .filter(|value| *value >= 0.0 && value.fract() == 0.0 && *value <= u32::MAX as f64)
+            .map(|value| value as usize)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The elements of an array, empty for anything else.
+    pub fn members(&self) -> &[Json] {
+        match self {
+            Json::Array(values) => values,
+            _ => &[],
+        }
+    }
+
+    /// The members of an object, empty for anything else.
+    pub fn entries(&self) -> &[(String, Json)] {
+        match self {
+            Json::Object(entries) => entries,
+            _ => &[],
+        }
+    }
+
+    /// An array of numbers as `f32`s, `None` if it isn't one or has another
+    /// length than `N`.
+    pub fn as_f32_array<const N: usize>(&self) -> Option<[f32; N]> {
+        let values = self.members();
+        if values.len() != N {
+            return None;
+        }
+
+        let mut array = [0.0; N];
+        for (value, json) in array.iter_mut().zip(values) {
+            *value = json.as_f32()?;
+        }
+        Some(array)
+    }
+}
+
+impl Index<&str> for Json {
+    type Output = Json;
+
+    fn index(&self, key: &str) -> &Json {
+        self.entries()
+            .iter()
+            .find(|(name, _)| name == key)
+            .map_or(&NULL, |(_, value)| value)
+    }
+}
+
+impl Index<usize> for Json {
+    type Output = Json;
+
+    fn index(&self, index: usize) -> &Json {
+        self.members().get(index).unwrap_or(&NULL)
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        format!("{} at byte {}", message, self.position)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.bytes.get(self.position) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.bytes.get(self.position).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", byte as char)))
+        }
+    }
+
+    fn literal(&mut self, literal: &str, value: Json) -> Result<Json, String> {
+        if self.bytes[self.position..].starts_with(literal.as_bytes()) {
+            self.position += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("unknown literal"))
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, String> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nested too deeply"));
+        }
+
+        match self.peek() {
+            Some(b'{') => {
+                self.position += 1;
+                let mut entries = Vec::new();
+                if self.peek() == Some(b'}') {
+                    self.position += 1;
+                    return Ok(Json::Object(entries));
+                }
+                loop {
+                    if self.peek() != Some(b'"') {
+                        return Err(self.error("expected a key"));
+                    }
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    entries.push((key, self.value(depth + 1)?));
+                    match self.peek() {
+                        Some(b',') => self.position += 1,
+                        Some(b'}') => {
+                            self.position += 1;
+                            return Ok(Json::Object(entries));
+                        }
+                        _ => return Err(self.error("expected ',' or '}'")),
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.position += 1;
+                let mut values = Vec::new();
+                if self.peek() == Some(b']') {
+                    self.position += 1;
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value(depth + 1)?);
+                    match self.peek() {
+                        Some(b',') => self.position += 1,
+                        Some(b']') => {
+                            self.position += 1;
+                            return Ok(Json::Array(values));
+                        }
+                        _ => return Err(self.error("expected ',' or ']'")),
+                    }
+                }
+            }
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') =
+            self.bytes.get(self.position)
+        {
+            self.position += 1;
+        }
+
+        // Only ASCII was consumed, so this can't split a character.
+        std::str::from_utf8(&self.bytes[start..self.position])
+            .ok()
+            .and_then(|number| number.parse().ok())
+            .map(Json::Number)
+            .ok_or_else(|| self.error("malformed number"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self
+            .bytes
+            .get(self.position..self.position + 4)
+            .filter(|digits| digits.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("malformed \\u escape"))?;
+        self.position += 4;
+        Ok(digits)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        // The opening quote.
+        self.position += 1;
+        let mut bytes = Vec::new();
+        loop {
+            let byte = *self
+                .bytes
+                .get(self.position)
+                .ok_or_else(|| self.error("unterminated string"))?;
+            self.position += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = *self
+                        .bytes
+                        .get(self.position)
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    self.position += 1;
+                    let unescaped = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            if (0xd800..0xdc00).contains(&code)
+                                && self.bytes[self.position..].starts_with(b"\\u")
+                            {
+                                self.position += 2;
+                                let low = self.hex4()?;
+                                if (0xdc00..0xe000).contains(&low) {
+                                    code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                                } else {
+                                    // Not a pair. The high half becomes a
+                                    // replacement character and the second
+                                    // escape is read on its own.
+                                    self.position -= 6;
+                                }
+                            }
+                            char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        _ => return Err(self.error("unknown escape")),
+                    };
+                    bytes.extend_from_slice(unescaped.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                _ => bytes.push(byte),
+            }
+        }
+
+        String::from_utf8(bytes).map_err(|_| self.error("string isn't UTF-8"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(text: &str) -> String {
+        Json::parse(text).unwrap().as_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn parses_values() {
+        let json = Json::parse(
+            r#" { "null": null, "yes": true, "no": false, "list": [1, "two", []],
+                  "nested": { "key": {} } } "#,
+        )
+        .unwrap();
+        assert!(json["null"].is_null());
+        assert_eq!(json["yes"].as_bool(), Some(true));
+        assert_eq!(json["no"].as_bool(), Some(false));
+        assert_eq!(json["list"].members().len(), 3);
+        assert_eq!(json["list"][0].as_usize(), Some(1));
+        assert_eq!(json["list"][1].as_str(), Some("two"));
+        assert_eq!(json["list"][2], Json::Array(Vec::new()));
+        assert_eq!(json["nested"]["key"], Json::Object(Vec::new()));
+        assert_eq!(json.entries().len(), 5);
+
+        // Missing keys and indices chain to null.
+        assert!(json["missing"]["deeper"][3].is_null());
+        assert!(json["list"][3].is_null());
+        assert!(json["yes"]["key"].is_null());
+    }
+
+    #[test]
+    fn finds_the_first_duplicate_key() {
+        let json = Json::parse(r#"{"a": 1, "a": 2}"#).unwrap();
+        assert_eq!(json["a"].as_f64(), Some(1.0));
+    }
+
+    #[test]
+    fn parses_numbers() {
+        let number = |text: &str| Json::parse(text).unwrap().as_f64().unwrap();
+        assert_eq!(number("0"), 0.0);
+        assert_eq!(number("-12"), -12.0);
+        assert_eq!(number("3.25"), 3.25);
+        assert_eq!(number("1e3"), 1000.0);
+        assert_eq!(number("-2.5E-2"), -0.025);
+        assert_eq!(number("6.02e+23"), 6.02e23);
+
+        for malformed in ["-", "1e", "1.2.3", "--1", "1e+-2", ".5", "+1"] {
+            assert!(Json::parse(malformed).is_err(), "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn converts_numbers() {
+        let json = Json::parse("[1.5, -1, 4294967295, 4294967296, 7, [1, 2, 3]]").unwrap();
+        assert_eq!(json[0].as_f32(), Some(1.5));
+        assert_eq!(json[0].as_usize(), None);
+        assert_eq!(json[1].as_usize(), None);
+        assert_eq!(json[2].as_usize(), Some(u32::MAX as usize));
+        assert_eq!(json[3].as_usize(), None);
+        assert_eq!(json[4].as_usize(), Some(7));
+        assert_eq!(json[5].as_f32_array::<3>(), Some([1.0, 2.0, 3.0]));
+        assert_eq!(json[5].as_f32_array::<2>(), None);
+        assert_eq!(json.as_f32_array::<6>(), None);
+    }
+
+    #[test]
+    fn unescapes_strings() {
+        assert_eq!(
+            string(r#""quote\" back\\ slash\/ \b\f\n\r\t""#),
+            "quote\" back\\ slash/ \u{8}\u{c}\n\r\t"
+        );
+        assert_eq!(string(r#""\u0041\u00e9\u20AC""#), "A\u{e9}\u{20ac}");
+        assert_eq!(string("\"caf\u{e9} \u{1f600}\""), "caf\u{e9} \u{1f600}");
+
+        for malformed in [
+            r#""unterminated"#,
+            r#""escape at the end\"#,
+            r#""\x""#,
+            r#""\u12""#,
+            r#""\u12g4""#,
+            r#""\u+123""#,
+        ] {
+            assert!(Json::parse(malformed).is_err(), "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn combines_surrogate_pairs() {
+        assert_eq!(string(r#""\ud83d\ude00""#), "\u{1f600}");
+        assert_eq!(string(r#""\uD834\uDD1E""#), "\u{1d11e}");
+
+        // Unpaired halves become replacement characters, without eating
+        // what follows them.
+        assert_eq!(string(r#""\ud83dx""#), "\u{fffd}x");
+        assert_eq!(string(r#""\ude00""#), "\u{fffd}");
+        assert_eq!(string(r#""\ud83d\u0041""#), "\u{fffd}A");
+        assert_eq!(string(r#""\ud83d\ud83d\ude00""#), "\u{fffd}\u{1f600}");
+    }
+
+    #[test]
+    fn limits_nesting_depth() {
+        let nested = |depth: usize| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(Json::parse(&nested(MAX_DEPTH + 1)).is_ok());
+        assert!(Json::parse(&nested(MAX_DEPTH + 2)).is_err());
+
+        // Deep enough to overflow the stack without the limit.
+        let deep = "{\"a\":".repeat(100_000);
+        assert!(Json::parse(&deep).is_err());
+    }
+
+    #[test]
+    fn rejects_trailing_garbage() {
+        assert!(Json::parse("{} ").is_ok());
+        assert!(Json::parse("{} {}").is_err());
+        assert!(Json::parse("[1] x").is_err());
+        assert!(Json::parse("1 2").is_err());
+        assert!(Json::parse("nullx").is_err());
+        assert!(Json::parse("truth").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_documents() {
+        for malformed in [
+            "",
+            "   ",
+            "{",
+            "[1,",
+            "[1 2]",
+            "[1,]",
+            "{\"a\" 1}",
+            "{\"a\": 1,}",
+            "{1: 2}",
+            "nul",
+            "'single'",
+        ] {
+            assert!(Json::parse(malformed).is_err(), "{:?}", malformed);
+        }
+    }
+}
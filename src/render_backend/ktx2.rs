@@ -0,0 +1,666 @@
+use ruzstd::decoding::FrameDecoder;
+use windows::Win32::Graphics::Dxgi::Common::*;
+
+use crate::error::{EngineError, EngineResult};
+use crate::vfs;
+
+use super::backend::Backend;
+use super::format_info::{format_name, FormatInfo};
+use super::texture_data::{LoadedTexture, TextureData, TextureDimension};
+
+const IDENTIFIER: [u8; 12] = [
+    0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+];
+const HEADER_SIZE: usize = 80;
+const LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+const SUPERCOMPRESSION_NONE: u32 = 0;
+const SUPERCOMPRESSION_BASIS_LZ: u32 = 1;
+const SUPERCOMPRESSION_ZSTD: u32 = 2;
+const SUPERCOMPRESSION_ZLIB: u32 = 3;
+
+const COLOR_MODEL_ETC1S: u8 = 163;
+const COLOR_MODEL_UASTC: u8 = 166;
+
+/// Reads a KTX2 file through the VFS and creates a texture of the type it
+/// describes.
+pub fn load(backend: &Backend, path: &str) -> EngineResult<LoadedTexture> {
+    let bytes = vfs::read(path).map_err(|source| EngineError::Io {
+        path: path.to_owned(),
+        source,
+    })?;
+    let data = parse(&bytes).map_err(|message| EngineError::TextureLoad {
+        path: path.to_owned(),
+        message,
+    })?;
+
+    data.create(backend)
+}
+
+/// Parses a whole KTX2 file, undoing Zstd or zlib supercompression and
+/// reordering the levels into D3D's subresource order.
+///
+/// Basis Universal textures (ETC1S or UASTC) have to be transcoded to a
+/// format the GPU can sample, which isn't supported. Export them as BC
+/// formats instead; Zstd supercompression of those is fine.
+pub fn parse(bytes: &[u8]) -> Result<TextureData, String> {
+    if bytes.len() < HEADER_SIZE || bytes[..12] != IDENTIFIER {
+        return Err("not a KTX2 file".to_owned());
+    }
+
+    let vk_format = read_u32(bytes, 12);
+    let width = read_u32(bytes, 20);
+    let height = read_u32(bytes, 24);
+    let depth = read_u32(bytes, 28);
+    let layer_count = read_u32(bytes, 32);
+    let face_count = read_u32(bytes, 36);
+    let level_count = read_u32(bytes, 40);
+    let supercompression = read_u32(bytes, 44);
+    let dfd_offset = read_u32(bytes, 48) as usize;
+
+    if vk_format == 0 || supercompression == SUPERCOMPRESSION_BASIS_LZ {
+        let kind = match dfd_offset.checked_add(12).and_then(|i| bytes.get(i)) {
+            Some(&COLOR_MODEL_ETC1S) => "ETC1S",
+            Some(&COLOR_MODEL_UASTC) => "UASTC",
+            _ => "an undefined format",
+        };
+        return Err(format!(
+            "texture is Basis Universal {}, which has to be transcoded and isn't supported",
+            kind
+        ));
+    }
+    let format = dxgi_format(vk_format)?;
+    let info = FormatInfo::of(format)
+        .ok_or_else(|| format!("{} is not supported", format_name(format)))?;
+
+    if width == 0 {
+        return Err("width is 0".to_owned());
+    }
+    let cubemap = match face_count {
+        1 => false,
+        6 if width == height && depth == 0 => true,
+        6 => {
+            return Err(format!(
+                "cubemap faces are {}x{}, not square",
+                width, height
+            ))
+        }
+        count => return Err(format!("{} faces, expected 1 or 6", count)),
+    };
+    let dimension = if depth > 0 {
+        if layer_count > 0 {
+            return Err("3D textures can't be arrays".to_owned());
+        }
+        TextureDimension::Texture3D
+    } else {
+        TextureDimension::Texture2D
+    };
+
+    // A level count of 0 asks the loader to generate mips, only the base
+    // level is stored.
+    let mip_levels = level_count.max(1);
+    let full_chain = 32 - width.max(height).max(depth).leading_zeros();
+    if mip_levels > full_chain {
+        return Err(format!(
+            "{} levels for a {}x{}x{} texture, at most {} fit",
+            mip_levels, width, height, depth, full_chain
+        ));
+    }
+    let level_index_end = HEADER_SIZE + mip_levels as usize * LEVEL_INDEX_ENTRY_SIZE;
+    if bytes.len() < level_index_end {
+        return Err("level index is truncated".to_owned());
+    }
+    let slices = layer_count.max(1).checked_mul(face_count).ok_or_else(|| {
+        format!(
+            "{} layers of {} faces are too many",
+            layer_count, face_count
+        )
+    })?;
+    let mut texture = TextureData {
+        format,
+        dimension,
+        width,
+        // 1D textures are loaded as 2D textures one texel high.
+        height: height.max(1),
+        depth: depth.max(1),
+        mip_levels,
+        array_size: slices,
+        cubemap,
+        data: Vec::new(),
+    };
+    // Before the levels are decompressed into buffers of the size this
+    // describes.
+    texture.check_limits()?;
+
+    let mut levels = Vec::with_capacity(mip_levels as usize);
+    let mut total_size = 0usize;
+    for level in 0..mip_levels {
+        let entry = HEADER_SIZE + level as usize * LEVEL_INDEX_ENTRY_SIZE;
+        let offset = read_u64(bytes, entry) as usize;
+        let length = read_u64(bytes, entry + 8) as usize;
+        let uncompressed_length = read_u64(bytes, entry + 16) as usize;
+
+        let size = [
+            mip_size(texture.width, level),
+            mip_size(texture.height, level),
+            mip_size(texture.depth, level),
+        ];
+        let too_large = || format!("level {} is too large", level);
+        let image_size = info
+            .row_pitch(size[0])
+            .checked_mul(info.rows(size[1]))
+            .and_then(|size_2d| size_2d.checked_mul(size[2] as usize))
+            .ok_or_else(too_large)?;
+        let level_size = image_size
+            .checked_mul(slices as usize)
+            .ok_or_else(too_large)?;
+        total_size = total_size.checked_add(level_size).ok_or_else(too_large)?;
+        if uncompressed_length != level_size {
+            return Err(format!(
+                "level {} has {} bytes, expected {}",
+                level, uncompressed_length, level_size
+            ));
+        }
+
+        let stored = offset
+            .checked_add(length)
+            .and_then(|end| bytes.get(offset..end))
+            .ok_or_else(|| format!("level {} is truncated", level))?;
+        let data = decompress(supercompression, stored, uncompressed_length)
+            .map_err(|err| format!("level {}: {}", level, err))?;
+
+        levels.push((data, image_size));
+    }
+
+    // KTX2 stores every slice of a level together, D3D every level of a
+    // slice together.
+    texture.data.reserve_exact(total_size);
+    for slice in 0..slices as usize {
+        for (data, image_size) in &levels {
+            let start = slice * image_size;
+            texture
+                .data
+                .extend_from_slice(&data[start..start + image_size]);
+        }
+    }
+    texture.subresources()?;
+
+    Ok(texture)
+}
+
+fn decompress(scheme: u32, stored: &[u8], uncompressed_length: usize) -> Result<Vec<u8>, String> {
+    let data = match scheme {
+        SUPERCOMPRESSION_NONE => stored.to_vec(),
+        SUPERCOMPRESSION_ZSTD => {
+            let mut data = Vec::with_capacity(uncompressed_length);
+            FrameDecoder::new()
+                .decode_all_to_vec(stored, &mut data)
+                .map_err(|err| format!("Zstd: {}", err))?;
+            data
+        }
+        // The output buffer doubles as it grows and inflating fails once
+        // that would pass the limit, so levels exactly at it need twice the
+        // room. The length is checked below.
+        SUPERCOMPRESSION_ZLIB => miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(
+            stored,
+            uncompressed_length.saturating_mul(2),
+        )
+        .map_err(|err| format!("zlib: {:?}", err))?,
+        scheme => return Err(format!("unknown supercompression scheme {}", scheme)),
+    };
+
+    if data.len() != uncompressed_length {
+        return Err(format!(
+            "decompressed to {} bytes, expected {}",
+            data.len(),
+            uncompressed_length
+        ));
+    }
+
+    Ok(data)
+}
+
+/// The DXGI format with the same memory layout as a `VkFormat`. Formats
+/// D3D11 can't sample, like ETC2 and ASTC, are errors.
+pub fn dxgi_format(vk_format: u32) -> Result<DXGI_FORMAT, String> {
+    let format = match vk_format {
+        4 => DXGI_FORMAT_B5G6R5_UNORM,
+        // Red in the high bits with alpha in bit 0, the reverse of B5G5R5A1.
+        8 => return Err("R5G5B5A1_UNORM_PACK16 has no DXGI equivalent".to_owned()),
+        9 => DXGI_FORMAT_R8_UNORM,
+        10 => DXGI_FORMAT_R8_SNORM,
+        13 => DXGI_FORMAT_R8_UINT,
+        14 => DXGI_FORMAT_R8_SINT,
+        16 => DXGI_FORMAT_R8G8_UNORM,
+        17 => DXGI_FORMAT_R8G8_SNORM,
+        20 => DXGI_FORMAT_R8G8_UINT,
+        21 => DXGI_FORMAT_R8G8_SINT,
+        37 => DXGI_FORMAT_R8G8B8A8_UNORM,
+        38 => DXGI_FORMAT_R8G8B8A8_SNORM,
+        41 => DXGI_FORMAT_R8G8B8A8_UINT,
+        42 => DXGI_FORMAT_R8G8B8A8_SINT,
+        43 => DXGI_FORMAT_R8G8B8A8_UNORM_SRGB,
+        44 => DXGI_FORMAT_B8G8R8A8_UNORM,
+        50 => DXGI_FORMAT_B8G8R8A8_UNORM_SRGB,
+        64 => DXGI_FORMAT_R10G10B10A2_UNORM,
+        68 => DXGI_FORMAT_R10G10B10A2_UINT,
+        70 => DXGI_FORMAT_R16_UNORM,
+        71 => DXGI_FORMAT_R16_SNORM,
+        74 => DXGI_FORMAT_R16_UINT,
+        75 => DXGI_FORMAT_R16_SINT,
+        76 => DXGI_FORMAT_R16_FLOAT,
+        77 => DXGI_FORMAT_R16G16_UNORM,
+        78 => DXGI_FORMAT_R16G16_SNORM,
+        81 => DXGI_FORMAT_R16G16_UINT,
+        82 => DXGI_FORMAT_R16G16_SINT,
+        83 => DXGI_FORMAT_R16G16_FLOAT,
+        91 => DXGI_FORMAT_R16G16B16A16_UNORM,
+        92 => DXGI_FORMAT_R16G16B16A16_SNORM,
+        95 => DXGI_FORMAT_R16G16B16A16_UINT,
+        96 => DXGI_FORMAT_R16G16B16A16_SINT,
+        97 => DXGI_FORMAT_R16G16B16A16_FLOAT,
+        98 => DXGI_FORMAT_R32_UINT,
+        99 => DXGI_FORMAT_R32_SINT,
+        100 => DXGI_FORMAT_R32_FLOAT,
+        101 => DXGI_FORMAT_R32G32_UINT,
+        102 => DXGI_FORMAT_R32G32_SINT,
+        103 => DXGI_FORMAT_R32G32_FLOAT,
+        104 => DXGI_FORMAT_R32G32B32_UINT,
+        105 => DXGI_FORMAT_R32G32B32_SINT,
+        106 => DXGI_FORMAT_R32G32B32_FLOAT,
+        107 => DXGI_FORMAT_R32G32B32A32_UINT,
+        108 => DXGI_FORMAT_R32G32B32A32_SINT,
+        109 => DXGI_FORMAT_R32G32B32A32_FLOAT,
+        122 => DXGI_FORMAT_R11G11B10_FLOAT,
+        123 => DXGI_FORMAT_R9G9B9E5_SHAREDEXP,
+        131 | 133 => DXGI_FORMAT_BC1_UNORM,
+        132 | 134 => DXGI_FORMAT_BC1_UNORM_SRGB,
+        135 => DXGI_FORMAT_BC2_UNORM,
+        136 => DXGI_FORMAT_BC2_UNORM_SRGB,
+        137 => DXGI_FORMAT_BC3_UNORM,
+        138 => DXGI_FORMAT_BC3_UNORM_SRGB,
+        139 => DXGI_FORMAT_BC4_UNORM,
+        140 => DXGI_FORMAT_BC4_SNORM,
+        141 => DXGI_FORMAT_BC5_UNORM,
+        142 => DXGI_FORMAT_BC5_SNORM,
+        143 => DXGI_FORMAT_BC6H_UF16,
+        144 => DXGI_FORMAT_BC6H_SF16,
+        145 => DXGI_FORMAT_BC7_UNORM,
+        146 => DXGI_FORMAT_BC7_UNORM_SRGB,
+        // VK_FORMAT_A1R5G5B5_UNORM_PACK16
+        1_000_338_000 => DXGI_FORMAT_B5G5R5A1_UNORM,
+        // VK_FORMAT_A4R4G4B4_UNORM_PACK16
+        1_000_340_000 => DXGI_FORMAT_B4G4R4A4_UNORM,
+        147..=156 => return Err(format!("ETC2/EAC format {} is not supported", vk_format)),
+        157..=184 | 1_000_066_000..=1_000_066_013 => {
+            return Err(format!("ASTC format {} is not supported", vk_format))
+        }
+        _ => return Err(format!("VkFormat {} is not supported", vk_format)),
+    };
+
+    Ok(format)
+}
+
+fn mip_size(size: u32, mip: u32) -> u32 {
+    (size >> mip).max(1)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VK_FORMAT_R8_UNORM: u32 = 9;
+    const VK_FORMAT_R8G8B8A8_SRGB: u32 = 43;
+    const VK_FORMAT_BC7_UNORM_BLOCK: u32 = 145;
+
+    struct Ktx2 {
+        vk_format: u32,
+        size: [u32; 3],
+        layer_count: u32,
+        face_count: u32,
+        supercompression: u32,
+        /// Uncompressed level data, largest first.
+        levels: Vec<Vec<u8>>,
+    }
+
+    impl Ktx2 {
+        fn new(vk_format: u32, size: [u32; 3], levels: Vec<Vec<u8>>) -> Ktx2 {
+            Ktx2 {
+                vk_format,
+                size,
+                layer_count: 0,
+                face_count: 1,
+                supercompression: SUPERCOMPRESSION_NONE,
+                levels,
+            }
+        }
+
+        /// Lays the file out like libktx does, smallest level first after
+        /// the level index.
+        fn write(&self) -> Vec<u8> {
+            let mut bytes = IDENTIFIER.to_vec();
+            for value in [
+                self.vk_format,
+                1,
+                self.size[0],
+                self.size[1],
+                self.size[2],
+                self.layer_count,
+                self.face_count,
+                self.levels.len() as u32,
+                self.supercompression,
+            ] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            bytes.resize(HEADER_SIZE, 0);
+
+            let stored: Vec<Vec<u8>> = self
+                .levels
+                .iter()
+                .map(|level| match self.supercompression {
+                    SUPERCOMPRESSION_ZSTD => ruzstd::encoding::compress_to_vec(
+                        level.as_slice(),
+                        ruzstd::encoding::CompressionLevel::Fastest,
+                    ),
+                    SUPERCOMPRESSION_ZLIB => miniz_oxide::deflate::compress_to_vec_zlib(level, 6),
+                    _ => level.clone(),
+                })
+                .collect();
+
+            let mut offset = HEADER_SIZE + self.levels.len() * LEVEL_INDEX_ENTRY_SIZE;
+            let mut offsets = vec![0; self.levels.len()];
+            for (level, data) in stored.iter().enumerate().rev() {
+                offsets[level] = offset;
+                offset += data.len();
+            }
+            for (level, data) in stored.iter().enumerate() {
+                for value in [offsets[level], data.len(), self.levels[level].len()] {
+                    bytes.extend_from_slice(&(value as u64).to_le_bytes());
+                }
+            }
+            for data in stored.iter().rev() {
+                bytes.extend_from_slice(data);
+            }
+
+            bytes
+        }
+    }
+
+    fn ramp(len: usize, seed: u8) -> Vec<u8> {
+        (0..len).map(|i| (i as u8).wrapping_mul(7) ^ seed).collect()
+    }
+
+    #[test]
+    fn parses_mip_chains() {
+        let levels = vec![ramp(4 * 4 * 4, 1), ramp(2 * 2 * 4, 2), ramp(4, 3)];
+        let bytes = Ktx2::new(VK_FORMAT_R8G8B8A8_SRGB, [4, 4, 0], levels.clone()).write();
+
+        let texture = parse(&bytes).unwrap();
+        assert_eq!(texture.format, DXGI_FORMAT_R8G8B8A8_UNORM_SRGB);
+        assert_eq!(texture.dimension, TextureDimension::Texture2D);
+        assert_eq!((texture.width, texture.height, texture.depth), (4, 4, 1));
+        assert_eq!((texture.mip_levels, texture.array_size), (3, 1));
+        assert_eq!(texture.data, levels.concat());
+    }
+
+    #[test]
+    fn reorders_array_levels_into_subresources() {
+        // Two layers of 2x2 and 1x1 R8, each level holding both layers.
+        let mut ktx2 = Ktx2::new(
+            VK_FORMAT_R8_UNORM,
+            [2, 2, 0],
+            vec![vec![0, 1, 2, 3, 10, 11, 12, 13], vec![4, 14]],
+        );
+        ktx2.layer_count = 2;
+
+        let texture = parse(&ktx2.write()).unwrap();
+        assert_eq!(texture.array_size, 2);
+        assert_eq!(texture.data, [0, 1, 2, 3, 4, 10, 11, 12, 13, 14]);
+    }
+
+    #[test]
+    fn parses_cubemaps_volumes_and_1d_textures() {
+        let mut cube = Ktx2::new(VK_FORMAT_R8_UNORM, [2, 2, 0], vec![ramp(6 * 4, 0)]);
+        cube.face_count = 6;
+        let texture = parse(&cube.write()).unwrap();
+        assert!(texture.cubemap);
+        assert_eq!(texture.array_size, 6);
+
+        cube.size = [2, 1, 0];
+        assert!(parse(&cube.write()).is_err());
+        cube.face_count = 3;
+        assert!(parse(&cube.write()).is_err());
+
+        let volume = Ktx2::new(VK_FORMAT_R8_UNORM, [2, 2, 2], vec![ramp(8, 0), ramp(1, 1)]);
+        let texture = parse(&volume.write()).unwrap();
+        assert_eq!(texture.dimension, TextureDimension::Texture3D);
+        assert_eq!((texture.depth, texture.mip_levels), (2, 2));
+        assert_eq!(texture.data.len(), 9);
+
+        let mut arrayed_volume = volume;
+        arrayed_volume.layer_count = 2;
+        assert!(parse(&arrayed_volume.write()).is_err());
+
+        let line = Ktx2::new(VK_FORMAT_R8_UNORM, [4, 0, 0], vec![ramp(4, 0)]);
+        let texture = parse(&line.write()).unwrap();
+        assert_eq!((texture.width, texture.height), (4, 1));
+    }
+
+    #[test]
+    fn undoes_supercompression() {
+        // BC7 blocks of a 8x8 texture, 4x4 and 1x1 mips.
+        let levels = vec![ramp(4 * 16, 1), ramp(16, 2), ramp(16, 3), ramp(16, 4)];
+
+        for supercompression in [
+            SUPERCOMPRESSION_NONE,
+            SUPERCOMPRESSION_ZSTD,
+            SUPERCOMPRESSION_ZLIB,
+        ] {
+            let mut ktx2 = Ktx2::new(VK_FORMAT_BC7_UNORM_BLOCK, [8, 8, 0], levels.clone());
+            ktx2.supercompression = supercompression;
+
+            let texture = parse(&ktx2.write()).unwrap();
+            assert_eq!(texture.format, DXGI_FORMAT_BC7_UNORM);
+            assert_eq!(texture.mip_levels, 4);
+            assert_eq!(texture.data, levels.concat(), "scheme {}", supercompression);
+        }
+
+        let mut unknown = Ktx2::new(VK_FORMAT_R8_UNORM, [1, 1, 0], vec![vec![0]]);
+        unknown.supercompression = 4;
+        assert!(parse(&unknown.write()).is_err());
+    }
+
+    #[test]
+    fn rejects_corrupt_supercompressed_levels() {
+        let mut ktx2 = Ktx2::new(VK_FORMAT_R8_UNORM, [16, 16, 0], vec![ramp(256, 0)]);
+        ktx2.supercompression = SUPERCOMPRESSION_ZSTD;
+        let mut bytes = ktx2.write();
+
+        // Claim a different uncompressed size than the level needs.
+        let uncompressed = HEADER_SIZE + 16;
+        bytes[uncompressed..uncompressed + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(parse(&bytes).is_err());
+        bytes[uncompressed..uncompressed + 8].copy_from_slice(&255u64.to_le_bytes());
+        assert!(parse(&bytes).is_err());
+
+        // Garbage instead of a Zstd frame.
+        let mut bytes = ktx2.write();
+        let end = bytes.len();
+        bytes[end - 8..].fill(0xAA);
+        let offset = read_u64(&bytes, HEADER_SIZE) as usize;
+        bytes[offset..offset + 4].fill(0);
+        assert!(parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_basis_universal() {
+        let mut bytes = Ktx2::new(0, [4, 4, 0], vec![ramp(16, 0)]).write();
+        // A data format descriptor whose color model is ETC1S.
+        let dfd_offset = bytes.len() as u32;
+        bytes[48..52].copy_from_slice(&dfd_offset.to_le_bytes());
+        bytes.extend_from_slice(&[0; 12]);
+        bytes.push(COLOR_MODEL_ETC1S);
+
+        let err = parse(&bytes).unwrap_err();
+        assert!(err.contains("ETC1S"), "{}", err);
+
+        // Out of range descriptor offsets don't panic.
+        bytes[48..52].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(parse(&bytes).is_err());
+
+        let mut basis_lz = Ktx2::new(VK_FORMAT_R8_UNORM, [4, 4, 0], vec![ramp(16, 0)]);
+        basis_lz.supercompression = SUPERCOMPRESSION_BASIS_LZ;
+        assert!(parse(&basis_lz.write()).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        let valid = Ktx2::new(VK_FORMAT_R8_UNORM, [2, 2, 0], vec![ramp(4, 0)]).write();
+        assert!(parse(&valid).is_ok());
+        assert!(parse(&valid[..HEADER_SIZE - 1]).is_err());
+        assert!(parse(&valid[..valid.len() - 1]).is_err());
+
+        let mut bad_identifier = valid.clone();
+        bad_identifier[1] = b'X';
+        assert!(parse(&bad_identifier).is_err());
+
+        // More levels than a 2x2 texture has, without reading or allocating
+        // a level index for them.
+        let mut too_many_levels = valid.clone();
+        too_many_levels[40..44].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = parse(&too_many_levels).unwrap_err();
+        assert!(err.contains("at most 2"), "{}", err);
+
+        let mut truncated_index = valid.clone();
+        truncated_index[40..44].copy_from_slice(&2u32.to_le_bytes());
+        truncated_index.truncate(HEADER_SIZE + LEVEL_INDEX_ENTRY_SIZE + 4);
+        assert!(parse(&truncated_index).is_err());
+
+        let mut zero_width = valid;
+        zero_width[20..24].fill(0);
+        assert!(parse(&zero_width).is_err());
+    }
+
+    #[test]
+    fn rejects_sizes_that_overflow() {
+        let mut layers = Ktx2::new(VK_FORMAT_R8_UNORM, [2, 2, 0], vec![ramp(4, 0)]);
+        layers.layer_count = u32::MAX;
+        layers.face_count = 6;
+        layers.size = [2, 2, 0];
+        assert!(parse(&layers.write()).is_err());
+
+        // The largest BC7 texture and array the header can describe.
+        let mut huge = Ktx2::new(
+            VK_FORMAT_BC7_UNORM_BLOCK,
+            [u32::MAX, u32::MAX, 0],
+            vec![vec![]],
+        );
+        huge.layer_count = u32::MAX;
+        assert!(parse(&huge.write()).is_err());
+
+        let mut huge_volume = Ktx2::new(
+            VK_FORMAT_BC7_UNORM_BLOCK,
+            [u32::MAX, u32::MAX, u32::MAX],
+            vec![vec![]],
+        );
+        huge_volume.layer_count = 0;
+        assert!(parse(&huge_volume.write()).is_err());
+    }
+
+    #[test]
+    fn rejects_sizes_beyond_d3d11_limits() {
+        let limit_error = |ktx2: &Ktx2| {
+            let err = parse(&ktx2.write()).unwrap_err();
+            assert!(err.contains("allow"), "{}", err);
+        };
+
+        limit_error(&Ktx2::new(VK_FORMAT_R8_UNORM, [16385, 1, 0], vec![vec![]]));
+        limit_error(&Ktx2::new(VK_FORMAT_R8_UNORM, [1, 2049, 1], vec![vec![]]));
+
+        let mut layers = Ktx2::new(VK_FORMAT_R8_UNORM, [1, 1, 0], vec![vec![]]);
+        layers.layer_count = 2049;
+        limit_error(&layers);
+        // Cube arrays count every face.
+        layers.layer_count = 342;
+        layers.face_count = 6;
+        limit_error(&layers);
+        layers.layer_count = 341;
+        assert!(!parse(&layers.write()).unwrap_err().contains("allow"));
+    }
+
+    #[test]
+    fn limits_inflated_levels_to_their_size() {
+        let mut ktx2 = Ktx2::new(VK_FORMAT_R8_UNORM, [16, 16, 0], vec![vec![0; 100_000]]);
+        ktx2.supercompression = SUPERCOMPRESSION_ZLIB;
+        let mut bytes = ktx2.write();
+        let uncompressed = HEADER_SIZE + 16;
+        bytes[uncompressed..uncompressed + 8].copy_from_slice(&256u64.to_le_bytes());
+
+        let err = parse(&bytes).unwrap_err();
+        assert!(err.contains("zlib"), "{}", err);
+    }
+
+    #[test]
+    fn maps_vk_formats_to_dxgi() {
+        let formats = [
+            (4, DXGI_FORMAT_B5G6R5_UNORM),
+            (9, DXGI_FORMAT_R8_UNORM),
+            (37, DXGI_FORMAT_R8G8B8A8_UNORM),
+            (43, DXGI_FORMAT_R8G8B8A8_UNORM_SRGB),
+            (44, DXGI_FORMAT_B8G8R8A8_UNORM),
+            (50, DXGI_FORMAT_B8G8R8A8_UNORM_SRGB),
+            (64, DXGI_FORMAT_R10G10B10A2_UNORM),
+            (97, DXGI_FORMAT_R16G16B16A16_FLOAT),
+            (109, DXGI_FORMAT_R32G32B32A32_FLOAT),
+            (122, DXGI_FORMAT_R11G11B10_FLOAT),
+            (123, DXGI_FORMAT_R9G9B9E5_SHAREDEXP),
+            (131, DXGI_FORMAT_BC1_UNORM),
+            (134, DXGI_FORMAT_BC1_UNORM_SRGB),
+            (141, DXGI_FORMAT_BC5_UNORM),
+            (143, DXGI_FORMAT_BC6H_UF16),
+            (146, DXGI_FORMAT_BC7_UNORM_SRGB),
+            (1_000_338_000, DXGI_FORMAT_B5G5R5A1_UNORM),
+            (1_000_340_000, DXGI_FORMAT_B4G4R4A4_UNORM),
+        ];
+        for (vk_format, format) in formats {
+            assert_eq!(dxgi_format(vk_format), Ok(format), "VkFormat {}", vk_format);
+        }
+
+        // R5G5B5A1 and R4G4B4A4 have no DXGI layout, and neither do
+        // compressed formats D3D11 can't sample.
+        for vk_format in [0, 2, 8, 23, 147, 160, 1_000_066_000, 1_000_340_001] {
+            assert!(dxgi_format(vk_format).is_err(), "VkFormat {}", vk_format);
+        }
+    }
+
+    #[test]
+    fn maps_vk_formats_to_known_dxgi_formats() {
+        // Every texel or block size agrees with the VkFormat's.
+        let vk_bytes = |vk_format: u32| match vk_format {
+            4 | 8 | 16..=21 | 70..=76 | 1_000_338_000 | 1_000_340_000 => 2,
+            9..=14 => 1,
+            37..=50 | 64 | 68 | 77..=83 | 98..=100 | 122 | 123 => 4,
+            91..=97 | 101..=103 | 131..=134 | 139 | 140 => 8,
+            104..=106 => 12,
+            _ => 16,
+        };
+
+        let mut mapped = 0;
+        for vk_format in (0..200).chain([1_000_338_000, 1_000_340_000]) {
+            if let Ok(format) = dxgi_format(vk_format) {
+                let info = FormatInfo::of(format).unwrap();
+                assert_eq!(info.bytes, vk_bytes(vk_format), "VkFormat {}", vk_format);
+                mapped += 1;
+            }
+        }
+        assert_eq!(mapped, 65);
+    }
+}
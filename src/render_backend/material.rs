@@ -0,0 +1,337 @@
+use glam::Vec3;
+
+use crate::error::{EngineError, EngineResult};
+use crate::vfs;
+
+use super::backend::Backend;
+use super::cpu_image::{CpuImage, PixelType};
+use super::mipmaps::MipGenerator;
+use super::texture::{Tex, Tex2D};
+
+/// Surface parameters of an imported material. Texture paths are VFS paths,
+/// already resolved against the file that referenced them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaterialDesc {
+    pub name: String,
+    /// `Kd`, linear RGB.
+    pub diffuse: Vec3,
+    /// `Ks`, linear RGB.
+    pub specular: Vec3,
+    /// `Ns`, the Phong exponent.
+    pub shininess: f32,
+    /// `d`, or one minus `Tr`.
+    pub opacity: f32,
+    /// `illum`, the MTL illumination model.
+    pub illumination: u32,
+    /// `map_Kd`
+    pub diffuse_map: Option<String>,
+    /// `map_Bump` or `bump`
+    pub bump_map: Option<String>,
+    /// `map_d`
+    pub opacity_map: Option<String>,
+}
+
+impl MaterialDesc {
+    /// White, not shiny and opaque, what MTL assumes for missing statements.
+    pub fn new(name: &str) -> MaterialDesc {
+        MaterialDesc {
+            name: name.to_owned(),
+            diffuse: Vec3::ONE,
+            specular: Vec3::ZERO,
+            shininess: 0.0,
+            opacity: 1.0,
+            illumination: 1,
+            diffuse_map: None,
+            bump_map: None,
+            opacity_map: None,
+        }
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.opacity < 1.0 || self.opacity_map.is_some()
+    }
+
+    /// The diffuse map, or a 1x1 texture of the diffuse color if there is
+    /// none, so every material can be bound the same way.
+    pub fn diffuse_texture(&self, backend: &Backend) -> EngineResult<Tex2D> {
+        if let Some(file) = &self.diffuse_map {
+            return Tex2D::from_file(backend, file);
+        }
+
+        let color = [self.diffuse.x, self.diffuse.y, self.diffuse.z, self.opacity];
+        solid_color_texture(backend, &self.name, color)
+    }
+}
+
+/// A 1x1 RGBA8 texture of a linear `color`, for materials without a map.
+pub fn solid_color_texture(backend: &Backend, name: &str, color: [f32; 4]) -> EngineResult<Tex2D> {
+    let image = CpuImage::from_rgba32f(1, 1, PixelType::Rgba8, vec![color]);
+    MipGenerator::new()
+        .texture_data(&image)
+        .create(backend)?
+        .into_2d(name)
+}
+
+/// Loads the materials of an MTL library through the VFS.
+pub fn load_mtl(file: &str) -> EngineResult<Vec<MaterialDesc>> {
+    let io_error = |source| EngineError::Io {
+        path: file.to_owned(),
+        source,
+    };
+
+    let dir = vfs::parent(&vfs::normalize(file).map_err(io_error)?).to_owned();
+    let text = vfs::read_to_string(file).map_err(io_error)?;
+
+    parse_mtl(&dir, &text).map_err(|message| EngineError::ObjParse {
+        path: file.to_owned(),
+        message,
+    })
+}
+
+/// Parses an MTL library whose texture paths are relative to `dir`.
+/// Statements other than the ones `MaterialDesc` keeps are skipped.
+pub fn parse_mtl(dir: &str, text: &str) -> Result<Vec<MaterialDesc>, String> {
+    let mut materials: Vec<MaterialDesc> = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let mut words = line.split_whitespace();
+        let statement = match words.next() {
+            Some(statement) if !statement.starts_with('#') => statement,
+            _ => continue,
+        };
+        let arguments: Vec<&str> = words.collect();
+
+        if statement == "newmtl" {
+            if arguments.is_empty() {
+                return Err(format!("line {}: newmtl without a name", line_number));
+            }
+            materials.push(MaterialDesc::new(&arguments.join(" ")));
+            continue;
+        }
+
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => continue,
+        };
+        let numbers = || -> Result<Vec<f32>, String> {
+            arguments
+                .iter()
+                .map(|argument| argument.parse::<f32>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| format!("line {}: expected numbers after {}", line_number, statement))
+        };
+        let number = || -> Result<f32, String> {
+            numbers()?.first().copied().ok_or_else(|| {
+                format!(
+                    "line {}: expected a number after {}",
+                    line_number, statement
+                )
+            })
+        };
+        // A single value is a grey, spectral and XYZ colors aren't supported.
+        let color = || -> Result<Vec3, String> {
+            match numbers()?.as_slice() {
+                [grey] => Ok(Vec3::splat(*grey)),
+                [r, g, b, ..] => Ok(Vec3::new(*r, *g, *b)),
+                _ => Err(format!(
+                    "line {}: expected a color after {}",
+                    line_number, statement
+                )),
+            }
+        };
+        let texture = || -> Result<String, String> {
+            let file = texture_file(&arguments)
+                .ok_or_else(|| format!("line {}: {} without a file", line_number, statement))?;
+            vfs::join(dir, &file).map_err(|err| format!("line {}: {}", line_number, err))
+        };
+
+        match statement {
+            "Kd" => material.diffuse = color()?,
+            "Ks" => material.specular = color()?,
+            "Ns" => material.shininess = number()?,
+            "d" => material.opacity = number()?,
+            "Tr" => material.opacity = 1.0 - number()?,
+            "illum" => material.illumination = number()? as u32,
+            "map_Kd" => material.diffuse_map = Some(texture()?),
+            "map_Bump" | "map_bump" | "bump" => material.bump_map = Some(texture()?),
+            "map_d" => material.opacity_map = Some(texture()?),
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
+
+/// The file name of a texture statement, after its options. File names may
+/// contain spaces.
+fn texture_file(arguments: &[&str]) -> Option<String> {
+    let mut rest = arguments;
+    while let Some(option) = rest.first().filter(|argument| argument.starts_with('-')) {
+        // -o, -s and -t take up to three numbers, the rest a fixed count.
+        let (min, max) = match *option {
+            "-o" | "-s" | "-t" => (1, 3),
+            "-mm" => (2, 2),
+            _ => (1, 1),
+        };
+        let mut count = min;
+        while count < max
+            && rest
+                .get(count + 1)
+                .is_some_and(|value| value.parse::<f32>().is_ok())
+        {
+            count += 1;
+        }
+        rest = rest.get(count + 1..)?;
+    }
+
+    (!rest.is_empty()).then(|| rest.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_statements() {
+        let materials = parse_mtl(
+            "models",
+            "# Exported\n\
+             newmtl grass\n\
+             \tKd 0.1 0.5 0.2\n\
+             Ks 0.5\n\
+             Ns 32\n\
+             illum 2\n\
+             map_Kd textures/grass.png\n\
+             map_Bump grass_normal.png\n\
+             Ka 1 1 1\n\
+             \n\
+             newmtl glass pane\n\
+             d 0.25\n\
+             map_d glass_alpha.png\n\
+             bump glass_bump.png\n",
+        )
+        .unwrap();
+
+        assert_eq!(materials.len(), 2);
+        let grass = &materials[0];
+        assert_eq!(grass.name, "grass");
+        assert_eq!(grass.diffuse, Vec3::new(0.1, 0.5, 0.2));
+        assert_eq!(grass.specular, Vec3::splat(0.5));
+        assert_eq!((grass.shininess, grass.illumination), (32.0, 2));
+        assert_eq!(
+            grass.diffuse_map.as_deref(),
+            Some("models/textures/grass.png")
+        );
+        assert_eq!(grass.bump_map.as_deref(), Some("models/grass_normal.png"));
+        assert!(!grass.is_transparent());
+
+        let glass = &materials[1];
+        assert_eq!(glass.name, "glass pane");
+        assert_eq!(glass.opacity, 0.25);
+        assert_eq!(glass.opacity_map.as_deref(), Some("models/glass_alpha.png"));
+        assert_eq!(glass.bump_map.as_deref(), Some("models/glass_bump.png"));
+        assert!(glass.is_transparent());
+    }
+
+    #[test]
+    fn defaults_missing_statements() {
+        // Statements before the first newmtl have no material to go to.
+        let materials = parse_mtl("", "Kd 1 0 0\nnewmtl plain\n").unwrap();
+        assert_eq!(materials, [MaterialDesc::new("plain")]);
+
+        let plain = &materials[0];
+        assert_eq!(plain.diffuse, Vec3::ONE);
+        assert_eq!(plain.specular, Vec3::ZERO);
+        assert_eq!(
+            (plain.shininess, plain.opacity, plain.illumination),
+            (0.0, 1.0, 1)
+        );
+        assert_eq!(plain.diffuse_map, None);
+        assert!(!plain.is_transparent());
+
+        assert!(parse_mtl("", "").unwrap().is_empty());
+        let masked = MaterialDesc {
+            opacity_map: Some("mask.png".to_owned()),
+            ..MaterialDesc::new("masked")
+        };
+        assert!(masked.is_transparent());
+    }
+
+    #[test]
+    fn reads_tr_as_transparency() {
+        let opacity = |statements: &str| {
+            parse_mtl("", &format!("newmtl m\n{}\n", statements)).unwrap()[0].opacity
+        };
+        assert_eq!(opacity("d 0.75"), 0.75);
+        assert_eq!(opacity("Tr 0.75"), 0.25);
+        assert_eq!(opacity("Tr 0"), 1.0);
+        // The later statement wins.
+        assert_eq!(opacity("d 0.5\nTr 0.1"), 0.9);
+        assert_eq!(opacity("Tr 0.1\nd 0.5"), 0.5);
+    }
+
+    #[test]
+    fn skips_texture_options() {
+        let file =
+            |arguments: &str| texture_file(&arguments.split_whitespace().collect::<Vec<_>>());
+        assert_eq!(file("file.png").as_deref(), Some("file.png"));
+        assert_eq!(file("-o 1 2 3 file.png").as_deref(), Some("file.png"));
+        assert_eq!(file("-o 0.5 file.png").as_deref(), Some("file.png"));
+        assert_eq!(file("-s 1 1 file.png").as_deref(), Some("file.png"));
+        assert_eq!(file("-bm 0.5 file").as_deref(), Some("file"));
+        assert_eq!(file("-mm 0 1 file.png").as_deref(), Some("file.png"));
+        assert_eq!(
+            file("-clamp on -blendu off -imfchan r file.png").as_deref(),
+            Some("file.png")
+        );
+        assert_eq!(
+            file("-bm 0.5 my grass texture.png").as_deref(),
+            Some("my grass texture.png")
+        );
+        assert_eq!(file("my texture.png").as_deref(), Some("my texture.png"));
+
+        // Options without a file.
+        assert_eq!(file(""), None);
+        assert_eq!(file("-bm 0.5"), None);
+        assert_eq!(file("-o 1 2 3"), None);
+        assert_eq!(file("-clamp"), None);
+    }
+
+    #[test]
+    fn resolves_textures_against_the_library() {
+        let materials = parse_mtl(
+            "models/house",
+            "newmtl wall\nmap_Kd -s 2 2 1 ../shared/brick wall.png\nmap_d ./alpha.png\n",
+        )
+        .unwrap();
+        assert_eq!(
+            materials[0].diffuse_map.as_deref(),
+            Some("models/shared/brick wall.png")
+        );
+        assert_eq!(
+            materials[0].opacity_map.as_deref(),
+            Some("models/house/alpha.png")
+        );
+
+        assert!(parse_mtl("", "newmtl m\nmap_Kd ../outside.png\n").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_statements() {
+        for text in [
+            "newmtl\n",
+            "newmtl m\nKd red\n",
+            "newmtl m\nKd 1 0\n",
+            "newmtl m\nNs\n",
+            "newmtl m\nd high\n",
+            "newmtl m\nmap_Kd\n",
+            "newmtl m\nmap_Kd -bm 0.5\n",
+        ] {
+            assert!(parse_mtl("", text).is_err(), "{:?}", text);
+        }
+
+        let err = parse_mtl("", "newmtl m\n\nKs 1 x 1\n").unwrap_err();
+        assert!(err.starts_with("line 3:"), "{}", err);
+    }
+}
@@ -1,8 +1,23 @@
-use glam::{Vec2, Vec3};
+use std::collections::HashMap;
+use std::path::Path;
+
+use glam::{Vec2, Vec3, Vec4};
 use obj::*;
-use windows::core::*;
+use windows::Win32::Graphics::Dxgi::Common::{
+    DXGI_FORMAT, DXGI_FORMAT_R16_UINT, DXGI_FORMAT_R32_UINT,
+};
+
+use crate::error::{EngineError, EngineResult};
+use crate::vfs;
 
-use super::{backend::Backend, gpu_buffer::GPUBuffer};
+use super::{
+    atlas::{AtlasLayout, AtlasRegion},
+    backend::Backend,
+    gpu_buffer::GPUBuffer,
+    material::{load_mtl, MaterialDesc},
+    pipeline_state::{InputElement, VertexFormat},
+    typed_buffer::{BufferUsage, Pod, TypedBuffer},
+};
 
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 #[repr(C)]
@@ -10,12 +25,53 @@ pub struct Vertex {
     pub position: Vec3,
     pub normal: Vec3,
     pub uv: Vec2,
+    /// The direction U grows in, with the handedness of the bitangent in
+    /// `w`: `bitangent = w * normal.cross(tangent)`. Zero until generated
+    /// or imported.
+    pub tangent: Vec4,
+}
+
+// Four vectors with nothing between them.
+unsafe impl Pod for Vertex {}
+const _: () = assert!(std::mem::size_of::<Vertex>() == 48);
+
+impl Vertex {
+    pub const LAYOUT: [InputElement; 4] = [
+        InputElement::per_vertex("POSITION", VertexFormat::Float3, 0),
+        InputElement::per_vertex("NORMAL", VertexFormat::Float3, 12),
+        InputElement::per_vertex("TEXCOORD", VertexFormat::Float2, 24),
+        InputElement::per_vertex("TANGENT", VertexFormat::Float4, 32),
+    ];
+}
+
+/// Indices are uploaded with 16 bits when every vertex can be reached
+/// with them, halving the index fetch bandwidth.
+#[derive(Clone)]
+pub enum IndexBuffer {
+    U16(TypedBuffer<u16>),
+    U32(TypedBuffer<u32>),
+}
+
+impl IndexBuffer {
+    pub fn buffer(&self) -> &GPUBuffer {
+        match self {
+            IndexBuffer::U16(buffer) => buffer,
+            IndexBuffer::U32(buffer) => buffer,
+        }
+    }
+
+    pub fn to_dxgi(&self) -> DXGI_FORMAT {
+        match self {
+            IndexBuffer::U16(_) => DXGI_FORMAT_R16_UINT,
+            IndexBuffer::U32(_) => DXGI_FORMAT_R32_UINT,
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct GpuMesh {
-    pub vertex_buffer: GPUBuffer,
-    pub index_buffer: GPUBuffer,
+    pub vertex_buffer: TypedBuffer<Vertex>,
+    pub index_buffer: IndexBuffer,
     pub num_indices: u32,
 }
 
@@ -23,67 +79,251 @@ pub struct GpuMesh {
 pub struct CpuMesh {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
+    /// Per vertex UV sets after the first, which is `Vertex::uv`.
+    pub extra_uvs: Vec<Vec<Vec2>>,
 }
 
 impl CpuMesh {
-    pub fn from_obj(file_name: &str) -> Result<Vec<CpuMesh>> {
-        let obj =
-            Obj::load(file_name).map_err(|_| Error::fast_error(HRESULT::from_win32(0x80070057)))?;
+    /// Loads `file_name` through the VFS, one mesh per object.
+    pub fn from_obj(file_name: &str) -> EngineResult<Vec<CpuMesh>> {
+        let bytes = vfs::read(file_name).map_err(|source| EngineError::Io {
+            path: file_name.to_owned(),
+            source,
+        })?;
+
+        CpuMesh::parse_obj(file_name, &bytes)
+    }
+
+    /// Parses an OBJ file, `file_name` is only used in errors. Materials
+    /// are ignored, see `ObjModel` to split objects by material.
+    ///
+    /// Polygons are triangulated by ear clipping. Missing UVs are zero and
+    /// missing normals are smoothed over the faces sharing a position.
+    /// Tangents are generated and identical vertices welded. Objects without
+    /// faces are skipped.
+    pub fn parse_obj(file_name: &str, bytes: &[u8]) -> EngineResult<Vec<CpuMesh>> {
+        let data = parse_obj_data(file_name, bytes)?;
 
         let mut meshes = Vec::new();
+        for object in &data.objects {
+            let triangles =
+                triangulate_object(&data, object).map_err(|message| EngineError::ObjParse {
+                    path: file_name.to_owned(),
+                    message,
+                })?;
+            if triangles.is_empty() {
+                continue;
+            }
 
-        for object in obj.data.objects {
-            let mut vertices = Vec::new();
-            let mut indices = Vec::new();
+            let smooth_normals = smooth_normals(&data, &triangles);
+            let triangles = triangles.iter().map(|(_, triangle)| triangle);
+            meshes.push(obj_mesh(&data, triangles, &smooth_normals));
+        }
 
-            let mut index = 0;
+        Ok(meshes)
+    }
 
-            for group in object.groups {
-                for poly in group.polys {
-                    assert!(poly.0.len() == 3);
+    /// Merges vertices whose attributes are all bitwise identical and points
+    /// the indices at the first of each, keeping the order vertices are
+    /// first used in. Unused vertices are dropped.
+    pub fn weld(&mut self) {
+        let mut unique: HashMap<([u32; 12], Vec<u32>), u32> =
+            HashMap::with_capacity(self.vertices.len());
+        let mut remap = Vec::with_capacity(self.vertices.len());
 
-                    for index_tuple in poly.0 {
-                        let position = Vec3::from(obj.data.position[index_tuple.0]);
+        for index in &mut self.indices {
+            let i = *index as usize;
+            let vertex = self.vertices[i];
+            let key = [
+                vertex.position.x,
+                vertex.position.y,
+                vertex.position.z,
+                vertex.normal.x,
+                vertex.normal.y,
+                vertex.normal.z,
+                vertex.uv.x,
+                vertex.uv.y,
+                vertex.tangent.x,
+                vertex.tangent.y,
+                vertex.tangent.z,
+                vertex.tangent.w,
+            ]
+            .map(float_bits);
+            let extra_uvs = self
+                .extra_uvs
+                .iter()
+                .flat_map(|uvs| [float_bits(uvs[i].x), float_bits(uvs[i].y)])
+                .collect();
 
-                        let uv_index = index_tuple.1.expect("Should have a UV index");
-                        let uv = Vec2::from(obj.data.texture[uv_index]);
+            *index = *unique.entry((key, extra_uvs)).or_insert_with(|| {
+                remap.push(i);
+                remap.len() as u32 - 1
+            });
+        }
 
-                        let normal_index = index_tuple.2.expect("Should have a normal index");
-                        let normal = Vec3::from(obj.data.normal[normal_index]);
+        self.vertices = remap.iter().map(|&i| self.vertices[i]).collect();
+        for uvs in &mut self.extra_uvs {
+            *uvs = remap.iter().map(|&i| uvs[i]).collect();
+        }
+    }
 
-                        vertices.push(Vertex {
-                            position,
-                            uv,
-                            normal,
-                        })
-                    }
-                    indices.push(index);
-                    indices.push(index + 1);
-                    indices.push(index + 2);
+    /// Recomputes normals by averaging the faces around each position,
+    /// weighted by their angle at it. Faces further than `max_angle` radians
+    /// apart aren't averaged with each other, so edges sharper than that
+    /// stay hard and their vertices are split.
+    pub fn generate_smooth_normals(&mut self, max_angle: f32) {
+        let min_cos = max_angle.cos();
+        let faces: Vec<[Vertex; 3]> = self.triangles().collect();
+        let normals: Vec<Vec3> = faces.iter().map(face_normal).collect();
 
-                    index = index + 3
-                }
+        // The faces around each position, with their angle there.
+        let mut around: HashMap<[u32; 3], Vec<(usize, f32)>> = HashMap::new();
+        for (face, vertices) in faces.iter().enumerate() {
+            for corner in 0..3 {
+                let position = vertices[corner].position;
+                let angle = angle_between(
+                    vertices[(corner + 1) % 3].position - position,
+                    vertices[(corner + 2) % 3].position - position,
+                );
+                around
+                    .entry(position.to_array().map(float_bits))
+                    .or_default()
+                    .push((face, angle));
             }
+        }
 
-            meshes.push(CpuMesh { vertices, indices });
+        self.split_corners();
+        for (corner, vertex) in self.vertices.iter_mut().enumerate() {
+            let normal = normals[corner / 3];
+            // Degenerate faces have no direction of their own to keep.
+            vertex.normal = around[&vertex.position.to_array().map(float_bits)]
+                .iter()
+                .filter(|(other, _)| normal == Vec3::ZERO || normal.dot(normals[*other]) >= min_cos)
+                .fold(Vec3::ZERO, |sum, (other, angle)| {
+                    sum + normals[*other] * *angle
+                })
+                .normalize_or_zero();
         }
+        self.weld();
+    }
 
-        Ok(meshes)
+    /// Gives every face its own normal, splitting the vertices between
+    /// faces that aren't coplanar.
+    pub fn generate_flat_normals(&mut self) {
+        self.split_corners();
+        for vertices in self.vertices.chunks_exact_mut(3) {
+            let normal = face_normal(&[vertices[0], vertices[1], vertices[2]]);
+            for vertex in vertices {
+                vertex.normal = normal;
+            }
+        }
+        self.weld();
+    }
+
+    /// Generates tangents from the normals and the first UV set the way
+    /// MikkTSpace does, so normal maps baked against MikkTSpace line up.
+    /// Face tangents are projected onto each corner's normal and averaged by
+    /// the corner's angle over the faces sharing the vertex and the UV
+    /// winding. Vertices where mirrored UVs meet are split, since the
+    /// handedness differs on either side. Unlike the reference
+    /// implementation, faces that only touch a vertex without sharing an
+    /// edge around it are averaged too.
+    pub fn generate_tangents(&mut self) {
+        let faces: Vec<[Vertex; 3]> = self.triangles().collect();
+
+        let mut sums: HashMap<([u32; 8], bool), Vec3> = HashMap::new();
+        let mut corner_keys = Vec::with_capacity(faces.len() * 3);
+        for vertices in &faces {
+            let [a, b, c] = *vertices;
+            let (edge1, edge2) = (b.position - a.position, c.position - a.position);
+            let (duv1, duv2) = (b.uv - a.uv, c.uv - a.uv);
+            // Twice the signed UV area. Negative when the UVs are mirrored.
+            let area = duv1.perp_dot(duv2);
+            let positive = area >= 0.0;
+            let tangent = if area == 0.0 {
+                Vec3::ZERO
+            } else {
+                (edge1 * duv2.y - edge2 * duv1.y) * area.signum()
+            };
+
+            for corner in 0..3 {
+                let vertex = vertices[corner];
+                let normal = vertex.normal.normalize_or_zero();
+                let project = |vector: Vec3| vector - normal * normal.dot(vector);
+                let angle = angle_between(
+                    project(vertices[(corner + 1) % 3].position - vertex.position),
+                    project(vertices[(corner + 2) % 3].position - vertex.position),
+                );
+
+                let key = (vertex_key(&vertex), positive);
+                *sums.entry(key).or_insert(Vec3::ZERO) +=
+                    project(tangent).normalize_or_zero() * angle;
+                corner_keys.push(key);
+            }
+        }
+
+        self.split_corners();
+        for (vertex, key) in self.vertices.iter_mut().zip(&corner_keys) {
+            let normal = vertex.normal.normalize_or_zero();
+            let mut tangent = sums[key].normalize_or_zero();
+            // Without usable UVs any direction along the surface will do.
+            if tangent == Vec3::ZERO && normal != Vec3::ZERO {
+                tangent = normal.any_orthonormal_vector();
+            }
+            vertex.tangent = tangent.extend(if key.1 { 1.0 } else { -1.0 });
+        }
+        self.weld();
+    }
+
+    /// The vertices of each triangle.
+    fn triangles(&self) -> impl Iterator<Item = [Vertex; 3]> + '_ {
+        self.indices
+            .chunks_exact(3)
+            .map(|triangle| [0, 1, 2].map(|corner| self.vertices[triangle[corner] as usize]))
     }
 
-    pub fn upload(&self, backend: &Backend) -> Result<GpuMesh> {
-        let vertex_buffer_size = self.vertices.len() * std::mem::size_of::<Vertex>();
-        let index_buffer_size = self.indices.len() * std::mem::size_of::<u32>();
+    /// Gives every corner of every triangle its own vertex, in index order.
+    fn split_corners(&mut self) {
+        let corners: Vec<usize> = self.indices.iter().map(|&index| index as usize).collect();
 
-        let vertex_buffer = GPUBuffer::vertex_buffer(backend, vertex_buffer_size as u32)?;
-        let index_buffer = GPUBuffer::index_buffer(backend, index_buffer_size as u32)?;
-        {
-            let mapped_vertex_buffer = vertex_buffer.map(backend)?;
-            let mapped_index_buffer = index_buffer.map(backend)?;
+        self.vertices = corners.iter().map(|&i| self.vertices[i]).collect();
+        for uvs in &mut self.extra_uvs {
+            *uvs = corners.iter().map(|&i| uvs[i]).collect();
+        }
+        self.indices = (0..corners.len() as u32).collect();
+    }
 
-            mapped_vertex_buffer.copy_from(self.vertices.as_slice());
-            mapped_index_buffer.copy_from(self.indices.as_slice());
+    /// Moves the UVs into `region` of a packed atlas, e.g. the region named
+    /// after the mesh's texture. Array layers keep their UVs, the layer is
+    /// picked with `region.layer` instead.
+    pub fn remap_uvs(&mut self, region: &AtlasRegion) {
+        for vertex in &mut self.vertices {
+            vertex.uv = region.remap(vertex.uv);
         }
+    }
+
+    /// Whether 16 bit indices can reach every vertex.
+    pub fn fits_16_bit_indices(&self) -> bool {
+        self.vertices.len() <= u16::MAX as usize + 1
+    }
+
+    pub fn upload(&self, backend: &Backend) -> EngineResult<GpuMesh> {
+        let vertex_buffer =
+            TypedBuffer::vertex_buffer(backend, &self.vertices, BufferUsage::Immutable)?;
+        let index_buffer = if self.fits_16_bit_indices() {
+            let indices: Vec<u16> = self.indices.iter().map(|&index| index as u16).collect();
+            IndexBuffer::U16(TypedBuffer::index_buffer(
+                backend,
+                &indices,
+                BufferUsage::Immutable,
+            )?)
+        } else {
+            IndexBuffer::U32(TypedBuffer::index_buffer(
+                backend,
+                &self.indices,
+                BufferUsage::Immutable,
+            )?)
+        };
 
         Ok(GpuMesh {
             index_buffer,
@@ -97,7 +337,7 @@ impl GpuMesh {
     //    pub fn from_meshes(
     //        backend: &Backend,
     //        meshes: &[CpuMesh],
-    //    ) -> Result<(Vec<GpuMesh>, GPUBuffer, GPUBuffer)> {
+    //    ) -> EngineResult<(Vec<GpuMesh>, GPUBuffer, GPUBuffer)> {
     //        let num_vertices = meshes
     //            .iter()
     //            .fold(0, |total, mesh| total + mesh.vertices.len());
@@ -144,3 +384,757 @@ impl GpuMesh {
     //        Ok((gpu_meshes, vertex_buffer, index_buffer))
     //    }
 }
+
+/// One object of an OBJ file, or the part of it using one material.
+#[derive(Debug)]
+pub struct Submesh {
+    pub object: String,
+    /// Index into `ObjModel::materials`, `None` for faces without a known
+    /// material.
+    pub material: Option<usize>,
+    pub mesh: CpuMesh,
+}
+
+/// An OBJ file with its MTL materials, split into a submesh per object and
+/// material.
+#[derive(Debug, Default)]
+pub struct ObjModel {
+    pub submeshes: Vec<Submesh>,
+    pub materials: Vec<MaterialDesc>,
+}
+
+impl ObjModel {
+    /// Loads `file_name` and the MTL libraries it names through the VFS.
+    /// Libraries and their textures are found relative to the OBJ file.
+    pub fn load(file_name: &str) -> EngineResult<ObjModel> {
+        let io_error = |source| EngineError::Io {
+            path: file_name.to_owned(),
+            source,
+        };
+
+        let bytes = vfs::read(file_name).map_err(io_error)?;
+        let data = parse_obj_data(file_name, &bytes)?;
+
+        let dir = vfs::parent(&vfs::normalize(file_name).map_err(io_error)?).to_owned();
+        let mut materials = Vec::new();
+        for library in &data.material_libs {
+            let file = vfs::join(&dir, &library.filename).map_err(io_error)?;
+            materials.extend(load_mtl(&file)?);
+        }
+
+        ObjModel::from_data(file_name, data, materials)
+    }
+
+    /// Splits parsed OBJ data by object and `usemtl` material. Normals are
+    /// smoothed across the whole object, so material borders don't show
+    /// seams.
+    fn from_data(
+        file_name: &str,
+        data: ObjData,
+        materials: Vec<MaterialDesc>,
+    ) -> EngineResult<ObjModel> {
+        let mut submeshes = Vec::new();
+
+        for object in &data.objects {
+            let triangles =
+                triangulate_object(&data, object).map_err(|message| EngineError::ObjParse {
+                    path: file_name.to_owned(),
+                    message,
+                })?;
+            let smooth_normals = smooth_normals(&data, &triangles);
+
+            // Materials in the order the object first uses them.
+            let group_materials: Vec<Option<usize>> = object
+                .groups
+                .iter()
+                .map(|group| match &group.material {
+                    Some(ObjMaterial::Ref(name)) => {
+                        materials.iter().position(|material| &material.name == name)
+                    }
+                    Some(ObjMaterial::Mtl(material)) => materials
+                        .iter()
+                        .position(|candidate| candidate.name == material.name),
+                    None => None,
+                })
+                .collect();
+            let mut used: Vec<Option<usize>> = Vec::new();
+            for (group, _) in &triangles {
+                if !used.contains(&group_materials[*group]) {
+                    used.push(group_materials[*group]);
+                }
+            }
+
+            for material in used {
+                let triangles = triangles
+                    .iter()
+                    .filter(|(group, _)| group_materials[*group] == material)
+                    .map(|(_, triangle)| triangle);
+                submeshes.push(Submesh {
+                    object: object.name.clone(),
+                    material,
+                    mesh: obj_mesh(&data, triangles, &smooth_normals),
+                });
+            }
+        }
+
+        Ok(ObjModel {
+            submeshes,
+            materials,
+        })
+    }
+
+    /// Remaps the UVs of every submesh whose diffuse map was packed into
+    /// `layout`, found by the map's file name without its extension like
+    /// `load_tiles` names tiles. Returns how many submeshes moved.
+    pub fn remap_to_atlas(&mut self, layout: &AtlasLayout) -> usize {
+        let mut remapped = 0;
+        for submesh in &mut self.submeshes {
+            let region = submesh
+                .material
+                .and_then(|index| self.materials[index].diffuse_map.as_deref())
+                .and_then(|map| Path::new(map).file_stem())
+                .and_then(|stem| layout.region(&stem.to_string_lossy()));
+            if let Some(region) = region {
+                submesh.mesh.remap_uvs(region);
+                remapped += 1;
+            }
+        }
+        remapped
+    }
+}
+
+fn parse_obj_data(file_name: &str, bytes: &[u8]) -> EngineResult<ObjData> {
+    ObjData::load_buf(bytes).map_err(|err| EngineError::ObjParse {
+        path: file_name.to_owned(),
+        message: err.to_string(),
+    })
+}
+
+/// The triangles of every face of `object`, each with the index of its
+/// group.
+fn triangulate_object(
+    data: &ObjData,
+    object: &Object,
+) -> Result<Vec<(usize, [IndexTuple; 3])>, String> {
+    let mut triangles = Vec::new();
+
+    for (group, poly) in object
+        .groups
+        .iter()
+        .enumerate()
+        .flat_map(|(index, group)| group.polys.iter().map(move |poly| (index, poly)))
+    {
+        if poly.0.len() < 3 {
+            return Err(format!(
+                "face with {} vertices in {}",
+                poly.0.len(),
+                object.name
+            ));
+        }
+        for &IndexTuple(position, uv, normal) in &poly.0 {
+            let in_range = position < data.position.len()
+                && uv.is_none_or(|uv| uv < data.texture.len())
+                && normal.is_none_or(|normal| normal < data.normal.len());
+            if !in_range {
+                return Err(format!("face index out of range in {}", object.name));
+            }
+        }
+
+        let points: Vec<Vec3> = poly
+            .0
+            .iter()
+            .map(|index_tuple| Vec3::from(data.position[index_tuple.0]))
+            .collect();
+        for [a, b, c] in triangulate(&points) {
+            triangles.push((group, [poly.0[a], poly.0[b], poly.0[c]]));
+        }
+    }
+
+    Ok(triangles)
+}
+
+/// Area weighted face normals summed per position, only computed if some
+/// faces come without normals.
+fn smooth_normals(data: &ObjData, triangles: &[(usize, [IndexTuple; 3])]) -> Vec<Vec3> {
+    let mut normals = Vec::new();
+    if triangles
+        .iter()
+        .flat_map(|(_, triangle)| triangle)
+        .any(|corner| corner.2.is_none())
+    {
+        normals = vec![Vec3::ZERO; data.position.len()];
+        for (_, triangle) in triangles {
+            let [a, b, c] = triangle.map(|corner| Vec3::from(data.position[corner.0]));
+            let normal = (b - a).cross(c - a);
+            for corner in triangle {
+                normals[corner.0] += normal;
+            }
+        }
+    }
+
+    normals
+}
+
+/// A welded mesh of `triangles`, with tangents since OBJ has none.
+fn obj_mesh<'a>(
+    data: &ObjData,
+    triangles: impl Iterator<Item = &'a [IndexTuple; 3]>,
+    smooth_normals: &[Vec3],
+) -> CpuMesh {
+    let vertices: Vec<Vertex> = triangles
+        .flatten()
+        .map(|&IndexTuple(position, uv, normal)| Vertex {
+            position: Vec3::from(data.position[position]),
+            normal: match normal {
+                Some(normal) => Vec3::from(data.normal[normal]),
+                None => smooth_normals[position].normalize_or_zero(),
+            },
+            uv: uv.map_or(Vec2::ZERO, |uv| Vec2::from(data.texture[uv])),
+            tangent: Vec4::ZERO,
+        })
+        .collect();
+
+    let mut mesh = CpuMesh {
+        indices: (0..vertices.len() as u32).collect(),
+        vertices,
+        ..Default::default()
+    };
+    mesh.generate_tangents();
+    mesh
+}
+
+/// The bits of `value` with -0.0 turned into 0.0, so both compare equal.
+fn float_bits(value: f32) -> u32 {
+    (value + 0.0).to_bits()
+}
+
+/// What MikkTSpace tells vertices apart by.
+fn vertex_key(vertex: &Vertex) -> [u32; 8] {
+    let Vertex {
+        position,
+        normal,
+        uv,
+        ..
+    } = vertex;
+    [
+        position.x, position.y, position.z, normal.x, normal.y, normal.z, uv.x, uv.y,
+    ]
+    .map(float_bits)
+}
+
+/// Front faces wind clockwise, seen from the side this points to.
+fn face_normal([a, b, c]: &[Vertex; 3]) -> Vec3 {
+    (b.position - a.position)
+        .cross(c.position - a.position)
+        .normalize_or_zero()
+}
+
+/// Zero if either vector has no length.
+fn angle_between(a: Vec3, b: Vec3) -> f32 {
+    let (a, b) = (a.normalize_or_zero(), b.normalize_or_zero());
+    if a == Vec3::ZERO || b == Vec3::ZERO {
+        return 0.0;
+    }
+    a.dot(b).clamp(-1.0, 1.0).acos()
+}
+
+/// Splits a polygon into triangles of indices into `points`, keeping its
+/// winding. Ears are clipped in the plane the polygon faces most, which
+/// handles convex and concave polygons. What's left when no ear can be
+/// found, e.g. of a self-intersecting polygon, is fanned.
+fn triangulate(points: &[Vec3]) -> Vec<[usize; 3]> {
+    if points.len() == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    // Newell's method gives a normal that is robust for concave and
+    // slightly non-planar polygons.
+    let mut normal = Vec3::ZERO;
+    for (i, a) in points.iter().enumerate() {
+        let b = points[(i + 1) % points.len()];
+        normal += Vec3::new(
+            (a.y - b.y) * (a.z + b.z),
+            (a.z - b.z) * (a.x + b.x),
+            (a.x - b.x) * (a.y + b.y),
+        );
+    }
+    let normal = normal.abs();
+    let projected: Vec<Vec2> = points
+        .iter()
+        .map(|point| {
+            if normal.x >= normal.y && normal.x >= normal.z {
+                Vec2::new(point.y, point.z)
+            } else if normal.y >= normal.z {
+                Vec2::new(point.z, point.x)
+            } else {
+                Vec2::new(point.x, point.y)
+            }
+        })
+        .collect();
+
+    let area: f32 = (0..projected.len())
+        .map(|i| projected[i].perp_dot(projected[(i + 1) % projected.len()]))
+        .sum();
+    let winding = if area < 0.0 { -1.0 } else { 1.0 };
+    let turn = |a: usize, b: usize, c: usize| {
+        (projected[b] - projected[a]).perp_dot(projected[c] - projected[a]) * winding
+    };
+
+    let mut remaining: Vec<usize> = (0..points.len()).collect();
+    let mut triangles = Vec::with_capacity(points.len() - 2);
+    while remaining.len() > 3 {
+        let count = remaining.len();
+        let ear = (0..count).find(|&i| {
+            let (a, b, c) = (
+                remaining[(i + count - 1) % count],
+                remaining[i],
+                remaining[(i + 1) % count],
+            );
+            turn(a, b, c) > 0.0
+                && !remaining.iter().any(|&p| {
+                    let corner = [a, b, c]
+                        .iter()
+                        .any(|&corner| projected[corner] == projected[p]);
+                    !corner && turn(a, b, p) >= 0.0 && turn(b, c, p) >= 0.0 && turn(c, a, p) >= 0.0
+                })
+        });
+
+        match ear {
+            Some(i) => {
+                triangles.push([
+                    remaining[(i + count - 1) % count],
+                    remaining[i],
+                    remaining[(i + 1) % count],
+                ]);
+                remaining.remove(i);
+            }
+            None => break,
+        }
+    }
+
+    for i in 1..remaining.len() - 1 {
+        triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+    }
+
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::render_backend::material::parse_mtl;
+
+    fn quad_mesh() -> CpuMesh {
+        let corners = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        CpuMesh {
+            vertices: corners
+                .iter()
+                .map(|&[x, y]| Vertex {
+                    position: Vec3::new(x, y, 0.0),
+                    normal: -Vec3::Z,
+                    uv: Vec2::new(x, 1.0 - y),
+                    ..Vertex::default()
+                })
+                .collect(),
+            indices: vec![0, 2, 1, 0, 3, 2],
+            extra_uvs: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn remaps_submeshes_by_diffuse_map() {
+        let layout = AtlasLayout::parse("atlas 64 64 1\ntile 0 32 0 32 16 grass\n").unwrap();
+        let material = |name: &str, map: Option<&str>| MaterialDesc {
+            diffuse_map: map.map(str::to_owned),
+            ..MaterialDesc::new(name)
+        };
+        let submesh = |material| Submesh {
+            object: "object".to_owned(),
+            material,
+            mesh: quad_mesh(),
+        };
+        let mut model = ObjModel {
+            submeshes: vec![
+                submesh(Some(0)),
+                submesh(Some(1)),
+                submesh(Some(2)),
+                submesh(None),
+            ],
+            materials: vec![
+                material("grass", Some("textures/grass.png")),
+                material("stone", Some("textures/stone.png")),
+                material("plain", None),
+            ],
+        };
+
+        assert_eq!(model.remap_to_atlas(&layout), 1);
+        let uvs: Vec<Vec2> = model.submeshes[0]
+            .mesh
+            .vertices
+            .iter()
+            .map(|v| v.uv)
+            .collect();
+        assert_eq!(uvs[0], Vec2::new(0.5, 0.25));
+        assert_eq!(uvs[1], Vec2::new(1.0, 0.25));
+        assert_eq!(uvs[3], Vec2::new(0.5, 0.0));
+        for submesh in &model.submeshes[1..] {
+            assert_eq!(submesh.mesh.vertices, quad_mesh().vertices);
+        }
+    }
+
+    fn parse(text: &str) -> Vec<CpuMesh> {
+        CpuMesh::parse_obj("test.obj", text.as_bytes()).unwrap()
+    }
+
+    fn signed_area(points: &[Vec3]) -> f32 {
+        (0..points.len())
+            .map(|i| {
+                let (a, b) = (points[i], points[(i + 1) % points.len()]);
+                a.x * b.y - b.x * a.y
+            })
+            .sum::<f32>()
+            / 2.0
+    }
+
+    #[test]
+    fn triangulates_concave_polygons() {
+        // An arrow pointing right, concave at its tail, counterclockwise.
+        let arrow = [
+            [0.0, 1.0],
+            [2.0, 1.0],
+            [2.0, 0.0],
+            [4.0, 2.0],
+            [2.0, 4.0],
+            [2.0, 3.0],
+            [0.0, 3.0],
+            [1.0, 2.0],
+        ]
+        .map(|[x, y]| Vec3::new(x, y, 0.0));
+
+        // Reversing the outline must reverse the triangles too.
+        for points in [arrow.to_vec(), arrow.iter().rev().copied().collect()] {
+            let triangles = triangulate(&points);
+            assert_eq!(triangles.len(), points.len() - 2);
+
+            let polygon_area = signed_area(&points);
+            let mut total = 0.0;
+            for triangle in &triangles {
+                let corners = triangle.map(|i| points[i]);
+                let area = signed_area(&corners);
+                assert_eq!(area.signum(), polygon_area.signum(), "{:?}", triangle);
+                total += area;
+
+                // Nothing covers the notch between the tail's prongs.
+                let centroid = (corners[0] + corners[1] + corners[2]) / 3.0;
+                assert!(
+                    !(centroid.x < 1.0 && (centroid.y - 2.0).abs() < 0.5),
+                    "{:?}",
+                    triangle
+                );
+            }
+            assert!(
+                (total - polygon_area).abs() < 1e-5,
+                "{} != {}",
+                total,
+                polygon_area
+            );
+        }
+
+        let meshes = parse(
+            "v 0 1 0\nv 2 1 0\nv 2 0 0\nv 4 2 0\nv 2 4 0\nv 2 3 0\nv 0 3 0\nv 1 2 0\n\
+             f 1 2 3 4 5 6 7 8\n",
+        );
+        assert_eq!(meshes[0].indices.len(), 6 * 3);
+        assert_eq!(meshes[0].vertices.len(), 8);
+    }
+
+    #[test]
+    fn fills_in_missing_uvs_and_normals() {
+        let positions = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0.25 0.75\nvn 0 0 -1\n";
+        for (face, uv, normal) in [
+            ("f 1 2 3", Vec2::ZERO, Vec3::Z),
+            ("f 1/1 2/1 3/1", Vec2::new(0.25, 0.75), Vec3::Z),
+            ("f 1//1 2//1 3//1", Vec2::ZERO, -Vec3::Z),
+        ] {
+            let meshes = parse(&format!("{}{}\n", positions, face));
+            assert_eq!(meshes.len(), 1);
+            assert_eq!(meshes[0].vertices.len(), 3, "{}", face);
+            for vertex in &meshes[0].vertices {
+                assert_eq!(vertex.uv, uv, "{}", face);
+                assert_eq!(vertex.normal, normal, "{}", face);
+            }
+        }
+
+        // Missing normals are averaged over the faces sharing a position.
+        let meshes = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nf 1 2 3\nf 1 3 4\n");
+        let corner = meshes[0]
+            .vertices
+            .iter()
+            .find(|vertex| vertex.position == Vec3::ZERO)
+            .unwrap();
+        assert!(corner
+            .normal
+            .abs_diff_eq(Vec3::new(1.0, 0.0, 1.0).normalize(), 1e-6));
+    }
+
+    #[test]
+    fn welds_shared_vertices() {
+        // A quad as two triangles repeating their shared corners.
+        let meshes = parse(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 1\nvn 0 0 1\n\
+             f 1/1/1 2/1/1 3/1/1\nf 1/1/1 3/1/1 4/1/1\n",
+        );
+        assert_eq!(meshes[0].vertices.len(), 4);
+        assert_eq!(meshes[0].indices, [0, 1, 2, 0, 2, 3]);
+
+        // A cube with a normal per face shares positions but not vertices.
+        let mut cube = String::new();
+        for [x, y, z] in [
+            [0, 0, 0],
+            [1, 0, 0],
+            [1, 1, 0],
+            [0, 1, 0],
+            [0, 0, 1],
+            [1, 0, 1],
+            [1, 1, 1],
+            [0, 1, 1],
+        ] {
+            cube += &format!("v {} {} {}\n", x, y, z);
+        }
+        cube += "vn 0 0 -1\nvn 0 0 1\nvn 0 -1 0\nvn 0 1 0\nvn -1 0 0\nvn 1 0 0\n";
+        for (face, normal) in [
+            ([1, 2, 3, 4], 1),
+            ([5, 8, 7, 6], 2),
+            ([1, 5, 6, 2], 3),
+            ([4, 3, 7, 8], 4),
+            ([1, 4, 8, 5], 5),
+            ([2, 6, 7, 3], 6),
+        ] {
+            let corners: Vec<String> = face
+                .iter()
+                .map(|position| format!("{}//{}", position, normal))
+                .collect();
+            cube += &format!("f {}\n", corners.join(" "));
+        }
+        let meshes = parse(&cube);
+        assert_eq!(meshes[0].vertices.len(), 24);
+        assert_eq!(meshes[0].indices.len(), 36);
+
+        // Copies of the first corner: one at -0.0 welds with it, one with
+        // another second UV doesn't.
+        let mut mesh = quad_mesh();
+        let first = mesh.vertices[0];
+        mesh.vertices.push(Vertex {
+            position: Vec3::new(-0.0, 0.0, 0.0),
+            ..first
+        });
+        mesh.vertices.push(first);
+        mesh.indices.extend([4, 2, 1, 5, 2, 1]);
+        mesh.extra_uvs = vec![vec![Vec2::ZERO; 6]];
+        mesh.extra_uvs[0][5] = Vec2::ONE;
+        mesh.weld();
+        assert_eq!(mesh.vertices.len(), 5);
+        assert_eq!(mesh.indices[6..], [0, 1, 2, 4, 1, 2]);
+        assert_eq!(mesh.extra_uvs[0][4], Vec2::ONE);
+    }
+
+    #[test]
+    fn skips_empty_objects_and_rejects_bad_indices() {
+        let meshes = parse("o empty\nv 0 0 0\nv 1 0 0\nv 0 1 0\no triangle\nf 1 2 3\n");
+        assert_eq!(meshes.len(), 1);
+
+        assert!(CpuMesh::parse_obj("test.obj", b"v 0 0 0\nv 1 0 0\nf 1 2 3\n").is_err());
+        assert!(CpuMesh::parse_obj("test.obj", b"v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/4 2 3\n").is_err());
+    }
+
+    /// A unit cube around the origin with 8 shared vertices and no normals,
+    /// front faces clockwise seen from outside.
+    fn cube() -> CpuMesh {
+        let mut vertices = Vec::new();
+        for normal in [Vec3::X, -Vec3::X, Vec3::Y, -Vec3::Y, Vec3::Z, -Vec3::Z] {
+            let u = normal.any_orthonormal_vector();
+            let v = u.cross(normal);
+            let corner = (normal - u - v) * 0.5;
+            for position in [
+                corner,
+                corner + v,
+                corner + u + v,
+                corner,
+                corner + u + v,
+                corner + u,
+            ] {
+                vertices.push(Vertex {
+                    position,
+                    ..Vertex::default()
+                });
+            }
+        }
+        let mut mesh = CpuMesh {
+            indices: (0..vertices.len() as u32).collect(),
+            vertices,
+            extra_uvs: Vec::new(),
+        };
+        mesh.weld();
+        mesh
+    }
+
+    fn is_axis(normal: Vec3) -> bool {
+        [Vec3::X, Vec3::Y, Vec3::Z]
+            .iter()
+            .any(|axis| normal.abs().abs_diff_eq(*axis, 1e-6))
+    }
+
+    #[test]
+    fn smooth_normals_keep_hard_edges() {
+        let mut mesh = cube();
+        assert_eq!(mesh.vertices.len(), 8);
+
+        // The cube's 90 degree edges stay hard at 45 degrees.
+        mesh.generate_smooth_normals(45f32.to_radians());
+        assert_eq!(mesh.vertices.len(), 24);
+        assert_eq!(mesh.indices.len(), 36);
+        for vertex in &mesh.vertices {
+            assert!(is_axis(vertex.normal), "{:?}", vertex.normal);
+            // Outwards, the side the clockwise faces face.
+            assert!(vertex.normal.dot(vertex.position) > 0.0);
+        }
+
+        // Above 90 degrees every corner averages its three faces.
+        let mut mesh = cube();
+        mesh.generate_smooth_normals(100f32.to_radians());
+        assert_eq!(mesh.vertices.len(), 8);
+        for vertex in &mesh.vertices {
+            assert!(vertex.normal.abs_diff_eq(vertex.position.normalize(), 1e-6));
+        }
+    }
+
+    #[test]
+    fn flat_normals_split_faces() {
+        let mut mesh = cube();
+        mesh.generate_flat_normals();
+        assert_eq!(mesh.vertices.len(), 24);
+        for triangle in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[triangle[i] as usize]);
+            assert!(is_axis(a.normal) && a.normal.dot(a.position) > 0.0);
+            assert!(a.normal == b.normal && b.normal == c.normal);
+        }
+
+        // Coplanar triangles keep sharing vertices.
+        let mut quad = quad_mesh();
+        quad.generate_flat_normals();
+        assert_eq!(quad.vertices.len(), 4);
+        assert!(quad.vertices.iter().all(|vertex| vertex.normal == -Vec3::Z));
+    }
+
+    #[test]
+    fn tangent_handedness_follows_mirrored_uvs() {
+        // Two quads side by side facing -Z, the right one with its U
+        // mirrored like a symmetric character's second half.
+        let mut vertices = Vec::new();
+        for x in 0..3 {
+            for y in 0..2 {
+                vertices.push(Vertex {
+                    position: Vec3::new(x as f32, y as f32, 0.0),
+                    normal: -Vec3::Z,
+                    uv: Vec2::new(1.0 - (x as f32 - 1.0).abs(), y as f32),
+                    ..Vertex::default()
+                });
+            }
+        }
+        let mut mesh = CpuMesh {
+            vertices,
+            indices: vec![0, 1, 3, 0, 3, 2, 2, 3, 5, 2, 5, 4],
+            extra_uvs: Vec::new(),
+        };
+        mesh.generate_tangents();
+
+        // The middle column is split between the two handednesses.
+        assert_eq!(mesh.vertices.len(), 8);
+        for vertex in &mesh.vertices {
+            let left =
+                vertex.position.x < 1.0 || (vertex.position.x == 1.0 && vertex.tangent.x > 0.0);
+            let tangent = vertex.tangent.truncate();
+            assert!(tangent.abs_diff_eq(if left { Vec3::X } else { -Vec3::X }, 1e-6));
+
+            // Either way the bitangent follows V up the quad.
+            let bitangent = vertex.normal.cross(tangent) * vertex.tangent.w;
+            assert!(bitangent.abs_diff_eq(Vec3::Y, 1e-6), "{:?}", vertex);
+        }
+        let handedness: Vec<f32> = mesh
+            .vertices
+            .iter()
+            .map(|vertex| vertex.tangent.w)
+            .collect();
+        assert!(handedness.contains(&1.0) && handedness.contains(&-1.0));
+
+        // OBJ meshes come with tangents.
+        let meshes = parse("v 0 0 0\nv 0 1 0\nv 1 0 0\nvt 0 0\nvt 0 1\nvt 1 0\nf 1/1 2/2 3/3\n");
+        for vertex in &meshes[0].vertices {
+            assert!(vertex.tangent.truncate().abs_diff_eq(Vec3::X, 1e-6));
+            assert_eq!(vertex.tangent.w.abs(), 1.0);
+        }
+    }
+
+    #[test]
+    fn splits_objects_by_material() {
+        let text = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\n\
+                    o a\nusemtl red\nf 1 2 3\nusemtl blue\nf 2 4 3\nusemtl red\nf 1 3 4\n\
+                    o b\nf 1 2 3\nusemtl missing\nf 2 4 3\n";
+        let data = parse_obj_data("test.obj", text.as_bytes()).unwrap();
+        let materials = parse_mtl("", "newmtl blue\nnewmtl red\n").unwrap();
+
+        let model = ObjModel::from_data("test.obj", data, materials).unwrap();
+        let submeshes: Vec<(&str, Option<usize>, usize)> = model
+            .submeshes
+            .iter()
+            .map(|submesh| {
+                (
+                    submesh.object.as_str(),
+                    submesh.material,
+                    submesh.mesh.indices.len() / 3,
+                )
+            })
+            .collect();
+        // In the order each object first uses a material; faces without a
+        // known one share a submesh.
+        assert_eq!(
+            submeshes,
+            [("a", Some(1), 2), ("a", Some(0), 1), ("b", None, 2)]
+        );
+    }
+
+    #[test]
+    fn loads_materials_next_to_the_obj() {
+        let dir = std::env::temp_dir().join(format!("obj_model_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("models")).unwrap();
+        std::fs::write(
+            dir.join("models/house.obj"),
+            "mtllib house.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl wall\nf 1 2 3\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("models/house.mtl"),
+            "newmtl wall\nmap_Kd -bm 1 textures/brick wall.png\nmap_d ../alpha.png\n",
+        )
+        .unwrap();
+
+        let obj = dir.join("models/house.obj");
+        let model = ObjModel::load(&obj.to_string_lossy()).unwrap();
+        let root = vfs::normalize(&dir.to_string_lossy()).unwrap();
+        assert_eq!(model.submeshes.len(), 1);
+        assert_eq!(model.submeshes[0].material, Some(0));
+        let wall = &model.materials[0];
+        assert_eq!(
+            wall.diffuse_map,
+            Some(format!("{}/models/textures/brick wall.png", root))
+        );
+        assert_eq!(wall.opacity_map, Some(format!("{}/alpha.png", root)));
+
+        // A library that isn't there fails the load.
+        std::fs::remove_file(dir.join("models/house.mtl")).unwrap();
+        assert!(ObjModel::load(&obj.to_string_lossy()).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
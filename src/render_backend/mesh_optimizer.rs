@@ -0,0 +1,545 @@
+use std::fmt;
+
+use glam::Vec3;
+
+use super::mesh::CpuMesh;
+
+/// Entries of the FIFO post-transform cache the optimizer and statistics
+/// assume. GPUs have at least this many.
+pub const VERTEX_CACHE_SIZE: usize = 16;
+
+/// How much worse than the vertex cache order a cluster may get to be
+/// drawn on its own in `CpuMesh::optimize`.
+pub const OVERDRAW_THRESHOLD: f32 = 1.05;
+
+/// How well the index order uses a post-transform vertex cache of
+/// `VERTEX_CACHE_SIZE` entries.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VertexCacheStats {
+    /// Average cache misses per triangle, from 3 down to about 0.5 for
+    /// large regular meshes.
+    pub acmr: f32,
+    /// Average times each vertex is transformed, 1 at best.
+    pub atvr: f32,
+}
+
+impl fmt::Display for VertexCacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ACMR {:.3}, ATVR {:.3}", self.acmr, self.atvr)
+    }
+}
+
+/// What `CpuMesh::optimize` did.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshOptimizationReport {
+    pub triangles: usize,
+    pub vertices: usize,
+    /// 16 or 32, what `CpuMesh::upload` will use.
+    pub index_bits: u32,
+    pub before: VertexCacheStats,
+    pub after: VertexCacheStats,
+}
+
+impl fmt::Display for MeshOptimizationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} triangles, {} vertices, {} bit indices, ACMR {:.3} -> {:.3}, ATVR {:.3} -> {:.3}",
+            self.triangles,
+            self.vertices,
+            self.index_bits,
+            self.before.acmr,
+            self.after.acmr,
+            self.before.atvr,
+            self.after.atvr
+        )
+    }
+}
+
+/// Simulates a FIFO post-transform cache.
+struct VertexCache {
+    misses: usize,
+    /// Misses are only cached after the last flush.
+    flushed_at: usize,
+    /// The miss that loaded each vertex.
+    loaded_at: Vec<Option<usize>>,
+}
+
+impl VertexCache {
+    fn new(vertex_count: usize) -> VertexCache {
+        VertexCache {
+            misses: 0,
+            flushed_at: 0,
+            loaded_at: vec![None; vertex_count],
+        }
+    }
+
+    /// The cache misses of drawing `triangle`.
+    fn draw(&mut self, triangle: &[u32]) -> usize {
+        let mut misses = 0;
+        for &vertex in triangle {
+            let loaded_at = &mut self.loaded_at[vertex as usize];
+            let cached = loaded_at
+                .is_some_and(|at| at >= self.flushed_at && self.misses - at < VERTEX_CACHE_SIZE);
+            if !cached {
+                *loaded_at = Some(self.misses);
+                self.misses += 1;
+                misses += 1;
+            }
+        }
+        misses
+    }
+
+    fn flush(&mut self) {
+        self.flushed_at = self.misses;
+    }
+}
+
+impl CpuMesh {
+    /// Runs every optimization in order: triangles for the vertex cache,
+    /// then clusters of them for less overdraw, then vertices for fetching.
+    /// Triangles keep their winding. Small or already well ordered meshes
+    /// can come out of a pass worse, so a pass is undone if it raises the
+    /// ACMR above the input's, or for overdraw above `OVERDRAW_THRESHOLD`
+    /// times what the vertex cache order reached.
+    pub fn optimize(&mut self) -> MeshOptimizationReport {
+        let before = self.vertex_cache_stats();
+
+        let input = self.indices.clone();
+        self.optimize_vertex_cache();
+        if self.vertex_cache_stats().acmr > before.acmr {
+            self.indices = input;
+        }
+
+        let cache_order = self.indices.clone();
+        let limit = (self.vertex_cache_stats().acmr * OVERDRAW_THRESHOLD).min(before.acmr);
+        self.optimize_overdraw(OVERDRAW_THRESHOLD);
+        if self.vertex_cache_stats().acmr > limit {
+            self.indices = cache_order;
+        }
+
+        self.optimize_vertex_fetch();
+
+        MeshOptimizationReport {
+            triangles: self.indices.len() / 3,
+            vertices: self.vertices.len(),
+            index_bits: if self.fits_16_bit_indices() { 16 } else { 32 },
+            before,
+            after: self.vertex_cache_stats(),
+        }
+    }
+
+    pub fn vertex_cache_stats(&self) -> VertexCacheStats {
+        let mut cache = VertexCache::new(self.vertices.len());
+        let misses: usize = self
+            .indices
+            .chunks_exact(3)
+            .map(|triangle| cache.draw(triangle))
+            .sum();
+
+        let mut used = vec![false; self.vertices.len()];
+        for &index in &self.indices {
+            used[index as usize] = true;
+        }
+        let used = used.iter().filter(|&&used| used).count();
+
+        VertexCacheStats {
+            acmr: misses as f32 / (self.indices.len() / 3).max(1) as f32,
+            atvr: misses as f32 / used.max(1) as f32,
+        }
+    }
+
+    /// Reorders triangles to reuse transformed vertices with Tipsify
+    /// (Sander et al. 2007): triangles are emitted in fans around a vertex,
+    /// moving on to a neighbour that is still cached.
+    pub fn optimize_vertex_cache(&mut self) {
+        let triangle_count = self.indices.len() / 3;
+        let vertex_count = self.vertices.len();
+
+        // The triangles using each vertex, `first_triangle[v]..first_triangle[v + 1]`.
+        let mut live = vec![0usize; vertex_count];
+        for &index in &self.indices[..triangle_count * 3] {
+            live[index as usize] += 1;
+        }
+        let mut first_triangle = vec![0; vertex_count + 1];
+        for vertex in 0..vertex_count {
+            first_triangle[vertex + 1] = first_triangle[vertex] + live[vertex];
+        }
+        let mut triangles_of = vec![0; triangle_count * 3];
+        let mut filled = first_triangle.clone();
+        for (corner, &index) in self.indices[..triangle_count * 3].iter().enumerate() {
+            triangles_of[filled[index as usize]] = corner / 3;
+            filled[index as usize] += 1;
+        }
+
+        let mut emitted = vec![false; triangle_count];
+        // When each vertex was last loaded, starting out of the cache.
+        let mut loaded_at = vec![0; vertex_count];
+        let mut time = VERTEX_CACHE_SIZE + 1;
+        // Recently used vertices to continue from when a fan ends nowhere.
+        let mut dead_ends = Vec::new();
+        let mut next_unused = 0;
+        let mut next_live = |live: &[usize]| {
+            while next_unused < vertex_count {
+                if live[next_unused] > 0 {
+                    return Some(next_unused);
+                }
+                next_unused += 1;
+            }
+            None
+        };
+
+        let mut indices = Vec::with_capacity(self.indices.len());
+        let mut fan = next_live(&live);
+        while let Some(center) = fan {
+            let mut candidates = Vec::new();
+            for &triangle in &triangles_of[first_triangle[center]..first_triangle[center + 1]] {
+                if emitted[triangle] {
+                    continue;
+                }
+                emitted[triangle] = true;
+
+                for &index in &self.indices[triangle * 3..triangle * 3 + 3] {
+                    let vertex = index as usize;
+                    indices.push(index);
+                    dead_ends.push(vertex);
+                    candidates.push(vertex);
+                    live[vertex] -= 1;
+                    if time - loaded_at[vertex] > VERTEX_CACHE_SIZE {
+                        loaded_at[vertex] = time;
+                        time += 1;
+                    }
+                }
+            }
+
+            // The candidate cached longest that stays cached through its
+            // own fan, or any with triangles left.
+            let mut best: Option<(usize, usize)> = None;
+            for &vertex in &candidates {
+                if live[vertex] == 0 {
+                    continue;
+                }
+                let age = time - loaded_at[vertex];
+                let priority = if age + 2 * live[vertex] <= VERTEX_CACHE_SIZE {
+                    age
+                } else {
+                    0
+                };
+                if best.is_none_or(|(_, best)| priority > best) {
+                    best = Some((vertex, priority));
+                }
+            }
+
+            fan = match best {
+                Some((vertex, _)) => Some(vertex),
+                None => loop {
+                    match dead_ends.pop() {
+                        Some(vertex) if live[vertex] > 0 => break Some(vertex),
+                        Some(_) => {}
+                        None => break next_live(&live),
+                    }
+                },
+            };
+        }
+
+        // A trailing partial triangle isn't drawn, but is kept.
+        indices.extend_from_slice(&self.indices[triangle_count * 3..]);
+        self.indices = indices;
+    }
+
+    /// Splits the triangles into clusters and draws the ones facing away
+    /// from the mesh's center first, so they hide what is behind them
+    /// whatever the view. Runs after `optimize_vertex_cache`: clusters start
+    /// where the cache starts over anyway, or where the cluster so far
+    /// misses at most `threshold` times as often as the whole.
+    pub fn optimize_overdraw(&mut self, threshold: f32) {
+        let triangle_count = self.indices.len() / 3;
+        let triangle = |index: usize| &self.indices[index * 3..index * 3 + 3];
+
+        // Triangles missing on every vertex start over anyway.
+        let mut cache = VertexCache::new(self.vertices.len());
+        let mut hard_starts: Vec<usize> = (0..triangle_count)
+            .filter(|&index| cache.draw(triangle(index)) == 3 || index == 0)
+            .collect();
+        hard_starts.push(triangle_count);
+
+        let mut starts = Vec::new();
+        for range in hard_starts.windows(2) {
+            let (start, end) = (range[0], range[1]);
+            cache.flush();
+            let misses: usize = (start..end).map(|index| cache.draw(triangle(index))).sum();
+            let acmr = misses as f32 / (end - start) as f32;
+
+            cache.flush();
+            starts.push(start);
+            let (mut misses, mut triangles) = (0, 0);
+            for index in start..end {
+                misses += cache.draw(triangle(index));
+                triangles += 1;
+                if index + 1 < end && misses as f32 / triangles as f32 <= acmr * threshold {
+                    cache.flush();
+                    starts.push(index + 1);
+                    (misses, triangles) = (0, 0);
+                }
+            }
+        }
+        starts.push(triangle_count);
+
+        // Area weighted centers and normals.
+        let position = |index: u32| self.vertices[index as usize].position;
+        let clusters: Vec<(usize, usize, Vec3, Vec3, f32)> = starts
+            .windows(2)
+            .map(|range| {
+                let (mut center, mut normal, mut area) = (Vec3::ZERO, Vec3::ZERO, 0.0);
+                for index in range[0]..range[1] {
+                    let [a, b, c] = [0, 1, 2].map(|corner| position(triangle(index)[corner]));
+                    let face = (b - a).cross(c - a);
+                    center += (a + b + c) / 3.0 * face.length();
+                    normal += face;
+                    area += face.length();
+                }
+                (range[0], range[1], center, normal, area)
+            })
+            .collect();
+        let total_area: f32 = clusters.iter().map(|cluster| cluster.4).sum();
+        if total_area == 0.0 {
+            return;
+        }
+        let mesh_center = clusters
+            .iter()
+            .fold(Vec3::ZERO, |sum, cluster| sum + cluster.2)
+            / total_area;
+
+        let mut order: Vec<(f32, usize, usize)> = clusters
+            .iter()
+            .map(|&(start, end, center, normal, area)| {
+                let center = if area > 0.0 {
+                    center / area
+                } else {
+                    mesh_center
+                };
+                let facing = (center - mesh_center).dot(normal.normalize_or_zero());
+                (facing, start, end)
+            })
+            .collect();
+        order.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut indices: Vec<u32> = order
+            .iter()
+            .flat_map(|&(_, start, end)| &self.indices[start * 3..end * 3])
+            .copied()
+            .collect();
+        indices.extend_from_slice(&self.indices[triangle_count * 3..]);
+        self.indices = indices;
+    }
+
+    /// Renumbers vertices in the order the indices first use them, so
+    /// vertex fetches move through memory in order. Unused vertices are
+    /// dropped.
+    pub fn optimize_vertex_fetch(&mut self) {
+        let mut new_index = vec![None; self.vertices.len()];
+        let mut order = Vec::with_capacity(self.vertices.len());
+        for index in &mut self.indices {
+            let old = *index as usize;
+            *index = *new_index[old].get_or_insert_with(|| {
+                order.push(old);
+                order.len() as u32 - 1
+            });
+        }
+
+        self.vertices = order.iter().map(|&i| self.vertices[i]).collect();
+        for uvs in &mut self.extra_uvs {
+            *uvs = order.iter().map(|&i| uvs[i]).collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Vec2, Vec4};
+
+    use super::*;
+    use crate::render_backend::mesh::Vertex;
+
+    /// A `size` x `size` grid of quads in the XY plane, rows in order.
+    fn grid(size: u32) -> CpuMesh {
+        let vertices = (0..(size + 1) * (size + 1))
+            .map(|i| Vertex {
+                position: Vec3::new((i % (size + 1)) as f32, (i / (size + 1)) as f32, 0.0),
+                normal: -Vec3::Z,
+                ..Vertex::default()
+            })
+            .collect();
+        let mut indices = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                let corner = y * (size + 1) + x;
+                let (right, up) = (corner + 1, corner + size + 1);
+                indices.extend([corner, up, up + 1, corner, up + 1, right]);
+            }
+        }
+
+        CpuMesh {
+            vertices,
+            indices,
+            extra_uvs: Vec::new(),
+        }
+    }
+
+    /// Shuffles the triangles and rotates their corners, keeping winding.
+    fn scramble(mesh: &mut CpuMesh) {
+        let mut triangles: Vec<[u32; 3]> = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect();
+        let mut state = 0x9e37_79b9_u32;
+        for i in (1..triangles.len()).rev() {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            triangles.swap(i, state as usize % (i + 1));
+            triangles[i].rotate_left(state as usize % 3);
+        }
+        mesh.indices = triangles.into_iter().flatten().collect();
+    }
+
+    /// Every triangle by its corner positions, rotated to start at the
+    /// smallest, so the same triangle with the same winding compares equal.
+    fn triangle_set(mesh: &CpuMesh) -> Vec<[[u32; 3]; 3]> {
+        let mut triangles: Vec<[[u32; 3]; 3]> = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                let mut corners = [0, 1, 2].map(|corner| {
+                    let position = mesh.vertices[triangle[corner] as usize].position;
+                    position.to_array().map(f32::to_bits)
+                });
+                let first = (0..3).min_by_key(|&corner| corners[corner]).unwrap();
+                corners.rotate_left(first);
+                corners
+            })
+            .collect();
+        triangles.sort();
+        triangles
+    }
+
+    #[test]
+    fn optimize_keeps_triangles_and_winding() {
+        let mut mesh = grid(20);
+        scramble(&mut mesh);
+        let before = triangle_set(&mesh);
+
+        let report = mesh.optimize();
+        assert_eq!(triangle_set(&mesh), before);
+        assert_eq!(report.triangles, 800);
+        assert_eq!(report.vertices, 441);
+
+        // Each pass on its own keeps them too.
+        let mut mesh = grid(20);
+        scramble(&mut mesh);
+        mesh.optimize_vertex_cache();
+        assert_eq!(triangle_set(&mesh), before);
+        mesh.optimize_overdraw(OVERDRAW_THRESHOLD);
+        assert_eq!(triangle_set(&mesh), before);
+    }
+
+    #[test]
+    fn optimize_never_worsens_acmr_on_grids() {
+        for size in [1, 2, 3, 4, 5, 8, 16, 64] {
+            let mut mesh = grid(size);
+            let report = mesh.optimize();
+            assert!(
+                report.after.acmr <= report.before.acmr + 1e-6,
+                "{}: {}",
+                size,
+                report
+            );
+            assert_eq!(report.after, mesh.vertex_cache_stats());
+        }
+
+        // Scrambled, the order is rebuilt close to what a grid allows.
+        let mut mesh = grid(64);
+        scramble(&mut mesh);
+        let report = mesh.optimize();
+        assert!(report.before.acmr > 2.0, "{}", report);
+        assert!(report.after.acmr < 0.9, "{}", report);
+        assert!(report.after.atvr < 1.8, "{}", report);
+    }
+
+    #[test]
+    fn vertex_cache_stats_count_fifo_misses() {
+        let mut mesh = grid(1);
+        assert_eq!(
+            mesh.vertex_cache_stats(),
+            VertexCacheStats {
+                acmr: 2.0,
+                atvr: 1.0
+            }
+        );
+
+        mesh.indices.clear();
+        assert_eq!(mesh.vertex_cache_stats().acmr, 0.0);
+    }
+
+    #[test]
+    fn vertex_fetch_drops_unused_vertices_and_keeps_extra_uvs() {
+        let mut mesh = grid(3);
+        // Drop the middle quad, whose corners the others still use, and the
+        // two right quads of the top row, leaving two corners unused.
+        mesh.indices.drain(6 * 7..6 * 9);
+        mesh.indices.drain(6 * 4..6 * 5);
+        let uv_of = |vertex: &Vertex| vertex.position.truncate() * 0.5;
+        mesh.extra_uvs = vec![
+            mesh.vertices.iter().map(uv_of).collect(),
+            mesh.vertices
+                .iter()
+                .map(|vertex| uv_of(vertex) + Vec2::ONE)
+                .collect(),
+        ];
+        mesh.vertices[5].tangent = Vec4::ONE;
+        let before = triangle_set(&mesh);
+
+        mesh.optimize_vertex_fetch();
+        assert_eq!(mesh.vertices.len(), 14);
+        assert_eq!(triangle_set(&mesh), before);
+
+        // Numbered in order of first use.
+        let mut next = 0;
+        for &index in &mesh.indices {
+            assert!(index <= next);
+            next = next.max(index + 1);
+        }
+        for (i, vertex) in mesh.vertices.iter().enumerate() {
+            assert_eq!(mesh.extra_uvs[0][i], uv_of(vertex));
+            assert_eq!(mesh.extra_uvs[1][i], uv_of(vertex) + Vec2::ONE);
+        }
+        assert_eq!(
+            mesh.vertices
+                .iter()
+                .filter(|vertex| vertex.tangent == Vec4::ONE)
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn picks_16_bit_indices_up_to_65536_vertices() {
+        for (vertex_count, bits) in [(65535, 16), (65536, 16), (65537, 32)] {
+            let mut indices: Vec<u32> = (0..vertex_count).collect();
+            while !indices.len().is_multiple_of(3) {
+                indices.push(0);
+            }
+            let mut mesh = CpuMesh {
+                vertices: vec![Vertex::default(); vertex_count as usize],
+                indices,
+                extra_uvs: Vec::new(),
+            };
+
+            assert_eq!(mesh.fits_16_bit_indices(), bits == 16, "{}", vertex_count);
+            let report = mesh.optimize();
+            assert_eq!(report.vertices, vertex_count as usize);
+            assert_eq!(report.index_bits, bits, "{}", vertex_count);
+        }
+    }
+}
@@ -0,0 +1,594 @@
+use std::f32::consts::PI;
+
+use windows::Win32::Graphics::Dxgi::Common::*;
+
+use super::cpu_image::{CpuImage, PixelType};
+use super::texture_data::{TextureData, TextureDimension};
+
+/// The filter each mip is resampled from the previous one with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MipFilter {
+    /// Averages the texels a mip texel covers. Blurry, but never rings.
+    Box,
+    /// Kaiser windowed sinc. Sharp with little ringing, a good default.
+    Kaiser,
+    /// Lanczos 3. Sharpest, but rings the most around hard edges.
+    Lanczos,
+}
+
+impl MipFilter {
+    /// How far the filter reaches, in texels of the smaller image.
+    fn support(&self) -> f32 {
+        match self {
+            MipFilter::Box => 0.5,
+            MipFilter::Kaiser | MipFilter::Lanczos => 3.0,
+        }
+    }
+
+    fn weight(&self, x: f32) -> f32 {
+        let x = x.abs();
+        if x > self.support() {
+            return 0.0;
+        }
+
+        match self {
+            MipFilter::Box if x == 0.5 => 0.5,
+            MipFilter::Box => 1.0,
+            MipFilter::Kaiser => {
+                const ALPHA: f32 = 4.0;
+                let ratio = x / self.support();
+                sinc(x) * bessel_i0(ALPHA * (1.0 - ratio * ratio).sqrt()) / bessel_i0(ALPHA)
+            }
+            MipFilter::Lanczos => sinc(x) * sinc(x / self.support()),
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Modified Bessel function of the first kind, for the Kaiser window.
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half_x = x / 2.0;
+    for k in 1..32 {
+        term *= half_x / k as f32;
+        sum += term * term;
+        if term * term < sum * 1e-8 {
+            break;
+        }
+    }
+
+    sum
+}
+
+/// Builds full mip chains on the CPU, so textures don't need
+/// `D3D11_BIND_RENDER_TARGET` just for `GenerateMips`.
+///
+/// Every mip is filtered from the previous one, in linear space for sRGB
+/// images. Texture sizes don't have to be powers of two, odd sizes round
+/// down like D3D's mip sizes do. Edges are clamped.
+#[derive(Clone, Copy, Debug)]
+pub struct MipGenerator {
+    filter: MipFilter,
+    srgb: bool,
+    alpha_cutoff: Option<f32>,
+    normal_map: bool,
+}
+
+impl MipGenerator {
+    pub fn new() -> MipGenerator {
+        MipGenerator {
+            filter: MipFilter::Kaiser,
+            srgb: false,
+            alpha_cutoff: None,
+            normal_map: false,
+        }
+    }
+
+    pub fn filter(mut self, filter: MipFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// The color channels of 8-bit images are sRGB encoded. They are decoded
+    /// before filtering and encoded again after. Alpha and float images are
+    /// always linear.
+    pub fn srgb(mut self, srgb: bool) -> Self {
+        self.srgb = srgb;
+        self
+    }
+
+    /// For alpha tested textures: scales the alpha of every mip so the same
+    /// fraction of texels passes `alpha > cutoff` as in the top mip. Without
+    /// it cutouts like foliage thin out and vanish in the distance.
+    pub fn alpha_coverage(mut self, cutoff: f32) -> Self {
+        self.alpha_cutoff = Some(cutoff);
+        self
+    }
+
+    /// RGB holds a normal encoded as `n * 0.5 + 0.5`. Filtered normals are
+    /// renormalized, and `srgb` is ignored.
+    pub fn normal_map(mut self, normal_map: bool) -> Self {
+        self.normal_map = normal_map;
+        self
+    }
+
+    /// Every mip down to 1x1, starting with `image` itself. Mips have the
+    /// same pixel type as `image`.
+    pub fn generate(&self, image: &CpuImage) -> Vec<CpuImage> {
+        let pixel_type = image.pixel_type();
+        let srgb = self.srgb && pixel_type == PixelType::Rgba8;
+        let mut level = self.decode(image.to_rgba32f(), srgb);
+        let coverage = self
+            .alpha_cutoff
+            .map(|cutoff| (cutoff, alpha_coverage(&level, cutoff, 1.0)));
+
+        let (mut width, mut height) = (image.width, image.height);
+        let mut chain = vec![image.clone()];
+        while width > 1 || height > 1 {
+            let (mip_width, mip_height) = ((width / 2).max(1), (height / 2).max(1));
+            level = resample(
+                self.filter,
+                &level,
+                [width, height],
+                [mip_width, mip_height],
+            );
+            // Sharp filters overshoot, colors and alpha can't go negative.
+            for pixel in &mut level {
+                if self.normal_map {
+                    renormalize(pixel);
+                    pixel[3] = pixel[3].max(0.0);
+                } else {
+                    *pixel = pixel.map(|value| value.max(0.0));
+                }
+            }
+
+            // The next mip is filtered from the unscaled alpha, so scales
+            // don't compound.
+            let mut mip = level.clone();
+            if let Some((cutoff, target)) = coverage {
+                scale_alpha(&mut mip, cutoff, target);
+            }
+
+            chain.push(CpuImage::from_rgba32f(
+                mip_width,
+                mip_height,
+                pixel_type,
+                self.encode(mip, srgb),
+            ));
+            (width, height) = (mip_width, mip_height);
+        }
+
+        chain
+    }
+
+    /// The mip chain of `image` as a 2D texture, in `format`.
+    pub fn texture_data(&self, image: &CpuImage) -> TextureData {
+        let chain = self.generate(image);
+
+        TextureData {
+            format: self.format(image.pixel_type()),
+            dimension: TextureDimension::Texture2D,
+            width: image.width,
+            height: image.height,
+            depth: 1,
+            mip_levels: chain.len() as u32,
+            array_size: 1,
+            cubemap: false,
+            data: chain.iter().flat_map(CpuImage::to_bytes).collect(),
+        }
+    }
+
+    /// `image` as `pixel_type`. 8-bit images turned into float ones are
+    /// decoded from sRGB if `srgb` is set, since float textures are linear.
+    pub fn convert(&self, image: &CpuImage, pixel_type: PixelType) -> CpuImage {
+        let to_float = image.pixel_type() == PixelType::Rgba8 && pixel_type != PixelType::Rgba8;
+        let srgb = self.srgb && !self.normal_map && to_float;
+        let mut pixels = image.to_rgba32f();
+        if srgb {
+            for pixel in &mut pixels {
+                for channel in &mut pixel[..3] {
+                    *channel = srgb_to_linear(*channel);
+                }
+            }
+        }
+
+        CpuImage::from_rgba32f(image.width, image.height, pixel_type, pixels)
+    }
+
+    /// The texture format mips of `pixel_type` are stored in. 8-bit images
+    /// become `R8G8B8A8_UNORM`, or `_SRGB` if `srgb` is set; float images
+    /// keep their precision.
+    pub fn format(&self, pixel_type: PixelType) -> DXGI_FORMAT {
+        match pixel_type {
+            PixelType::Rgba8 if self.srgb && !self.normal_map => DXGI_FORMAT_R8G8B8A8_UNORM_SRGB,
+            PixelType::Rgba8 => DXGI_FORMAT_R8G8B8A8_UNORM,
+            PixelType::Rgba16F => DXGI_FORMAT_R16G16B16A16_FLOAT,
+            PixelType::Rgba32F => DXGI_FORMAT_R32G32B32A32_FLOAT,
+        }
+    }
+
+    fn decode(&self, mut pixels: Vec<[f32; 4]>, srgb: bool) -> Vec<[f32; 4]> {
+        for pixel in &mut pixels {
+            for channel in &mut pixel[..3] {
+                if self.normal_map {
+                    *channel = *channel * 2.0 - 1.0;
+                } else if srgb {
+                    *channel = srgb_to_linear(*channel);
+                }
+            }
+        }
+
+        pixels
+    }
+
+    fn encode(&self, mut pixels: Vec<[f32; 4]>, srgb: bool) -> Vec<[f32; 4]> {
+        for pixel in &mut pixels {
+            for channel in &mut pixel[..3] {
+                if self.normal_map {
+                    *channel = *channel * 0.5 + 0.5;
+                } else if srgb {
+                    *channel = linear_to_srgb(*channel);
+                }
+            }
+        }
+
+        pixels
+    }
+}
+
+impl Default for MipGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(value: f32) -> f32 {
+    let value = value.max(0.0);
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Which source texels a destination texel reads, and how much of each.
+fn taps(filter: MipFilter, source: u32, destination: u32) -> Vec<Vec<(usize, f32)>> {
+    let scale = source as f32 / destination as f32;
+    let radius = filter.support() * scale;
+
+    (0..destination)
+        .map(|x| {
+            let center = (x as f32 + 0.5) * scale;
+            let first = (center - radius).floor() as i64;
+            let last = (center + radius).ceil() as i64;
+
+            let mut taps: Vec<(usize, f32)> = Vec::new();
+            for i in first..=last {
+                let weight = filter.weight((i as f32 + 0.5 - center) / scale);
+                if weight == 0.0 {
+                    continue;
+                }
+                let i = i.clamp(0, source as i64 - 1) as usize;
+                match taps.iter_mut().find(|(index, _)| *index == i) {
+                    Some((_, total)) => *total += weight,
+                    None => taps.push((i, weight)),
+                }
+            }
+
+            let sum: f32 = taps.iter().map(|(_, weight)| weight).sum();
+            taps.iter_mut().for_each(|(_, weight)| *weight /= sum);
+            taps
+        })
+        .collect()
+}
+
+/// Separable resampling, rows first.
+fn resample(
+    filter: MipFilter,
+    pixels: &[[f32; 4]],
+    [width, height]: [u32; 2],
+    [new_width, new_height]: [u32; 2],
+) -> Vec<[f32; 4]> {
+    let (width, new_width) = (width as usize, new_width as usize);
+    let apply = |taps: &[(usize, f32)], texel: &dyn Fn(usize) -> [f32; 4]| {
+        let mut sum = [0.0; 4];
+        for &(index, weight) in taps {
+            let value = texel(index);
+            for channel in 0..4 {
+                sum[channel] += value[channel] * weight;
+            }
+        }
+        sum
+    };
+
+    let horizontal = taps(filter, width as u32, new_width as u32);
+    let mut rows = Vec::with_capacity(new_width * height as usize);
+    for y in 0..height as usize {
+        let row = &pixels[y * width..(y + 1) * width];
+        rows.extend(horizontal.iter().map(|taps| apply(taps, &|x| row[x])));
+    }
+
+    let vertical = taps(filter, height, new_height);
+    let mut resampled = Vec::with_capacity(new_width * new_height as usize);
+    for taps in &vertical {
+        resampled.extend((0..new_width).map(|x| apply(taps, &|y| rows[y * new_width + x])));
+    }
+
+    resampled
+}
+
+fn renormalize(pixel: &mut [f32; 4]) {
+    let [x, y, z, _] = *pixel;
+    let length = (x * x + y * y + z * z).sqrt();
+    if length > 1e-6 {
+        pixel[0] = x / length;
+        pixel[1] = y / length;
+        pixel[2] = z / length;
+    } else {
+        pixel[..3].copy_from_slice(&[0.0, 0.0, 1.0]);
+    }
+}
+
+/// Fraction of texels whose alpha, scaled by `scale`, passes the cutoff.
+fn alpha_coverage(pixels: &[[f32; 4]], cutoff: f32, scale: f32) -> f32 {
+    let passing = pixels
+        .iter()
+        .filter(|pixel| pixel[3] * scale > cutoff)
+        .count();
+
+    passing as f32 / pixels.len() as f32
+}
+
+/// Scales alpha so `alpha_coverage` comes as close to `target` as it can.
+fn scale_alpha(pixels: &mut [[f32; 4]], cutoff: f32, target: f32) {
+    if target == 0.0 {
+        return;
+    }
+
+    let (mut low, mut high) = (0.0f32, 4.0f32);
+    for _ in 0..16 {
+        let middle = (low + high) / 2.0;
+        if alpha_coverage(pixels, cutoff, middle) < target {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+
+    // Small mips can't hit the target exactly, take the closer side.
+    let miss = |scale| (alpha_coverage(pixels, cutoff, scale) - target).abs();
+    let scale = if miss(high) <= miss(low) { high } else { low };
+    for pixel in pixels {
+        pixel[3] = (pixel[3] * scale).min(1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_backend::cpu_image::CpuPixels;
+
+    const FILTERS: [MipFilter; 3] = [MipFilter::Box, MipFilter::Kaiser, MipFilter::Lanczos];
+
+    fn float_image(width: u32, height: u32, pixel: impl Fn(u32, u32) -> [f32; 4]) -> CpuImage {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| pixel(x, y))
+            .collect();
+        CpuImage::from_rgba32f(width, height, PixelType::Rgba32F, pixels)
+    }
+
+    #[test]
+    fn keeps_constant_images_constant() {
+        for filter in FILTERS {
+            let image = float_image(7, 5, |_, _| [0.25, 0.5, 2.0, 0.75]);
+            for mip in MipGenerator::new().filter(filter).generate(&image) {
+                for pixel in mip.to_rgba32f() {
+                    for (value, expected) in pixel.iter().zip([0.25, 0.5, 2.0, 0.75]) {
+                        assert!((value - expected).abs() < 1e-5, "{:?}: {:?}", filter, pixel);
+                    }
+                }
+            }
+
+            let image = CpuImage {
+                width: 6,
+                height: 6,
+                pixels: CpuPixels::Rgba8(vec![[10, 128, 250, 77]; 36]),
+            };
+            for mip in MipGenerator::new()
+                .filter(filter)
+                .srgb(true)
+                .generate(&image)
+            {
+                assert_eq!(
+                    mip.to_rgba8(),
+                    vec![[10, 128, 250, 77]; mip.to_rgba8().len()]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn halves_sizes_down_to_one_texel() {
+        let sizes = |width, height| -> Vec<(u32, u32)> {
+            MipGenerator::new()
+                .generate(&float_image(width, height, |_, _| [0.0; 4]))
+                .iter()
+                .map(|mip| (mip.width, mip.height))
+                .collect()
+        };
+
+        assert_eq!(sizes(1, 1), [(1, 1)]);
+        assert_eq!(sizes(4, 4), [(4, 4), (2, 2), (1, 1)]);
+        assert_eq!(sizes(5, 3), [(5, 3), (2, 1), (1, 1)]);
+        assert_eq!(sizes(1, 6), [(1, 6), (1, 3), (1, 1)]);
+        assert_eq!(sizes(256, 1).len(), 9);
+        assert_eq!(sizes(300, 17).last(), Some(&(1, 1)));
+        assert_eq!(sizes(300, 17).len(), 9);
+    }
+
+    #[test]
+    fn box_filter_averages_texel_pairs() {
+        let image = float_image(4, 2, |x, y| [x as f32, y as f32, (x * y) as f32, 1.0]);
+        let chain = MipGenerator::new().filter(MipFilter::Box).generate(&image);
+
+        assert_eq!(
+            chain[1].to_rgba32f(),
+            [[0.5, 0.5, 0.25, 1.0], [2.5, 0.5, 1.25, 1.0]]
+        );
+        assert_eq!(chain[2].to_rgba32f(), [[1.5, 0.5, 0.75, 1.0]]);
+    }
+
+    #[test]
+    fn filter_weights_sum_to_one() {
+        for filter in FILTERS {
+            for (source, destination) in [(2, 1), (7, 3), (64, 32), (5, 2)] {
+                for taps in taps(filter, source, destination) {
+                    let sum: f32 = taps.iter().map(|(_, weight)| weight).sum();
+                    assert!((sum - 1.0).abs() < 1e-5, "{:?}", filter);
+                    assert!(taps.iter().all(|&(index, _)| index < source as usize));
+                }
+            }
+        }
+
+        // Sharper filters reach further, and Lanczos has negative lobes.
+        assert_eq!(taps(MipFilter::Box, 8, 4)[1].len(), 2);
+        assert!(taps(MipFilter::Kaiser, 8, 4)[1].len() > 2);
+        assert!(taps(MipFilter::Lanczos, 16, 8)[4]
+            .iter()
+            .any(|&(_, weight)| weight < 0.0));
+    }
+
+    #[test]
+    fn sharp_filters_never_go_negative() {
+        // A hard edge makes Lanczos undershoot next to it.
+        let image = float_image(16, 1, |x, _| {
+            let value = if x < 8 { 0.0 } else { 1.0 };
+            [value, value, value, value]
+        });
+
+        for filter in FILTERS {
+            for mip in MipGenerator::new().filter(filter).generate(&image) {
+                assert!(mip.to_rgba32f().iter().flatten().all(|&value| value >= 0.0));
+            }
+        }
+    }
+
+    #[test]
+    fn filters_8_bit_images_in_linear_space() {
+        let image = CpuImage {
+            width: 2,
+            height: 1,
+            pixels: CpuPixels::Rgba8(vec![[0, 0, 0, 0], [255, 255, 255, 255]]),
+        };
+        let generator = MipGenerator::new().filter(MipFilter::Box);
+
+        // Half of linear white is 188 in sRGB, alpha stays linear.
+        let srgb = generator.srgb(true).generate(&image);
+        assert_eq!(srgb[1].to_rgba8(), [[188, 188, 188, 128]]);
+
+        let linear = generator.generate(&image);
+        assert_eq!(linear[1].to_rgba8(), [[128, 128, 128, 128]]);
+    }
+
+    #[test]
+    fn never_decodes_float_images_as_srgb() {
+        let image = float_image(2, 1, |x, _| [x as f32 * 4.0, 0.5, 0.0, 1.0]);
+        for pixel_type in [PixelType::Rgba16F, PixelType::Rgba32F] {
+            let image = CpuImage::from_rgba32f(2, 1, pixel_type, image.to_rgba32f());
+            let chain = MipGenerator::new()
+                .filter(MipFilter::Box)
+                .srgb(true)
+                .generate(&image);
+
+            assert_eq!(chain[1].pixel_type(), pixel_type);
+            assert_eq!(chain[1].to_rgba32f(), [[2.0, 0.5, 0.0, 1.0]]);
+        }
+    }
+
+    #[test]
+    fn renormalizes_normal_maps() {
+        // Normals tilted left and right average to straight up.
+        let image = CpuImage::from_rgba32f(
+            2,
+            2,
+            PixelType::Rgba32F,
+            [[0.2, 0.5, 0.9, 1.0], [0.8, 0.5, 0.9, 1.0]].repeat(2),
+        );
+        let chain = MipGenerator::new()
+            .filter(MipFilter::Box)
+            .srgb(true)
+            .normal_map(true)
+            .generate(&image);
+
+        let [x, y, z, _] = chain[1].to_rgba32f()[0].map(|value| value * 2.0 - 1.0);
+        assert!((x * x + y * y + z * z - 1.0).abs() < 1e-5);
+        assert!(x.abs() < 1e-5 && y.abs() < 1e-5 && z > 0.99);
+    }
+
+    #[test]
+    fn keeps_alpha_coverage() {
+        // A noisy cutout, about 30% opaque.
+        let image = float_image(32, 32, |x, y| {
+            let noise = ((x * 7919 + y * 104_729) % 97) as f32 / 96.0;
+            [1.0, 1.0, 1.0, if noise < 0.3 { 1.0 } else { 0.2 }]
+        });
+        let coverage = |mip: &CpuImage| alpha_coverage(&mip.to_rgba32f(), 0.5, 1.0);
+        let top = coverage(&image);
+
+        let chain = MipGenerator::new().alpha_coverage(0.5).generate(&image);
+        for mip in &chain[1..4] {
+            assert!(
+                (coverage(mip) - top).abs() < 0.1,
+                "{} vs {}",
+                coverage(mip),
+                top
+            );
+        }
+
+        // Without it the cutout all but vanishes.
+        let plain = MipGenerator::new().generate(&image);
+        assert!(coverage(&plain[3]) < top / 2.0);
+    }
+
+    #[test]
+    fn packs_the_chain_into_texture_data() {
+        let image = float_image(4, 2, |_, _| [1.0; 4]);
+        let data = MipGenerator::new().texture_data(&image);
+        assert_eq!(data.format, DXGI_FORMAT_R32G32B32A32_FLOAT);
+        assert_eq!(data.mip_levels, 3);
+        assert_eq!(data.data.len(), (8 + 2 + 1) * 16);
+        assert_eq!(data.subresources().unwrap().len(), 3);
+
+        let image = CpuImage::from_rgba32f(4, 2, PixelType::Rgba8, image.to_rgba32f());
+        let generator = MipGenerator::new().srgb(true);
+        assert_eq!(
+            generator.texture_data(&image).format,
+            DXGI_FORMAT_R8G8B8A8_UNORM_SRGB
+        );
+        assert_eq!(
+            generator.normal_map(true).format(PixelType::Rgba8),
+            DXGI_FORMAT_R8G8B8A8_UNORM
+        );
+        assert_eq!(
+            generator.format(PixelType::Rgba16F),
+            DXGI_FORMAT_R16G16B16A16_FLOAT
+        );
+    }
+}
@@ -1,7 +1,31 @@
+pub mod atlas;
 pub mod backend;
+pub mod block_compression;
+pub mod cbuffer_layout;
+pub mod compute_pass;
+pub mod cpu_image;
+pub mod dds;
+pub mod environment_map;
+pub mod file_watcher;
+pub mod format_info;
+pub mod gltf;
 pub mod gpu_buffer;
+pub mod json;
+pub mod ktx2;
+pub mod material;
 pub mod mesh;
+pub mod mesh_optimizer;
+pub mod mipmaps;
+pub mod pipeline_state;
 pub mod render_pass;
 pub mod renderer;
 pub mod shader;
+pub mod shader_cache;
+pub mod shader_diagnostics;
+pub mod shader_library;
+pub mod shader_permutation;
+pub mod shader_preprocessor;
+pub mod shader_reflection;
 pub mod texture;
+pub mod texture_data;
+pub mod typed_buffer;
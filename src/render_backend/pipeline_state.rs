@@ -0,0 +1,893 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::hash::{Hash, Hasher};
+
+use windows::Win32::Foundation::PSTR;
+use windows::Win32::Graphics::{Direct3D11::*, Dxgi::Common::*};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ComparisonFunc {
+    Never,
+    Less,
+    Equal,
+    LessEqual,
+    Greater,
+    NotEqual,
+    GreaterEqual,
+    Always,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    Replace,
+    IncrementSaturate,
+    DecrementSaturate,
+    Invert,
+    Increment,
+    Decrement,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StencilFaceDesc {
+    pub fail_op: StencilOp,
+    pub depth_fail_op: StencilOp,
+    pub pass_op: StencilOp,
+    pub func: ComparisonFunc,
+}
+
+impl Default for StencilFaceDesc {
+    fn default() -> Self {
+        StencilFaceDesc {
+            fail_op: StencilOp::Keep,
+            depth_fail_op: StencilOp::Keep,
+            pass_op: StencilOp::Keep,
+            func: ComparisonFunc::Always,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DepthStencilDesc {
+    pub depth_enable: bool,
+    pub depth_write: bool,
+    pub depth_func: ComparisonFunc,
+    pub stencil_enable: bool,
+    pub stencil_read_mask: u8,
+    pub stencil_write_mask: u8,
+    pub front_face: StencilFaceDesc,
+    pub back_face: StencilFaceDesc,
+}
+
+impl Default for DepthStencilDesc {
+    fn default() -> Self {
+        Self::depth_less()
+    }
+}
+
+impl DepthStencilDesc {
+    pub fn disabled() -> Self {
+        DepthStencilDesc {
+            depth_enable: false,
+            depth_write: false,
+            ..Self::depth_less()
+        }
+    }
+
+    pub fn depth_less() -> Self {
+        DepthStencilDesc {
+            depth_enable: true,
+            depth_write: true,
+            depth_func: ComparisonFunc::Less,
+            stencil_enable: false,
+            stencil_read_mask: 0xFF,
+            stencil_write_mask: 0xFF,
+            front_face: Default::default(),
+            back_face: Default::default(),
+        }
+    }
+
+    /// `depth_less` with the stencil counting depth test failures: front
+    /// faces increment and back faces decrement, as for z-fail shadow volumes.
+    pub fn depth_less_stencil_count() -> Self {
+        DepthStencilDesc {
+            stencil_enable: true,
+            front_face: StencilFaceDesc {
+                depth_fail_op: StencilOp::Increment,
+                ..Default::default()
+            },
+            back_face: StencilFaceDesc {
+                depth_fail_op: StencilOp::Decrement,
+                ..Default::default()
+            },
+            ..Self::depth_less()
+        }
+    }
+
+    /// Depth tested against but never written, e.g. for passes drawn on top of the g-buffer.
+    pub fn depth_read_only() -> Self {
+        DepthStencilDesc {
+            depth_write: false,
+            depth_func: ComparisonFunc::LessEqual,
+            ..Self::depth_less()
+        }
+    }
+
+    pub fn to_d3d11(&self) -> D3D11_DEPTH_STENCIL_DESC {
+        D3D11_DEPTH_STENCIL_DESC {
+            DepthEnable: self.depth_enable.into(),
+            DepthWriteMask: if self.depth_write {
+                D3D11_DEPTH_WRITE_MASK_ALL
+            } else {
+                D3D11_DEPTH_WRITE_MASK_ZERO
+            },
+            DepthFunc: self.depth_func.to_d3d11(),
+            StencilEnable: self.stencil_enable.into(),
+            StencilReadMask: self.stencil_read_mask,
+            StencilWriteMask: self.stencil_write_mask,
+            FrontFace: self.front_face.to_d3d11(),
+            BackFace: self.back_face.to_d3d11(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SrcColour,
+    InvSrcColour,
+    SrcAlpha,
+    InvSrcAlpha,
+    DestAlpha,
+    InvDestAlpha,
+    DestColour,
+    InvDestColour,
+    SrcAlphaSaturate,
+    BlendFactor,
+    InvBlendFactor,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlendOp {
+    Add,
+    Subtract,
+    ReverseSubtract,
+    Min,
+    Max,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RenderTargetBlendDesc {
+    pub blend_enable: bool,
+    pub src_blend: BlendFactor,
+    pub dest_blend: BlendFactor,
+    pub blend_op: BlendOp,
+    pub src_blend_alpha: BlendFactor,
+    pub dest_blend_alpha: BlendFactor,
+    pub blend_op_alpha: BlendOp,
+    pub write_mask: u8,
+}
+
+impl Default for RenderTargetBlendDesc {
+    fn default() -> Self {
+        RenderTargetBlendDesc {
+            blend_enable: false,
+            src_blend: BlendFactor::One,
+            dest_blend: BlendFactor::Zero,
+            blend_op: BlendOp::Add,
+            src_blend_alpha: BlendFactor::One,
+            dest_blend_alpha: BlendFactor::Zero,
+            blend_op_alpha: BlendOp::Add,
+            write_mask: D3D11_COLOR_WRITE_ENABLE_ALL as u8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BlendDesc {
+    pub alpha_to_coverage: bool,
+    pub independent_blend: bool,
+    pub render_targets: [RenderTargetBlendDesc; 8],
+}
+
+impl BlendDesc {
+    pub fn opaque() -> Self {
+        Default::default()
+    }
+
+    /// Applies the same blend equation to every render target.
+    pub fn uniform(render_target: RenderTargetBlendDesc) -> Self {
+        BlendDesc {
+            render_targets: [render_target; 8],
+            ..Default::default()
+        }
+    }
+
+    pub fn alpha_blend() -> Self {
+        Self::uniform(RenderTargetBlendDesc {
+            blend_enable: true,
+            src_blend: BlendFactor::SrcAlpha,
+            dest_blend: BlendFactor::InvSrcAlpha,
+            src_blend_alpha: BlendFactor::One,
+            dest_blend_alpha: BlendFactor::InvSrcAlpha,
+            ..Default::default()
+        })
+    }
+
+    pub fn premultiplied_alpha() -> Self {
+        Self::uniform(RenderTargetBlendDesc {
+            blend_enable: true,
+            src_blend: BlendFactor::One,
+            dest_blend: BlendFactor::InvSrcAlpha,
+            src_blend_alpha: BlendFactor::One,
+            dest_blend_alpha: BlendFactor::InvSrcAlpha,
+            ..Default::default()
+        })
+    }
+
+    pub fn additive() -> Self {
+        Self::uniform(RenderTargetBlendDesc {
+            blend_enable: true,
+            src_blend: BlendFactor::One,
+            dest_blend: BlendFactor::One,
+            src_blend_alpha: BlendFactor::One,
+            dest_blend_alpha: BlendFactor::One,
+            ..Default::default()
+        })
+    }
+
+    pub fn to_d3d11(&self) -> D3D11_BLEND_DESC {
+        D3D11_BLEND_DESC {
+            AlphaToCoverageEnable: self.alpha_to_coverage.into(),
+            IndependentBlendEnable: self.independent_blend.into(),
+            RenderTarget: self
+                .render_targets
+                .map(|rt| D3D11_RENDER_TARGET_BLEND_DESC {
+                    BlendEnable: rt.blend_enable.into(),
+                    SrcBlend: rt.src_blend.to_d3d11(),
+                    DestBlend: rt.dest_blend.to_d3d11(),
+                    BlendOp: rt.blend_op.to_d3d11(),
+                    SrcBlendAlpha: rt.src_blend_alpha.to_d3d11(),
+                    DestBlendAlpha: rt.dest_blend_alpha.to_d3d11(),
+                    BlendOpAlpha: rt.blend_op_alpha.to_d3d11(),
+                    RenderTargetWriteMask: rt.write_mask,
+                }),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FillMode {
+    Solid,
+    Wireframe,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CullMode {
+    None,
+    Front,
+    Back,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RasterizerDesc {
+    pub fill_mode: FillMode,
+    pub cull_mode: CullMode,
+    pub front_counter_clockwise: bool,
+    pub depth_bias: i32,
+    pub depth_bias_clamp: f32,
+    pub slope_scaled_depth_bias: f32,
+    pub depth_clip: bool,
+    pub scissor: bool,
+    pub multisample: bool,
+    pub antialiased_lines: bool,
+}
+
+impl Default for RasterizerDesc {
+    fn default() -> Self {
+        Self::cull_back()
+    }
+}
+
+impl RasterizerDesc {
+    pub fn cull_back() -> Self {
+        RasterizerDesc {
+            fill_mode: FillMode::Solid,
+            cull_mode: CullMode::Back,
+            front_counter_clockwise: false,
+            depth_bias: 0,
+            depth_bias_clamp: 0.0,
+            slope_scaled_depth_bias: 0.0,
+            depth_clip: true,
+            scissor: false,
+            multisample: false,
+            antialiased_lines: false,
+        }
+    }
+
+    pub fn cull_none() -> Self {
+        RasterizerDesc {
+            cull_mode: CullMode::None,
+            ..Self::cull_back()
+        }
+    }
+
+    pub fn wireframe() -> Self {
+        RasterizerDesc {
+            fill_mode: FillMode::Wireframe,
+            cull_mode: CullMode::None,
+            ..Self::cull_back()
+        }
+    }
+
+    pub fn to_d3d11(&self) -> D3D11_RASTERIZER_DESC {
+        D3D11_RASTERIZER_DESC {
+            FillMode: match self.fill_mode {
+                FillMode::Solid => D3D11_FILL_SOLID,
+                FillMode::Wireframe => D3D11_FILL_WIREFRAME,
+            },
+            CullMode: match self.cull_mode {
+                CullMode::None => D3D11_CULL_NONE,
+                CullMode::Front => D3D11_CULL_FRONT,
+                CullMode::Back => D3D11_CULL_BACK,
+            },
+            FrontCounterClockwise: self.front_counter_clockwise.into(),
+            DepthBias: self.depth_bias,
+            DepthBiasClamp: self.depth_bias_clamp,
+            SlopeScaledDepthBias: self.slope_scaled_depth_bias,
+            DepthClipEnable: self.depth_clip.into(),
+            ScissorEnable: self.scissor.into(),
+            MultisampleEnable: self.multisample.into(),
+            AntialiasedLineEnable: self.antialiased_lines.into(),
+        }
+    }
+
+    fn key(&self) -> impl Hash + Eq {
+        (
+            self.fill_mode,
+            self.cull_mode,
+            self.front_counter_clockwise,
+            self.depth_bias,
+            self.depth_bias_clamp.to_bits(),
+            self.slope_scaled_depth_bias.to_bits(),
+            (
+                self.depth_clip,
+                self.scissor,
+                self.multisample,
+                self.antialiased_lines,
+            ),
+        )
+    }
+}
+
+impl PartialEq for RasterizerDesc {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for RasterizerDesc {}
+
+impl Hash for RasterizerDesc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Filter {
+    Point,
+    Linear,
+    MinMagLinearMipPoint,
+    Anisotropic,
+    ComparisonPoint,
+    ComparisonLinear,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AddressMode {
+    Wrap,
+    Mirror,
+    Clamp,
+    Border,
+    MirrorOnce,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SamplerDesc {
+    pub filter: Filter,
+    pub address_u: AddressMode,
+    pub address_v: AddressMode,
+    pub address_w: AddressMode,
+    pub mip_lod_bias: f32,
+    pub max_anisotropy: u32,
+    pub comparison_func: ComparisonFunc,
+    pub border_colour: [f32; 4],
+    pub min_lod: f32,
+    pub max_lod: f32,
+}
+
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self::linear_wrap()
+    }
+}
+
+impl SamplerDesc {
+    pub fn with_address_mode(filter: Filter, address_mode: AddressMode) -> Self {
+        SamplerDesc {
+            filter,
+            address_u: address_mode,
+            address_v: address_mode,
+            address_w: address_mode,
+            mip_lod_bias: 0.0,
+            max_anisotropy: 1,
+            comparison_func: ComparisonFunc::Always,
+            border_colour: [0.0, 0.0, 0.0, 0.0],
+            min_lod: 0.0,
+            max_lod: D3D11_FLOAT32_MAX,
+        }
+    }
+
+    pub fn linear_wrap() -> Self {
+        Self::with_address_mode(Filter::Linear, AddressMode::Wrap)
+    }
+
+    pub fn linear_clamp() -> Self {
+        Self::with_address_mode(Filter::Linear, AddressMode::Clamp)
+    }
+
+    pub fn point_clamp() -> Self {
+        Self::with_address_mode(Filter::Point, AddressMode::Clamp)
+    }
+
+    pub fn anisotropic_wrap(max_anisotropy: u32) -> Self {
+        SamplerDesc {
+            max_anisotropy,
+            ..Self::with_address_mode(Filter::Anisotropic, AddressMode::Wrap)
+        }
+    }
+
+    /// Comparison sampler for shadow map lookups.
+    pub fn shadow() -> Self {
+        SamplerDesc {
+            comparison_func: ComparisonFunc::LessEqual,
+            border_colour: [1.0, 1.0, 1.0, 1.0],
+            ..Self::with_address_mode(Filter::ComparisonLinear, AddressMode::Border)
+        }
+    }
+
+    pub fn to_d3d11(&self) -> D3D11_SAMPLER_DESC {
+        D3D11_SAMPLER_DESC {
+            Filter: match self.filter {
+                Filter::Point => D3D11_FILTER_MIN_MAG_MIP_POINT,
+                Filter::Linear => D3D11_FILTER_MIN_MAG_MIP_LINEAR,
+                Filter::MinMagLinearMipPoint => D3D11_FILTER_MIN_MAG_LINEAR_MIP_POINT,
+                Filter::Anisotropic => D3D11_FILTER_ANISOTROPIC,
+                Filter::ComparisonPoint => D3D11_FILTER_COMPARISON_MIN_MAG_MIP_POINT,
+                Filter::ComparisonLinear => D3D11_FILTER_COMPARISON_MIN_MAG_MIP_LINEAR,
+            },
+            AddressU: self.address_u.to_d3d11(),
+            AddressV: self.address_v.to_d3d11(),
+            AddressW: self.address_w.to_d3d11(),
+            MipLODBias: self.mip_lod_bias,
+            MaxAnisotropy: self.max_anisotropy,
+            ComparisonFunc: self.comparison_func.to_d3d11(),
+            BorderColor: self.border_colour,
+            MinLOD: self.min_lod,
+            MaxLOD: self.max_lod,
+        }
+    }
+
+    fn key(&self) -> impl Hash + Eq {
+        (
+            self.filter,
+            [self.address_u, self.address_v, self.address_w],
+            self.mip_lod_bias.to_bits(),
+            self.max_anisotropy,
+            self.comparison_func,
+            self.border_colour.map(f32::to_bits),
+            self.min_lod.to_bits(),
+            self.max_lod.to_bits(),
+        )
+    }
+}
+
+impl PartialEq for SamplerDesc {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for SamplerDesc {}
+
+impl Hash for SamplerDesc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VertexFormat {
+    Float,
+    Float2,
+    Float3,
+    Float4,
+    UInt,
+    UInt2,
+    UInt3,
+    UInt4,
+    UNorm4x8,
+}
+
+impl VertexFormat {
+    pub fn size(&self) -> u32 {
+        match self {
+            VertexFormat::Float | VertexFormat::UInt | VertexFormat::UNorm4x8 => 4,
+            VertexFormat::Float2 | VertexFormat::UInt2 => 8,
+            VertexFormat::Float3 | VertexFormat::UInt3 => 12,
+            VertexFormat::Float4 | VertexFormat::UInt4 => 16,
+        }
+    }
+
+    pub fn to_dxgi(&self) -> DXGI_FORMAT {
+        match self {
+            VertexFormat::Float => DXGI_FORMAT_R32_FLOAT,
+            VertexFormat::Float2 => DXGI_FORMAT_R32G32_FLOAT,
+            VertexFormat::Float3 => DXGI_FORMAT_R32G32B32_FLOAT,
+            VertexFormat::Float4 => DXGI_FORMAT_R32G32B32A32_FLOAT,
+            VertexFormat::UInt => DXGI_FORMAT_R32_UINT,
+            VertexFormat::UInt2 => DXGI_FORMAT_R32G32_UINT,
+            VertexFormat::UInt3 => DXGI_FORMAT_R32G32B32_UINT,
+            VertexFormat::UInt4 => DXGI_FORMAT_R32G32B32A32_UINT,
+            VertexFormat::UNorm4x8 => DXGI_FORMAT_R8G8B8A8_UNORM,
+        }
+    }
+}
+
+/// One attribute of a vertex input layout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct InputElement {
+    pub semantic: &'static str,
+    pub semantic_index: u32,
+    pub format: VertexFormat,
+    pub slot: u32,
+    pub offset: u32,
+    pub per_instance: bool,
+}
+
+impl InputElement {
+    pub const fn per_vertex(semantic: &'static str, format: VertexFormat, offset: u32) -> Self {
+        InputElement {
+            semantic,
+            semantic_index: 0,
+            format,
+            slot: 0,
+            offset,
+            per_instance: false,
+        }
+    }
+}
+
+/// Calls `f` with the D3D11 equivalent of `layout`. The semantic name
+/// strings only live for the duration of the call.
+pub fn with_d3d11_input_layout<T>(
+    layout: &[InputElement],
+    f: impl FnOnce(&[D3D11_INPUT_ELEMENT_DESC]) -> T,
+) -> T {
+    let semantics: Vec<CString> = layout
+        .iter()
+        .map(|element| CString::new(element.semantic).expect("Semantic name contains a nul"))
+        .collect();
+
+    let descs: Vec<D3D11_INPUT_ELEMENT_DESC> = layout
+        .iter()
+        .zip(semantics.iter())
+        .map(|(element, semantic)| D3D11_INPUT_ELEMENT_DESC {
+            SemanticName: PSTR(semantic.as_ptr() as _),
+            SemanticIndex: element.semantic_index,
+            Format: element.format.to_dxgi(),
+            InputSlot: element.slot,
+            AlignedByteOffset: element.offset,
+            InputSlotClass: if element.per_instance {
+                D3D11_INPUT_PER_INSTANCE_DATA
+            } else {
+                D3D11_INPUT_PER_VERTEX_DATA
+            },
+            InstanceDataStepRate: element.per_instance as u32,
+        })
+        .collect();
+
+    f(&descs)
+}
+
+impl ComparisonFunc {
+    pub fn to_d3d11(&self) -> D3D11_COMPARISON_FUNC {
+        match self {
+            ComparisonFunc::Never => D3D11_COMPARISON_NEVER,
+            ComparisonFunc::Less => D3D11_COMPARISON_LESS,
+            ComparisonFunc::Equal => D3D11_COMPARISON_EQUAL,
+            ComparisonFunc::LessEqual => D3D11_COMPARISON_LESS_EQUAL,
+            ComparisonFunc::Greater => D3D11_COMPARISON_GREATER,
+            ComparisonFunc::NotEqual => D3D11_COMPARISON_NOT_EQUAL,
+            ComparisonFunc::GreaterEqual => D3D11_COMPARISON_GREATER_EQUAL,
+            ComparisonFunc::Always => D3D11_COMPARISON_ALWAYS,
+        }
+    }
+}
+
+impl StencilOp {
+    pub fn to_d3d11(&self) -> D3D11_STENCIL_OP {
+        match self {
+            StencilOp::Keep => D3D11_STENCIL_OP_KEEP,
+            StencilOp::Zero => D3D11_STENCIL_OP_ZERO,
+            StencilOp::Replace => D3D11_STENCIL_OP_REPLACE,
+            StencilOp::IncrementSaturate => D3D11_STENCIL_OP_INCR_SAT,
+            StencilOp::DecrementSaturate => D3D11_STENCIL_OP_DECR_SAT,
+            StencilOp::Invert => D3D11_STENCIL_OP_INVERT,
+            StencilOp::Increment => D3D11_STENCIL_OP_INCR,
+            StencilOp::Decrement => D3D11_STENCIL_OP_DECR,
+        }
+    }
+}
+
+impl StencilFaceDesc {
+    pub fn to_d3d11(&self) -> D3D11_DEPTH_STENCILOP_DESC {
+        D3D11_DEPTH_STENCILOP_DESC {
+            StencilFailOp: self.fail_op.to_d3d11(),
+            StencilDepthFailOp: self.depth_fail_op.to_d3d11(),
+            StencilPassOp: self.pass_op.to_d3d11(),
+            StencilFunc: self.func.to_d3d11(),
+        }
+    }
+}
+
+impl BlendFactor {
+    pub fn to_d3d11(&self) -> D3D11_BLEND {
+        match self {
+            BlendFactor::Zero => D3D11_BLEND_ZERO,
+            BlendFactor::One => D3D11_BLEND_ONE,
+            BlendFactor::SrcColour => D3D11_BLEND_SRC_COLOR,
+            BlendFactor::InvSrcColour => D3D11_BLEND_INV_SRC_COLOR,
+            BlendFactor::SrcAlpha => D3D11_BLEND_SRC_ALPHA,
+            BlendFactor::InvSrcAlpha => D3D11_BLEND_INV_SRC_ALPHA,
+            BlendFactor::DestAlpha => D3D11_BLEND_DEST_ALPHA,
+            BlendFactor::InvDestAlpha => D3D11_BLEND_INV_DEST_ALPHA,
+            BlendFactor::DestColour => D3D11_BLEND_DEST_COLOR,
+            BlendFactor::InvDestColour => D3D11_BLEND_INV_DEST_COLOR,
+            BlendFactor::SrcAlphaSaturate => D3D11_BLEND_SRC_ALPHA_SAT,
+            BlendFactor::BlendFactor => D3D11_BLEND_BLEND_FACTOR,
+            BlendFactor::InvBlendFactor => D3D11_BLEND_INV_BLEND_FACTOR,
+        }
+    }
+}
+
+impl BlendOp {
+    pub fn to_d3d11(&self) -> D3D11_BLEND_OP {
+        match self {
+            BlendOp::Add => D3D11_BLEND_OP_ADD,
+            BlendOp::Subtract => D3D11_BLEND_OP_SUBTRACT,
+            BlendOp::ReverseSubtract => D3D11_BLEND_OP_REV_SUBTRACT,
+            BlendOp::Min => D3D11_BLEND_OP_MIN,
+            BlendOp::Max => D3D11_BLEND_OP_MAX,
+        }
+    }
+}
+
+impl AddressMode {
+    pub fn to_d3d11(&self) -> D3D11_TEXTURE_ADDRESS_MODE {
+        match self {
+            AddressMode::Wrap => D3D11_TEXTURE_ADDRESS_WRAP,
+            AddressMode::Mirror => D3D11_TEXTURE_ADDRESS_MIRROR,
+            AddressMode::Clamp => D3D11_TEXTURE_ADDRESS_CLAMP,
+            AddressMode::Border => D3D11_TEXTURE_ADDRESS_BORDER,
+            AddressMode::MirrorOnce => D3D11_TEXTURE_ADDRESS_MIRROR_ONCE,
+        }
+    }
+}
+
+/// Key for a cached input layout: the layout itself plus a hash of the
+/// vertex shader bytecode it was validated against.
+pub type InputLayoutKey = (Vec<InputElement>, u64);
+
+/// Device state objects, created once per unique description and shared by
+/// handing out clones of the COM pointer.
+#[derive(Default)]
+pub struct StateCache {
+    pub(super) depth_stencil: RefCell<HashMap<DepthStencilDesc, ID3D11DepthStencilState>>,
+    pub(super) blend: RefCell<HashMap<BlendDesc, ID3D11BlendState>>,
+    pub(super) rasterizer: RefCell<HashMap<RasterizerDesc, ID3D11RasterizerState>>,
+    pub(super) sampler: RefCell<HashMap<SamplerDesc, ID3D11SamplerState>>,
+    pub(super) input_layout: RefCell<HashMap<InputLayoutKey, ID3D11InputLayout>>,
+}
+
+impl StateCache {
+    pub fn len(&self) -> usize {
+        self.depth_stencil.borrow().len()
+            + self.blend.borrow().len()
+            + self.rasterizer.borrow().len()
+            + self.sampler.borrow().len()
+            + self.input_layout.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.depth_stencil.borrow_mut().clear();
+        self.blend.borrow_mut().clear();
+        self.rasterizer.borrow_mut().clear();
+        self.sampler.borrow_mut().clear();
+        self.input_layout.borrow_mut().clear();
+    }
+}
+
+pub(super) fn get_or_create<K, V, E>(
+    cache: &RefCell<HashMap<K, V>>,
+    key: K,
+    create: impl FnOnce() -> std::result::Result<V, E>,
+) -> std::result::Result<V, E>
+where
+    K: Hash + Eq,
+    V: Clone,
+{
+    if let Some(state) = cache.borrow().get(&key) {
+        return Ok(state.clone());
+    }
+
+    let state = create()?;
+    cache.borrow_mut().insert(key, state.clone());
+
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_stencil_maps_to_d3d11() {
+        let desc = DepthStencilDesc::depth_less_stencil_count().to_d3d11();
+        assert!(desc.DepthEnable.as_bool());
+        assert_eq!(desc.DepthWriteMask, D3D11_DEPTH_WRITE_MASK_ALL);
+        assert_eq!(desc.DepthFunc, D3D11_COMPARISON_LESS);
+        assert!(desc.StencilEnable.as_bool());
+        assert_eq!(desc.StencilReadMask, 0xFF);
+        assert_eq!(desc.StencilWriteMask, 0xFF);
+        assert_eq!(desc.FrontFace.StencilFailOp, D3D11_STENCIL_OP_KEEP);
+        assert_eq!(desc.FrontFace.StencilDepthFailOp, D3D11_STENCIL_OP_INCR);
+        assert_eq!(desc.FrontFace.StencilPassOp, D3D11_STENCIL_OP_KEEP);
+        assert_eq!(desc.FrontFace.StencilFunc, D3D11_COMPARISON_ALWAYS);
+        assert_eq!(desc.BackFace.StencilDepthFailOp, D3D11_STENCIL_OP_DECR);
+        assert_eq!(desc.BackFace.StencilFunc, D3D11_COMPARISON_ALWAYS);
+
+        let desc = DepthStencilDesc::depth_read_only().to_d3d11();
+        assert_eq!(desc.DepthWriteMask, D3D11_DEPTH_WRITE_MASK_ZERO);
+        assert_eq!(desc.DepthFunc, D3D11_COMPARISON_LESS_EQUAL);
+        assert!(!desc.StencilEnable.as_bool());
+
+        assert!(!DepthStencilDesc::disabled()
+            .to_d3d11()
+            .DepthEnable
+            .as_bool());
+    }
+
+    #[test]
+    fn blend_maps_to_d3d11() {
+        let desc = BlendDesc::alpha_blend().to_d3d11();
+        assert!(!desc.AlphaToCoverageEnable.as_bool());
+        assert!(!desc.IndependentBlendEnable.as_bool());
+        for rt in desc.RenderTarget {
+            assert!(rt.BlendEnable.as_bool());
+            assert_eq!(rt.SrcBlend, D3D11_BLEND_SRC_ALPHA);
+            assert_eq!(rt.DestBlend, D3D11_BLEND_INV_SRC_ALPHA);
+            assert_eq!(rt.BlendOp, D3D11_BLEND_OP_ADD);
+            assert_eq!(rt.SrcBlendAlpha, D3D11_BLEND_ONE);
+            assert_eq!(rt.DestBlendAlpha, D3D11_BLEND_INV_SRC_ALPHA);
+            assert_eq!(rt.BlendOpAlpha, D3D11_BLEND_OP_ADD);
+            assert_eq!(rt.RenderTargetWriteMask, D3D11_COLOR_WRITE_ENABLE_ALL as u8);
+        }
+
+        let rt = BlendDesc::opaque().to_d3d11().RenderTarget[0];
+        assert!(!rt.BlendEnable.as_bool());
+        assert_eq!(rt.SrcBlend, D3D11_BLEND_ONE);
+        assert_eq!(rt.DestBlend, D3D11_BLEND_ZERO);
+    }
+
+    #[test]
+    fn rasterizer_maps_to_d3d11() {
+        let desc = RasterizerDesc {
+            depth_bias: 4,
+            slope_scaled_depth_bias: 1.5,
+            ..RasterizerDesc::wireframe()
+        }
+        .to_d3d11();
+        assert_eq!(desc.FillMode, D3D11_FILL_WIREFRAME);
+        assert_eq!(desc.CullMode, D3D11_CULL_NONE);
+        assert!(!desc.FrontCounterClockwise.as_bool());
+        assert_eq!(desc.DepthBias, 4);
+        assert_eq!(desc.SlopeScaledDepthBias, 1.5);
+        assert!(desc.DepthClipEnable.as_bool());
+        assert!(!desc.ScissorEnable.as_bool());
+
+        let desc = RasterizerDesc::cull_back().to_d3d11();
+        assert_eq!(desc.FillMode, D3D11_FILL_SOLID);
+        assert_eq!(desc.CullMode, D3D11_CULL_BACK);
+    }
+
+    #[test]
+    fn sampler_maps_to_d3d11() {
+        let desc = SamplerDesc::shadow().to_d3d11();
+        assert_eq!(desc.Filter, D3D11_FILTER_COMPARISON_MIN_MAG_MIP_LINEAR);
+        assert_eq!(desc.AddressU, D3D11_TEXTURE_ADDRESS_BORDER);
+        assert_eq!(desc.AddressV, D3D11_TEXTURE_ADDRESS_BORDER);
+        assert_eq!(desc.AddressW, D3D11_TEXTURE_ADDRESS_BORDER);
+        assert_eq!(desc.ComparisonFunc, D3D11_COMPARISON_LESS_EQUAL);
+        assert_eq!(desc.BorderColor, [1.0; 4]);
+        assert_eq!(desc.MaxLOD, D3D11_FLOAT32_MAX);
+
+        let desc = SamplerDesc::anisotropic_wrap(8).to_d3d11();
+        assert_eq!(desc.Filter, D3D11_FILTER_ANISOTROPIC);
+        assert_eq!(desc.AddressU, D3D11_TEXTURE_ADDRESS_WRAP);
+        assert_eq!(desc.MaxAnisotropy, 8);
+    }
+
+    #[test]
+    fn float_fields_are_keyed_by_their_bits() {
+        let positive = RasterizerDesc::cull_back();
+        let negative = RasterizerDesc {
+            depth_bias_clamp: -0.0,
+            ..positive
+        };
+        assert_ne!(positive, negative);
+
+        let nan = SamplerDesc {
+            mip_lod_bias: f32::NAN,
+            ..SamplerDesc::linear_wrap()
+        };
+        assert_eq!(nan, nan);
+
+        let mut samplers = HashMap::new();
+        samplers.insert(nan, 0);
+        samplers.insert(nan, 1);
+        samplers.insert(SamplerDesc::linear_wrap(), 2);
+        assert_eq!(samplers.len(), 2);
+        assert_eq!(samplers[&nan], 1);
+    }
+
+    #[test]
+    fn get_or_create_creates_once_per_desc() {
+        let cache = RefCell::new(HashMap::new());
+        let mut created = 0;
+        let mut create = |desc: SamplerDesc| {
+            get_or_create::<_, _, ()>(&cache, desc, || {
+                created += 1;
+                Ok(created)
+            })
+        };
+
+        assert_eq!(create(SamplerDesc::linear_wrap()), Ok(1));
+        assert_eq!(create(SamplerDesc::linear_wrap()), Ok(1));
+        assert_eq!(create(SamplerDesc::point_clamp()), Ok(2));
+        assert_eq!(create(SamplerDesc::point_clamp()), Ok(2));
+        assert_eq!(cache.borrow().len(), 2);
+    }
+
+    #[test]
+    fn get_or_create_does_not_cache_failures() {
+        let cache = RefCell::new(HashMap::new());
+        let desc = DepthStencilDesc::depth_less();
+
+        assert_eq!(
+            get_or_create(&cache, desc, || Err("device lost")),
+            Err("device lost")
+        );
+        assert!(cache.borrow().is_empty());
+        assert_eq!(get_or_create::<_, _, &str>(&cache, desc, || Ok(7)), Ok(7));
+        assert_eq!(get_or_create(&cache, desc, || Err("unused")), Ok(7));
+    }
+}
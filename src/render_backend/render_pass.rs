@@ -1,9 +1,18 @@
-use windows::core::{Error, Result};
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
 use windows::Win32::Graphics::Direct3D11::*;
 
+use crate::error::{Context, EngineError, EngineResult};
+
 use super::backend::Backend;
 use super::mesh::GpuMesh;
-use super::shader::Shader;
+use super::pipeline_state::InputElement;
+use super::shader::{Shader, ShaderStage};
+use super::shader_library::ShaderHandle;
+use super::shader_permutation::{ShaderPermutations, VariantKey};
+use super::shader_reflection::{ResourceBinding, ResourceKind, ShaderReflection};
 
 #[derive(Default, Clone)]
 pub struct DepthAttachment {
@@ -15,14 +24,24 @@ pub struct DepthAttachment {
 #[derive(Default)]
 pub struct RenderPass {
     depth_attachment: DepthAttachment,
-    input_desc: Option<ID3D11InputLayout>,
+    input_layout: Vec<InputElement>,
+    /// Input layout for the vertex shader generation it was created from.
+    input_desc: RefCell<Option<(u64, ID3D11InputLayout)>>,
+    blend_state: Option<ID3D11BlendState>,
+    rasterizer_state: Option<ID3D11RasterizerState>,
     pub vertex_stride: u32,
     pub shader_resources: Vec<ID3D11ShaderResourceView>,
     render_targets: Vec<ID3D11RenderTargetView>,
     sampler_states: Vec<ID3D11SamplerState>,
-    pixel_shader: Option<Shader>,
-    vertex_shader: Option<Shader>,
-    execution: Option<Box<dyn Fn(&Self, &Backend, u32) -> Result<()>>>,
+    named_shader_resources: Vec<(String, ID3D11ShaderResourceView)>,
+    named_sampler_states: Vec<(String, ID3D11SamplerState)>,
+    constant_buffer_slots: Vec<(String, u32)>,
+    external_resources: Vec<String>,
+    pixel_shader: Option<ShaderHandle>,
+    pixel_permutations: Option<Rc<ShaderPermutations>>,
+    pixel_variant: RefCell<VariantKey>,
+    vertex_shader: Option<ShaderHandle>,
+    execution: Option<Box<dyn Fn(&Self, &Backend, u32) -> EngineResult<()>>>,
     clear_rtv: bool,
 }
 
@@ -47,6 +66,16 @@ impl RenderPass {
         self
     }
 
+    pub fn blend_state(mut self, blend_state: ID3D11BlendState) -> Self {
+        self.blend_state = Some(blend_state);
+        self
+    }
+
+    pub fn rasterizer_state(mut self, rasterizer_state: ID3D11RasterizerState) -> Self {
+        self.rasterizer_state = Some(rasterizer_state);
+        self
+    }
+
     pub fn shader_resource(mut self, srv: ID3D11ShaderResourceView) -> Self {
         self.shader_resources.push(srv);
 
@@ -65,6 +94,36 @@ impl RenderPass {
         self
     }
 
+    /// Binds `srv` to whatever register the shaders declare `name` at.
+    pub fn named_shader_resource(mut self, name: &str, srv: ID3D11ShaderResourceView) -> Self {
+        self.named_shader_resources.push((name.to_owned(), srv));
+
+        self
+    }
+
+    /// Binds `sampler_state` to whatever register the shaders declare `name` at.
+    pub fn named_sampler_state(mut self, name: &str, sampler_state: ID3D11SamplerState) -> Self {
+        self.named_sampler_states
+            .push((name.to_owned(), sampler_state));
+
+        self
+    }
+
+    /// Constant buffers are bound by the backend rather than the pass, so
+    /// this only records the slot `name` is expected at for `binding_report`.
+    pub fn constant_buffer_slot(mut self, name: &str, slot: u32) -> Self {
+        self.constant_buffer_slots.push((name.to_owned(), slot));
+
+        self
+    }
+
+    /// Marks a resource as bound outside the pass, e.g. per object.
+    pub fn external_resource(mut self, name: &str) -> Self {
+        self.external_resources.push(name.to_owned());
+
+        self
+    }
+
     pub fn clear_rtv(mut self, clear_rtv: bool) -> Self {
         self.clear_rtv = clear_rtv;
 
@@ -72,52 +131,95 @@ impl RenderPass {
     }
 
     pub fn vertex_shader(
-        mut self,
+        self,
         backend: &Backend,
         shader: Shader,
-        input_element_desc: &[D3D11_INPUT_ELEMENT_DESC],
+        input_layout: &[InputElement],
         vertex_stride: u32,
-    ) -> Self {
-        if let Shader::Vertex(_, _) = shader {
-            self.vertex_shader = Some(shader);
+    ) -> EngineResult<Self> {
+        let handle = ShaderHandle::new(shader)?;
 
-            if let Some(Shader::Vertex(_, blob)) = &self.vertex_shader {
-                let input_layout = unsafe {
-                    backend
-                        .device
-                        .CreateInputLayout(
-                            input_element_desc.as_ptr(),
-                            input_element_desc.len() as u32,
-                            blob.GetBufferPointer(),
-                            blob.GetBufferSize(),
-                        )
-                        .expect("Create input layout for vertex shader")
-                };
-                self.input_desc = Some(input_layout);
-                self.vertex_stride = vertex_stride;
-            }
-        } else {
-            panic!("Attaching a non-vertex shader to the vertex shader slot");
+        self.vertex_shader_handle(backend, handle, input_layout, vertex_stride)
+    }
+
+    /// Like `vertex_shader`, but follows the handle when it is reloaded.
+    pub fn vertex_shader_handle(
+        mut self,
+        backend: &Backend,
+        handle: ShaderHandle,
+        input_layout: &[InputElement],
+        vertex_stride: u32,
+    ) -> EngineResult<Self> {
+        if handle.stage() != ShaderStage::Vertex {
+            return Err(EngineError::Validation(format!(
+                "attaching a {:?} shader to the vertex shader slot",
+                handle.stage()
+            )));
         }
-        self
+
+        let layout = backend
+            .input_layout(input_layout, &handle.get().shader)
+            .context("Create input layout for vertex shader")?;
+        *self.input_desc.get_mut() = Some((handle.generation(), layout));
+        self.input_layout = input_layout.to_vec();
+        self.vertex_stride = vertex_stride;
+        self.vertex_shader = Some(handle);
+        Ok(self)
     }
 
-    pub fn pixel_shader(mut self, shader: Shader) -> Self {
-        if let Shader::Pixel(_, _) = shader {
-            self.pixel_shader = Some(shader);
-        } else {
-            panic!("Attaching a non-pixel shader to the pixel shader slot");
+    pub fn pixel_shader(self, shader: Shader) -> EngineResult<Self> {
+        let handle = ShaderHandle::new(shader)?;
+
+        self.pixel_shader_handle(handle)
+    }
+
+    /// Like `pixel_shader`, but follows the handle when it is reloaded.
+    pub fn pixel_shader_handle(mut self, handle: ShaderHandle) -> EngineResult<Self> {
+        if handle.stage() != ShaderStage::Pixel {
+            return Err(EngineError::Validation(format!(
+                "attaching a {:?} shader to the pixel shader slot",
+                handle.stage()
+            )));
         }
-        self
+
+        self.pixel_shader = Some(handle);
+        Ok(self)
+    }
+
+    /// Uses a pixel shader with keywords. The default variant is compiled
+    /// now; others are compiled when `select_pixel_variant` first asks for them.
+    pub fn pixel_shader_permutations(
+        mut self,
+        backend: &Backend,
+        permutations: Rc<ShaderPermutations>,
+    ) -> EngineResult<Self> {
+        if permutations.stage() != ShaderStage::Pixel {
+            return Err(EngineError::Validation(format!(
+                "attaching {:?} shader permutations to the pixel shader slot",
+                permutations.stage()
+            )));
+        }
+
+        permutations.variant(backend, &VariantKey::new())?;
+        self.pixel_permutations = Some(permutations);
+        Ok(self)
     }
 
-    pub fn execution(mut self, func: Box<dyn Fn(&Self, &Backend, u32) -> Result<()>>) -> Self {
+    /// Picks the pixel shader variant used from the next `bind` on.
+    pub fn select_pixel_variant(&self, key: VariantKey) {
+        *self.pixel_variant.borrow_mut() = key;
+    }
+
+    pub fn execution(
+        mut self,
+        func: Box<dyn Fn(&Self, &Backend, u32) -> EngineResult<()>>,
+    ) -> Self {
         self.execution = Some(func);
 
         self
     }
 
-    pub fn clear(&self, backend: &Backend) -> Result<()> {
+    pub fn clear(&self, backend: &Backend) -> EngineResult<()> {
         for rtv in &self.render_targets {
             backend.clear_render_target_view(rtv, [0.0, 0.0, 0.0, 1.0]);
         }
@@ -131,7 +233,7 @@ impl RenderPass {
         Ok(())
     }
 
-    pub fn bind(&self, backend: &Backend) -> Result<()> {
+    pub fn bind(&self, backend: &Backend) -> EngineResult<()> {
         let depth_attachment = if self.depth_attachment.bind_depth_buffer {
             &self.depth_attachment.depth_view
         } else {
@@ -150,14 +252,41 @@ impl RenderPass {
             }
         }
 
-        if let Some(Shader::Pixel(s, _)) = &self.pixel_shader {
+        unsafe {
+            backend
+                .device_context
+                .OMSetBlendState(&self.blend_state, std::ptr::null(), 0xFFFFFFFF);
+            backend.device_context.RSSetState(&self.rasterizer_state);
+        }
+
+        // Clone out of the handles so a reload between frames can't change
+        // the shader halfway through binding.
+        let pixel = match (&self.pixel_permutations, &self.pixel_shader) {
+            (Some(permutations), _) => {
+                let variant = permutations.variant(backend, &self.pixel_variant.borrow())?;
+                Some((variant.shader, variant.reflection))
+            }
+            (None, Some(handle)) => {
+                let loaded = handle.get();
+                Some((loaded.shader.clone(), loaded.reflection.clone()))
+            }
+            (None, None) => None,
+        };
+        let vertex_reflection = self
+            .vertex_shader
+            .as_ref()
+            .map(|handle| handle.get().reflection.clone());
+
+        if let Some((Shader::Pixel(s, _), _)) = &pixel {
             unsafe {
                 backend.device_context.PSSetShader(s, std::ptr::null(), 0);
             }
         }
-        if let Some(Shader::Vertex(s, _)) = &self.vertex_shader {
-            unsafe {
-                backend.device_context.VSSetShader(s, std::ptr::null(), 0);
+        if let Some(handle) = &self.vertex_shader {
+            if let Shader::Vertex(s, _) = &handle.get().shader {
+                unsafe {
+                    backend.device_context.VSSetShader(s, std::ptr::null(), 0);
+                }
             }
         }
 
@@ -168,7 +297,14 @@ impl RenderPass {
         backend.set_pixel_shader_attachments(&self.shader_resources, 0);
         backend.set_vertex_shader_attachments(&self.shader_resources, 0);
 
-        if let Some(layout) = &self.input_desc {
+        self.bind_named(
+            backend,
+            pixel.as_ref().map(|(_, reflection)| reflection),
+            vertex_reflection.as_ref(),
+        );
+
+        self.update_input_layout(backend);
+        if let Some((_, layout)) = &*self.input_desc.borrow() {
             unsafe {
                 backend.device_context.IASetInputLayout(layout);
             }
@@ -177,20 +313,198 @@ impl RenderPass {
         Ok(())
     }
 
-    pub fn execute(&self, backend: &Backend, num_vertices: u32) -> Result<()> {
-        debug_assert!(self.execution.is_some());
-        if let Some(func) = &self.execution {
-            self.bind(backend)?;
+    /// Recreates the input layout after the vertex shader was reloaded. If
+    /// the new shader doesn't match the layout the old one stays bound.
+    fn update_input_layout(&self, backend: &Backend) {
+        let handle = match &self.vertex_shader {
+            Some(handle) => handle,
+            None => return,
+        };
+
+        let generation = handle.generation();
+        let mut input_desc = self.input_desc.borrow_mut();
+        if matches!(&*input_desc, Some((current, _)) if *current == generation) {
+            return;
+        }
+
+        match backend.input_layout(&self.input_layout, &handle.get().shader) {
+            Ok(layout) => *input_desc = Some((generation, layout)),
+            Err(err) => {
+                eprintln!(
+                    "Keeping previous input layout, reloaded vertex shader does not match it: {}",
+                    err
+                );
+                if let Some((current, _)) = &mut *input_desc {
+                    *current = generation;
+                }
+            }
+        }
+    }
+
+    fn bind_named(
+        &self,
+        backend: &Backend,
+        pixel_reflection: Option<&ShaderReflection>,
+        vertex_reflection: Option<&ShaderReflection>,
+    ) {
+        let register = |reflection: Option<&ShaderReflection>, name: &str, class: char| {
+            reflection
+                .and_then(|reflection| reflection.find(name))
+                .filter(|binding| binding.kind.register_class() == class)
+                .map(|binding| binding.register)
+        };
 
-            if self.clear_rtv {
-                self.clear(backend)?;
+        for (name, srv) in &self.named_shader_resources {
+            let srv = Some(srv.clone());
+            unsafe {
+                if let Some(slot) = register(pixel_reflection, name, 't') {
+                    backend.device_context.PSSetShaderResources(slot, 1, &srv);
+                }
+                if let Some(slot) = register(vertex_reflection, name, 't') {
+                    backend.device_context.VSSetShaderResources(slot, 1, &srv);
+                }
             }
+        }
 
-            return func(self, backend, num_vertices);
-        } else {
-            Err(Error::fast_error(windows::core::HRESULT::from_win32(
-                0x80004005,
-            )))
+        for (name, sampler_state) in &self.named_sampler_states {
+            let sampler_state = Some(sampler_state.clone());
+            unsafe {
+                if let Some(slot) = register(pixel_reflection, name, 's') {
+                    backend
+                        .device_context
+                        .PSSetSamplers(slot, 1, &sampler_state);
+                }
+                if let Some(slot) = register(vertex_reflection, name, 's') {
+                    backend
+                        .device_context
+                        .VSSetSamplers(slot, 1, &sampler_state);
+                }
+            }
+        }
+    }
+
+    /// Compares what the pass binds against what its shaders declare.
+    pub fn binding_report(&self, backend: &Backend) -> BindingReport {
+        let mut reflection = ShaderReflection::default();
+        if let Some(handle) = &self.vertex_shader {
+            reflection.merge(&handle.get().reflection);
+        }
+        match (&self.pixel_permutations, &self.pixel_shader) {
+            (Some(permutations), _) => {
+                // A variant that fails to compile still declares its resources.
+                let pixel_reflection = permutations
+                    .variant(backend, &self.pixel_variant.borrow())
+                    .map(|variant| variant.reflection)
+                    .or_else(|_| permutations.source_reflection());
+                if let Ok(pixel_reflection) = pixel_reflection {
+                    reflection.merge(&pixel_reflection);
+                }
+            }
+            (None, Some(handle)) => reflection.merge(&handle.get().reflection),
+            (None, None) => {}
+        }
+
+        let mut report = BindingReport::default();
+
+        for binding in &reflection.bindings {
+            let name = binding.name.as_str();
+            let bound = match binding.kind.register_class() {
+                'b' => {
+                    if let Some((_, slot)) = self
+                        .constant_buffer_slots
+                        .iter()
+                        .find(|(n, slot)| n == name && *slot != binding.register)
+                    {
+                        report.misplaced.push((binding.clone(), *slot));
+                    }
+                    true
+                }
+                't' => {
+                    binding.register < self.shader_resources.len() as u32
+                        || self.named_shader_resources.iter().any(|(n, _)| n == name)
+                }
+                's' => {
+                    binding.register < self.sampler_states.len() as u32
+                        || self.named_sampler_states.iter().any(|(n, _)| n == name)
+                }
+                _ => false,
+            };
+
+            if !bound && !self.external_resources.iter().any(|n| n == name) {
+                report.missing.push(binding.clone());
+            }
+        }
+
+        let expected = |name: &str, kind: fn(&ResourceKind) -> bool| {
+            reflection
+                .find(name)
+                .is_some_and(|binding| kind(&binding.kind))
+        };
+
+        for (name, _) in &self.named_shader_resources {
+            if !expected(name, ResourceKind::is_shader_resource) {
+                report.unknown.push(name.clone());
+            }
+        }
+        for (name, _) in &self.named_sampler_states {
+            if !expected(name, |kind| *kind == ResourceKind::Sampler) {
+                report.unknown.push(name.clone());
+            }
+        }
+        for (name, _) in &self.constant_buffer_slots {
+            if !expected(name, |kind| *kind == ResourceKind::ConstantBuffer) {
+                report.unknown.push(name.clone());
+            }
+        }
+
+        report
+    }
+
+    pub fn execute(&self, backend: &Backend, num_vertices: u32) -> EngineResult<()> {
+        let func = self.execution.as_ref().ok_or_else(|| {
+            EngineError::Validation("render pass has no execution function".to_owned())
+        })?;
+
+        self.bind(backend)?;
+
+        if self.clear_rtv {
+            self.clear(backend)?;
+        }
+
+        func(self, backend, num_vertices)
+    }
+}
+
+/// Mismatches between the resources a `RenderPass` binds and the ones its
+/// shaders declare.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BindingReport {
+    /// Declared by a shader but never bound.
+    pub missing: Vec<ResourceBinding>,
+    /// Bound by name but not declared by any shader.
+    pub unknown: Vec<String>,
+    /// Constant buffers declared at a different register than expected.
+    pub misplaced: Vec<(ResourceBinding, u32)>,
+}
+
+impl BindingReport {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.unknown.is_empty() && self.misplaced.is_empty()
+    }
+}
+
+impl fmt::Display for BindingReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for binding in &self.missing {
+            writeln!(f, "missing: {}", binding)?;
         }
+        for name in &self.unknown {
+            writeln!(f, "unknown: {}", name)?;
+        }
+        for (binding, slot) in &self.misplaced {
+            writeln!(f, "misplaced: {}, expected at b{}", binding, slot)?;
+        }
+
+        Ok(())
     }
 }
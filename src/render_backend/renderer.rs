@@ -1,24 +1,35 @@
 use std::collections::HashMap;
 
-use windows::Win32::Foundation::*;
 use windows::Win32::Graphics::{Direct3D::*, Direct3D11::*, Dxgi::Common::*};
 
 use crate::{
+    error::{Context, EngineResult},
     object::{Flag, GameObject},
     scene::Scene,
 };
 
 use super::mesh::{GpuMesh, Vertex};
-use super::shader::Shader;
+use super::shader::ShaderStage;
+use super::shader_cache::ShaderSource;
+use super::shader_library::ShaderLibrary;
+use super::shader_permutation::VariantKey;
+use super::shader_preprocessor::ShaderPreprocessor;
 use super::{
-    backend::Backend,
+    backend::{Backend, FRAME_CONSTANTS, OBJECT_CONSTANTS},
+    pipeline_state::{DepthStencilDesc, SamplerDesc},
     render_pass::RenderPass,
     texture::{Tex, Tex2D, TextureDescBuilder},
 };
 use crate::atmosphere::precompute::{precompute_textures, AtmosphericConstants};
 
 pub trait Renderer {
-    fn render(&self, backend: &Backend, scene: &mut Scene, time: usize, delta_time: usize);
+    fn render(
+        &self,
+        backend: &Backend,
+        scene: &mut Scene,
+        time: usize,
+        delta_time: usize,
+    ) -> EngineResult<()>;
 }
 
 pub struct GBufferRTV {
@@ -34,10 +45,35 @@ pub struct BasicRenderer {
     pub combination_pass: RenderPass,
     pub backbuffer_rtv: ID3D11RenderTargetView,
     pub gbuffer: GBufferRTV,
+    pub shaders: ShaderLibrary,
 }
 
 impl BasicRenderer {
-    pub fn new(backend: &Backend, width: u32, height: u32) -> BasicRenderer {
+    /// Every shader `new` loads, for `precompile_shaders`.
+    pub const SHADERS: &'static [ShaderSource] = &[
+        ShaderSource {
+            stage: ShaderStage::Vertex,
+            path: "gbuffer.hlsl",
+            entry_point: "vertex",
+        },
+        ShaderSource {
+            stage: ShaderStage::Pixel,
+            path: "gbuffer.hlsl",
+            entry_point: "pixel",
+        },
+        ShaderSource {
+            stage: ShaderStage::Vertex,
+            path: "vertex_shader.hlsl",
+            entry_point: "main",
+        },
+        ShaderSource {
+            stage: ShaderStage::Pixel,
+            path: "fragment_shader.hlsl",
+            entry_point: "main",
+        },
+    ];
+
+    pub fn new(backend: &Backend, width: u32, height: u32) -> EngineResult<BasicRenderer> {
         let viewport = D3D11_VIEWPORT {
             TopLeftX: 0.0,
             TopLeftY: 0.0,
@@ -49,11 +85,11 @@ impl BasicRenderer {
         };
 
         unsafe { backend.device_context.RSSetViewports(1, &viewport) }
-        let backbuffer = backend.backbuffer(0).expect("Get backbuffer texture");
+        let backbuffer = backend.backbuffer(0).context("Get backbuffer texture")?;
 
         let backbuffer_rtv = backend
             .render_target_view(&backbuffer, None)
-            .expect("Create backbuffer rtv");
+            .context("Create backbuffer rtv")?;
 
         unsafe {
             backend
@@ -61,33 +97,9 @@ impl BasicRenderer {
                 .IASetPrimitiveTopology(D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST)
         }
 
-        let depth_stencil_desc = D3D11_DEPTH_STENCIL_DESC {
-            DepthEnable: true.into(),
-            DepthWriteMask: D3D11_DEPTH_WRITE_MASK_ALL,
-            DepthFunc: D3D11_COMPARISON_LESS,
-            StencilEnable: true.into(),
-            StencilReadMask: 0xFF,
-            StencilWriteMask: 0xFF,
-            FrontFace: D3D11_DEPTH_STENCILOP_DESC {
-                StencilFailOp: D3D11_STENCIL_OP_KEEP,
-                StencilDepthFailOp: D3D11_STENCIL_OP_INCR,
-                StencilPassOp: D3D11_STENCIL_OP_KEEP,
-                StencilFunc: D3D11_COMPARISON_ALWAYS,
-            },
-            BackFace: D3D11_DEPTH_STENCILOP_DESC {
-                StencilFailOp: D3D11_STENCIL_OP_KEEP,
-                StencilDepthFailOp: D3D11_STENCIL_OP_DECR,
-                StencilPassOp: D3D11_STENCIL_OP_KEEP,
-                StencilFunc: D3D11_COMPARISON_ALWAYS,
-            },
-        };
-
-        let depth_stencil_state = unsafe {
-            backend
-                .device
-                .CreateDepthStencilState(&depth_stencil_desc)
-                .expect("Create depth stencil state")
-        };
+        let depth_stencil_state = backend
+            .depth_stencil_state(&DepthStencilDesc::depth_less_stencil_count())
+            .context("Create depth stencil state")?;
 
         let depth_texture = Tex2D::new(
             &backend,
@@ -98,11 +110,11 @@ impl BasicRenderer {
                 .bind_flags(D3D11_BIND_DEPTH_STENCIL)
                 .build_texture2d(),
         )
-        .expect("Create depth texture");
+        .context("Create depth texture")?;
 
         let depth_stencil_view = backend
             .depth_stencil_view(&depth_texture, None)
-            .expect("Create depth stencil view");
+            .context("Create depth stencil view")?;
 
         let position_texture = Tex2D::new(
             &backend,
@@ -113,13 +125,13 @@ impl BasicRenderer {
                 .bind_flags(D3D11_BIND_SHADER_RESOURCE | D3D11_BIND_RENDER_TARGET)
                 .build_texture2d(),
         )
-        .expect("Creating position texture");
+        .context("Create position texture")?;
         let position_rtv = backend
             .render_target_view(&position_texture, None)
-            .expect("Create position rtv");
+            .context("Create position rtv")?;
         let position_srv = backend
             .shader_resource_view(&position_texture, None)
-            .expect("Create position srv");
+            .context("Create position srv")?;
 
         let albedo_texture = Tex2D::new(
             &backend,
@@ -130,13 +142,13 @@ impl BasicRenderer {
                 .bind_flags(D3D11_BIND_SHADER_RESOURCE | D3D11_BIND_RENDER_TARGET)
                 .build_texture2d(),
         )
-        .expect("Creating albedo texture");
+        .context("Create albedo texture")?;
         let albedo_rtv = backend
             .render_target_view(&albedo_texture, None)
-            .expect("Create albedo rtv");
+            .context("Create albedo rtv")?;
         let albedo_srv = backend
             .shader_resource_view(&albedo_texture, None)
-            .expect("Create albedo srv");
+            .context("Create albedo srv")?;
 
         let normal_texture = Tex2D::new(
             &backend,
@@ -147,64 +159,35 @@ impl BasicRenderer {
                 .bind_flags(D3D11_BIND_SHADER_RESOURCE | D3D11_BIND_RENDER_TARGET)
                 .build_texture2d(),
         )
-        .expect("Creating normal texture");
+        .context("Create normal texture")?;
         let normal_rtv = backend
             .render_target_view(&normal_texture, None)
-            .expect("Create normal rtv");
+            .context("Create normal rtv")?;
 
         let normal_srv = backend
             .shader_resource_view(&normal_texture, None)
-            .expect("Create normal srv");
-
-        let sampler_state_desc = D3D11_SAMPLER_DESC {
-            Filter: D3D11_FILTER_MIN_MAG_MIP_LINEAR,
-            AddressU: D3D11_TEXTURE_ADDRESS_WRAP,
-            AddressV: D3D11_TEXTURE_ADDRESS_WRAP,
-            AddressW: D3D11_TEXTURE_ADDRESS_WRAP,
-            MipLODBias: 0.0,
-            MaxAnisotropy: 1,
-            ComparisonFunc: D3D11_COMPARISON_ALWAYS,
-            BorderColor: [0.0, 0.0, 0.0, 0.0],
-            MinLOD: 0.0,
-            MaxLOD: D3D11_FLOAT32_MAX,
-        };
-
-        let sampler_state = unsafe {
-            backend
-                .device
-                .CreateSamplerState(&sampler_state_desc)
-                .expect("Creating sampler")
-        };
-
-        let mesh_input_desc = [
-            D3D11_INPUT_ELEMENT_DESC {
-                SemanticName: PSTR(b"POSITION\0".as_ptr() as _),
-                SemanticIndex: 0,
-                Format: DXGI_FORMAT_R32G32B32_FLOAT,
-                InputSlot: 0,
-                AlignedByteOffset: 0,
-                InputSlotClass: D3D11_INPUT_PER_VERTEX_DATA,
-                InstanceDataStepRate: 0,
-            },
-            D3D11_INPUT_ELEMENT_DESC {
-                SemanticName: PSTR(b"NORMAL\0".as_ptr() as _),
-                SemanticIndex: 0,
-                Format: DXGI_FORMAT_R32G32B32_FLOAT,
-                InputSlot: 0,
-                AlignedByteOffset: 12,
-                InputSlotClass: D3D11_INPUT_PER_VERTEX_DATA,
-                InstanceDataStepRate: 0,
-            },
-            D3D11_INPUT_ELEMENT_DESC {
-                SemanticName: PSTR(b"TEXCOORD\0".as_ptr() as _),
-                SemanticIndex: 0,
-                Format: DXGI_FORMAT_R32G32_FLOAT,
-                InputSlot: 0,
-                AlignedByteOffset: 24,
-                InputSlotClass: D3D11_INPUT_PER_VERTEX_DATA,
-                InstanceDataStepRate: 0,
-            },
-        ];
+            .context("Create normal srv")?;
+
+        let sampler_state = backend
+            .sampler_state(&SamplerDesc::linear_wrap())
+            .context("Create sampler")?;
+
+        let shaders = ShaderLibrary::new();
+
+        let gbuffer_vertex = shaders.load(
+            backend,
+            ShaderStage::Vertex,
+            ShaderPreprocessor::new(),
+            "gbuffer.hlsl",
+            "vertex",
+        )?;
+        let gbuffer_pixel = shaders.load(
+            backend,
+            ShaderStage::Pixel,
+            ShaderPreprocessor::new(),
+            "gbuffer.hlsl",
+            "pixel",
+        )?;
 
         let gbuffer_write_pass = RenderPass::new()
             .enable_depth(true)
@@ -213,19 +196,18 @@ impl BasicRenderer {
             .render_target(position_rtv.clone())
             .render_target(albedo_rtv.clone())
             .render_target(normal_rtv.clone())
-            .sampler_state(sampler_state.clone())
+            .named_sampler_state("Sampler", sampler_state.clone())
+            .constant_buffer_slot("FrameConstants", FRAME_CONSTANTS)
+            .constant_buffer_slot("ModelConstants", OBJECT_CONSTANTS)
+            .external_resource("albedoTex")
             .clear_rtv(true)
-            .vertex_shader(
-                &backend,
-                Shader::vertex_shader(&backend, "gbuffer.hlsl", "vertex")
-                    .expect("Create vertex shader"),
-                &mesh_input_desc,
+            .vertex_shader_handle(
+                backend,
+                gbuffer_vertex,
+                &Vertex::LAYOUT,
                 std::mem::size_of::<Vertex>() as u32,
-            )
-            .pixel_shader(
-                Shader::pixel_shader(&backend, "gbuffer.hlsl", "pixel")
-                    .expect("Create pixel shader"),
-            )
+            )?
+            .pixel_shader_handle(gbuffer_pixel)?
             .execution(Box::new(move |_, backend, num_vertices: u32| {
                 unsafe {
                     backend.device_context.DrawIndexed(num_vertices, 0, 0);
@@ -235,43 +217,48 @@ impl BasicRenderer {
             }));
 
         let consts = AtmosphericConstants::default();
-        let (transmittance, irradiance, inscatter) = precompute_textures(backend, consts);
+        let (transmittance, irradiance, inscatter) = precompute_textures(backend, consts)?;
 
         let transmittance_srv = backend
             .shader_resource_view(&transmittance, None)
-            .expect("Create transmittance srv");
+            .context("Create transmittance srv")?;
 
         let irradiance_srv = backend
             .shader_resource_view(&irradiance, None)
-            .expect("Create irradiance srv");
+            .context("Create irradiance srv")?;
 
         let inscatter_srv = backend
             .shader_resource_view(&inscatter, None)
-            .expect("Create inscatter srv");
+            .context("Create inscatter srv")?;
+
+        let combination_vertex = shaders.load(
+            backend,
+            ShaderStage::Vertex,
+            ShaderPreprocessor::new(),
+            "vertex_shader.hlsl",
+            "main",
+        )?;
+        let combination_pixel = shaders.load_permutations(
+            ShaderStage::Pixel,
+            ShaderPreprocessor::new(),
+            "fragment_shader.hlsl",
+            "main",
+        )?;
 
         let gbuffer_combination_pass = RenderPass::new()
             .enable_depth(true)
             .depth_state(depth_stencil_state.clone())
-            .shader_resource(position_srv)
-            .shader_resource(albedo_srv)
-            .shader_resource(normal_srv)
-            .shader_resource(transmittance_srv)
-            .shader_resource(irradiance_srv)
-            .shader_resource(inscatter_srv)
-            .sampler_state(sampler_state)
+            .named_shader_resource("pos", position_srv)
+            .named_shader_resource("albedo", albedo_srv)
+            .named_shader_resource("normal", normal_srv)
+            .named_shader_resource("transmittance", transmittance_srv)
+            .named_shader_resource("irradiance", irradiance_srv)
+            .named_shader_resource("inscatter", inscatter_srv)
+            .named_sampler_state("Sampler", sampler_state)
             .render_target(backbuffer_rtv.clone())
             .clear_rtv(true)
-            .vertex_shader(
-                &backend,
-                Shader::vertex_shader(&backend, "vertex_shader.hlsl", "main")
-                    .expect("Create vertex shader"),
-                &[],
-                0,
-            )
-            .pixel_shader(
-                Shader::pixel_shader(&backend, "fragment_shader.hlsl", "main")
-                    .expect("Creating pixel shader"),
-            )
+            .vertex_shader_handle(backend, combination_vertex, &[], 0)?
+            .pixel_shader_permutations(backend, combination_pixel)?
             .execution(Box::new(move |_, backend, _| {
                 unsafe {
                     backend.device_context.Draw(6, 0);
@@ -280,7 +267,17 @@ impl BasicRenderer {
                 Ok(())
             }));
 
-        BasicRenderer {
+        for (name, pass) in [
+            ("gbuffer", &gbuffer_write_pass),
+            ("combination", &gbuffer_combination_pass),
+        ] {
+            let report = pass.binding_report(backend);
+            if !report.is_empty() {
+                eprintln!("Binding mismatches in {} pass:\n{}", name, report);
+            }
+        }
+
+        Ok(BasicRenderer {
             depth_stencil_view,
             backbuffer_rtv,
             gbuffer_write_pass,
@@ -291,40 +288,51 @@ impl BasicRenderer {
                 normal: normal_rtv,
             },
             depth_state: depth_stencil_state,
-        }
+            shaders,
+        })
+    }
+}
+
+impl BasicRenderer {
+    /// Shows a G-buffer channel instead of the lit image. `view` is one of the
+    /// `DEBUG_VIEW` values declared in `fragment_shader.hlsl`, e.g. `VIEW_NORMAL`.
+    pub fn set_debug_view(&self, view: &str) {
+        self.combination_pass
+            .select_pixel_variant(VariantKey::new().set("DEBUG_VIEW", view));
     }
 }
 
 impl Renderer for BasicRenderer {
-    fn render(&self, backend: &Backend, scene: &mut Scene, time: usize, delta_time: usize) {
-        scene.camera.bind(backend);
+    fn render(
+        &self,
+        backend: &Backend,
+        scene: &mut Scene,
+        time: usize,
+        delta_time: usize,
+    ) -> EngineResult<()> {
+        let report = self.shaders.reload_changed(backend);
+        if !report.is_empty() {
+            eprint!("{}", report);
+        }
+
+        scene.camera.bind(backend)?;
 
-        let mut opaque_objects: Vec<_> = scene
+        let opaque_objects = scene
             .objects
             .iter_mut()
-            .filter(|obj| obj.flags().contains(&Flag::Opaque))
-            .collect();
+            .filter(|obj| obj.flags().contains(&Flag::Opaque));
 
-        opaque_objects.iter_mut().for_each(|object| {
+        for object in opaque_objects {
             if let Some(mesh) = object.mesh() {
                 backend.unbind_shader_resources();
-                object.bind(backend);
+                object.bind(backend)?;
 
-                self.gbuffer_write_pass
-                    .execute(backend, mesh.num_indices)
-                    .expect("Execute gbuffer pass")
+                self.gbuffer_write_pass.execute(backend, mesh.num_indices)?;
             }
-        });
+        }
 
-        self.combination_pass
-            .execute(backend, 6)
-            .expect("Combine gbuffer pass");
+        self.combination_pass.execute(backend, 6)?;
 
-        unsafe {
-            backend
-                .swap_chain
-                .Present(1, 0)
-                .expect("Presenting swapchain");
-        }
+        unsafe { backend.swap_chain.Present(1, 0) }.context("Present swapchain")
     }
 }
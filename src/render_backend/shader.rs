@@ -1,23 +1,160 @@
 use windows::core::*;
+use windows::Win32::Foundation::E_POINTER;
 use windows::Win32::Graphics::Direct3D::{Fxc::*, ID3DBlob};
 use windows::Win32::Graphics::Direct3D11::*;
 
+use std::ffi::c_void;
 use std::ffi::CStr;
+use std::fmt;
 
 use super::backend::Backend;
+use super::shader_cache::ShaderCache;
+use super::shader_diagnostics::{map_to_original, parse_diagnostics, ShaderCompileError};
+use super::shader_permutation::PermutationError;
+use super::shader_preprocessor::{PreprocessError, ShaderPreprocessor};
+use super::shader_reflection::ShaderReflection;
 
-fn compile_shader(path: &str, entry_point: &str, target: &str) -> Result<ID3DBlob> {
-    let flags = D3DCOMPILE_DEBUG | D3DCOMPILE_SKIP_OPTIMIZATION;
-    //let flags = if cfg!(debug_assertions) {
-    //    D3DCOMPILE_DEBUG | D3DCOMPILE_SKIP_OPTIMIZATION
-    //} else {
-    //    0
-    //};
+pub enum ShaderError {
+    Preprocess(PreprocessError),
+    Permutation(PermutationError),
+    Compile(ShaderCompileError),
+    /// Failures from D3D itself, e.g. creating the shader object.
+    Device(Error),
+    /// A read-only `ShaderCache` has no entry for the shader.
+    NotPrecompiled {
+        path: String,
+        entry_point: String,
+    },
+}
 
-    let mut shader_blob = None;
+pub type ShaderResult<T> = std::result::Result<T, ShaderError>;
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderError::Preprocess(err) => write!(f, "{}", err),
+            ShaderError::Permutation(err) => write!(f, "{}", err),
+            ShaderError::Compile(err) => write!(f, "{}", err),
+            ShaderError::Device(err) => write!(f, "{}", err),
+            ShaderError::NotPrecompiled { path, entry_point } => write!(
+                f,
+                "{} ({}) is not in the shader cache, run with --precompile-shaders",
+                path, entry_point
+            ),
+        }
+    }
+}
+
+// Shaders are mostly created with `.expect(...)` at startup, so Debug
+// prints the rendered diagnostics rather than the nested structs.
+impl fmt::Debug for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+impl From<PreprocessError> for ShaderError {
+    fn from(err: PreprocessError) -> Self {
+        ShaderError::Preprocess(err)
+    }
+}
+
+impl From<PermutationError> for ShaderError {
+    fn from(err: PermutationError) -> Self {
+        ShaderError::Permutation(err)
+    }
+}
+
+impl From<ShaderCompileError> for ShaderError {
+    fn from(err: ShaderCompileError) -> Self {
+        ShaderError::Compile(err)
+    }
+}
+
+impl From<Error> for ShaderError {
+    fn from(err: Error) -> Self {
+        ShaderError::Device(err)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderStage {
+    Vertex,
+    Pixel,
+    Compute,
+}
+
+impl ShaderStage {
+    pub fn target(&self) -> &'static str {
+        match self {
+            ShaderStage::Vertex => "vs_5_0",
+            ShaderStage::Pixel => "ps_5_0",
+            ShaderStage::Compute => "cs_5_0",
+        }
+    }
+}
 
+fn blob_to_string(blob: &ID3DBlob) -> String {
+    String::from_utf8_lossy(blob_bytes(blob)).into_owned()
+}
+
+fn blob_from_bytes(bytes: &[u8]) -> Result<ID3DBlob> {
     unsafe {
-        D3DCompileFromFile(
+        let blob = D3DCreateBlob(bytes.len())?;
+        std::ptr::copy_nonoverlapping(
+            bytes.as_ptr(),
+            blob.GetBufferPointer() as *mut u8,
+            bytes.len(),
+        );
+
+        Ok(blob)
+    }
+}
+
+fn blob_bytes(blob: &ID3DBlob) -> &[u8] {
+    unsafe {
+        std::slice::from_raw_parts(blob.GetBufferPointer() as *const u8, blob.GetBufferSize())
+    }
+}
+
+/// Compiles `path` with the flags of the cache's profile, or loads the
+/// result of an identical earlier compile from the cache.
+pub fn compile_shader(
+    cache: &ShaderCache,
+    preprocessor: &ShaderPreprocessor,
+    path: &str,
+    entry_point: &str,
+    target: &str,
+) -> ShaderResult<ID3DBlob> {
+    let preprocessed = preprocessor.preprocess(path)?;
+
+    let key = cache.key(
+        &preprocessed.source,
+        preprocessor.defines(),
+        entry_point,
+        target,
+    );
+    if let Some(bytecode) = cache.load(key) {
+        return Ok(blob_from_bytes(&bytecode)?);
+    }
+    if cache.is_read_only() {
+        return Err(ShaderError::NotPrecompiled {
+            path: path.to_owned(),
+            entry_point: entry_point.to_owned(),
+        });
+    }
+
+    let flags = cache.profile().flags();
+
+    let mut shader_blob = None;
+    let mut error_blob = None;
+
+    let result = unsafe {
+        D3DCompile(
+            preprocessed.source.as_ptr() as *const c_void,
+            preprocessed.source.len(),
             path,
             std::ptr::null(),
             None,
@@ -26,11 +163,35 @@ fn compile_shader(path: &str, entry_point: &str, target: &str) -> Result<ID3DBlo
             flags,
             0,
             &mut shader_blob,
-            std::ptr::null_mut(),
-        )?
-    }
+            &mut error_blob,
+        )
+    };
+
+    let mut diagnostics = error_blob
+        .map(|blob| parse_diagnostics(&blob_to_string(&blob)))
+        .unwrap_or_default();
+    map_to_original(&mut diagnostics, &preprocessed);
+
+    match (result, shader_blob) {
+        (Ok(()), Some(shader_blob)) => {
+            for warning in &diagnostics {
+                eprint!("{}", warning);
+            }
 
-    shader_blob.ok_or_else(|| Error::fast_error(HRESULT::from_win32(0x80070057)))
+            if let Err(err) = cache.store(key, blob_bytes(&shader_blob)) {
+                eprintln!("Failed to cache {} ({}): {}", path, entry_point, err);
+            }
+
+            Ok(shader_blob)
+        }
+        (Err(err), _) if diagnostics.is_empty() => Err(ShaderError::Device(err)),
+        _ => Err(ShaderCompileError {
+            path: path.to_owned(),
+            entry_point: entry_point.to_owned(),
+            diagnostics,
+        }
+        .into()),
+    }
 }
 
 #[derive(Clone)]
@@ -41,45 +202,131 @@ pub enum Shader {
 }
 
 impl Shader {
-    pub fn pixel_shader(backend: &Backend, path: &str, entry_point: &str) -> Result<Shader> {
-        let shader_blob = compile_shader(path, entry_point, "ps_5_0")?;
+    /// Compiles `path` for `stage`, resolving includes and defines through `preprocessor`.
+    pub fn compile(
+        backend: &Backend,
+        stage: ShaderStage,
+        preprocessor: &ShaderPreprocessor,
+        path: &str,
+        entry_point: &str,
+    ) -> ShaderResult<Shader> {
+        let shader_blob = compile_shader(
+            &backend.shader_cache,
+            preprocessor,
+            path,
+            entry_point,
+            stage.target(),
+        )?;
+
+        Self::from_blob(backend, stage, shader_blob)
+    }
+
+    pub fn from_blob(
+        backend: &Backend,
+        stage: ShaderStage,
+        shader_blob: ID3DBlob,
+    ) -> ShaderResult<Shader> {
+        let (bytecode, size) =
+            unsafe { (shader_blob.GetBufferPointer(), shader_blob.GetBufferSize()) };
 
         let shader = unsafe {
-            backend.device.CreatePixelShader(
-                shader_blob.GetBufferPointer(),
-                shader_blob.GetBufferSize(),
-                None,
-            )?
+            match stage {
+                ShaderStage::Vertex => Shader::Vertex(
+                    backend.device.CreateVertexShader(bytecode, size, None)?,
+                    shader_blob,
+                ),
+                ShaderStage::Pixel => Shader::Pixel(
+                    backend.device.CreatePixelShader(bytecode, size, None)?,
+                    shader_blob,
+                ),
+                ShaderStage::Compute => Shader::Compute(
+                    backend.device.CreateComputeShader(bytecode, size, None)?,
+                    shader_blob,
+                ),
+            }
         };
 
-        Ok(Shader::Pixel(shader, shader_blob))
+        Ok(shader)
     }
 
-    pub fn vertex_shader(backend: &Backend, path: &str, entry_point: &str) -> Result<Shader> {
-        let shader_blob = compile_shader(path, entry_point, "vs_5_0")?;
+    pub fn pixel_shader(backend: &Backend, path: &str, entry_point: &str) -> ShaderResult<Shader> {
+        Self::compile(
+            backend,
+            ShaderStage::Pixel,
+            &ShaderPreprocessor::new(),
+            path,
+            entry_point,
+        )
+    }
 
-        let shader = unsafe {
-            backend.device.CreateVertexShader(
-                shader_blob.GetBufferPointer(),
-                shader_blob.GetBufferSize(),
-                None,
-            )?
-        };
+    pub fn vertex_shader(backend: &Backend, path: &str, entry_point: &str) -> ShaderResult<Shader> {
+        Self::compile(
+            backend,
+            ShaderStage::Vertex,
+            &ShaderPreprocessor::new(),
+            path,
+            entry_point,
+        )
+    }
 
-        Ok(Shader::Vertex(shader, shader_blob))
+    pub fn compute_shader(
+        backend: &Backend,
+        path: &str,
+        entry_point: &str,
+    ) -> ShaderResult<Shader> {
+        Self::compile(
+            backend,
+            ShaderStage::Compute,
+            &ShaderPreprocessor::new(),
+            path,
+            entry_point,
+        )
     }
 
-    pub fn compute_shader(backend: &Backend, path: &str, entry_point: &str) -> Result<Shader> {
-        let shader_blob = compile_shader(path, entry_point, "cs_5_0")?;
+    pub fn stage(&self) -> ShaderStage {
+        match self {
+            Shader::Vertex(_, _) => ShaderStage::Vertex,
+            Shader::Pixel(_, _) => ShaderStage::Pixel,
+            Shader::Compute(_, _) => ShaderStage::Compute,
+        }
+    }
 
-        let shader = unsafe {
-            backend.device.CreateComputeShader(
-                shader_blob.GetBufferPointer(),
-                shader_blob.GetBufferSize(),
-                None,
-            )?
-        };
+    pub fn blob(&self) -> &ID3DBlob {
+        match self {
+            Shader::Vertex(_, blob) | Shader::Pixel(_, blob) | Shader::Compute(_, blob) => blob,
+        }
+    }
+
+    pub fn reflection(&self) -> ShaderResult<ID3D11ShaderReflection> {
+        let blob = self.blob();
+        let mut reflection: Option<ID3D11ShaderReflection> = None;
+
+        unsafe {
+            D3DReflect(
+                blob.GetBufferPointer(),
+                blob.GetBufferSize(),
+                &ID3D11ShaderReflection::IID,
+                &mut reflection as *mut _ as *mut *mut c_void,
+            )?;
+        }
+
+        reflection.ok_or_else(|| Error::from(E_POINTER).into())
+    }
+
+    /// The `[numthreads(x, y, z)]` declared by a compute shader.
+    pub fn thread_group_size(&self) -> ShaderResult<[u32; 3]> {
+        let reflection = self.reflection()?;
+
+        let mut size = [0u32; 3];
+        unsafe {
+            reflection.GetThreadGroupSize(&mut size[0], &mut size[1], &mut size[2]);
+        }
+
+        Ok(size)
+    }
 
-        Ok(Shader::Compute(shader, shader_blob))
+    /// Resource names, types and registers of the compiled shader.
+    pub fn reflect(&self) -> ShaderResult<ShaderReflection> {
+        ShaderReflection::from_d3d11(&self.reflection()?)
     }
 }
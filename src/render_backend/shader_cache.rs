@@ -0,0 +1,318 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use windows::Win32::Graphics::Direct3D::Fxc::*;
+
+use super::shader::{compile_shader, ShaderResult, ShaderStage};
+use super::shader_permutation::{all_variants, parse_keywords};
+use super::shader_preprocessor::ShaderPreprocessor;
+
+/// Bump when anything that affects compiled output changes outside of the
+/// inputs hashed by `ShaderCache::key`, e.g. the compiler.
+const CACHE_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CompileProfile {
+    /// Debug info, no optimization.
+    Debug,
+    Release,
+}
+
+impl CompileProfile {
+    /// `Debug` for debug builds of the engine, `Release` otherwise.
+    pub fn for_build() -> CompileProfile {
+        if cfg!(debug_assertions) {
+            CompileProfile::Debug
+        } else {
+            CompileProfile::Release
+        }
+    }
+
+    pub fn flags(&self) -> u32 {
+        match self {
+            CompileProfile::Debug => D3DCOMPILE_DEBUG | D3DCOMPILE_SKIP_OPTIMIZATION,
+            CompileProfile::Release => D3DCOMPILE_OPTIMIZATION_LEVEL3,
+        }
+    }
+}
+
+/// Compiled shader bytecode on disk, keyed by a hash of everything that
+/// goes into a compile. Editing a shader or one of its includes changes the
+/// key, so stale entries are never used; they are just left behind.
+///
+/// A read-only cache never compiles: a miss is an error. Builds with the
+/// `shipping` feature use one, so shaders must be precompiled with
+/// `--precompile-shaders`.
+#[derive(Clone, Debug)]
+pub struct ShaderCache {
+    dir: Option<PathBuf>,
+    profile: CompileProfile,
+    read_only: bool,
+}
+
+impl Default for ShaderCache {
+    fn default() -> Self {
+        ShaderCache::new(Self::default_dir(), CompileProfile::for_build())
+            .read_only(cfg!(feature = "shipping"))
+    }
+}
+
+impl ShaderCache {
+    pub fn new(dir: impl Into<PathBuf>, profile: CompileProfile) -> ShaderCache {
+        ShaderCache {
+            dir: Some(dir.into()),
+            profile,
+            read_only: false,
+        }
+    }
+
+    /// `shader_cache` next to the executable, so it is found from any
+    /// working directory.
+    pub fn default_dir() -> PathBuf {
+        std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|dir| dir.join("shader_cache")))
+            .unwrap_or_else(|| PathBuf::from("shader_cache"))
+    }
+
+    /// Always compiles and stores nothing.
+    pub fn disabled(profile: CompileProfile) -> ShaderCache {
+        ShaderCache {
+            dir: None,
+            profile,
+            read_only: false,
+        }
+    }
+
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+
+        self
+    }
+
+    pub fn profile(&self) -> CompileProfile {
+        self.profile
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    pub fn key(
+        &self,
+        source: &str,
+        defines: &[(String, String)],
+        entry_point: &str,
+        target: &str,
+    ) -> u64 {
+        let mut hasher = Fnv1a::new();
+        hasher.write(&CACHE_VERSION.to_le_bytes());
+        hasher.write(&self.profile.flags().to_le_bytes());
+        hasher.write_str(target);
+        hasher.write_str(entry_point);
+        for (name, value) in defines {
+            hasher.write_str(name);
+            hasher.write_str(value);
+        }
+        hasher.write_str(source);
+
+        hasher.finish()
+    }
+
+    fn entry_path(&self, key: u64) -> Option<PathBuf> {
+        self.dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{:016x}.cso", key)))
+    }
+
+    pub fn load(&self, key: u64) -> Option<Vec<u8>> {
+        fs::read(self.entry_path(key)?).ok()
+    }
+
+    /// Writes through a temporary file so a crash can't leave a truncated entry.
+    /// Does nothing for a disabled or read-only cache.
+    pub fn store(&self, key: u64, bytecode: &[u8]) -> io::Result<()> {
+        let (dir, path) = match (&self.dir, self.entry_path(key)) {
+            (Some(dir), Some(path)) if !self.read_only => (dir, path),
+            _ => return Ok(()),
+        };
+
+        fs::create_dir_all(dir)?;
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, bytecode)?;
+        fs::rename(&temp, &path)
+    }
+}
+
+/// 64-bit FNV-1a. Unlike `DefaultHasher` its output is stable across Rust
+/// versions, which matters for keys that end up on disk.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Fnv1a {
+        Fnv1a(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    /// Length-prefixed, so `("ab", "c")` and `("a", "bc")` hash differently.
+    fn write_str(&mut self, s: &str) {
+        self.write(&(s.len() as u64).to_le_bytes());
+        self.write(s.as_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// A shader entry point the engine loads at runtime.
+#[derive(Clone, Copy, Debug)]
+pub struct ShaderSource {
+    pub stage: ShaderStage,
+    pub path: &'static str,
+    pub entry_point: &'static str,
+}
+
+/// Compiles every variant of every shader in `shaders` into `cache`.
+/// Returns the number of variants compiled.
+pub fn precompile_shaders(cache: &ShaderCache, shaders: &[ShaderSource]) -> ShaderResult<usize> {
+    let mut count = 0;
+
+    for source in shaders {
+        let preprocessor = ShaderPreprocessor::new();
+        let preprocessed = preprocessor.preprocess(source.path)?;
+        let keywords = parse_keywords(preprocessed.keywords.iter().map(String::as_str))?;
+
+        for key in all_variants(&keywords) {
+            let preprocessor = key
+                .defines()
+                .fold(preprocessor.clone(), |preprocessor, (name, value)| {
+                    preprocessor.define(name, value)
+                });
+
+            compile_shader(
+                cache,
+                &preprocessor,
+                source.path,
+                source.entry_point,
+                source.stage.target(),
+            )?;
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        dir
+    }
+
+    #[test]
+    fn keys_cover_every_input() {
+        let cache = ShaderCache::disabled(CompileProfile::Debug);
+        let define = |name: &str, value: &str| vec![(name.to_owned(), value.to_owned())];
+        let defines = define("LUT_SIZE", "32");
+        let base = cache.key("float4 main();", &defines, "main", "ps_5_0");
+
+        assert_eq!(
+            cache.key("float4 main();", &defines, "main", "ps_5_0"),
+            base
+        );
+        assert_ne!(
+            cache.key("float4 main2();", &defines, "main", "ps_5_0"),
+            base
+        );
+        assert_ne!(cache.key("float4 main();", &[], "main", "ps_5_0"), base);
+        let other_value = define("LUT_SIZE", "64");
+        assert_ne!(
+            cache.key("float4 main();", &other_value, "main", "ps_5_0"),
+            base
+        );
+        assert_ne!(
+            cache.key("float4 main();", &defines, "ps_main", "ps_5_0"),
+            base
+        );
+        assert_ne!(
+            cache.key("float4 main();", &defines, "main", "vs_5_0"),
+            base
+        );
+
+        let release = ShaderCache::disabled(CompileProfile::Release);
+        assert_ne!(
+            release.key("float4 main();", &defines, "main", "ps_5_0"),
+            base
+        );
+
+        // Strings are length-prefixed, so moving a character between a
+        // define's name and value changes the key.
+        assert_ne!(
+            cache.key("", &define("AB", "C"), "main", "ps_5_0"),
+            cache.key("", &define("A", "BC"), "main", "ps_5_0")
+        );
+    }
+
+    #[test]
+    fn stores_and_loads_entries() {
+        let dir = temp_dir("shader_cache_round_trip");
+        let cache = ShaderCache::new(dir.join("nested"), CompileProfile::Debug);
+        let key = cache.key("float4 main();", &[], "main", "ps_5_0");
+
+        assert_eq!(cache.load(key), None);
+        cache.store(key, &[1, 2, 3]).unwrap();
+        assert_eq!(cache.load(key), Some(vec![1, 2, 3]));
+        cache.store(key, &[4, 5]).unwrap();
+        assert_eq!(cache.load(key), Some(vec![4, 5]));
+        assert_eq!(cache.load(key ^ 1), None);
+
+        // Only the entry itself is left behind, no temporary file.
+        let files: Vec<_> = fs::read_dir(dir.join("nested"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, [format!("{:016x}.cso", key).as_str()]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_only_caches_never_write() {
+        let dir = temp_dir("shader_cache_read_only");
+        let cache = ShaderCache::new(&dir, CompileProfile::Release).read_only(true);
+        assert!(cache.is_read_only());
+
+        cache.store(1, &[1, 2, 3]).unwrap();
+        assert!(!dir.exists());
+
+        // Entries precompiled by a writable cache are still read.
+        ShaderCache::new(&dir, CompileProfile::Release)
+            .store(1, &[1, 2, 3])
+            .unwrap();
+        cache.store(1, &[4, 5]).unwrap();
+        assert_eq!(cache.load(1), Some(vec![1, 2, 3]));
+
+        let disabled = ShaderCache::disabled(CompileProfile::Release);
+        disabled.store(1, &[4, 5]).unwrap();
+        assert_eq!(disabled.load(1), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
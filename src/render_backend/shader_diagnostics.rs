@@ -0,0 +1,368 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use super::shader_preprocessor::PreprocessedShader;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// One error or warning reported by the shader compiler.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Compiler message code such as `X3004`.
+    pub code: Option<String>,
+    pub message: String,
+    /// `None` for diagnostics that aren't tied to a line, e.g. a missing entry point.
+    pub file: Option<PathBuf>,
+    /// 1-based; 0 when unknown.
+    pub line: usize,
+    /// 1-based; 0 when unknown.
+    pub column: usize,
+    /// The offending source line, once mapped back through the preprocessor.
+    pub snippet: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.severity)?;
+        if let Some(code) = &self.code {
+            write!(f, " {}", code)?;
+        }
+        writeln!(f, ": {}", self.message)?;
+
+        if let Some(file) = &self.file {
+            write!(f, "  --> {}", file.display())?;
+            if self.line > 0 {
+                write!(f, ":{}", self.line)?;
+                if self.column > 0 {
+                    write!(f, ":{}", self.column)?;
+                }
+            }
+            writeln!(f)?;
+        }
+
+        if let Some(snippet) = &self.snippet {
+            let gutter = " ".repeat(self.line.to_string().len());
+            writeln!(f, "{} |", gutter)?;
+            writeln!(f, "{} | {}", self.line, snippet)?;
+            if self.column > 0 {
+                // Keep tabs so the caret lines up with the snippet above it.
+                let indent: String = snippet
+                    .chars()
+                    .take(self.column - 1)
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+                writeln!(f, "{} | {}^", gutter, indent)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses the text fxc writes to its error blob, e.g.
+///
+/// ```text
+/// shader.hlsl(12,5-9): error X3004: undeclared identifier 'foo'
+/// shader.hlsl(40,12): warning X3206: implicit truncation of vector type
+/// error X3501: 'main': entrypoint not found
+/// ```
+///
+/// Lines that don't start a diagnostic are appended to the previous message.
+pub fn parse_diagnostics(output: &str) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    for line in output.lines() {
+        let line = line.trim_end_matches('\0').trim_end();
+        if line.is_empty() || line.starts_with("compilation failed") {
+            continue;
+        }
+
+        match parse_line(line) {
+            Some(diagnostic) => diagnostics.push(diagnostic),
+            None => {
+                if let Some(previous) = diagnostics.last_mut() {
+                    previous.message.push('\n');
+                    previous.message.push_str(line.trim());
+                }
+            }
+        }
+    }
+
+    diagnostics
+}
+
+fn parse_line(line: &str) -> Option<Diagnostic> {
+    let (location, severity, rest) = split_severity(line)?;
+
+    let (code, message) = match rest.split_once(": ") {
+        Some((code, message)) if is_message_code(code) => (Some(code.to_owned()), message),
+        _ => (None, rest),
+    };
+
+    let (file, line, column) = match location {
+        Some(location) => parse_location(location),
+        None => (None, 0, 0),
+    };
+
+    Some(Diagnostic {
+        severity,
+        code,
+        message: message.trim().to_owned(),
+        file,
+        line,
+        column,
+        snippet: None,
+    })
+}
+
+/// Splits `location: error rest` or `error rest`.
+fn split_severity(line: &str) -> Option<(Option<&str>, Severity, &str)> {
+    const SEVERITIES: [(&str, Severity); 2] =
+        [("error ", Severity::Error), ("warning ", Severity::Warning)];
+
+    for (word, severity) in SEVERITIES {
+        if let Some(rest) = line.strip_prefix(word) {
+            return Some((None, severity, rest));
+        }
+    }
+
+    SEVERITIES
+        .iter()
+        .filter_map(|(word, severity)| {
+            let marker = format!(": {}", word);
+            line.find(&marker)
+                .map(|index| (index, marker.len(), *severity))
+        })
+        .min_by_key(|(index, _, _)| *index)
+        .map(|(index, len, severity)| (Some(&line[..index]), severity, &line[index + len..]))
+}
+
+fn is_message_code(code: &str) -> bool {
+    let mut chars = code.chars();
+    chars.next().is_some_and(|c| c.is_ascii_uppercase()) && chars.all(|c| c.is_ascii_digit())
+}
+
+/// Splits `file(line,column-end)` into its parts; the range end is dropped.
+fn parse_location(location: &str) -> (Option<PathBuf>, usize, usize) {
+    let parsed = location.strip_suffix(')').and_then(|location| {
+        let open = location.rfind('(')?;
+        let mut numbers = location[open + 1..].split(',');
+
+        let line = numbers.next()?.trim().parse().ok()?;
+        let column = numbers
+            .next()
+            .and_then(|column| column.split('-').next())
+            .and_then(|column| column.trim().parse().ok())
+            .unwrap_or(0);
+
+        Some((PathBuf::from(&location[..open]), line, column))
+    });
+
+    match parsed {
+        Some((file, line, column)) => (Some(file), line, column),
+        None => (Some(PathBuf::from(location)), 0, 0),
+    }
+}
+
+/// Rewrites locations in the flattened source the compiler saw into the
+/// file and line they came from, and attaches the source line.
+pub fn map_to_original(diagnostics: &mut [Diagnostic], preprocessed: &PreprocessedShader) {
+    for diagnostic in diagnostics {
+        if diagnostic.file.is_none() || diagnostic.line == 0 {
+            continue;
+        }
+
+        diagnostic.snippet = preprocessed
+            .source
+            .lines()
+            .nth(diagnostic.line - 1)
+            .map(str::to_owned);
+
+        if let Some(location) = preprocessed.original_location(diagnostic.line) {
+            diagnostic.file = Some(location.file.clone());
+            diagnostic.line = location.line;
+        }
+    }
+}
+
+/// The diagnostics of a failed compile.
+#[derive(Clone, Debug)]
+pub struct ShaderCompileError {
+    pub path: String,
+    pub entry_point: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl fmt::Display for ShaderCompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "failed to compile {} ({})", self.path, self.entry_point)?;
+        for diagnostic in &self.diagnostics {
+            write!(f, "{}", diagnostic)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ShaderCompileError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_backend::shader_preprocessor::{ShaderPreprocessor, SourceLocation};
+
+    #[test]
+    fn parses_compiler_output() {
+        let diagnostics = parse_diagnostics(
+            "C:\\shaders\\shader.hlsl(12,5-9): error X3004: undeclared identifier 'foo'\n\
+             shader.hlsl(40,12): warning X3206: implicit truncation of vector type\n\
+             error X3501: 'main': entrypoint not found\n\
+             \n\
+             compilation failed; no code produced\0",
+        );
+
+        assert_eq!(diagnostics.len(), 3);
+
+        let error = &diagnostics[0];
+        assert!(error.is_error());
+        assert_eq!(error.code.as_deref(), Some("X3004"));
+        assert_eq!(error.message, "undeclared identifier 'foo'");
+        assert_eq!(error.file, Some(PathBuf::from("C:\\shaders\\shader.hlsl")));
+        assert_eq!((error.line, error.column), (12, 5));
+
+        let warning = &diagnostics[1];
+        assert_eq!(warning.severity, Severity::Warning);
+        assert_eq!(warning.code.as_deref(), Some("X3206"));
+        assert_eq!((warning.line, warning.column), (40, 12));
+
+        let entry_point = &diagnostics[2];
+        assert_eq!(entry_point.file, None);
+        assert_eq!(entry_point.line, 0);
+        assert_eq!(entry_point.code.as_deref(), Some("X3501"));
+        assert_eq!(entry_point.message, "'main': entrypoint not found");
+    }
+
+    #[test]
+    fn parses_locations_without_columns_or_codes() {
+        let diagnostics = parse_diagnostics(
+            "shader.hlsl(7): error X3000: syntax error\n\
+             memory(1,1): warning some message: with a colon\n\
+             \x20   continued on the next line",
+        );
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (7, 0));
+        assert_eq!(diagnostics[0].message, "syntax error");
+
+        assert_eq!(diagnostics[1].code, None);
+        assert_eq!(
+            diagnostics[1].message,
+            "some message: with a colon\ncontinued on the next line"
+        );
+    }
+
+    #[test]
+    fn maps_lines_through_the_source_map() {
+        let location = |file: &str, line| {
+            Some(SourceLocation {
+                file: PathBuf::from(file),
+                line,
+            })
+        };
+        let preprocessed = PreprocessedShader {
+            source: "#define A 1\nfloat x;\nfloat y = foo;\n".to_owned(),
+            dependencies: Vec::new(),
+            source_map: vec![None, location("main.hlsl", 1), location("common.hlsl", 9)],
+            keywords: Vec::new(),
+        };
+
+        let mut diagnostics = parse_diagnostics(
+            "memory(3,11): error X3004: undeclared identifier 'foo'\n\
+             memory(1,1): warning X1000: generated\n\
+             error X3501: 'main': entrypoint not found",
+        );
+        map_to_original(&mut diagnostics, &preprocessed);
+
+        assert_eq!(diagnostics[0].file, Some(PathBuf::from("common.hlsl")));
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (9, 11));
+        assert_eq!(diagnostics[0].snippet.as_deref(), Some("float y = foo;"));
+
+        // Generated lines keep the location the compiler reported.
+        assert_eq!(diagnostics[1].file, Some(PathBuf::from("memory")));
+        assert_eq!(diagnostics[1].line, 1);
+        assert_eq!(diagnostics[1].snippet.as_deref(), Some("#define A 1"));
+
+        assert_eq!(diagnostics[2].snippet, None);
+    }
+
+    #[test]
+    fn maps_lines_of_included_files() {
+        let dir = std::env::temp_dir().join(format!("shader_diagnostics_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("common.hlsl"),
+            "#pragma once\nfloat3 tonemap(float3 c)\n{\n    return c / (1 + c);\n}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("main.hlsl"),
+            "#include \"common.hlsl\"\n#include \"common.hlsl\"\n\nfloat4 main() : SV_Target\n{\n    return foo;\n}\n",
+        )
+        .unwrap();
+
+        let preprocessed = ShaderPreprocessor::new()
+            .define("QUALITY", "2")
+            .preprocess(dir.join("main.hlsl"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        let preprocessed = preprocessed.unwrap();
+
+        // The define, the included file once, then main.hlsl after its
+        // includes, which leave no lines behind.
+        let line = preprocessed
+            .source
+            .lines()
+            .position(|line| line.contains("return foo"))
+            .unwrap()
+            + 1;
+        assert_eq!(line, 1 + 5 + 4);
+
+        let mut diagnostics = parse_diagnostics(&format!(
+            "memory({},12-14): error X3004: undeclared identifier 'foo'\n\
+             memory(4,5): warning X3206: implicit truncation",
+            line
+        ));
+        map_to_original(&mut diagnostics, &preprocessed);
+
+        let error = &diagnostics[0];
+        assert!(error.file.as_ref().unwrap().ends_with("main.hlsl"));
+        assert_eq!((error.line, error.column), (6, 12));
+        assert_eq!(error.snippet.as_deref(), Some("    return foo;"));
+
+        let warning = &diagnostics[1];
+        assert!(warning.file.as_ref().unwrap().ends_with("common.hlsl"));
+        assert_eq!(warning.line, 3);
+
+        assert!(error.to_string().contains("main.hlsl:6:12\n"));
+        assert!(error.to_string().ends_with("  |            ^\n"));
+    }
+}
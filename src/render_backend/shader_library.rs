@@ -0,0 +1,326 @@
+use std::cell::{Ref, RefCell};
+use std::fmt;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::vfs;
+
+use super::backend::Backend;
+use super::file_watcher::FileWatcher;
+use super::shader::{Shader, ShaderError, ShaderResult, ShaderStage};
+use super::shader_permutation::ShaderPermutations;
+use super::shader_preprocessor::ShaderPreprocessor;
+use super::shader_reflection::ShaderReflection;
+
+pub struct LoadedShader {
+    pub shader: Shader,
+    pub reflection: ShaderReflection,
+    /// Bumped every time the shader is replaced.
+    pub generation: u64,
+}
+
+/// A shader shared between the passes using it and the `ShaderLibrary` that
+/// reloads it. Replacing it swaps the shader and its reflection together, so
+/// passes never see one without the other.
+#[derive(Clone)]
+pub struct ShaderHandle(Rc<RefCell<LoadedShader>>);
+
+impl ShaderHandle {
+    pub fn new(shader: Shader) -> ShaderResult<ShaderHandle> {
+        let reflection = shader.reflect()?;
+
+        Ok(ShaderHandle(Rc::new(RefCell::new(LoadedShader {
+            shader,
+            reflection,
+            generation: 0,
+        }))))
+    }
+
+    pub fn get(&self) -> Ref<'_, LoadedShader> {
+        self.0.borrow()
+    }
+
+    pub fn shader(&self) -> Shader {
+        self.0.borrow().shader.clone()
+    }
+
+    pub fn stage(&self) -> ShaderStage {
+        self.0.borrow().shader.stage()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.0.borrow().generation
+    }
+
+    pub fn replace(&self, shader: Shader) -> ShaderResult<()> {
+        let reflection = shader.reflect()?;
+
+        let mut loaded = self.0.borrow_mut();
+        loaded.shader = shader;
+        loaded.reflection = reflection;
+        loaded.generation += 1;
+
+        Ok(())
+    }
+}
+
+enum Entry {
+    Single {
+        handle: ShaderHandle,
+        stage: ShaderStage,
+        preprocessor: ShaderPreprocessor,
+        path: String,
+        entry_point: String,
+    },
+    Permutations(Rc<ShaderPermutations>),
+}
+
+impl Entry {
+    fn name(&self) -> String {
+        match self {
+            Entry::Single {
+                path, entry_point, ..
+            } => format!("{} ({})", path, entry_point),
+            Entry::Permutations(permutations) => permutations.path().to_owned(),
+        }
+    }
+
+    /// The files preprocessing reads now, which for a shader that fails to
+    /// preprocess is as far as it gets.
+    fn dependencies(&self) -> Vec<PathBuf> {
+        match self {
+            Entry::Single {
+                preprocessor, path, ..
+            } => preprocessor.dependencies(path),
+            Entry::Permutations(permutations) => permutations
+                .preprocessor()
+                .dependencies(permutations.path()),
+        }
+    }
+}
+
+/// Loads shaders and recompiles them when their source or any of their
+/// includes change. A shader that fails to recompile keeps running the
+/// previous version.
+#[derive(Default)]
+pub struct ShaderLibrary {
+    entries: RefCell<Vec<Entry>>,
+    watcher: RefCell<FileWatcher<usize>>,
+}
+
+impl ShaderLibrary {
+    pub fn new() -> ShaderLibrary {
+        Default::default()
+    }
+
+    pub fn load(
+        &self,
+        backend: &Backend,
+        stage: ShaderStage,
+        preprocessor: ShaderPreprocessor,
+        path: &str,
+        entry_point: &str,
+    ) -> ShaderResult<ShaderHandle> {
+        let dependencies = preprocessor.preprocess(path)?.dependencies;
+        let shader = Shader::compile(backend, stage, &preprocessor, path, entry_point)?;
+        let handle = ShaderHandle::new(shader)?;
+
+        let mut entries = self.entries.borrow_mut();
+        self.watch(entries.len(), dependencies);
+        entries.push(Entry::Single {
+            handle: handle.clone(),
+            stage,
+            preprocessor,
+            path: path.to_owned(),
+            entry_point: entry_point.to_owned(),
+        });
+
+        Ok(handle)
+    }
+
+    pub fn load_permutations(
+        &self,
+        stage: ShaderStage,
+        preprocessor: ShaderPreprocessor,
+        path: &str,
+        entry_point: &str,
+    ) -> ShaderResult<Rc<ShaderPermutations>> {
+        let permutations = Rc::new(ShaderPermutations::new(
+            stage,
+            preprocessor,
+            path,
+            entry_point,
+        )?);
+
+        let mut entries = self.entries.borrow_mut();
+        self.watch(entries.len(), permutations.dependencies());
+        entries.push(Entry::Permutations(permutations.clone()));
+
+        Ok(permutations)
+    }
+
+    /// Recompiles every shader whose files changed since the last call.
+    /// Checks the file system at most every poll interval of the watcher.
+    pub fn reload_changed(&self, backend: &Backend) -> ReloadReport {
+        let changed = self.watcher.borrow_mut().changed();
+        self.reload(backend, changed)
+    }
+
+    /// Recompiles every shader regardless of whether it changed.
+    pub fn reload_all(&self, backend: &Backend) -> ReloadReport {
+        let all = (0..self.entries.borrow().len()).collect();
+        self.reload(backend, all)
+    }
+
+    fn reload(&self, backend: &Backend, mut indices: Vec<usize>) -> ReloadReport {
+        indices.sort_unstable();
+
+        let entries = self.entries.borrow();
+        let mut report = ReloadReport::default();
+
+        for index in indices {
+            let entry = &entries[index];
+            match reload_entry(backend, entry) {
+                Ok(dependencies) => {
+                    // Includes may have been added or removed.
+                    self.watch(index, dependencies);
+                    report.reloaded.push(entry.name());
+                }
+                Err(err) => {
+                    // The broken version may include files the previous one
+                    // didn't, and fixing any of them should retry it.
+                    self.watch(index, entry.dependencies());
+                    report.failed.push((entry.name(), err));
+                }
+            }
+        }
+
+        report
+    }
+
+    fn watch(&self, index: usize, dependencies: Vec<PathBuf>) {
+        self.watcher
+            .borrow_mut()
+            .watch(index, on_disk(dependencies));
+    }
+}
+
+/// Maps VFS paths to the files behind them. Files in archives or compiled
+/// into the executable can't change, so they aren't watched.
+fn on_disk(dependencies: Vec<PathBuf>) -> Vec<PathBuf> {
+    dependencies
+        .iter()
+        .filter_map(|dependency| vfs::real_path(&dependency.to_string_lossy()))
+        .collect()
+}
+
+fn reload_entry(backend: &Backend, entry: &Entry) -> ShaderResult<Vec<PathBuf>> {
+    match entry {
+        Entry::Single {
+            handle,
+            stage,
+            preprocessor,
+            path,
+            entry_point,
+        } => {
+            let dependencies = preprocessor.preprocess(path)?.dependencies;
+            let shader = Shader::compile(backend, *stage, preprocessor, path, entry_point)?;
+            handle.replace(shader)?;
+
+            Ok(dependencies)
+        }
+        Entry::Permutations(permutations) => {
+            permutations.reload(backend)?;
+
+            Ok(permutations.dependencies())
+        }
+    }
+}
+
+/// What `ShaderLibrary::reload_changed` did. Failed shaders keep running
+/// their previous version.
+#[derive(Debug, Default)]
+pub struct ReloadReport {
+    pub reloaded: Vec<String>,
+    pub failed: Vec<(String, ShaderError)>,
+}
+
+impl ReloadReport {
+    pub fn is_empty(&self) -> bool {
+        self.reloaded.is_empty() && self.failed.is_empty()
+    }
+}
+
+impl fmt::Display for ReloadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for name in &self.reloaded {
+            writeln!(f, "reloaded {}", name)?;
+        }
+        for (name, err) in &self.failed {
+            writeln!(
+                f,
+                "failed to reload {}, keeping previous version:\n{}",
+                name, err
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    fn touch(file: &Path, seconds: u64) {
+        fs::File::options()
+            .write(true)
+            .open(file)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+            .unwrap();
+    }
+
+    #[test]
+    fn failed_reloads_watch_the_files_they_got_through() {
+        let dir = std::env::temp_dir().join(format!("shader_library_{}", std::process::id()));
+        let dir = PathBuf::from(vfs::normalize(&dir.to_string_lossy()).unwrap());
+        fs::create_dir_all(&dir).unwrap();
+        let (root, a, b) = (
+            dir.join("root.hlsl"),
+            dir.join("a.hlsli"),
+            dir.join("b.hlsli"),
+        );
+        fs::write(&root, "#include \"a.hlsli\"\n").unwrap();
+        fs::write(&a, "float a;\n").unwrap();
+        fs::write(&b, "float b;\n").unwrap();
+
+        let library = ShaderLibrary::new();
+        library
+            .load_permutations(
+                ShaderStage::Pixel,
+                ShaderPreprocessor::new(),
+                &root.to_string_lossy(),
+                "main",
+            )
+            .unwrap();
+
+        // An edit that adds an include along with one that doesn't resolve.
+        fs::write(&root, "#include \"b.hlsli\"\n#include \"missing.hlsli\"\n").unwrap();
+        let entries = library.entries.borrow();
+        let dependencies = entries[0].dependencies();
+        assert_eq!(dependencies, [root.clone(), b.clone()]);
+
+        library.watch(0, dependencies);
+        touch(&a, 1);
+        assert!(library.watcher.borrow_mut().check_now().is_empty());
+        touch(&b, 1);
+        assert_eq!(library.watcher.borrow_mut().check_now(), [0]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
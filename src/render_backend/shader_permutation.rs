@@ -0,0 +1,560 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::PathBuf;
+
+use super::backend::Backend;
+use super::pipeline_state::get_or_create;
+use super::shader::{Shader, ShaderResult, ShaderStage};
+use super::shader_preprocessor::ShaderPreprocessor;
+use super::shader_reflection::ShaderReflection;
+
+/// A feature a shader can be compiled with, declared in HLSL as
+///
+/// ```hlsl
+/// #pragma keyword AERIAL_PERSPECTIVE
+/// #pragma keyword LUT_SIZE 32 64
+/// ```
+///
+/// A keyword without values is a toggle, defined as `1` when enabled and
+/// left undefined otherwise. A keyword with values is always defined, to the
+/// first value unless a variant picks another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keyword {
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+impl Keyword {
+    /// Parses the arguments of a `#pragma keyword` line.
+    pub fn parse(declaration: &str) -> Result<Keyword, PermutationError> {
+        let mut parts = declaration.split_whitespace();
+        let name = parts
+            .next()
+            .filter(|name| is_identifier(name))
+            .ok_or_else(|| PermutationError::InvalidDeclaration(declaration.to_owned()))?;
+
+        let values: Vec<String> = parts.map(str::to_owned).collect();
+        if values
+            .iter()
+            .enumerate()
+            .any(|(i, value)| values[..i].contains(value))
+        {
+            return Err(PermutationError::InvalidDeclaration(declaration.to_owned()));
+        }
+
+        Ok(Keyword {
+            name: name.to_owned(),
+            values,
+        })
+    }
+
+    pub fn is_toggle(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Number of variants this keyword contributes.
+    pub fn variant_count(&self) -> usize {
+        if self.is_toggle() {
+            2
+        } else {
+            self.values.len()
+        }
+    }
+}
+
+pub fn parse_keywords<'a>(
+    declarations: impl IntoIterator<Item = &'a str>,
+) -> Result<Vec<Keyword>, PermutationError> {
+    let mut keywords: Vec<Keyword> = Vec::new();
+    for declaration in declarations {
+        let keyword = Keyword::parse(declaration)?;
+        if keywords.iter().any(|other| other.name == keyword.name) {
+            return Err(PermutationError::DuplicateKeyword(keyword.name));
+        }
+        keywords.push(keyword);
+    }
+
+    Ok(keywords)
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The define set of one shader variant. Ordered, so equal sets hash equally
+/// regardless of the order keywords were set in.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VariantKey(BTreeMap<String, String>);
+
+impl VariantKey {
+    pub fn new() -> VariantKey {
+        Default::default()
+    }
+
+    /// Turns on a toggle keyword.
+    pub fn enable(self, name: &str) -> Self {
+        self.set(name, "1")
+    }
+
+    pub fn set(mut self, name: &str, value: &str) -> Self {
+        self.0.insert(name.to_owned(), value.to_owned());
+
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    pub fn defines(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+impl fmt::Display for VariantKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, value)) in self.defines().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}={}", name, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PermutationError {
+    InvalidDeclaration(String),
+    DuplicateKeyword(String),
+    UnknownKeyword(String),
+    InvalidValue {
+        keyword: String,
+        value: String,
+        allowed: Vec<String>,
+    },
+}
+
+impl fmt::Display for PermutationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PermutationError::InvalidDeclaration(declaration) => {
+                write!(f, "invalid keyword declaration `{}`", declaration)
+            }
+            PermutationError::DuplicateKeyword(name) => {
+                write!(f, "keyword `{}` is declared twice", name)
+            }
+            PermutationError::UnknownKeyword(name) => {
+                write!(f, "shader does not declare keyword `{}`", name)
+            }
+            PermutationError::InvalidValue {
+                keyword,
+                value,
+                allowed,
+            } => write!(
+                f,
+                "`{}` is not a value of keyword `{}`, expected one of {:?}",
+                value, keyword, allowed
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PermutationError {}
+
+/// Checks `key` against the declared keywords and fills in the default of
+/// every valued keyword it leaves out, so equivalent keys compare equal.
+pub fn resolve_variant(
+    keywords: &[Keyword],
+    key: &VariantKey,
+) -> Result<VariantKey, PermutationError> {
+    for (name, _) in key.defines() {
+        if !keywords.iter().any(|keyword| keyword.name == name) {
+            return Err(PermutationError::UnknownKeyword(name.to_owned()));
+        }
+    }
+
+    let mut resolved = VariantKey::new();
+    for keyword in keywords {
+        let value = key.get(&keyword.name);
+        let allowed = if keyword.is_toggle() {
+            vec!["0".to_owned(), "1".to_owned()]
+        } else {
+            keyword.values.clone()
+        };
+
+        match value {
+            Some(value) if !allowed.iter().any(|allowed| allowed == value) => {
+                return Err(PermutationError::InvalidValue {
+                    keyword: keyword.name.clone(),
+                    value: value.to_owned(),
+                    allowed,
+                });
+            }
+            Some("0") if keyword.is_toggle() => {}
+            Some(value) => resolved = resolved.set(&keyword.name, value),
+            None if keyword.is_toggle() => {}
+            None => resolved = resolved.set(&keyword.name, &keyword.values[0]),
+        }
+    }
+
+    Ok(resolved)
+}
+
+/// Every resolved variant the keywords allow.
+pub fn all_variants(keywords: &[Keyword]) -> Vec<VariantKey> {
+    keywords
+        .iter()
+        .fold(vec![VariantKey::new()], |keys, keyword| {
+            let values: Vec<Option<&str>> = if keyword.is_toggle() {
+                vec![None, Some("1")]
+            } else {
+                keyword
+                    .values
+                    .iter()
+                    .map(|value| Some(value.as_str()))
+                    .collect()
+            };
+
+            keys.iter()
+                .flat_map(|key| {
+                    values.iter().map(move |value| match value {
+                        Some(value) => key.clone().set(&keyword.name, value),
+                        None => key.clone(),
+                    })
+                })
+                .collect()
+        })
+}
+
+#[derive(Clone)]
+pub struct ShaderVariant {
+    pub shader: Shader,
+    pub reflection: ShaderReflection,
+}
+
+/// All variants of one shader entry point. Variants are compiled the first
+/// time they are asked for and cached by their resolved `VariantKey`.
+pub struct ShaderPermutations {
+    stage: ShaderStage,
+    preprocessor: ShaderPreprocessor,
+    path: String,
+    entry_point: String,
+    keywords: RefCell<Vec<Keyword>>,
+    dependencies: RefCell<Vec<PathBuf>>,
+    variants: RefCell<HashMap<VariantKey, ShaderVariant>>,
+}
+
+impl ShaderPermutations {
+    /// Reads the keywords `path` and its includes declare. Nothing is
+    /// compiled until a variant is requested.
+    pub fn new(
+        stage: ShaderStage,
+        preprocessor: ShaderPreprocessor,
+        path: &str,
+        entry_point: &str,
+    ) -> ShaderResult<ShaderPermutations> {
+        let preprocessed = preprocessor.preprocess(path)?;
+        let keywords = parse_keywords(preprocessed.keywords.iter().map(String::as_str))?;
+
+        Ok(ShaderPermutations {
+            stage,
+            preprocessor,
+            path: path.to_owned(),
+            entry_point: entry_point.to_owned(),
+            keywords: RefCell::new(keywords),
+            dependencies: RefCell::new(preprocessed.dependencies),
+            variants: RefCell::new(HashMap::new()),
+        })
+    }
+
+    pub fn stage(&self) -> ShaderStage {
+        self.stage
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn preprocessor(&self) -> &ShaderPreprocessor {
+        &self.preprocessor
+    }
+
+    pub fn keywords(&self) -> Vec<Keyword> {
+        self.keywords.borrow().clone()
+    }
+
+    /// The source file and every file it includes.
+    pub fn dependencies(&self) -> Vec<PathBuf> {
+        self.dependencies.borrow().clone()
+    }
+
+    /// Number of distinct variants the keywords allow.
+    pub fn variant_count(&self) -> usize {
+        self.keywords
+            .borrow()
+            .iter()
+            .map(Keyword::variant_count)
+            .product()
+    }
+
+    /// Number of variants compiled so far.
+    pub fn compiled_count(&self) -> usize {
+        self.variants.borrow().len()
+    }
+
+    pub fn resolve(&self, key: &VariantKey) -> ShaderResult<VariantKey> {
+        Ok(resolve_variant(&self.keywords.borrow(), key)?)
+    }
+
+    pub fn variant(&self, backend: &Backend, key: &VariantKey) -> ShaderResult<ShaderVariant> {
+        let key = self.resolve(key)?;
+
+        get_or_create(&self.variants, key.clone(), || self.compile(backend, &key))
+    }
+
+    fn compile(&self, backend: &Backend, key: &VariantKey) -> ShaderResult<ShaderVariant> {
+        let preprocessor = key
+            .defines()
+            .fold(self.preprocessor.clone(), |preprocessor, (name, value)| {
+                preprocessor.define(name, value)
+            });
+
+        let shader = Shader::compile(
+            backend,
+            self.stage,
+            &preprocessor,
+            &self.path,
+            &self.entry_point,
+        )?;
+        let reflection = shader.reflect()?;
+
+        Ok(ShaderVariant { shader, reflection })
+    }
+
+    /// Resources declared in the source, for when no variant compiles. See
+    /// `ShaderReflection::from_hlsl` for how far its registers can be trusted.
+    pub fn source_reflection(&self) -> ShaderResult<ShaderReflection> {
+        let preprocessed = self.preprocessor.preprocess(&self.path)?;
+
+        Ok(ShaderReflection::from_hlsl(&preprocessed.source))
+    }
+
+    /// Re-reads the source and recompiles every variant compiled so far.
+    /// Either all of them are replaced or, if anything fails, none are.
+    /// Variants whose keywords no longer exist are dropped.
+    pub fn reload(&self, backend: &Backend) -> ShaderResult<()> {
+        let preprocessed = self.preprocessor.preprocess(&self.path)?;
+        let keywords = parse_keywords(preprocessed.keywords.iter().map(String::as_str))?;
+
+        let mut variants = HashMap::new();
+        for key in self.variants.borrow().keys() {
+            if let Ok(key) = resolve_variant(&keywords, key) {
+                let variant = self.compile(backend, &key)?;
+                variants.insert(key, variant);
+            }
+        }
+
+        *self.keywords.borrow_mut() = keywords;
+        *self.dependencies.borrow_mut() = preprocessed.dependencies;
+        *self.variants.borrow_mut() = variants;
+
+        Ok(())
+    }
+
+    /// Drops every compiled variant.
+    pub fn clear(&self) {
+        self.variants.borrow_mut().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keywords() -> Vec<Keyword> {
+        parse_keywords(["AERIAL_PERSPECTIVE", "LUT_SIZE 32 64", "SHADOWS"]).unwrap()
+    }
+
+    #[test]
+    fn parses_keywords() {
+        let toggle = Keyword::parse("AERIAL_PERSPECTIVE").unwrap();
+        assert!(toggle.is_toggle());
+        assert_eq!(toggle.variant_count(), 2);
+
+        let valued = Keyword::parse("  LUT_SIZE\t32 64  128 ").unwrap();
+        assert_eq!(valued.name, "LUT_SIZE");
+        assert_eq!(valued.values, ["32", "64", "128"]);
+        assert_eq!(valued.variant_count(), 3);
+    }
+
+    #[test]
+    fn rejects_invalid_keywords() {
+        for declaration in ["", "1ST", "A-B", "LUT_SIZE 32 32"] {
+            assert_eq!(
+                Keyword::parse(declaration),
+                Err(PermutationError::InvalidDeclaration(declaration.to_owned()))
+            );
+        }
+        assert_eq!(
+            parse_keywords(["A", "B 1 2", "A"]),
+            Err(PermutationError::DuplicateKeyword("A".to_owned()))
+        );
+    }
+
+    #[test]
+    fn variant_keys_ignore_set_order() {
+        let a = VariantKey::new().set("LUT_SIZE", "64").enable("SHADOWS");
+        let b = VariantKey::new().enable("SHADOWS").set("LUT_SIZE", "64");
+
+        assert_eq!(a, b);
+        assert_eq!(a.to_string(), "LUT_SIZE=64 SHADOWS=1");
+        assert_eq!(a.get("SHADOWS"), Some("1"));
+        assert_eq!(a.get("AERIAL_PERSPECTIVE"), None);
+    }
+
+    #[test]
+    fn resolves_defaults_and_disabled_toggles() {
+        let keywords = keywords();
+
+        let default = resolve_variant(&keywords, &VariantKey::new()).unwrap();
+        assert_eq!(default, VariantKey::new().set("LUT_SIZE", "32"));
+
+        let explicit = VariantKey::new()
+            .set("AERIAL_PERSPECTIVE", "0")
+            .set("LUT_SIZE", "32");
+        assert_eq!(resolve_variant(&keywords, &explicit).unwrap(), default);
+
+        let enabled = VariantKey::new().enable("SHADOWS").set("LUT_SIZE", "64");
+        assert_eq!(resolve_variant(&keywords, &enabled).unwrap(), enabled);
+    }
+
+    #[test]
+    fn rejects_unknown_keywords_and_values() {
+        let keywords = keywords();
+
+        assert_eq!(
+            resolve_variant(&keywords, &VariantKey::new().enable("FOG")),
+            Err(PermutationError::UnknownKeyword("FOG".to_owned()))
+        );
+        assert_eq!(
+            resolve_variant(&keywords, &VariantKey::new().set("LUT_SIZE", "16")),
+            Err(PermutationError::InvalidValue {
+                keyword: "LUT_SIZE".to_owned(),
+                value: "16".to_owned(),
+                allowed: vec!["32".to_owned(), "64".to_owned()],
+            })
+        );
+        assert!(resolve_variant(&keywords, &VariantKey::new().set("SHADOWS", "2")).is_err());
+    }
+
+    #[test]
+    fn enumerates_every_variant_once() {
+        let keywords = keywords();
+        let mut variants = all_variants(&keywords);
+        assert_eq!(variants.len(), 2 * 2 * 2);
+
+        for key in &variants {
+            assert_eq!(&resolve_variant(&keywords, key).unwrap(), key);
+        }
+        variants.sort();
+        variants.dedup();
+        assert_eq!(variants.len(), 8);
+
+        assert_eq!(all_variants(&[]), [VariantKey::new()]);
+    }
+
+    #[test]
+    fn caches_equivalent_keys_once() {
+        let keywords = keywords();
+        let cache = RefCell::new(HashMap::new());
+        let mut compiled = 0;
+
+        for key in [
+            VariantKey::new(),
+            VariantKey::new().set("LUT_SIZE", "32"),
+            VariantKey::new().set("SHADOWS", "0"),
+            VariantKey::new().enable("SHADOWS"),
+        ] {
+            let key = resolve_variant(&keywords, &key).unwrap();
+            get_or_create(&cache, key.clone(), || -> Result<String, ()> {
+                compiled += 1;
+                Ok(key.to_string())
+            })
+            .unwrap();
+        }
+
+        assert_eq!(compiled, 2);
+        assert_eq!(cache.borrow().len(), 2);
+    }
+
+    #[test]
+    fn reads_keywords_from_source() {
+        let dir = std::env::temp_dir().join(format!("shader_permutation_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("permutations.hlsl");
+        std::fs::write(
+            &path,
+            "#pragma keyword AERIAL_PERSPECTIVE\n#pragma keyword LUT_SIZE 32 64\nfloat4 main() : SV_Target { return 0; }\n",
+        )
+        .unwrap();
+
+        let permutations = ShaderPermutations::new(
+            ShaderStage::Pixel,
+            ShaderPreprocessor::new(),
+            &path.to_string_lossy(),
+            "main",
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let permutations = permutations.unwrap();
+        assert_eq!(
+            permutations.keywords(),
+            parse_keywords(["AERIAL_PERSPECTIVE", "LUT_SIZE 32 64"]).unwrap()
+        );
+        assert_eq!(permutations.variant_count(), 4);
+        assert_eq!(permutations.compiled_count(), 0);
+        assert_eq!(permutations.dependencies().len(), 1);
+    }
+
+    #[test]
+    fn reflects_resources_from_source() {
+        let dir =
+            std::env::temp_dir().join(format!("shader_source_reflection_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("resources.hlsli"),
+            "Texture2D albedo : register(t2);\nSamplerState Sampler;\n",
+        )
+        .unwrap();
+        let path = dir.join("reflected.hlsl");
+        std::fs::write(
+            &path,
+            "#include \"resources.hlsli\"\n#pragma keyword DEBUG\nTexture2D normal;\nfloat4 main() : SV_Target { return 0; }\n",
+        )
+        .unwrap();
+
+        let reflection = ShaderPermutations::new(
+            ShaderStage::Pixel,
+            ShaderPreprocessor::new(),
+            &path.to_string_lossy(),
+            "main",
+        )
+        .and_then(|permutations| permutations.source_reflection());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let reflection = reflection.unwrap();
+        assert_eq!(reflection.register_of("albedo"), Some(2));
+        assert_eq!(reflection.register_of("normal"), Some(0));
+        assert_eq!(reflection.register_of("Sampler"), Some(0));
+        assert!(!reflection.find("normal").unwrap().explicit);
+    }
+}
@@ -0,0 +1,549 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::vfs;
+
+#[derive(Debug)]
+pub enum PreprocessError {
+    Io(PathBuf, io::Error),
+    IncludeNotFound {
+        name: String,
+        file: PathBuf,
+        line: usize,
+    },
+    RecursiveInclude {
+        file: PathBuf,
+        line: usize,
+    },
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreprocessError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            PreprocessError::IncludeNotFound { name, file, line } => write!(
+                f,
+                "{}({}): cannot find include file `{}`",
+                file.display(),
+                line,
+                name
+            ),
+            PreprocessError::RecursiveInclude { file, line } => {
+                write!(f, "{}({}): file includes itself", file.display(), line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PreprocessError {}
+
+/// Where a line of preprocessed output came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: PathBuf,
+    /// 1-based line number in `file`.
+    pub line: usize,
+}
+
+pub struct PreprocessedShader {
+    pub source: String,
+    /// Every file that went into `source`, starting with the root file, as
+    /// normalized VFS paths.
+    pub dependencies: Vec<PathBuf>,
+    /// One entry per line of `source`; `None` for lines the preprocessor generated.
+    pub source_map: Vec<Option<SourceLocation>>,
+    /// Arguments of every `#pragma keyword` line, which are removed from `source`.
+    pub keywords: Vec<String>,
+}
+
+impl PreprocessedShader {
+    /// Maps a 1-based line of the preprocessed source back to its file.
+    pub fn original_location(&self, line: usize) -> Option<&SourceLocation> {
+        self.source_map.get(line.checked_sub(1)?)?.as_ref()
+    }
+}
+
+/// Reads shaders through the VFS and resolves `#include` directives on the Rust side so the engine knows
+/// every file a shader depends on. Other directives, including `#if`, are
+/// left for the shader compiler; its diagnostics refer to lines of the
+/// flattened source and are mapped back through `source_map`.
+///
+/// A file is only included once if it has `#pragma once` or is wrapped in a
+/// `#ifndef X` / `#define X` / `#endif` guard.
+#[derive(Clone, Debug, Default)]
+pub struct ShaderPreprocessor {
+    search_paths: Vec<PathBuf>,
+    defines: Vec<(String, String)>,
+}
+
+impl ShaderPreprocessor {
+    pub fn new() -> ShaderPreprocessor {
+        Default::default()
+    }
+
+    /// VFS directory searched for includes not found next to the including
+    /// file. `#include <...>` only looks in search paths.
+    pub fn search_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.search_paths.push(path.into());
+
+        self
+    }
+
+    pub fn define(mut self, name: &str, value: &str) -> Self {
+        self.defines.push((name.to_owned(), value.to_owned()));
+
+        self
+    }
+
+    pub fn defines(&self) -> &[(String, String)] {
+        &self.defines
+    }
+
+    pub fn preprocess(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<PreprocessedShader, PreprocessError> {
+        let (output, result) = self.run(path.as_ref());
+
+        result.map(|()| output)
+    }
+
+    /// The files `preprocess` reads, up to and including the one it fails
+    /// in if it fails, so a shader that doesn't preprocess can still be
+    /// watched for the fix.
+    pub fn dependencies(&self, path: impl AsRef<Path>) -> Vec<PathBuf> {
+        self.run(path.as_ref()).0.dependencies
+    }
+
+    fn run(&self, path: &Path) -> (PreprocessedShader, Result<(), PreprocessError>) {
+        let mut state = State {
+            output: PreprocessedShader {
+                source: String::new(),
+                dependencies: Vec::new(),
+                source_map: Vec::new(),
+                keywords: Vec::new(),
+            },
+            included_once: HashSet::new(),
+            guarded: HashSet::new(),
+            stack: Vec::new(),
+        };
+
+        for (name, value) in &self.defines {
+            state.push_line(format!("#define {} {}", name, value), None);
+        }
+
+        let result = self.include(&mut state, path);
+
+        (state.output, result)
+    }
+
+    fn include(&self, state: &mut State, path: &Path) -> Result<(), PreprocessError> {
+        let canonical = PathBuf::from(
+            vfs::normalize(&path.to_string_lossy())
+                .map_err(|err| PreprocessError::Io(path.into(), err))?,
+        );
+        let path = canonical.as_path();
+
+        // A guarded file expands to nothing the second time around, so skip
+        // it rather than leave the compiler to do so.
+        if state.included_once.contains(&canonical) || state.guarded.contains(&canonical) {
+            return Ok(());
+        }
+
+        if !state.output.dependencies.contains(&canonical) {
+            state.output.dependencies.push(canonical.clone());
+        }
+        let source = vfs::read_to_string(&canonical.to_string_lossy())
+            .map_err(|err| PreprocessError::Io(path.into(), err))?;
+
+        if has_include_guard(&source) {
+            state.guarded.insert(canonical.clone());
+        }
+
+        state.stack.push(canonical.clone());
+        for (index, line) in source.lines().enumerate() {
+            let location = SourceLocation {
+                file: path.to_path_buf(),
+                line: index + 1,
+            };
+
+            match directive(line) {
+                Some(("pragma", "once")) => {
+                    state.included_once.insert(canonical.clone());
+                    state.push_line(String::new(), Some(location));
+                }
+                Some(("pragma", pragma)) if directive_name(pragma) == "keyword" => {
+                    let keyword = pragma["keyword".len()..].trim();
+                    state.output.keywords.push(keyword.to_owned());
+                    state.push_line(String::new(), Some(location));
+                }
+                Some(("include", argument)) => {
+                    let (name, system) = parse_include(argument).ok_or_else(|| {
+                        PreprocessError::IncludeNotFound {
+                            name: argument.to_owned(),
+                            file: path.into(),
+                            line: index + 1,
+                        }
+                    })?;
+
+                    let resolved = self.resolve(path, name, system).ok_or_else(|| {
+                        PreprocessError::IncludeNotFound {
+                            name: name.to_owned(),
+                            file: path.into(),
+                            line: index + 1,
+                        }
+                    })?;
+
+                    if state.stack.contains(&resolved)
+                        && !state.included_once.contains(&resolved)
+                        && !state.guarded.contains(&resolved)
+                    {
+                        return Err(PreprocessError::RecursiveInclude {
+                            file: path.into(),
+                            line: index + 1,
+                        });
+                    }
+
+                    self.include(state, &resolved)?;
+                }
+                _ => state.push_line(line.to_owned(), Some(location)),
+            }
+        }
+
+        state.stack.pop();
+
+        Ok(())
+    }
+
+    fn resolve(&self, from: &Path, name: &str, system: bool) -> Option<PathBuf> {
+        let from = from.to_string_lossy();
+        let local = if system {
+            None
+        } else {
+            Some(vfs::parent(&from).to_owned())
+        };
+
+        local
+            .into_iter()
+            .chain(
+                self.search_paths
+                    .iter()
+                    .map(|dir| dir.to_string_lossy().into_owned()),
+            )
+            .filter_map(|dir| vfs::join(&dir, name).ok())
+            .find(|candidate| vfs::exists(candidate))
+            .map(PathBuf::from)
+    }
+}
+
+struct State {
+    output: PreprocessedShader,
+    included_once: HashSet<PathBuf>,
+    guarded: HashSet<PathBuf>,
+    stack: Vec<PathBuf>,
+}
+
+impl State {
+    fn push_line(&mut self, line: String, location: Option<SourceLocation>) {
+        self.output.source.push_str(&line);
+        self.output.source.push('\n');
+        self.output.source_map.push(location);
+    }
+}
+
+/// Splits `#  name  rest` into `(name, rest)`.
+fn directive(line: &str) -> Option<(&str, &str)> {
+    let rest = line.trim_start().strip_prefix('#')?.trim_start();
+    let name = directive_name(rest);
+
+    Some((name, rest[name.len()..].trim()))
+}
+
+fn directive_name(text: &str) -> &str {
+    let end = text
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(text.len());
+
+    &text[..end]
+}
+
+/// Parses `"name"` or `<name>`, returning whether it was the latter.
+fn parse_include(argument: &str) -> Option<(&str, bool)> {
+    if let Some(rest) = argument.strip_prefix('"') {
+        Some((&rest[..rest.find('"')?], false))
+    } else if let Some(rest) = argument.strip_prefix('<') {
+        Some((&rest[..rest.find('>')?], true))
+    } else {
+        None
+    }
+}
+
+/// Whether the whole file is wrapped in a classic `#ifndef X` / `#define X` ... `#endif` guard.
+/// The `#endif` closing the `#ifndef` has to be the last line, and nothing
+/// may follow an `#else` of the guard.
+fn has_include_guard(source: &str) -> bool {
+    let mut directives = source
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with("//"))
+        .map(directive);
+
+    let guard = match directives.next() {
+        Some(Some(("ifndef", guard))) => guard,
+        _ => return false,
+    };
+    match directives.next() {
+        Some(Some(("define", defined))) if defined.split_whitespace().next() == Some(guard) => {}
+        _ => return false,
+    }
+
+    let mut depth = 1;
+    for directive in directives {
+        if depth == 0 {
+            // Something follows the guard's `#endif`.
+            return false;
+        }
+        match directive {
+            Some(("if" | "ifdef" | "ifndef", _)) => depth += 1,
+            Some(("endif", _)) => depth -= 1,
+            Some(("else" | "elif", _)) if depth == 1 => return false,
+            _ => {}
+        }
+    }
+
+    depth == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    /// Writes `files` to a fresh temporary directory, returned as a
+    /// normalized path the VFS reads from disk.
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (file, contents) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        PathBuf::from(vfs::normalize(&dir.to_string_lossy()).unwrap())
+    }
+
+    #[test]
+    fn detects_include_guards() {
+        let guarded =
+            "// Comment\n#ifndef COMMON\n#define COMMON\n\n#if A\n#endif\nfloat x;\n#endif\n";
+        assert!(has_include_guard(guarded));
+        assert!(has_include_guard(
+            "#ifndef COMMON\n#define COMMON 1\n#ifdef B\n#else\n#endif\n#endif // COMMON"
+        ));
+
+        // The guard's #endif closes before the end of the file.
+        assert!(!has_include_guard(
+            "#ifndef X\n#define X 1\n#endif\nfloat x;\n#if Y\n#endif\n"
+        ));
+        assert!(!has_include_guard(
+            "#ifndef X\n#define X\n#endif\n#ifdef Y\nfloat y;\n#endif\n"
+        ));
+        // An #else of the guard itself.
+        assert!(!has_include_guard(
+            "#ifndef X\n#define X\n#else\nfloat x;\n#endif\n"
+        ));
+        // Unbalanced, or not a guard to begin with.
+        assert!(!has_include_guard("#ifndef X\n#define X\n#if A\n#endif\n"));
+        assert!(!has_include_guard("#ifndef X\n#define Y\n#endif\n"));
+        assert!(!has_include_guard(
+            "float x;\n#ifndef X\n#define X\n#endif\n"
+        ));
+        assert!(!has_include_guard("#ifndef X\n#define X\n"));
+        assert!(!has_include_guard(""));
+    }
+
+    #[test]
+    fn resolves_includes() {
+        let dir = write_files(
+            "preprocessor_includes",
+            &[
+                (
+                    "shaders/main.hlsl",
+                    "#include \"common.hlsli\"\n#include \"once.hlsli\"\n#include <lib.hlsli>\n\
+                     #include \"common.hlsli\"\n#include \"once.hlsli\"\n#pragma keyword SHADOWS\nfloat4 main();\n",
+                ),
+                (
+                    "shaders/common.hlsli",
+                    "#ifndef COMMON\n#define COMMON\nfloat common;\n#endif\n",
+                ),
+                ("shaders/once.hlsli", "#pragma once\nfloat once;\n"),
+                ("shaders/lib.hlsli", "float local_lib;\n"),
+                ("lib/lib.hlsli", "#include \"nested/inner.hlsli\"\nfloat lib;\n"),
+                ("lib/nested/inner.hlsli", "float inner;\n"),
+            ],
+        );
+
+        let preprocessed = ShaderPreprocessor::new()
+            .search_path(dir.join("lib"))
+            .define("QUALITY", "2")
+            .preprocess(dir.join("shaders/main.hlsl"))
+            .unwrap();
+
+        let code: Vec<&str> = preprocessed
+            .source
+            .lines()
+            .filter(|line| line.starts_with("float"))
+            .collect();
+        // `<...>` skips the including file's directory, and guarded and
+        // `#pragma once` files are only included once.
+        assert_eq!(
+            code,
+            [
+                "float common;",
+                "float once;",
+                "float inner;",
+                "float lib;",
+                "float4 main();"
+            ]
+        );
+        assert!(preprocessed.source.starts_with("#define QUALITY 2\n"));
+        assert!(!preprocessed.source.contains("#include"));
+        assert_eq!(preprocessed.keywords, ["SHADOWS"]);
+        assert_eq!(
+            preprocessed.dependencies,
+            [
+                "shaders/main.hlsl",
+                "shaders/common.hlsli",
+                "shaders/once.hlsli",
+                "lib/lib.hlsli",
+                "lib/nested/inner.hlsli"
+            ]
+            .map(|file| dir.join(file))
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn maps_lines_back_to_their_files() {
+        let dir = write_files(
+            "preprocessor_lines",
+            &[
+                ("main.hlsl", "float a;\n#include \"b.hlsli\"\nfloat c;\n"),
+                ("b.hlsli", "// b\nfloat b;\n"),
+            ],
+        );
+
+        let preprocessed = ShaderPreprocessor::new()
+            .define("A", "1")
+            .preprocess(dir.join("main.hlsl"))
+            .unwrap();
+        let location = |line: usize| {
+            preprocessed
+                .original_location(line)
+                .map(|location| (location.file.clone(), location.line))
+        };
+
+        assert_eq!(
+            preprocessed.source_map.len(),
+            preprocessed.source.lines().count()
+        );
+        // The generated #define.
+        assert_eq!(location(1), None);
+        assert_eq!(location(2), Some((dir.join("main.hlsl"), 1)));
+        assert_eq!(location(3), Some((dir.join("b.hlsli"), 1)));
+        assert_eq!(location(4), Some((dir.join("b.hlsli"), 2)));
+        assert_eq!(location(5), Some((dir.join("main.hlsl"), 3)));
+        assert_eq!(location(0), None);
+        assert_eq!(location(6), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reports_include_errors() {
+        let dir = write_files(
+            "preprocessor_errors",
+            &[
+                ("a.hlsl", "float a;\n#include \"b.hlsli\"\n"),
+                ("b.hlsli", "\n\n#include \"a.hlsl\"\n"),
+                ("missing.hlsl", "#include \"nowhere.hlsli\"\n"),
+                ("malformed.hlsl", "\n#include nowhere.hlsli\n"),
+                ("system.hlsl", "#include <b.hlsli>\n"),
+                (
+                    "guarded.hlsl",
+                    "#ifndef G\n#define G\n#include \"guarded.hlsl\"\n#endif\n",
+                ),
+            ],
+        );
+        let preprocessor = ShaderPreprocessor::new();
+
+        match preprocessor.preprocess(dir.join("a.hlsl")) {
+            Err(PreprocessError::RecursiveInclude { file, line }) => {
+                assert_eq!((file, line), (dir.join("b.hlsli"), 3));
+            }
+            result => panic!("expected a recursive include, got {:?}", result.err()),
+        }
+        match preprocessor.preprocess(dir.join("missing.hlsl")) {
+            Err(PreprocessError::IncludeNotFound { name, file, line }) => {
+                assert_eq!(name, "nowhere.hlsli");
+                assert_eq!((file, line), (dir.join("missing.hlsl"), 1));
+            }
+            result => panic!("expected a missing include, got {:?}", result.err()),
+        }
+        assert!(matches!(
+            preprocessor.preprocess(dir.join("malformed.hlsl")),
+            Err(PreprocessError::IncludeNotFound { line: 2, .. })
+        ));
+        // `<...>` doesn't look next to the including file.
+        assert!(matches!(
+            preprocessor.preprocess(dir.join("system.hlsl")),
+            Err(PreprocessError::IncludeNotFound { .. })
+        ));
+        assert!(matches!(
+            preprocessor.preprocess(dir.join("nonexistent.hlsl")),
+            Err(PreprocessError::Io(..))
+        ));
+        // A guarded file including itself expands to nothing.
+        assert!(preprocessor.preprocess(dir.join("guarded.hlsl")).is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn lists_dependencies_up_to_the_first_error() {
+        let dir = write_files(
+            "shader_preprocessor_partial",
+            &[
+                (
+                    "root.hlsl",
+                    "#include \"a.hlsli\"\n#include \"b.hlsli\"\n#include \"c.hlsli\"\n",
+                ),
+                ("a.hlsli", "float a;\n"),
+                ("b.hlsli", "#include \"missing.hlsli\"\n"),
+                ("c.hlsli", "float c;\n"),
+            ],
+        );
+        let root = dir.join("root.hlsl");
+        let preprocessor = ShaderPreprocessor::new();
+
+        assert!(matches!(
+            preprocessor.preprocess(&root),
+            Err(PreprocessError::IncludeNotFound { .. })
+        ));
+        assert_eq!(
+            preprocessor.dependencies(&root),
+            [root.clone(), dir.join("a.hlsli"), dir.join("b.hlsli")]
+        );
+
+        fs::write(dir.join("b.hlsli"), "float b;\n").unwrap();
+        let dependencies = preprocessor.preprocess(&root).unwrap().dependencies;
+        assert_eq!(preprocessor.dependencies(&root), dependencies);
+        assert_eq!(dependencies.len(), 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,642 @@
+use std::ffi::CStr;
+use std::fmt;
+
+use super::shader::ShaderResult;
+
+use windows::Win32::Graphics::Direct3D::*;
+use windows::Win32::Graphics::Direct3D11::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ResourceKind {
+    ConstantBuffer,
+    TextureBuffer,
+    Texture,
+    StructuredBuffer,
+    ByteAddressBuffer,
+    Sampler,
+    RwTexture,
+    RwStructuredBuffer,
+    RwByteAddressBuffer,
+    AppendStructuredBuffer,
+    ConsumeStructuredBuffer,
+}
+
+impl ResourceKind {
+    /// The register class used in `register(...)`: `b`, `t`, `s` or `u`.
+    pub fn register_class(&self) -> char {
+        match self {
+            ResourceKind::ConstantBuffer => 'b',
+            ResourceKind::TextureBuffer
+            | ResourceKind::Texture
+            | ResourceKind::StructuredBuffer
+            | ResourceKind::ByteAddressBuffer => 't',
+            ResourceKind::Sampler => 's',
+            ResourceKind::RwTexture
+            | ResourceKind::RwStructuredBuffer
+            | ResourceKind::RwByteAddressBuffer
+            | ResourceKind::AppendStructuredBuffer
+            | ResourceKind::ConsumeStructuredBuffer => 'u',
+        }
+    }
+
+    /// Maps an HLSL object type (without template arguments) to its kind.
+    pub fn from_hlsl_type(ty: &str) -> Option<ResourceKind> {
+        let kind = match ty {
+            "cbuffer" | "ConstantBuffer" => ResourceKind::ConstantBuffer,
+            "tbuffer" => ResourceKind::TextureBuffer,
+            "Buffer" | "Texture1D" | "Texture1DArray" | "Texture2D" | "Texture2DArray"
+            | "Texture2DMS" | "Texture2DMSArray" | "Texture3D" | "TextureCube"
+            | "TextureCubeArray" => ResourceKind::Texture,
+            "StructuredBuffer" => ResourceKind::StructuredBuffer,
+            "ByteAddressBuffer" => ResourceKind::ByteAddressBuffer,
+            "SamplerState" | "SamplerComparisonState" | "sampler" => ResourceKind::Sampler,
+            "RWBuffer" | "RWTexture1D" | "RWTexture1DArray" | "RWTexture2D"
+            | "RWTexture2DArray" | "RWTexture3D" => ResourceKind::RwTexture,
+            "RWStructuredBuffer" => ResourceKind::RwStructuredBuffer,
+            "RWByteAddressBuffer" => ResourceKind::RwByteAddressBuffer,
+            "AppendStructuredBuffer" => ResourceKind::AppendStructuredBuffer,
+            "ConsumeStructuredBuffer" => ResourceKind::ConsumeStructuredBuffer,
+            _ => return None,
+        };
+
+        Some(kind)
+    }
+
+    pub fn is_shader_resource(&self) -> bool {
+        self.register_class() == 't'
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResourceBinding {
+    pub name: String,
+    pub kind: ResourceKind,
+    pub register: u32,
+    pub space: u32,
+    /// Number of consecutive registers, > 1 for resource arrays.
+    pub count: u32,
+    /// False when the register was not given through `register(...)` and was
+    /// assigned by `ShaderReflection::from_hlsl` instead.
+    pub explicit: bool,
+}
+
+impl fmt::Display for ResourceBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({:?}, register {}{}",
+            self.name,
+            self.kind,
+            self.kind.register_class(),
+            self.register
+        )?;
+        if self.space != 0 {
+            write!(f, ", space{}", self.space)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Resources, registers and thread group size of a shader, extracted either
+/// from HLSL source or from a compiled blob.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShaderReflection {
+    pub bindings: Vec<ResourceBinding>,
+    pub thread_group_size: Option<[u32; 3]>,
+}
+
+impl ShaderReflection {
+    pub fn find(&self, name: &str) -> Option<&ResourceBinding> {
+        self.bindings.iter().find(|binding| binding.name == name)
+    }
+
+    pub fn register_of(&self, name: &str) -> Option<u32> {
+        self.find(name).map(|binding| binding.register)
+    }
+
+    /// Combines the reflection of several stages, e.g. the vertex and pixel
+    /// shaders of a render pass. Bindings with the same name are listed once.
+    pub fn merge(&mut self, other: &ShaderReflection) {
+        for binding in &other.bindings {
+            if self.find(&binding.name).is_none() {
+                self.bindings.push(binding.clone());
+            }
+        }
+
+        if self.thread_group_size.is_none() {
+            self.thread_group_size = other.thread_group_size;
+        }
+    }
+
+    /// Parses resource declarations out of HLSL source.
+    ///
+    /// Resources without an explicit `register(...)` are assigned the lowest
+    /// free register of their class in declaration order. The compiler does
+    /// the same but skips resources that are never used, so only explicit
+    /// registers are guaranteed to match the compiled shader. Preprocessor
+    /// directives are ignored rather than evaluated.
+    pub fn from_hlsl(source: &str) -> ShaderReflection {
+        let tokens = tokenize(source);
+
+        let mut reflection = ShaderReflection::default();
+        let mut depth = 0;
+        let mut i = 0;
+
+        while i < tokens.len() {
+            let token = tokens[i].as_str();
+            match token {
+                "{" | "(" => depth += 1,
+                "}" | ")" => depth -= 1,
+                "[" if depth == 0 && token_at(&tokens, i + 1) == "numthreads" => {
+                    if let Some((size, next)) = parse_numthreads(&tokens, i + 2) {
+                        reflection.thread_group_size = Some(size);
+                        i = next;
+                        continue;
+                    }
+                }
+                _ if depth == 0 => {
+                    if let Some(kind) = ResourceKind::from_hlsl_type(token) {
+                        if let Some((bindings, next)) = parse_declaration(&tokens, i + 1, kind) {
+                            reflection.bindings.extend(bindings);
+                            i = next;
+                            continue;
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            i += 1;
+        }
+
+        reflection.assign_implicit_registers();
+        reflection
+    }
+
+    fn assign_implicit_registers(&mut self) {
+        let mut used: Vec<(char, u32, u32, u32)> = self
+            .bindings
+            .iter()
+            .filter(|binding| binding.explicit)
+            .map(|binding| {
+                (
+                    binding.kind.register_class(),
+                    binding.space,
+                    binding.register,
+                    binding.count,
+                )
+            })
+            .collect();
+
+        for binding in self.bindings.iter_mut().filter(|binding| !binding.explicit) {
+            let class = binding.kind.register_class();
+            let register = (0..)
+                .find(|&register| {
+                    !used.iter().any(|&(c, space, start, count)| {
+                        c == class
+                            && space == binding.space
+                            && register < start + count
+                            && start < register + binding.count
+                    })
+                })
+                .unwrap_or(0);
+
+            binding.register = register;
+            used.push((class, binding.space, register, binding.count));
+        }
+    }
+
+    /// Reads the bindings of a compiled shader. Unlike `from_hlsl`, this
+    /// only lists resources the shader actually uses.
+    pub fn from_d3d11(reflection: &ID3D11ShaderReflection) -> ShaderResult<ShaderReflection> {
+        let desc = unsafe { reflection.GetDesc()? };
+
+        let mut bindings = Vec::with_capacity(desc.BoundResources as usize);
+        for i in 0..desc.BoundResources {
+            let bind_desc = unsafe { reflection.GetResourceBindingDesc(i)? };
+
+            let kind = match bind_desc.Type {
+                D3D_SIT_CBUFFER => ResourceKind::ConstantBuffer,
+                D3D_SIT_TBUFFER => ResourceKind::TextureBuffer,
+                D3D_SIT_TEXTURE => ResourceKind::Texture,
+                D3D_SIT_SAMPLER => ResourceKind::Sampler,
+                D3D_SIT_UAV_RWTYPED => ResourceKind::RwTexture,
+                D3D_SIT_STRUCTURED => ResourceKind::StructuredBuffer,
+                D3D_SIT_UAV_RWSTRUCTURED | D3D_SIT_UAV_RWSTRUCTURED_WITH_COUNTER => {
+                    ResourceKind::RwStructuredBuffer
+                }
+                D3D_SIT_BYTEADDRESS => ResourceKind::ByteAddressBuffer,
+                D3D_SIT_UAV_RWBYTEADDRESS => ResourceKind::RwByteAddressBuffer,
+                D3D_SIT_UAV_APPEND_STRUCTURED => ResourceKind::AppendStructuredBuffer,
+                D3D_SIT_UAV_CONSUME_STRUCTURED => ResourceKind::ConsumeStructuredBuffer,
+                _ => continue,
+            };
+
+            let name = unsafe { CStr::from_ptr(bind_desc.Name.0 as *const _) };
+
+            bindings.push(ResourceBinding {
+                name: name.to_string_lossy().into_owned(),
+                kind,
+                register: bind_desc.BindPoint,
+                space: 0,
+                count: bind_desc.BindCount,
+                explicit: true,
+            });
+        }
+
+        let mut size = [0u32; 3];
+        let thread_group_size = unsafe {
+            reflection.GetThreadGroupSize(&mut size[0], &mut size[1], &mut size[2]);
+            if size == [0, 0, 0] {
+                None
+            } else {
+                Some(size)
+            }
+        };
+
+        Ok(ShaderReflection {
+            bindings,
+            thread_group_size,
+        })
+    }
+}
+
+/// Splits HLSL into identifier/number tokens and single punctuation
+/// characters, dropping comments and preprocessor lines.
+fn tokenize(source: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line_start = true;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line_start = true,
+            c if c.is_whitespace() => {}
+            '#' if line_start => {
+                // Skip the directive, including backslash continuations.
+                let mut previous = '#';
+                for c in chars.by_ref() {
+                    if c == '\n' && previous != '\\' {
+                        break;
+                    }
+                    if !c.is_whitespace() || c == '\n' {
+                        previous = c;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line_start = true;
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                line_start = false;
+                let mut token = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' || c == '.' {
+                        token.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(token);
+            }
+            c => {
+                line_start = false;
+                tokens.push(c.to_string());
+            }
+        }
+    }
+
+    tokens
+}
+
+fn token_at(tokens: &[String], i: usize) -> &str {
+    tokens.get(i).map(String::as_str).unwrap_or("")
+}
+
+fn is_identifier(token: &str) -> bool {
+    token
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_')
+}
+
+/// Parses `(x, y, z)]` following `[numthreads`.
+fn parse_numthreads(tokens: &[String], i: usize) -> Option<([u32; 3], usize)> {
+    if token_at(tokens, i) != "(" {
+        return None;
+    }
+
+    let mut size = [1u32; 3];
+    for (n, component) in size.iter_mut().enumerate() {
+        *component = token_at(tokens, i + 1 + 2 * n).parse().ok()?;
+    }
+
+    if token_at(tokens, i + 6) != ")" || token_at(tokens, i + 7) != "]" {
+        return None;
+    }
+
+    Some((size, i + 8))
+}
+
+/// Skips a balanced `open ... close` group starting at `i`, returning the
+/// index after it.
+fn skip_group(tokens: &[String], i: usize, open: &str, close: &str) -> usize {
+    let mut depth = 0;
+    for (offset, token) in tokens[i..].iter().enumerate() {
+        if token == open {
+            depth += 1;
+        } else if token == close {
+            depth -= 1;
+            if depth == 0 {
+                return i + offset + 1;
+            }
+        }
+    }
+
+    tokens.len()
+}
+
+/// Parses `: register(t3, space1)`, returning `(class, register, space)`
+/// and the index after it.
+fn parse_register(tokens: &[String], i: usize) -> Option<((char, u32, u32), usize)> {
+    if token_at(tokens, i) != ":" || token_at(tokens, i + 1) != "register" {
+        return None;
+    }
+
+    let end = skip_group(tokens, i + 2, "(", ")");
+    let mut register = None;
+    let mut space = 0;
+
+    for token in &tokens[i + 3..end.saturating_sub(1).max(i + 3)] {
+        if let Some(n) = token.strip_prefix("space") {
+            space = n.parse().ok()?;
+        } else if let Some(class) = token.chars().next() {
+            if let Ok(n) = token[class.len_utf8()..].parse() {
+                register = Some((class.to_ascii_lowercase(), n));
+            }
+        }
+    }
+
+    let (class, register) = register?;
+    Some(((class, register, space), end))
+}
+
+/// Parses the declarators following a resource type, e.g. `<float4> a : register(t0), b[4];`
+/// or a `cbuffer Name : register(b0) { ... }` block.
+fn parse_declaration(
+    tokens: &[String],
+    mut i: usize,
+    kind: ResourceKind,
+) -> Option<(Vec<ResourceBinding>, usize)> {
+    if token_at(tokens, i) == "<" {
+        i = skip_group(tokens, i, "<", ">");
+    }
+
+    let mut bindings = Vec::new();
+
+    loop {
+        let name = token_at(tokens, i);
+        if !is_identifier(name) {
+            return None;
+        }
+        i += 1;
+
+        let mut count = 1;
+        if token_at(tokens, i) == "[" {
+            count = token_at(tokens, i + 1).parse().unwrap_or(1);
+            i = skip_group(tokens, i, "[", "]");
+        }
+
+        let register = parse_register(tokens, i);
+        if let Some((_, next)) = register {
+            i = next;
+        }
+
+        let next = token_at(tokens, i);
+        let block = matches!(
+            kind,
+            ResourceKind::ConstantBuffer | ResourceKind::TextureBuffer
+        ) && next == "{";
+        if !(block || next == "," || next == ";") {
+            // A function returning a resource, or something we don't understand.
+            return None;
+        }
+
+        let (register, space, explicit) = match register {
+            Some(((class, register, space), _)) if class == kind.register_class() => {
+                (register, space, true)
+            }
+            _ => (0, 0, false),
+        };
+
+        bindings.push(ResourceBinding {
+            name: name.to_string(),
+            kind,
+            register,
+            space,
+            count,
+            explicit,
+        });
+
+        if block {
+            i = skip_group(tokens, i, "{", "}");
+            if token_at(tokens, i) == ";" {
+                i += 1;
+            }
+            return Some((bindings, i));
+        }
+
+        i += 1;
+        if next == ";" {
+            return Some((bindings, i));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(reflection: &ShaderReflection, name: &str) -> ResourceBinding {
+        reflection
+            .find(name)
+            .unwrap_or_else(|| panic!("{} not reflected", name))
+            .clone()
+    }
+
+    #[test]
+    fn reads_explicit_registers() {
+        let reflection = ShaderReflection::from_hlsl(
+            "Texture2D<float4> albedo : register(t3);
+             SamplerState linear_sampler : register(s1, space2);
+             RWStructuredBuffer<uint> counters : register(u0);",
+        );
+
+        let albedo = binding(&reflection, "albedo");
+        assert_eq!(albedo.kind, ResourceKind::Texture);
+        assert_eq!((albedo.register, albedo.space, albedo.count), (3, 0, 1));
+        assert!(albedo.explicit);
+
+        let sampler = binding(&reflection, "linear_sampler");
+        assert_eq!(sampler.kind, ResourceKind::Sampler);
+        assert_eq!((sampler.register, sampler.space), (1, 2));
+
+        let counters = binding(&reflection, "counters");
+        assert_eq!(counters.kind, ResourceKind::RwStructuredBuffer);
+        assert_eq!(counters.register, 0);
+    }
+
+    #[test]
+    fn assigns_lowest_free_registers() {
+        let reflection = ShaderReflection::from_hlsl(
+            "Texture2D a;
+             Texture2D b : register(t0);
+             Texture2D c[2];
+             Texture2D d : register(t1, space1);
+             Texture2D e;
+             SamplerState s;",
+        );
+
+        let registers: Vec<(&str, u32, bool)> = reflection
+            .bindings
+            .iter()
+            .map(|binding| (binding.name.as_str(), binding.register, binding.explicit))
+            .collect();
+        assert_eq!(
+            registers,
+            [
+                ("a", 1, false),
+                ("b", 0, true),
+                ("c", 2, false),
+                ("d", 1, true),
+                ("e", 4, false),
+                ("s", 0, false),
+            ]
+        );
+        assert_eq!(binding(&reflection, "c").count, 2);
+    }
+
+    #[test]
+    fn reads_constant_buffer_blocks() {
+        let reflection = ShaderReflection::from_hlsl(
+            "cbuffer FrameConstants : register(b0)
+             {
+                 float4x4 view;
+                 Texture2D not_a_resource;
+             };
+             cbuffer ObjectConstants
+             {
+                 float4x4 world;
+             }
+             tbuffer Lights : register(t2) { float4 colors[8]; };
+             ConstantBuffer<Material> material : register(b4);",
+        );
+
+        let names: Vec<&str> = reflection
+            .bindings
+            .iter()
+            .map(|binding| binding.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            ["FrameConstants", "ObjectConstants", "Lights", "material"]
+        );
+        assert_eq!(reflection.register_of("ObjectConstants"), Some(1));
+        assert_eq!(
+            binding(&reflection, "Lights").kind,
+            ResourceKind::TextureBuffer
+        );
+        assert_eq!(reflection.register_of("material"), Some(4));
+    }
+
+    #[test]
+    fn reads_declarator_lists_and_arrays() {
+        let reflection =
+            ShaderReflection::from_hlsl("Texture2D a : register(t4), b[3] : register(t8), c;");
+
+        assert_eq!(reflection.register_of("a"), Some(4));
+        let b = binding(&reflection, "b");
+        assert_eq!((b.register, b.count), (8, 3));
+        assert_eq!(reflection.register_of("c"), Some(0));
+    }
+
+    #[test]
+    fn reads_numthreads() {
+        let reflection = ShaderReflection::from_hlsl(
+            "RWTexture2D<float4> output;
+             [numthreads(8, 4, 1)]
+             void main(uint3 id : SV_DispatchThreadID) {}",
+        );
+
+        assert_eq!(reflection.thread_group_size, Some([8, 4, 1]));
+        assert_eq!(binding(&reflection, "output").kind, ResourceKind::RwTexture);
+    }
+
+    #[test]
+    fn ignores_comments_directives_and_function_bodies() {
+        let reflection = ShaderReflection::from_hlsl(
+            "// Texture2D commented;
+             /* Texture2D block_commented : register(t9); */
+             #define RESOURCE Texture2D hidden; \\
+                 Texture2D continued;
+             #include \"common.hlsl\"
+             Texture2D<float4> shown;
+             Texture2D pick(Texture2D a, Texture2D b) { return a; }
+             float4 main() : SV_Target
+             {
+                 Texture2D local;
+                 return 0;
+             }",
+        );
+
+        let names: Vec<&str> = reflection
+            .bindings
+            .iter()
+            .map(|binding| binding.name.as_str())
+            .collect();
+        assert_eq!(names, ["shown"]);
+        assert_eq!(reflection.thread_group_size, None);
+    }
+
+    #[test]
+    fn ignores_registers_of_another_class() {
+        let reflection = ShaderReflection::from_hlsl("Texture2D a : register(s0);");
+
+        let a = binding(&reflection, "a");
+        assert!(!a.explicit);
+        assert_eq!(a.register, 0);
+    }
+
+    #[test]
+    fn merges_stages() {
+        let mut pixel = ShaderReflection::from_hlsl(
+            "Texture2D albedo : register(t0); SamplerState s : register(s0);",
+        );
+        let vertex = ShaderReflection::from_hlsl(
+            "Texture2D albedo : register(t5); Texture2D height : register(t1);
+             [numthreads(1, 1, 1)] void main() {}",
+        );
+
+        pixel.merge(&vertex);
+        assert_eq!(pixel.register_of("albedo"), Some(0));
+        assert_eq!(pixel.register_of("height"), Some(1));
+        assert_eq!(pixel.bindings.len(), 3);
+        assert_eq!(pixel.thread_group_size, Some([1, 1, 1]));
+    }
+}
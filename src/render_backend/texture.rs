@@ -1,14 +1,20 @@
 use std::marker::PhantomData;
 
-use image::io::Reader as ImageReader;
-
 use windows::{
     core::*,
     Win32::Graphics::Direct3D11::*,
     Win32::Graphics::Dxgi::{Common::*, DXGI_SWAP_CHAIN_DESC},
 };
 
+use crate::error::{Context, EngineError, EngineResult};
+
 use super::backend::Backend;
+use super::cpu_image::{decode_image, CpuImage, ReadbackFormat};
+use super::dds;
+use super::format_info::format_name;
+use super::ktx2;
+use super::mipmaps::MipGenerator;
+use super::texture_data::LoadedTexture;
 
 #[derive(Clone, Copy)]
 pub struct TextureDescBuilder {
@@ -86,6 +92,15 @@ impl TextureDescBuilder {
         self
     }
 
+    /// `count` cubes of six faces each. Sets the array size and
+    /// `D3D11_RESOURCE_MISC_TEXTURECUBE`, so default views sample the
+    /// texture as a cube, or a cube array if `count` is more than one.
+    pub fn cube(mut self, count: u32) -> Self {
+        self.array_size = 6 * count;
+        self.misc_flags |= D3D11_RESOURCE_MISC_TEXTURECUBE;
+        self
+    }
+
     pub fn build_texture2d(&self) -> D3D11_TEXTURE2D_DESC {
         D3D11_TEXTURE2D_DESC {
             Width: self.size[0],
@@ -169,8 +184,8 @@ pub trait Tex<'a>: Sized {
 
     fn device_texture(&self) -> Self::TextureType;
     fn desc(&self) -> Self::DescType;
-    fn new(backend: &Backend, desc: Self::DescType) -> Result<Self>;
-    fn from_file(backend: &Backend, file: &str) -> Result<Self>;
+    fn new(backend: &Backend, desc: Self::DescType) -> EngineResult<Self>;
+    fn from_file(backend: &Backend, file: &str) -> EngineResult<Self>;
 }
 
 pub struct Tex3D {
@@ -191,14 +206,21 @@ impl<'a> Tex<'a> for Tex3D {
         self.desc
     }
 
-    fn new(backend: &Backend, desc: Self::DescType) -> Result<Self> {
-        let texture = unsafe { backend.device.CreateTexture3D(&desc, std::ptr::null())? };
+    fn new(backend: &Backend, desc: Self::DescType) -> EngineResult<Self> {
+        let texture = unsafe { backend.device.CreateTexture3D(&desc, std::ptr::null()) }
+            .context("Create 3D texture")?;
 
         Ok(Tex3D { desc, texture })
     }
 
-    fn from_file(backend: &Backend, file: &str) -> Result<Self> {
-        todo!()
+    fn from_file(backend: &Backend, file: &str) -> EngineResult<Self> {
+        match load_container(backend, file) {
+            Some(loaded) => loaded?.into_3d(file),
+            None => Err(EngineError::Validation(format!(
+                "{}: 3D textures can only be loaded from DDS or KTX2 files",
+                file
+            ))),
+        }
     }
 }
 
@@ -219,58 +241,260 @@ impl<'a> Tex<'a> for Tex2D {
         self.desc
     }
 
-    fn new(backend: &Backend, desc: D3D11_TEXTURE2D_DESC) -> Result<Tex2D> {
-        let texture = unsafe { backend.device.CreateTexture2D(&desc, std::ptr::null())? };
+    fn new(backend: &Backend, desc: D3D11_TEXTURE2D_DESC) -> EngineResult<Tex2D> {
+        let texture = unsafe { backend.device.CreateTexture2D(&desc, std::ptr::null()) }
+            .context("Create 2D texture")?;
 
         Ok(Tex2D { desc, texture })
     }
 
-    fn from_file(backend: &Backend, file: &str) -> Result<Self> {
-        let img = ImageReader::open(file)
-            .map_err(|_| Error::fast_error(HRESULT::from_win32(0x80070057)))?
-            .decode()
-            .map_err(|_| HRESULT::from_win32(0x80070057))?;
-
-        let img = img.to_rgba8();
+    /// DDS and KTX2 files are loaded as they are, including their mips,
+    /// arrays and cube faces. Other images get a mip chain from
+    /// `MipGenerator`, see `from_file_with_mips`: `.hdr` and `.exr` files as
+    /// linear float textures, anything else as sRGB RGBA8.
+    fn from_file(backend: &Backend, file: &str) -> EngineResult<Self> {
+        Self::from_file_with_mips(backend, file, &MipGenerator::new().srgb(true))
+    }
+}
 
-        let mut samples = img.into_flat_samples();
-        let (_, w, h) = samples.extents();
-        let (_, width_stride, height_stride) = samples.strides_cwh();
+/// Loads texture containers that describe their own layout, `None` for
+/// other files.
+fn load_container(backend: &Backend, file: &str) -> Option<EngineResult<LoadedTexture>> {
+    let extension = file.rsplit('.').next()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "dds" => Some(dds::load(backend, file)),
+        "ktx2" => Some(ktx2::load(backend, file)),
+        _ => None,
+    }
+}
 
-        let desc = TextureDescBuilder::new()
-            .size([w as u32, h as u32, 0])
-            .mip_levels(0)
-            .bind_flags(D3D11_BIND_SHADER_RESOURCE | D3D11_BIND_RENDER_TARGET)
-            .misc_flags(D3D11_RESOURCE_MISC_GENERATE_MIPS)
-            .cpu_access_flags(D3D11_CPU_ACCESS_WRITE)
-            .format(DXGI_FORMAT_R8G8B8A8_UNORM_SRGB)
-            .build_texture2d();
+fn mip_size(size: u32, mip: u32) -> u32 {
+    (size >> mip).max(1)
+}
 
-        let texture = unsafe {
-            backend
-                .device
-                .CreateTexture2D(&desc, std::ptr::null_mut())?
+/// Maps a staging texture for reading and passes `f` the bytes of the
+/// `width` x `height` image at depth slice `z`.
+fn read_mapped<'a, T: D3DTexture<'a>, R>(
+    backend: &Backend,
+    staging: T,
+    format: DXGI_FORMAT,
+    [width, height]: [u32; 2],
+    mut f: impl FnMut(&dyn Fn(u32) -> EngineResult<CpuImage>) -> EngineResult<R>,
+) -> EngineResult<R> {
+    let readback = ReadbackFormat::of(format).ok_or_else(|| {
+        EngineError::Validation(format!("{} can't be read back", format_name(format)))
+    })?;
+
+    let mapped = unsafe {
+        backend
+            .device_context
+            .Map(staging.clone(), 0, D3D11_MAP_READ, 0)
+    }
+    .context("Map staging texture")?;
+
+    let row_pitch = mapped.RowPitch as usize;
+    let len = row_pitch * (height as usize - 1) + width as usize * readback.bytes_per_pixel;
+    let decode_slice = |z: u32| {
+        let data = unsafe {
+            std::slice::from_raw_parts(
+                (mapped.pData as *const u8).add(z as usize * mapped.DepthPitch as usize),
+                len,
+            )
         };
 
+        decode_image(format, width, height, row_pitch, data)
+    };
+    let result = f(&decode_slice);
+
+    unsafe {
+        backend.device_context.Unmap(staging, 0);
+    }
+
+    result
+}
+
+impl Tex2D {
+    /// Like `from_file`, but generates the mips of decoded images with
+    /// `mips`, e.g. to keep the coverage of cutouts or renormalize normal
+    /// maps. Containers keep their own mips.
+    pub fn from_file_with_mips(
+        backend: &Backend,
+        file: &str,
+        mips: &MipGenerator,
+    ) -> EngineResult<Tex2D> {
+        if let Some(loaded) = load_container(backend, file) {
+            return loaded?.into_2d(file);
+        }
+
+        let image = CpuImage::load(file)?;
+        mips.texture_data(&image).create(backend)?.into_2d(file)
+    }
+
+    /// Copies one mip of one array slice into a staging texture and reads it
+    /// back. Waits for the GPU to finish writing the texture, so it's slow.
+    pub fn read_back(
+        &self,
+        backend: &Backend,
+        mip: u32,
+        array_slice: u32,
+    ) -> EngineResult<CpuImage> {
+        // `self.desc` may say 0 mips for a full chain.
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { self.texture.GetDesc(&mut desc) }
+
+        if mip >= desc.MipLevels || array_slice >= desc.ArraySize {
+            return Err(EngineError::Validation(format!(
+                "reading back mip {} of slice {} from a texture with {} mips and {} slices",
+                mip, array_slice, desc.MipLevels, desc.ArraySize
+            )));
+        }
+        if desc.SampleDesc.Count > 1 {
+            return Err(EngineError::Validation(
+                "multisampled textures have to be resolved before reading them back".to_owned(),
+            ));
+        }
+
+        let size = [mip_size(desc.Width, mip), mip_size(desc.Height, mip)];
+        let staging = Tex2D::new(
+            backend,
+            TextureDescBuilder::new()
+                .size([size[0], size[1], 0])
+                .mip_levels(1)
+                .format(desc.Format)
+                .usage(D3D11_USAGE_STAGING)
+                .cpu_access_flags(D3D11_CPU_ACCESS_READ)
+                .build_texture2d(),
+        )
+        .context("Create staging texture")?;
+
         unsafe {
-            backend.device_context.UpdateSubresource(
-                texture.clone(),
+            backend.device_context.CopySubresourceRegion(
+                &staging.texture,
+                0,
+                0,
+                0,
                 0,
+                &self.texture,
+                mip + array_slice * desc.MipLevels,
                 std::ptr::null(),
-                samples.samples.as_mut_ptr() as _,
-                height_stride as u32,
-                (height_stride * h) as u32,
             );
         }
 
-        let tex = Tex2D { desc, texture };
+        read_mapped(
+            backend,
+            staging.texture.clone(),
+            desc.Format,
+            size,
+            |decode| decode(0),
+        )
+    }
+}
+
+impl Tex3D {
+    /// Reads depth slice `z` of `mip` back, see `Tex2D::read_back`.
+    pub fn read_slice(&self, backend: &Backend, mip: u32, z: u32) -> EngineResult<CpuImage> {
+        let (desc, size) = self.mip_desc(mip)?;
+        if z >= size[2] {
+            return Err(EngineError::Validation(format!(
+                "reading back slice {} of a mip {} deep",
+                z, size[2]
+            )));
+        }
 
-        let srv = backend.shader_resource_view(&tex, None)?;
+        let staging = self.staging(backend, &desc, [size[0], size[1], 1])?;
+        let region = D3D11_BOX {
+            left: 0,
+            top: 0,
+            front: z,
+            right: size[0],
+            bottom: size[1],
+            back: z + 1,
+        };
 
         unsafe {
-            backend.device_context.GenerateMips(&srv);
+            backend.device_context.CopySubresourceRegion(
+                &staging.texture,
+                0,
+                0,
+                0,
+                0,
+                &self.texture,
+                mip,
+                &region,
+            );
         }
 
-        Ok(tex)
+        read_mapped(
+            backend,
+            staging.texture.clone(),
+            desc.Format,
+            [size[0], size[1]],
+            |decode| decode(0),
+        )
+    }
+
+    /// Reads every depth slice of `mip` back.
+    pub fn read_back(&self, backend: &Backend, mip: u32) -> EngineResult<Vec<CpuImage>> {
+        let (desc, size) = self.mip_desc(mip)?;
+        let staging = self.staging(backend, &desc, size)?;
+
+        unsafe {
+            backend.device_context.CopySubresourceRegion(
+                &staging.texture,
+                0,
+                0,
+                0,
+                0,
+                &self.texture,
+                mip,
+                std::ptr::null(),
+            );
+        }
+
+        read_mapped(
+            backend,
+            staging.texture.clone(),
+            desc.Format,
+            [size[0], size[1]],
+            |decode| (0..size[2]).map(decode).collect(),
+        )
+    }
+
+    fn mip_desc(&self, mip: u32) -> EngineResult<(D3D11_TEXTURE3D_DESC, [u32; 3])> {
+        let mut desc = D3D11_TEXTURE3D_DESC::default();
+        unsafe { self.texture.GetDesc(&mut desc) }
+
+        if mip >= desc.MipLevels {
+            return Err(EngineError::Validation(format!(
+                "reading back mip {} from a texture with {} mips",
+                mip, desc.MipLevels
+            )));
+        }
+
+        let size = [
+            mip_size(desc.Width, mip),
+            mip_size(desc.Height, mip),
+            mip_size(desc.Depth, mip),
+        ];
+        Ok((desc, size))
+    }
+
+    fn staging(
+        &self,
+        backend: &Backend,
+        desc: &D3D11_TEXTURE3D_DESC,
+        size: [u32; 3],
+    ) -> EngineResult<Tex3D> {
+        Tex3D::new(
+            backend,
+            TextureDescBuilder::new()
+                .size(size)
+                .mip_levels(1)
+                .format(desc.Format)
+                .usage(D3D11_USAGE_STAGING)
+                .cpu_access_flags(D3D11_CPU_ACCESS_READ)
+                .build_texture3d(),
+        )
+        .context("Create staging texture")
     }
 }
@@ -0,0 +1,204 @@
+use windows::Win32::Graphics::{Direct3D11::*, Dxgi::Common::*};
+
+use crate::error::{Context, EngineError, EngineResult};
+
+use super::backend::Backend;
+use super::format_info::{format_name, FormatInfo};
+use super::texture::{Tex2D, Tex3D, TextureDescBuilder};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureDimension {
+    Texture2D,
+    Texture3D,
+}
+
+/// A texture with all of its subresources in CPU memory, e.g. loaded from a
+/// DDS file. `data` holds them in D3D order: every mip of the first array
+/// slice, then every mip of the next. Mips of 3D textures hold all of their
+/// depth slices. Rows are tightly packed, see `FormatInfo::row_pitch`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextureData {
+    pub format: DXGI_FORMAT,
+    pub dimension: TextureDimension,
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub mip_levels: u32,
+    /// Number of 2D slices, six per cube for cubemaps.
+    pub array_size: u32,
+    pub cubemap: bool,
+    pub data: Vec<u8>,
+}
+
+/// Where one subresource lives in `TextureData::data`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Subresource {
+    pub offset: usize,
+    pub row_pitch: usize,
+    pub slice_pitch: usize,
+    pub size: [u32; 3],
+}
+
+/// A loaded texture of whichever type the file described.
+pub enum LoadedTexture {
+    Texture2D(Tex2D),
+    Texture3D(Tex3D),
+}
+
+fn mip_size(size: u32, mip: u32) -> u32 {
+    (size >> mip).max(1)
+}
+
+impl TextureData {
+    /// Fails if the texture is larger than D3D11 allows, which also keeps
+    /// the size arithmetic of crafted headers from overflowing.
+    pub fn check_limits(&self) -> Result<(), String> {
+        let (max_size, max_depth) = match self.dimension {
+            TextureDimension::Texture2D => (D3D11_REQ_TEXTURE2D_U_OR_V_DIMENSION, 1),
+            TextureDimension::Texture3D => (
+                D3D11_REQ_TEXTURE3D_U_V_OR_W_DIMENSION,
+                D3D11_REQ_TEXTURE3D_U_V_OR_W_DIMENSION,
+            ),
+        };
+        if self.width > max_size || self.height > max_size || self.depth > max_depth {
+            return Err(format!(
+                "{}x{}x{} is larger than the {} D3D11 allows",
+                self.width, self.height, self.depth, max_size
+            ));
+        }
+        if self.array_size > D3D11_REQ_TEXTURE2D_ARRAY_AXIS_DIMENSION {
+            return Err(format!(
+                "{} array slices, D3D11 allows at most {}",
+                self.array_size, D3D11_REQ_TEXTURE2D_ARRAY_AXIS_DIMENSION
+            ));
+        }
+        Ok(())
+    }
+
+    /// The layout of every subresource, in the order D3D numbers them.
+    /// Fails if the format isn't supported, there are more mips than the
+    /// size allows, the total size overflows or `data` is too short.
+    pub fn subresources(&self) -> Result<Vec<Subresource>, String> {
+        let largest = self.width.max(self.height).max(self.depth);
+        let full_chain = 32 - largest.leading_zeros();
+        if self.mip_levels > full_chain {
+            return Err(format!(
+                "{} mips for a {}x{}x{} texture, at most {} fit",
+                self.mip_levels, self.width, self.height, self.depth, full_chain
+            ));
+        }
+
+        let info = FormatInfo::of(self.format)
+            .ok_or_else(|| format!("{} is not supported", format_name(self.format)))?;
+        let mut subresources = Vec::new();
+        let mut offset = 0usize;
+        let overflow = || {
+            format!(
+                "{}x{}x{} texture with {} slices is too large",
+                self.width, self.height, self.depth, self.array_size
+            )
+        };
+
+        for _ in 0..self.array_size {
+            for mip in 0..self.mip_levels {
+                let size = [
+                    mip_size(self.width, mip),
+                    mip_size(self.height, mip),
+                    mip_size(self.depth, mip),
+                ];
+                let row_pitch = info.row_pitch(size[0]);
+                let slice_pitch = row_pitch
+                    .checked_mul(info.rows(size[1]))
+                    .ok_or_else(overflow)?;
+
+                subresources.push(Subresource {
+                    offset,
+                    row_pitch,
+                    slice_pitch,
+                    size,
+                });
+                offset = slice_pitch
+                    .checked_mul(size[2] as usize)
+                    .and_then(|size| offset.checked_add(size))
+                    .ok_or_else(overflow)?;
+            }
+        }
+
+        if offset > self.data.len() {
+            return Err(format!(
+                "expected {} bytes of texture data, found {}",
+                offset,
+                self.data.len()
+            ));
+        }
+
+        Ok(subresources)
+    }
+
+    /// Creates an immutable shader resource with every subresource
+    /// initialized. Cubemaps get `D3D11_RESOURCE_MISC_TEXTURECUBE`, so a
+    /// default view samples them as cubes.
+    pub fn create(&self, backend: &Backend) -> EngineResult<LoadedTexture> {
+        let subresources = self.subresources().map_err(EngineError::Validation)?;
+        let initial_data: Vec<D3D11_SUBRESOURCE_DATA> = subresources
+            .iter()
+            .map(|subresource| D3D11_SUBRESOURCE_DATA {
+                pSysMem: self.data[subresource.offset..].as_ptr() as _,
+                SysMemPitch: subresource.row_pitch as u32,
+                SysMemSlicePitch: subresource.slice_pitch as u32,
+            })
+            .collect();
+
+        let mut builder = TextureDescBuilder::new()
+            .size([self.width, self.height, self.depth])
+            .mip_levels(self.mip_levels)
+            .array_size(self.array_size)
+            .format(self.format)
+            .usage(D3D11_USAGE_IMMUTABLE)
+            .bind_flags(D3D11_BIND_SHADER_RESOURCE);
+        if self.cubemap {
+            builder = builder.cube(self.array_size / 6);
+        }
+
+        match self.dimension {
+            TextureDimension::Texture2D => {
+                let desc = builder.build_texture2d();
+                let texture =
+                    unsafe { backend.device.CreateTexture2D(&desc, initial_data.as_ptr()) }
+                        .context("Create 2D texture")?;
+
+                Ok(LoadedTexture::Texture2D(Tex2D { texture, desc }))
+            }
+            TextureDimension::Texture3D => {
+                let desc = builder.build_texture3d();
+                let texture =
+                    unsafe { backend.device.CreateTexture3D(&desc, initial_data.as_ptr()) }
+                        .context("Create 3D texture")?;
+
+                Ok(LoadedTexture::Texture3D(Tex3D { texture, desc }))
+            }
+        }
+    }
+}
+
+impl LoadedTexture {
+    pub fn into_2d(self, path: &str) -> EngineResult<Tex2D> {
+        match self {
+            LoadedTexture::Texture2D(texture) => Ok(texture),
+            LoadedTexture::Texture3D(_) => Err(EngineError::TextureLoad {
+                path: path.to_owned(),
+                message: "expected a 2D texture, found a 3D one".to_owned(),
+            }),
+        }
+    }
+
+    pub fn into_3d(self, path: &str) -> EngineResult<Tex3D> {
+        match self {
+            LoadedTexture::Texture3D(texture) => Ok(texture),
+            LoadedTexture::Texture2D(_) => Err(EngineError::TextureLoad {
+                path: path.to_owned(),
+                message: "expected a 3D texture, found a 2D one".to_owned(),
+            }),
+        }
+    }
+}
@@ -0,0 +1,446 @@
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use windows::Win32::Graphics::Direct3D11::*;
+
+use crate::error::{Context, EngineError, EngineResult};
+
+use super::backend::Backend;
+use super::gpu_buffer::GPUBuffer;
+
+/// How a buffer is written after it is created.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferUsage {
+    /// Never written again. Needs initial data.
+    Immutable,
+    /// Written by the GPU, or by the CPU through `update` now and then.
+    Default,
+    /// Written by the CPU often, e.g. every frame. Can't be bound as a UAV.
+    Dynamic,
+}
+
+impl BufferUsage {
+    fn d3d11(&self) -> (D3D11_USAGE, D3D11_CPU_ACCESS_FLAG) {
+        match self {
+            BufferUsage::Immutable => (D3D11_USAGE_IMMUTABLE, 0),
+            BufferUsage::Default => (D3D11_USAGE_DEFAULT, 0),
+            BufferUsage::Dynamic => (D3D11_USAGE_DYNAMIC, D3D11_CPU_ACCESS_WRITE),
+        }
+    }
+}
+
+/// Plain data that can be copied to and from a buffer as raw bytes.
+///
+/// # Safety
+///
+/// Implementors must be `#[repr(C)]` or primitive, have no padding bytes,
+/// and be valid for any bit pattern, as `TypedBuffer::read` fills them from
+/// whatever the GPU wrote.
+pub unsafe trait Pod: Copy + 'static {}
+
+unsafe impl Pod for u16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for f32 {}
+unsafe impl Pod for Vec2 {}
+unsafe impl Pod for Vec3 {}
+unsafe impl Pod for Vec4 {}
+unsafe impl Pod for Mat4 {}
+
+/// A `GPUBuffer` holding `len` elements of `T`, laid out like the shader
+/// expects.
+///
+/// Buffers bound as shader resources or UAVs are created as structured
+/// buffers with a stride of `size_of::<T>()`.
+pub struct TypedBuffer<T> {
+    buffer: GPUBuffer,
+    len: u32,
+    usage: BufferUsage,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Clone for TypedBuffer<T> {
+    fn clone(&self) -> Self {
+        TypedBuffer {
+            buffer: self.buffer.clone(),
+            len: self.len,
+            usage: self.usage,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for TypedBuffer<T> {
+    type Target = GPUBuffer;
+
+    fn deref(&self) -> &GPUBuffer {
+        &self.buffer
+    }
+}
+
+impl<T: Pod> TypedBuffer<T> {
+    /// A buffer of `len` elements with undefined contents.
+    pub fn new(
+        backend: &Backend,
+        len: u32,
+        usage: BufferUsage,
+        bind_flags: D3D11_BIND_FLAG,
+    ) -> EngineResult<Self> {
+        if usage == BufferUsage::Immutable {
+            return Err(EngineError::Validation(
+                "an immutable buffer needs initial data".to_owned(),
+            ));
+        }
+
+        Self::create(backend, len, None, usage, bind_flags)
+    }
+
+    pub fn from_slice(
+        backend: &Backend,
+        data: &[T],
+        usage: BufferUsage,
+        bind_flags: D3D11_BIND_FLAG,
+    ) -> EngineResult<Self> {
+        Self::create(backend, data.len() as u32, Some(data), usage, bind_flags)
+    }
+
+    pub fn vertex_buffer(backend: &Backend, data: &[T], usage: BufferUsage) -> EngineResult<Self> {
+        Self::from_slice(backend, data, usage, D3D11_BIND_VERTEX_BUFFER)
+    }
+
+    pub fn index_buffer(backend: &Backend, data: &[T], usage: BufferUsage) -> EngineResult<Self> {
+        Self::from_slice(backend, data, usage, D3D11_BIND_INDEX_BUFFER)
+    }
+
+    /// A structured buffer compute shaders write to and other shaders read.
+    pub fn structured_buffer(backend: &Backend, len: u32) -> EngineResult<Self> {
+        Self::new(
+            backend,
+            len,
+            BufferUsage::Default,
+            D3D11_BIND_SHADER_RESOURCE | D3D11_BIND_UNORDERED_ACCESS,
+        )
+    }
+
+    fn create(
+        backend: &Backend,
+        len: u32,
+        data: Option<&[T]>,
+        usage: BufferUsage,
+        bind_flags: D3D11_BIND_FLAG,
+    ) -> EngineResult<Self> {
+        let desc = buffer_desc::<T>(len, usage, bind_flags)?;
+        let buffer = match data {
+            Some(data) => GPUBuffer::with_data(backend, desc, &padded(data, desc.ByteWidth))?,
+            None => GPUBuffer::new(backend, desc)?,
+        };
+
+        Ok(TypedBuffer {
+            buffer,
+            len,
+            usage,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn usage(&self) -> BufferUsage {
+        self.usage
+    }
+
+    pub fn buffer(&self) -> &GPUBuffer {
+        &self.buffer
+    }
+
+    /// Overwrites the elements starting at `offset` with `data`.
+    ///
+    /// Constant buffers can only be updated as a whole. Partial updates of
+    /// dynamic buffers don't discard the rest, so they must not touch data
+    /// the GPU may still be reading.
+    pub fn update(&self, backend: &Backend, offset: u32, data: &[T]) -> EngineResult<()> {
+        let stride = std::mem::size_of::<T>();
+        let constant = self.desc.BindFlags & D3D11_BIND_CONSTANT_BUFFER != 0;
+        let std::ops::Range { start, end } = update_range(self.len, offset, data.len(), constant)?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        let whole = start == 0 && end == self.len as usize;
+
+        match self.usage {
+            BufferUsage::Immutable => Err(EngineError::Validation(
+                "can't update an immutable buffer".to_owned(),
+            )),
+            BufferUsage::Default => {
+                let region = D3D11_BOX {
+                    left: (start * stride) as u32,
+                    right: (end * stride) as u32,
+                    top: 0,
+                    bottom: 1,
+                    front: 0,
+                    back: 1,
+                };
+                // Constant buffers don't take a region, so the source has to
+                // cover the padding too.
+                let (region, bytes): (*const D3D11_BOX, _) = if constant {
+                    (std::ptr::null(), padded(data, self.desc.ByteWidth))
+                } else {
+                    (&region, Cow::Borrowed(as_bytes(data)))
+                };
+
+                unsafe {
+                    backend.device_context.UpdateSubresource(
+                        &self.buffer.buffer,
+                        0,
+                        region,
+                        bytes.as_ptr() as _,
+                        0,
+                        0,
+                    );
+                }
+
+                Ok(())
+            }
+            BufferUsage::Dynamic => {
+                let map_type = if whole {
+                    D3D11_MAP_WRITE_DISCARD
+                } else {
+                    D3D11_MAP_WRITE_NO_OVERWRITE
+                };
+
+                let mapped = unsafe {
+                    backend
+                        .device_context
+                        .Map(&self.buffer.buffer, 0, map_type, 0)
+                }
+                .context("Map buffer")?;
+
+                unsafe {
+                    std::ptr::copy_nonoverlapping(
+                        data.as_ptr() as *const u8,
+                        (mapped.pData as *mut u8).add(start * stride),
+                        std::mem::size_of_val(data),
+                    );
+                    backend.device_context.Unmap(&self.buffer.buffer, 0);
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Copies the buffer into a staging buffer and reads it back. Waits for
+    /// the GPU to finish everything writing to it, so it's slow.
+    pub fn read(&self, backend: &Backend) -> EngineResult<Vec<T>> {
+        let staging = GPUBuffer::new(
+            backend,
+            D3D11_BUFFER_DESC {
+                Usage: D3D11_USAGE_STAGING,
+                BindFlags: 0,
+                CPUAccessFlags: D3D11_CPU_ACCESS_READ,
+                ..self.desc
+            },
+        )
+        .context("Create staging buffer")?;
+
+        unsafe {
+            backend
+                .device_context
+                .CopyResource(&staging.buffer, &self.buffer.buffer);
+        }
+
+        let mapped = unsafe {
+            backend
+                .device_context
+                .Map(&staging.buffer, 0, D3D11_MAP_READ, 0)
+        }
+        .context("Map staging buffer")?;
+
+        let len = self.len as usize;
+        let mut elements = Vec::<T>::with_capacity(len);
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                mapped.pData as *const u8,
+                elements.as_mut_ptr() as *mut u8,
+                len * std::mem::size_of::<T>(),
+            );
+            elements.set_len(len);
+            backend.device_context.Unmap(&staging.buffer, 0);
+        }
+
+        Ok(elements)
+    }
+}
+
+/// The description of a buffer of `len` elements of `T`.
+fn buffer_desc<T>(
+    len: u32,
+    usage: BufferUsage,
+    bind_flags: D3D11_BIND_FLAG,
+) -> EngineResult<D3D11_BUFFER_DESC> {
+    let stride = std::mem::size_of::<T>() as u32;
+    if stride == 0 || len == 0 {
+        return Err(EngineError::Validation(format!(
+            "can't create an empty buffer of {}",
+            std::any::type_name::<T>()
+        )));
+    }
+    if bind_flags & D3D11_BIND_UNORDERED_ACCESS != 0 && usage != BufferUsage::Default {
+        return Err(EngineError::Validation(format!(
+            "a {:?} buffer can't be bound as a UAV",
+            usage
+        )));
+    }
+    let size = stride.checked_mul(len).ok_or_else(|| {
+        EngineError::Validation(format!(
+            "{} elements of {} don't fit a buffer",
+            len,
+            std::any::type_name::<T>()
+        ))
+    })?;
+
+    let structured = bind_flags & (D3D11_BIND_SHADER_RESOURCE | D3D11_BIND_UNORDERED_ACCESS);
+    let (d3d11_usage, cpu_access) = usage.d3d11();
+    Ok(D3D11_BUFFER_DESC {
+        ByteWidth: byte_width(size, bind_flags),
+        Usage: d3d11_usage,
+        BindFlags: bind_flags,
+        CPUAccessFlags: cpu_access,
+        MiscFlags: if structured != 0 {
+            D3D11_RESOURCE_MISC_BUFFER_STRUCTURED
+        } else {
+            0
+        },
+        StructureByteStride: if structured != 0 { stride } else { 0 },
+    })
+}
+
+/// The elements a partial update of a buffer of `len` elements writes, or
+/// why it can't be done.
+fn update_range(
+    len: u32,
+    offset: u32,
+    count: usize,
+    constant: bool,
+) -> EngineResult<std::ops::Range<usize>> {
+    let start = offset as usize;
+    let end = start.saturating_add(count);
+    if end > len as usize {
+        return Err(EngineError::Validation(format!(
+            "updating elements {}..{} of a buffer of {}",
+            start, end, len
+        )));
+    }
+    if count > 0 && constant && !(start == 0 && end == len as usize) {
+        return Err(EngineError::Validation(
+            "constant buffers can only be updated as a whole".to_owned(),
+        ));
+    }
+
+    Ok(start..end)
+}
+
+/// Constant buffers have to be a multiple of 16 bytes.
+fn byte_width(size: u32, bind_flags: D3D11_BIND_FLAG) -> u32 {
+    if bind_flags & D3D11_BIND_CONSTANT_BUFFER != 0 {
+        size.div_ceil(16) * 16
+    } else {
+        size
+    }
+}
+
+fn as_bytes<T: Pod>(data: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data)) }
+}
+
+/// `data` as bytes, zero padded to `byte_width`.
+fn padded<T: Pod>(data: &[T], byte_width: u32) -> Cow<'_, [u8]> {
+    let bytes = as_bytes(data);
+    if bytes.len() >= byte_width as usize {
+        return Cow::Borrowed(bytes);
+    }
+
+    let mut padded = bytes.to_vec();
+    padded.resize(byte_width as usize, 0);
+    Cow::Owned(padded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_usage_to_d3d11_flags() {
+        let desc = buffer_desc::<u32>(4, BufferUsage::Immutable, D3D11_BIND_INDEX_BUFFER).unwrap();
+        assert_eq!(desc.Usage, D3D11_USAGE_IMMUTABLE);
+        assert_eq!(desc.CPUAccessFlags, 0);
+        assert_eq!(desc.BindFlags, D3D11_BIND_INDEX_BUFFER);
+        assert_eq!(
+            (desc.ByteWidth, desc.MiscFlags, desc.StructureByteStride),
+            (16, 0, 0)
+        );
+
+        let desc = buffer_desc::<Vec3>(
+            2,
+            BufferUsage::Default,
+            D3D11_BIND_SHADER_RESOURCE | D3D11_BIND_UNORDERED_ACCESS,
+        )
+        .unwrap();
+        assert_eq!(desc.Usage, D3D11_USAGE_DEFAULT);
+        assert_eq!(desc.CPUAccessFlags, 0);
+        assert_eq!(desc.MiscFlags, D3D11_RESOURCE_MISC_BUFFER_STRUCTURED);
+        assert_eq!((desc.ByteWidth, desc.StructureByteStride), (24, 12));
+
+        let desc =
+            buffer_desc::<Vec3>(1, BufferUsage::Dynamic, D3D11_BIND_CONSTANT_BUFFER).unwrap();
+        assert_eq!(desc.Usage, D3D11_USAGE_DYNAMIC);
+        assert_eq!(desc.CPUAccessFlags, D3D11_CPU_ACCESS_WRITE);
+        // Padded to a whole constant register.
+        assert_eq!(desc.ByteWidth, 16);
+    }
+
+    #[test]
+    fn rejects_invalid_buffers() {
+        assert!(buffer_desc::<u32>(0, BufferUsage::Default, D3D11_BIND_VERTEX_BUFFER).is_err());
+        assert!(buffer_desc::<()>(4, BufferUsage::Default, D3D11_BIND_VERTEX_BUFFER).is_err());
+        assert!(buffer_desc::<u32>(4, BufferUsage::Dynamic, D3D11_BIND_UNORDERED_ACCESS).is_err());
+        assert!(
+            buffer_desc::<u32>(4, BufferUsage::Immutable, D3D11_BIND_UNORDERED_ACCESS).is_err()
+        );
+        assert!(
+            buffer_desc::<Vec4>(u32::MAX, BufferUsage::Default, D3D11_BIND_VERTEX_BUFFER).is_err()
+        );
+    }
+
+    #[test]
+    fn checks_update_ranges() {
+        assert_eq!(update_range(8, 0, 8, false).unwrap(), 0..8);
+        assert_eq!(update_range(8, 2, 3, false).unwrap(), 2..5);
+        assert_eq!(update_range(8, 8, 0, false).unwrap(), 8..8);
+        assert!(update_range(8, 6, 3, false).is_err());
+        assert!(update_range(8, 9, 0, false).is_err());
+        assert!(update_range(8, u32::MAX, usize::MAX, false).is_err());
+
+        // Constant buffers only take whole updates, or empty ones.
+        assert_eq!(update_range(4, 0, 4, true).unwrap(), 0..4);
+        assert!(update_range(4, 0, 3, true).is_err());
+        assert!(update_range(4, 1, 3, true).is_err());
+        assert_eq!(update_range(4, 1, 0, true).unwrap(), 1..1);
+    }
+
+    #[test]
+    fn pads_data_to_the_byte_width() {
+        let data = [1u16, 2, 3];
+        assert_eq!(&*padded(&data, 6), &[1, 0, 2, 0, 3, 0]);
+        assert_eq!(&*padded(&data, 8), &[1, 0, 2, 0, 3, 0, 0, 0]);
+        assert_eq!(byte_width(20, D3D11_BIND_CONSTANT_BUFFER), 32);
+        assert_eq!(byte_width(20, D3D11_BIND_VERTEX_BUFFER), 20);
+    }
+}
@@ -1,16 +1,33 @@
 use std::collections::HashMap;
 
+use glam::Vec3;
+use windows::Win32::Graphics::Direct3D11::ID3D11ShaderResourceView;
+
 use crate::camera::Camera;
+use crate::error::{Context, EngineResult};
 use crate::object::{Flag, GameObject, SimpleMesh};
 use crate::render_backend::backend::Backend;
-use crate::render_backend::mesh::CpuMesh;
-use crate::render_backend::texture::Tex2D;
-use crate::render_backend::{render_pass::RenderPass, texture::Tex};
+use crate::render_backend::gltf::{GltfLight, GltfScene};
+use crate::render_backend::material::solid_color_texture;
+use crate::render_backend::mesh::ObjModel;
+use crate::render_backend::mipmaps::MipGenerator;
+use crate::render_backend::render_pass::RenderPass;
+use crate::render_backend::texture::{Tex, Tex2D};
 
 pub struct Scene {
     pub materials: HashMap<u32, RenderPass>, // For objects doing forward rendering directly to backbuffer
     pub objects: Vec<Box<dyn GameObject>>,
     pub camera: Camera,
+    pub lights: Vec<SceneLight>,
+}
+
+/// A light placed in the world. Nothing shades with these yet.
+#[derive(Clone, Debug)]
+pub struct SceneLight {
+    pub light: GltfLight,
+    pub position: Vec3,
+    /// Where directional and spot lights shine.
+    pub direction: Vec3,
 }
 
 impl Scene {
@@ -19,32 +36,138 @@ impl Scene {
     }
 }
 
-pub fn create_minecraft_scene(backend: &Backend) -> Scene {
-    //let world = &CpuMesh::from_obj("F:\\Models\\lost-empire\\lost_empire_triangulated.obj")
-    //    .expect("Load obj")[0];
+pub fn create_minecraft_scene(backend: &Backend) -> EngineResult<Scene> {
+    //let world = ObjModel::load("F:\\Models\\lost-empire\\lost_empire.obj")?;
+
+    let mut world = ObjModel::load("models/vokselia_spawn/vokselia_spawn_triangulated.obj")?;
+    for submesh in &mut world.submeshes {
+        submesh.mesh.optimize();
+    }
+
+    // One SRV per material, submeshes of other objects share them.
+    let material_srvs = world
+        .materials
+        .iter()
+        .map(|material| {
+            let albedo = material.diffuse_texture(backend)?;
+            backend
+                .shader_resource_view(&albedo, None)
+                .context("Create albedo srv")
+        })
+        .collect::<EngineResult<Vec<_>>>()?;
+    // Faces without a material keep the albedo the scene always used.
+    let default_srv = backend
+        .shader_resource_view(
+            &Tex2D::from_file(backend, "models/vokselia_spawn/vokselia_spawn.png")?,
+            None,
+        )
+        .context("Create albedo srv")?;
+
+    let mut objects: Vec<Box<dyn GameObject>> = Vec::new();
+    for submesh in &world.submeshes {
+        let uploaded = submesh.mesh.upload(backend).context("Upload world mesh")?;
+
+        let mut object = SimpleMesh::new(backend, uploaded)?;
+        let srv = submesh
+            .material
+            .map_or(&default_srv, |index| &material_srvs[index]);
+        object.textures.push(srv.clone());
 
-    //let albedo = Tex2D::from_file(backend, "F:\\Models\\lost-empire\\lost_empire-RGB.png")
-    //    .expect("Load albedo texture");
+        objects.push(Box::new(object));
+    }
+
+    Ok(Scene {
+        materials: HashMap::new(),
+        objects,
+        camera: Camera::new(backend)?,
+        lights: Vec::new(),
+    })
+}
 
-    let world = &CpuMesh::from_obj("F:\\Models\\vokselia_spawn\\vokselia_spawn_triangulated.obj")
-        .expect("Load obj")[0];
+/// An object per mesh primitive of a glTF file, placed by its node, with
+/// the first camera of the scene and every light. Only base colors are
+/// used so far.
+pub fn create_gltf_scene(backend: &Backend, file: &str) -> EngineResult<Scene> {
+    let mut gltf = GltfScene::load(file)?;
+    for primitive in gltf.meshes.iter_mut().flat_map(|mesh| &mut mesh.primitives) {
+        primitive.mesh.optimize();
+    }
 
-    let albedo = Tex2D::from_file(backend, "F:\\Models\\vokselia_spawn\\vokselia_spawn.png")
-        .expect("Load albedo texture");
+    // Images are shared between materials, decode each once.
+    let mut image_srvs: HashMap<usize, ID3D11ShaderResourceView> = HashMap::new();
+    let mut objects: Vec<Box<dyn GameObject>> = Vec::new();
+    let mut camera = None;
+    let mut lights = Vec::new();
 
-    let albedo_srv = backend
-        .shader_resource_view(&albedo, None)
-        .expect("albedo SRV");
+    for node in gltf.scene_nodes().into_iter().map(|node| &gltf.nodes[node]) {
+        for primitive in node
+            .mesh
+            .iter()
+            .flat_map(|mesh| &gltf.meshes[*mesh].primitives)
+        {
+            let material = primitive
+                .material
+                .and_then(|material| gltf.materials.get(material))
+                .cloned()
+                .unwrap_or_default();
 
-    let uploaded_world = world.upload(backend).expect("Upload mesh");
+            let srv = match material.base_color_texture {
+                Some(texture) if image_srvs.contains_key(&texture.image) => {
+                    image_srvs[&texture.image].clone()
+                }
+                Some(texture) => {
+                    let image = gltf.image(texture.image)?;
+                    let albedo = MipGenerator::new()
+                        .srgb(true)
+                        .texture_data(&image)
+                        .create(backend)?
+                        .into_2d(&gltf.images[texture.image].name)?;
+                    let srv = backend
+                        .shader_resource_view(&albedo, None)
+                        .context("Create albedo srv")?;
+                    image_srvs.insert(texture.image, srv.clone());
+                    srv
+                }
+                None => {
+                    let albedo =
+                        solid_color_texture(backend, &material.name, material.base_color.into())?;
+                    backend
+                        .shader_resource_view(&albedo, None)
+                        .context("Create albedo srv")?
+                }
+            };
 
-    let mut world_object = SimpleMesh::new(backend, uploaded_world);
+            let uploaded = primitive.mesh.upload(backend).context("Upload glTF mesh")?;
+            let mut object = SimpleMesh::new(backend, uploaded)?;
+            object.transform = node.world;
+            object.textures.push(srv);
+            objects.push(Box::new(object));
+        }
 
-    world_object.textures.push(albedo_srv);
+        if let (Some(index), None) = (node.camera, &camera) {
+            let projection = gltf.cameras[index].projection_matrix(1920.0 / 1080.0);
+            camera = Some(Camera::from_view_projection(
+                backend,
+                projection * node.world.inverse(),
+            )?);
+        }
 
-    Scene {
-        materials: HashMap::new(),
-        objects: vec![Box::new(world_object)],
-        camera: Camera::new(backend),
+        if let Some(index) = node.light {
+            lights.push(SceneLight {
+                light: gltf.lights[index].clone(),
+                position: node.world.transform_point3(Vec3::ZERO),
+                direction: node.world.transform_vector3(Vec3::Z).normalize_or_zero(),
+            });
+        }
     }
+
+    Ok(Scene {
+        materials: HashMap::new(),
+        objects,
+        camera: match camera {
+            Some(camera) => camera,
+            None => Camera::new(backend)?,
+        },
+        lights,
+    })
 }
@@ -8,6 +8,7 @@ use crate::render_backend::{
     backend::Backend,
     gpu_buffer::GPUBuffer,
     mesh::*,
+    pipeline_state::{DepthStencilDesc, SamplerDesc},
     render_pass::RenderPass,
     shader::Shader,
     texture::{Tex, Tex2D, TextureDescBuilder},
@@ -117,63 +118,9 @@ impl SimpleTriangleScene {
                 .IASetPrimitiveTopology(D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST)
         }
 
-        let mesh_input_desc = [
-            D3D11_INPUT_ELEMENT_DESC {
-                SemanticName: PSTR(b"POSITION\0".as_ptr() as _),
-                SemanticIndex: 0,
-                Format: DXGI_FORMAT_R32G32B32_FLOAT,
-                InputSlot: 0,
-                AlignedByteOffset: 0,
-                InputSlotClass: D3D11_INPUT_PER_VERTEX_DATA,
-                InstanceDataStepRate: 0,
-            },
-            D3D11_INPUT_ELEMENT_DESC {
-                SemanticName: PSTR(b"NORMAL\0".as_ptr() as _),
-                SemanticIndex: 0,
-                Format: DXGI_FORMAT_R32G32B32_FLOAT,
-                InputSlot: 0,
-                AlignedByteOffset: 12,
-                InputSlotClass: D3D11_INPUT_PER_VERTEX_DATA,
-                InstanceDataStepRate: 0,
-            },
-            D3D11_INPUT_ELEMENT_DESC {
-                SemanticName: PSTR(b"TEXCOORD\0".as_ptr() as _),
-                SemanticIndex: 0,
-                Format: DXGI_FORMAT_R32G32_FLOAT,
-                InputSlot: 0,
-                AlignedByteOffset: 24,
-                InputSlotClass: D3D11_INPUT_PER_VERTEX_DATA,
-                InstanceDataStepRate: 0,
-            },
-        ];
-
-        let depth_stencil_desc = D3D11_DEPTH_STENCIL_DESC {
-            DepthEnable: true.into(),
-            DepthWriteMask: D3D11_DEPTH_WRITE_MASK_ALL,
-            DepthFunc: D3D11_COMPARISON_LESS,
-            StencilEnable: true.into(),
-            StencilReadMask: 0xFF,
-            StencilWriteMask: 0xFF,
-            FrontFace: D3D11_DEPTH_STENCILOP_DESC {
-                StencilFailOp: D3D11_STENCIL_OP_KEEP,
-                StencilDepthFailOp: D3D11_STENCIL_OP_INCR,
-                StencilPassOp: D3D11_STENCIL_OP_KEEP,
-                StencilFunc: D3D11_COMPARISON_ALWAYS,
-            },
-            BackFace: D3D11_DEPTH_STENCILOP_DESC {
-                StencilFailOp: D3D11_STENCIL_OP_KEEP,
-                StencilDepthFailOp: D3D11_STENCIL_OP_DECR,
-                StencilPassOp: D3D11_STENCIL_OP_KEEP,
-                StencilFunc: D3D11_COMPARISON_ALWAYS,
-            },
-        };
-
-        let depth_stencil_state = unsafe {
-            backend
-                .device
-                .CreateDepthStencilState(&depth_stencil_desc)
-                .expect("Create depth stencil state")
-        };
+        let depth_stencil_state = backend
+            .depth_stencil_state(&DepthStencilDesc::depth_less_stencil_count())
+            .expect("Create depth stencil state");
 
         let depth_texture = Tex2D::new(
             &backend,
@@ -242,25 +189,9 @@ impl SimpleTriangleScene {
             .shader_resource_view(&normal_texture, None)
             .expect("Create normal srv");
 
-        let sampler_state_desc = D3D11_SAMPLER_DESC {
-            Filter: D3D11_FILTER_MIN_MAG_MIP_LINEAR,
-            AddressU: D3D11_TEXTURE_ADDRESS_WRAP,
-            AddressV: D3D11_TEXTURE_ADDRESS_WRAP,
-            AddressW: D3D11_TEXTURE_ADDRESS_WRAP,
-            MipLODBias: 0.0,
-            MaxAnisotropy: 1,
-            ComparisonFunc: D3D11_COMPARISON_ALWAYS,
-            BorderColor: [0.0, 0.0, 0.0, 0.0],
-            MinLOD: 0.0,
-            MaxLOD: D3D11_FLOAT32_MAX,
-        };
-
-        let sampler_state = unsafe {
-            backend
-                .device
-                .CreateSamplerState(&sampler_state_desc)
-                .expect("Creating sampler")
-        };
+        let sampler_state = backend
+            .sampler_state(&SamplerDesc::linear_wrap())
+            .expect("Creating sampler");
 
         let gbuffer_pass = RenderPass::new()
             .enable_depth(true)
@@ -274,13 +205,15 @@ impl SimpleTriangleScene {
                 &backend,
                 Shader::vertex_shader(&backend, "gbuffer.hlsl", "vertex")
                     .expect("Create vertex shader"),
-                &mesh_input_desc,
+                &Vertex::LAYOUT,
                 std::mem::size_of::<Vertex>() as u32,
             )
+            .expect("Attach vertex shader")
             .pixel_shader(
                 Shader::pixel_shader(&backend, "gbuffer.hlsl", "pixel")
                     .expect("Create pixel shader"),
-            );
+            )
+            .expect("Attach pixel shader");
         //.execution(Box::new(move |_, backend, mesh: &GpuMesh| {
         //unsafe {
         //backend.device_context.IASetIndexBuffer(
@@ -323,10 +256,12 @@ impl SimpleTriangleScene {
                 &[],
                 0,
             )
+            .expect("Attach vertex shader")
             .pixel_shader(
                 Shader::pixel_shader(&backend, "fragment_shader.hlsl", "main")
                     .expect("Creating pixel shader"),
             )
+            .expect("Attach pixel shader")
             .execution(Box::new(move |_, backend, _| {
                 unsafe {
                     backend.device_context.Draw(6, 0);
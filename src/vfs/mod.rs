@@ -0,0 +1,470 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock, RwLockReadGuard};
+
+pub mod zip;
+
+pub use self::zip::ZipMount;
+
+/// Turns a path into the form mounts are keyed by: `/` separators, no `.`
+/// or `..` components and no trailing separator. Relative paths may not
+/// climb above the root. Absolute paths keep their root and are read from
+/// disk as is, bypassing the mounts.
+pub fn normalize(path: &str) -> io::Result<String> {
+    let path = path.replace('\\', "/");
+
+    let (root, rest) = if let Some(rest) = path.strip_prefix('/') {
+        ("/", rest)
+    } else if path.len() >= 3 && path.as_bytes()[1] == b':' && path.as_bytes()[2] == b'/' {
+        path.split_at(3)
+    } else {
+        ("", path.as_str())
+    };
+
+    let mut components: Vec<&str> = Vec::new();
+    for component in rest.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                if components.pop().is_none() && root.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} points outside of the asset root", path),
+                    ));
+                }
+            }
+            component => components.push(component),
+        }
+    }
+
+    Ok(format!("{}{}", root, components.join("/")))
+}
+
+fn is_absolute(normalized: &str) -> bool {
+    normalized.starts_with('/') || normalized.as_bytes().get(1) == Some(&b':')
+}
+
+/// Directory part of a normalized path, `""` for a path in the root.
+pub fn parent(path: &str) -> &str {
+    path.rfind('/').map_or("", |index| &path[..index])
+}
+
+/// Joins a relative path onto a directory and normalizes the result.
+pub fn join(dir: &str, path: &str) -> io::Result<String> {
+    if dir.is_empty() {
+        normalize(path)
+    } else {
+        normalize(&format!("{}/{}", dir, path))
+    }
+}
+
+/// A source of files addressed by normalized relative paths.
+pub trait Mount: Send + Sync {
+    /// `None` if the mount doesn't have `path`, so the next one is tried.
+    fn read(&self, path: &str) -> Option<io::Result<Vec<u8>>>;
+    fn exists(&self, path: &str) -> bool;
+
+    /// Where `path` lives on disk, if it does, e.g. for watching it.
+    fn real_path(&self, _path: &str) -> Option<PathBuf> {
+        None
+    }
+
+    /// Shown in error messages.
+    fn describe(&self) -> String;
+}
+
+pub struct DirectoryMount {
+    root: PathBuf,
+}
+
+impl DirectoryMount {
+    pub fn new(root: impl Into<PathBuf>) -> DirectoryMount {
+        DirectoryMount { root: root.into() }
+    }
+}
+
+impl Mount for DirectoryMount {
+    fn read(&self, path: &str) -> Option<io::Result<Vec<u8>>> {
+        let file = self.real_path(path)?;
+
+        Some(fs::read(file))
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.root.join(path).is_file()
+    }
+
+    fn real_path(&self, path: &str) -> Option<PathBuf> {
+        let file = self.root.join(path);
+
+        file.is_file().then_some(file)
+    }
+
+    fn describe(&self) -> String {
+        self.root.display().to_string()
+    }
+}
+
+/// Files compiled into the executable, usually through `embed_assets!`.
+#[derive(Default)]
+pub struct EmbeddedMount {
+    files: HashMap<String, &'static [u8]>,
+}
+
+impl EmbeddedMount {
+    pub fn new(files: impl IntoIterator<Item = (&'static str, &'static [u8])>) -> EmbeddedMount {
+        EmbeddedMount {
+            files: files
+                .into_iter()
+                .filter_map(|(path, contents)| Some((normalize(path).ok()?, contents)))
+                .collect(),
+        }
+    }
+}
+
+impl Mount for EmbeddedMount {
+    fn read(&self, path: &str) -> Option<io::Result<Vec<u8>>> {
+        self.files.get(path).map(|contents| Ok(contents.to_vec()))
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.files.contains_key(path)
+    }
+
+    fn describe(&self) -> String {
+        format!("{} embedded files", self.files.len())
+    }
+}
+
+/// Compiles files, given relative to the crate root, into an `EmbeddedMount`.
+#[macro_export]
+macro_rules! embed_assets {
+    ($($path:literal),* $(,)?) => {
+        $crate::vfs::EmbeddedMount::new([
+            $(($path, include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $path)) as &'static [u8]),)*
+        ])
+    };
+}
+
+/// Mounts searched in the order they were added. A mount with a prefix
+/// only sees paths under it, with the prefix stripped, e.g. a directory
+/// mounted at `models` serves `models/cube.obj` as `cube.obj`.
+#[derive(Default)]
+pub struct Vfs {
+    mounts: Vec<(String, Box<dyn Mount>)>,
+}
+
+impl Vfs {
+    pub fn new() -> Vfs {
+        Default::default()
+    }
+
+    pub fn mount(mut self, prefix: &str, mount: impl Mount + 'static) -> Self {
+        let prefix = normalize(prefix).expect("Mount prefix must be a relative path");
+        self.mounts.push((prefix, Box::new(mount)));
+
+        self
+    }
+
+    pub fn mount_dir(self, prefix: &str, dir: impl Into<PathBuf>) -> Self {
+        self.mount(prefix, DirectoryMount::new(dir))
+    }
+
+    pub fn mount_archive(self, prefix: &str, archive: impl Into<PathBuf>) -> io::Result<Self> {
+        Ok(self.mount(prefix, ZipMount::open(archive)?))
+    }
+
+    /// The mounts the engine starts with, in search order:
+    ///
+    /// 1. `$RUST_D3D11_ASSETS`, if set
+    /// 2. the directory of the executable, then `assets.pak` next to it
+    /// 3. the crate root, in builds without the `shipping` feature
+    /// 4. the shader sources compiled in, in builds with it
+    ///
+    /// `$RUST_D3D11_MODELS`, if set, is mounted at `models`.
+    pub fn from_environment() -> Vfs {
+        let mut vfs = Vfs::new();
+
+        if let Some(models) = env::var_os("RUST_D3D11_MODELS") {
+            vfs = vfs.mount_dir("models", models);
+        }
+        if let Some(assets) = env::var_os("RUST_D3D11_ASSETS") {
+            vfs = vfs.mount_dir("", assets);
+        }
+
+        if let Some(exe_dir) = env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(Path::to_path_buf))
+        {
+            let pak = exe_dir.join("assets.pak");
+            vfs = vfs.mount_dir("", exe_dir);
+            if pak.is_file() {
+                match ZipMount::open(&pak) {
+                    Ok(archive) => vfs = vfs.mount("", archive),
+                    Err(err) => eprintln!("Not mounting {}: {}", pak.display(), err),
+                }
+            }
+        }
+
+        #[cfg(feature = "shipping")]
+        {
+            vfs = vfs.mount("", embedded_shaders());
+        }
+        #[cfg(not(feature = "shipping"))]
+        {
+            vfs = vfs.mount_dir("", env!("CARGO_MANIFEST_DIR"));
+        }
+
+        vfs
+    }
+
+    fn mounts_for<'a>(&'a self, path: &'a str) -> impl Iterator<Item = (&'a dyn Mount, &'a str)> {
+        self.mounts.iter().filter_map(move |(prefix, mount)| {
+            let inner = if prefix.is_empty() {
+                path
+            } else {
+                path.strip_prefix(prefix.as_str())?.strip_prefix('/')?
+            };
+
+            Some((mount.as_ref(), inner))
+        })
+    }
+
+    pub fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        let normalized = normalize(path)?;
+        if is_absolute(&normalized) {
+            return fs::read(&normalized);
+        }
+
+        let contents = self
+            .mounts_for(&normalized)
+            .find_map(|(mount, inner)| mount.read(inner));
+
+        contents.unwrap_or_else(|| Err(self.not_found(path)))
+    }
+
+    pub fn read_to_string(&self, path: &str) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        match normalize(path) {
+            Ok(normalized) if is_absolute(&normalized) => Path::new(&normalized).is_file(),
+            Ok(normalized) => self
+                .mounts_for(&normalized)
+                .any(|(mount, inner)| mount.exists(inner)),
+            Err(_) => false,
+        }
+    }
+
+    /// The file on disk `path` is read from, if it isn't in an archive or embedded.
+    pub fn real_path(&self, path: &str) -> Option<PathBuf> {
+        let normalized = normalize(path).ok()?;
+        if is_absolute(&normalized) {
+            return Some(PathBuf::from(normalized));
+        }
+
+        let real_path = self
+            .mounts_for(&normalized)
+            .find(|(mount, inner)| mount.exists(inner))
+            .and_then(|(mount, inner)| mount.real_path(inner));
+
+        real_path
+    }
+
+    fn not_found(&self, path: &str) -> io::Error {
+        let searched: Vec<String> = self
+            .mounts
+            .iter()
+            .map(|(prefix, mount)| format!("{}={}", prefix, mount.describe()))
+            .collect();
+
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} not found in [{}]", path, searched.join(", ")),
+        )
+    }
+}
+
+#[cfg(feature = "shipping")]
+fn embedded_shaders() -> EmbeddedMount {
+    embed_assets![
+        "atmosphere_common.hlsli",
+        "atmospheric_precompute_copy_irradiance.hlsl",
+        "atmospheric_precompute_copy_single_inscatter.hlsl",
+        "atmospheric_precompute_copy_transmittance.hlsl",
+        "atmospheric_precompute_single_inscatter.hlsl",
+        "atmospheric_precompute_single_irradiance.hlsl",
+        "atmospheric_precompute_transmittance.hlsl",
+        "fragment_shader.hlsl",
+        "gbuffer.hlsl",
+        "vertex_shader.hlsl",
+    ]
+}
+
+static GLOBAL: OnceLock<RwLock<Vfs>> = OnceLock::new();
+
+fn global_lock() -> &'static RwLock<Vfs> {
+    GLOBAL.get_or_init(|| RwLock::new(Vfs::from_environment()))
+}
+
+/// Replaces the file system every asset loader reads through.
+pub fn install(vfs: Vfs) {
+    *global_lock().write().expect("VFS lock poisoned") = vfs;
+}
+
+pub fn global() -> RwLockReadGuard<'static, Vfs> {
+    global_lock().read().expect("VFS lock poisoned")
+}
+
+pub fn read(path: &str) -> io::Result<Vec<u8>> {
+    global().read(path)
+}
+
+pub fn read_to_string(path: &str) -> io::Result<String> {
+    global().read_to_string(path)
+}
+
+pub fn exists(path: &str) -> bool {
+    global().exists(path)
+}
+
+pub fn real_path(path: &str) -> Option<PathBuf> {
+    global().real_path(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embedded(files: &[(&'static str, &'static [u8])]) -> EmbeddedMount {
+        EmbeddedMount::new(files.iter().copied())
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        let normalized = |path: &str| normalize(path).unwrap();
+        assert_eq!(normalized("models/cube.obj"), "models/cube.obj");
+        assert_eq!(
+            normalized("models\\textures\\wood.png"),
+            "models/textures/wood.png"
+        );
+        assert_eq!(normalized("./models//./cube.obj/"), "models/cube.obj");
+        assert_eq!(normalized("models/textures/../cube.obj"), "models/cube.obj");
+        assert_eq!(normalized("models/.."), "");
+        assert_eq!(normalized(""), "");
+
+        // Relative paths can't climb above the root.
+        assert!(normalize("..").is_err());
+        assert!(normalize("models/../../secrets.txt").is_err());
+        assert!(normalize("..\\secrets.txt").is_err());
+
+        // Absolute paths keep their root, which `..` stops at.
+        assert_eq!(normalized("/usr/share/../lib"), "/usr/lib");
+        assert_eq!(normalized("/.."), "/");
+        assert_eq!(normalized("C:\\Assets\\..\\cube.obj"), "C:/cube.obj");
+        assert_eq!(normalized("c:/../cube.obj"), "c:/cube.obj");
+        assert!(is_absolute(&normalized("/usr/lib")));
+        assert!(is_absolute(&normalized("C:\\cube.obj")));
+        assert!(!is_absolute(&normalized("models/cube.obj")));
+    }
+
+    #[test]
+    fn joins_and_splits_paths() {
+        assert_eq!(parent("models/textures/wood.png"), "models/textures");
+        assert_eq!(parent("cube.obj"), "");
+        assert_eq!(parent("/cube.obj"), "");
+
+        assert_eq!(join("models", "cube.mtl").unwrap(), "models/cube.mtl");
+        assert_eq!(join("", "cube.mtl").unwrap(), "cube.mtl");
+        assert_eq!(join("models/a", "../b/./c.png").unwrap(), "models/b/c.png");
+        assert_eq!(
+            join("models", "textures\\wood.png").unwrap(),
+            "models/textures/wood.png"
+        );
+        assert!(join("models", "../../outside.png").is_err());
+        assert!(join("", "../outside.png").is_err());
+    }
+
+    #[test]
+    fn searches_mounts_in_order() {
+        let vfs = Vfs::new()
+            .mount("", embedded(&[("shader.hlsl", b"first")]))
+            .mount(
+                "",
+                embedded(&[("shader.hlsl", b"second"), ("only_second.hlsl", b"2")]),
+            );
+
+        assert_eq!(vfs.read("shader.hlsl").unwrap(), b"first");
+        assert_eq!(vfs.read("./shader.hlsl").unwrap(), b"first");
+        assert_eq!(vfs.read("only_second.hlsl").unwrap(), b"2");
+        assert!(vfs.exists("only_second.hlsl"));
+        assert!(!vfs.exists("missing.hlsl"));
+        assert!(!vfs.exists("../shader.hlsl"));
+
+        let err = vfs.read("missing.hlsl").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("1 embedded files"));
+        assert_eq!(
+            vfs.read("../shader.hlsl").unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn strips_mount_prefixes() {
+        let vfs = Vfs::new()
+            .mount("models/", embedded(&[("cube.obj", b"prefixed")]))
+            .mount(
+                "",
+                embedded(&[("models/cube.obj", b"root"), ("cube.obj", b"bare")]),
+            );
+
+        assert_eq!(vfs.read("models/cube.obj").unwrap(), b"prefixed");
+        assert_eq!(vfs.read("models\\cube.obj").unwrap(), b"prefixed");
+        // Unprefixed paths only reach mounts without a prefix.
+        assert_eq!(vfs.read("cube.obj").unwrap(), b"bare");
+        // The prefix has to be a whole directory.
+        let vfs = Vfs::new().mount("models", embedded(&[("cube.obj", b"prefixed")]));
+        assert!(!vfs.exists("modelscube.obj"));
+        assert!(!vfs.exists("models"));
+        assert!(vfs.exists("models/cube.obj"));
+    }
+
+    #[test]
+    fn reads_directories_and_absolute_paths() {
+        let dir = env::temp_dir().join(format!("vfs_test_{}", std::process::id()));
+        fs::create_dir_all(dir.join("textures")).unwrap();
+        fs::write(dir.join("textures").join("wood.png"), b"wood").unwrap();
+
+        let vfs = Vfs::new().mount_dir("assets", &dir);
+        assert_eq!(vfs.read("assets/textures/wood.png").unwrap(), b"wood");
+        assert!(vfs.exists("assets/textures/wood.png"));
+        // Directories aren't files.
+        assert!(!vfs.exists("assets/textures"));
+        assert_eq!(
+            vfs.real_path("assets/textures/wood.png"),
+            Some(dir.join("textures/wood.png"))
+        );
+
+        // Absolute paths bypass the mounts.
+        let absolute = dir.join("textures").join("wood.png");
+        let absolute = absolute.to_str().unwrap();
+        assert_eq!(Vfs::new().read(absolute).unwrap(), b"wood");
+        assert!(Vfs::new().exists(absolute));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn embedded_mounts_normalize_their_paths() {
+        let mount = embedded(&[("shaders\\gbuffer.hlsl", b"hlsl"), ("../outside", b"")]);
+        assert!(mount.exists("shaders/gbuffer.hlsl"));
+        assert_eq!(mount.describe(), "1 embedded files");
+        assert!(mount.real_path("shaders/gbuffer.hlsl").is_none());
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{normalize, Mount};
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+struct Entry {
+    method: u16,
+    compressed_size: usize,
+    uncompressed_size: usize,
+    local_header: usize,
+}
+
+/// A zip (or renamed `.pak`) archive, read into memory when mounted. Only
+/// stored and deflated entries are supported, which covers every common
+/// archiver's defaults; zip64 and encryption are not.
+pub struct ZipMount {
+    archive: PathBuf,
+    data: Vec<u8>,
+    entries: HashMap<String, Entry>,
+}
+
+fn invalid(archive: &Path, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", archive.display(), message),
+    )
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+impl ZipMount {
+    pub fn open(archive: impl Into<PathBuf>) -> io::Result<ZipMount> {
+        let archive = archive.into();
+        let data = fs::read(&archive)?;
+
+        Self::from_bytes(archive, data)
+    }
+
+    /// `archive` is only used in error messages.
+    pub fn from_bytes(archive: impl Into<PathBuf>, data: Vec<u8>) -> io::Result<ZipMount> {
+        let archive = archive.into();
+        let entries = read_central_directory(&data).ok_or_else(|| {
+            invalid(
+                &archive,
+                "not a zip archive or zip64, which isn't supported",
+            )
+        })?;
+
+        Ok(ZipMount {
+            archive,
+            data,
+            entries,
+        })
+    }
+
+    fn extract(&self, entry: &Entry) -> io::Result<Vec<u8>> {
+        let data = &self.data;
+        let header = entry.local_header;
+        if u32_at(data, header) != Some(LOCAL_HEADER) {
+            return Err(invalid(&self.archive, "corrupt local file header"));
+        }
+
+        let name_len = u16_at(data, header + 26).unwrap_or(0) as usize;
+        let extra_len = u16_at(data, header + 28).unwrap_or(0) as usize;
+        let start = header + 30 + name_len + extra_len;
+        let compressed = data
+            .get(start..start + entry.compressed_size)
+            .ok_or_else(|| invalid(&self.archive, "entry extends past the end of the archive"))?;
+
+        let contents = match entry.method {
+            STORED => compressed.to_vec(),
+            // The output buffer doubles as it grows and inflating fails once
+            // that would pass the limit, so entries exactly at it need twice
+            // the room. The size is checked below.
+            DEFLATED => miniz_oxide::inflate::decompress_to_vec_with_limit(
+                compressed,
+                entry.uncompressed_size.saturating_mul(2),
+            )
+            .map_err(|err| invalid(&self.archive, &format!("inflate failed: {:?}", err)))?,
+            method => {
+                return Err(invalid(
+                    &self.archive,
+                    &format!("unsupported compression method {}", method),
+                ))
+            }
+        };
+
+        if contents.len() != entry.uncompressed_size {
+            return Err(invalid(&self.archive, "entry has the wrong size"));
+        }
+
+        Ok(contents)
+    }
+}
+
+fn read_central_directory(data: &[u8]) -> Option<HashMap<String, Entry>> {
+    // The end record sits at the very end, followed by a comment of up to 64 KiB.
+    let search_start = data.len().saturating_sub(22 + u16::MAX as usize);
+    let end = (search_start..data.len().saturating_sub(21))
+        .rev()
+        .find(|&offset| u32_at(data, offset) == Some(END_OF_CENTRAL_DIRECTORY))?;
+
+    let count = u16_at(data, end + 10)? as usize;
+    let mut offset = u32_at(data, end + 16)? as usize;
+    if count == 0xFFFF || offset == 0xFFFF_FFFF {
+        return None;
+    }
+
+    let mut entries = HashMap::new();
+    for _ in 0..count {
+        if u32_at(data, offset)? != CENTRAL_HEADER {
+            return None;
+        }
+
+        let method = u16_at(data, offset + 10)?;
+        let compressed_size = u32_at(data, offset + 20)? as usize;
+        let uncompressed_size = u32_at(data, offset + 24)? as usize;
+        let name_len = u16_at(data, offset + 28)? as usize;
+        let extra_len = u16_at(data, offset + 30)? as usize;
+        let comment_len = u16_at(data, offset + 32)? as usize;
+        let local_header = u32_at(data, offset + 42)? as usize;
+        // Zip64 entries move these into an extra field.
+        if [compressed_size, uncompressed_size, local_header].contains(&0xFFFF_FFFF) {
+            return None;
+        }
+        let name = String::from_utf8_lossy(data.get(offset + 46..offset + 46 + name_len)?);
+
+        // Directories are implied by the files in them.
+        if !name.ends_with('/') {
+            if let Ok(name) = normalize(&name) {
+                entries.insert(
+                    name,
+                    Entry {
+                        method,
+                        compressed_size,
+                        uncompressed_size,
+                        local_header,
+                    },
+                );
+            }
+        }
+
+        offset += 46 + name_len + extra_len + comment_len;
+    }
+
+    Some(entries)
+}
+
+impl Mount for ZipMount {
+    fn read(&self, path: &str) -> Option<io::Result<Vec<u8>>> {
+        self.entries.get(path).map(|entry| self.extract(entry))
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.entries.contains_key(path)
+    }
+
+    fn describe(&self) -> String {
+        self.archive.display().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An archive of `(name, method, stored bytes, uncompressed size)`
+    /// entries, without a comment.
+    fn archive(entries: &[(&str, u16, Vec<u8>, usize)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut central = Vec::new();
+        for (name, method, bytes, size) in entries {
+            let local_header = data.len() as u32;
+            data.extend(LOCAL_HEADER.to_le_bytes());
+            data.extend([20, 0, 0, 0]);
+            data.extend(method.to_le_bytes());
+            // Time, date and CRC, which aren't checked.
+            data.extend([0; 8]);
+            data.extend((bytes.len() as u32).to_le_bytes());
+            data.extend((*size as u32).to_le_bytes());
+            data.extend((name.len() as u16).to_le_bytes());
+            data.extend([0, 0]);
+            data.extend(name.as_bytes());
+            data.extend(bytes);
+
+            central.extend(CENTRAL_HEADER.to_le_bytes());
+            central.extend([20, 0, 20, 0, 0, 0]);
+            central.extend(method.to_le_bytes());
+            central.extend([0; 8]);
+            central.extend((bytes.len() as u32).to_le_bytes());
+            central.extend((*size as u32).to_le_bytes());
+            central.extend((name.len() as u16).to_le_bytes());
+            central.extend([0; 12]);
+            central.extend(local_header.to_le_bytes());
+            central.extend(name.as_bytes());
+        }
+
+        let central_offset = data.len() as u32;
+        data.extend(&central);
+        data.extend(END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        data.extend([0; 4]);
+        data.extend((entries.len() as u16).to_le_bytes());
+        data.extend((entries.len() as u16).to_le_bytes());
+        data.extend((central.len() as u32).to_le_bytes());
+        data.extend(central_offset.to_le_bytes());
+        data.extend([0, 0]);
+        data
+    }
+
+    fn stored<'a>(name: &'a str, contents: &[u8]) -> (&'a str, u16, Vec<u8>, usize) {
+        (name, STORED, contents.to_vec(), contents.len())
+    }
+
+    fn read(mount: &ZipMount, path: &str) -> Vec<u8> {
+        mount.read(path).unwrap().unwrap()
+    }
+
+    #[test]
+    fn reads_stored_entries() {
+        let data = archive(&[
+            stored("readme.txt", b"hello"),
+            stored("shaders/", b""),
+            stored("shaders\\gbuffer.hlsl", b"float4 main();"),
+            stored("./models/../empty.bin", b""),
+        ]);
+
+        let mount = ZipMount::from_bytes("test.pak", data).unwrap();
+        assert_eq!(read(&mount, "readme.txt"), b"hello");
+        assert_eq!(read(&mount, "shaders/gbuffer.hlsl"), b"float4 main();");
+        assert_eq!(read(&mount, "empty.bin"), b"");
+        assert!(mount.exists("readme.txt"));
+        // Directories aren't files.
+        assert!(!mount.exists("shaders"));
+        assert!(mount.read("missing.txt").is_none());
+        assert_eq!(mount.describe(), "test.pak");
+    }
+
+    #[test]
+    fn reads_deflated_entries() {
+        let contents = b"sky sky sky sky sky sky sky sky sky sky".repeat(20);
+        let deflated = miniz_oxide::deflate::compress_to_vec(&contents, 6);
+        assert!(deflated.len() < contents.len());
+        let data = archive(&[
+            ("sky.txt", DEFLATED, deflated.clone(), contents.len()),
+            ("short.txt", DEFLATED, deflated.clone(), contents.len() - 1),
+            ("bomb.txt", DEFLATED, deflated.clone(), 10),
+            ("bzip2.txt", 12, deflated, contents.len()),
+        ]);
+
+        let mount = ZipMount::from_bytes("test.pak", data).unwrap();
+        assert_eq!(read(&mount, "sky.txt"), contents);
+        // Entries that don't inflate to their stated size, or use another
+        // method, fail when read.
+        assert!(mount.read("short.txt").unwrap().is_err());
+        assert!(mount.read("bomb.txt").unwrap().is_err());
+        assert!(mount.read("bzip2.txt").unwrap().is_err());
+    }
+
+    #[test]
+    fn finds_the_end_record_before_a_comment() {
+        let mut data = archive(&[stored("a.txt", b"a")]);
+        let comment = b"made by a test";
+        let length = data.len();
+        data[length - 2..].copy_from_slice(&(comment.len() as u16).to_le_bytes());
+        data.extend(comment);
+
+        let mount = ZipMount::from_bytes("test.pak", data).unwrap();
+        assert_eq!(read(&mount, "a.txt"), b"a");
+    }
+
+    #[test]
+    fn rejects_truncated_archives() {
+        let data = archive(&[stored("a.txt", b"abc")]);
+        let length = data.len();
+        // The central directory follows the one local header.
+        let central = 30 + 5 + 3;
+
+        assert!(ZipMount::from_bytes("test.pak", Vec::new()).is_err());
+        assert!(ZipMount::from_bytes("test.pak", b"not a zip".to_vec()).is_err());
+        // Without the end record, or with the central directory cut off.
+        assert!(ZipMount::from_bytes("test.pak", data[..length - 22].to_vec()).is_err());
+        let mut cut = data[..central + 10].to_vec();
+        cut.extend(&data[length - 22..]);
+        assert!(ZipMount::from_bytes("test.pak", cut).is_err());
+
+        let mut moved = data.clone();
+        moved[length - 6..length - 2].copy_from_slice(&0x7fff_ffffu32.to_le_bytes());
+        assert!(ZipMount::from_bytes("test.pak", moved).is_err());
+
+        // Entries whose data is cut off, or whose local header is missing,
+        // fail when read.
+        let mut oversized = data.clone();
+        oversized[central + 20..central + 24].copy_from_slice(&1000u32.to_le_bytes());
+        let mount = ZipMount::from_bytes("test.pak", oversized).unwrap();
+        assert!(mount.read("a.txt").unwrap().is_err());
+
+        let mut misplaced = data;
+        misplaced[central + 42..central + 46].copy_from_slice(&4u32.to_le_bytes());
+        let mount = ZipMount::from_bytes("test.pak", misplaced).unwrap();
+        assert!(mount.read("a.txt").unwrap().is_err());
+    }
+
+    #[test]
+    fn rejects_zip64_archives() {
+        let data = archive(&[stored("a.txt", b"a")]);
+        let length = data.len();
+
+        let mut count = data.clone();
+        count[length - 12..length - 10].copy_from_slice(&[0xff, 0xff]);
+        assert!(ZipMount::from_bytes("test.pak", count).is_err());
+
+        let mut offset = data.clone();
+        offset[length - 6..length - 2].copy_from_slice(&[0xff; 4]);
+        assert!(ZipMount::from_bytes("test.pak", offset).is_err());
+
+        // Sizes moved into a zip64 extra field.
+        let mut size = data;
+        let central = 30 + 5 + 1;
+        size[central + 20..central + 28].copy_from_slice(&[0xff; 8]);
+        assert!(ZipMount::from_bytes("test.pak", size).is_err());
+    }
+}